@@ -0,0 +1,87 @@
+use bytecode::{Chunk, OpCode, VM, Value};
+use criterion::{Criterion, criterion_group, criterion_main};
+
+fn run_source(source: &str) {
+    VM::new().interpret(source).unwrap();
+}
+
+fn arithmetic_loop(c: &mut Criterion) {
+    let source = "
+        {
+            var acc = 0;
+            for (var i = 0; i < 100000; i = i + 1) {
+                acc = acc + i * 2 - 1;
+            }
+        }
+    ";
+    c.bench_function("arithmetic loop", |b| b.iter(|| run_source(source)));
+}
+
+fn global_churn(c: &mut Criterion) {
+    let source = "
+        var a = 0;
+        var b = 1;
+        var i = 0;
+        while (i < 100000) {
+            a = b;
+            b = a + 1;
+            i = i + 1;
+        }
+    ";
+    c.bench_function("global churn", |b| b.iter(|| run_source(source)));
+}
+
+fn string_concat(c: &mut Criterion) {
+    let source = "
+        var s = \"\";
+        for (var i = 0; i < 1000; i = i + 1) {
+            s = s + \"x\";
+        }
+    ";
+    c.bench_function("string concat", |b| b.iter(|| run_source(source)));
+}
+
+fn fib(c: &mut Criterion) {
+    let source = "
+        fun fib(n) {
+            if (n < 2) return n;
+            return fib(n - 1) + fib(n - 2);
+        }
+        fib(20);
+    ";
+    c.bench_function("fib 20", |b| b.iter(|| run_source(source)));
+}
+
+fn hand_built_chunk(c: &mut Criterion) {
+    // 1 + 2 * 3, repeated, without going through the compiler
+    let build = || {
+        let mut chunk = Chunk::new();
+        for _ in 0..1000 {
+            chunk.write_constant(Value::Number(1.0), 1);
+            chunk.write_constant(Value::Number(2.0), 1);
+            chunk.write_constant(Value::Number(3.0), 1);
+            chunk.write(OpCode::Multiply.into(), 1);
+            chunk.write(OpCode::Add.into(), 1);
+            chunk.write(OpCode::Pop.into(), 1);
+        }
+        chunk.write(OpCode::Return.into(), 1);
+        chunk
+    };
+    c.bench_function("hand-built chunk", |b| {
+        b.iter_batched(
+            build,
+            |chunk| VM::new().run(chunk).unwrap(),
+            criterion::BatchSize::SmallInput,
+        )
+    });
+}
+
+criterion_group!(
+    benches,
+    arithmetic_loop,
+    global_churn,
+    string_concat,
+    fib,
+    hand_built_chunk
+);
+criterion_main!(benches);
@@ -1,84 +1,112 @@
-use std::fmt::Display;
+use std::{collections::HashMap, fmt::Display, io::Write, rc::Rc};
 
-use crate::value::Value;
+use crate::{
+    Error,
+    session::CompilerSession,
+    value::{DebugValue, Value},
+};
 
+mod asm;
+mod decode;
+mod loxc;
+
+pub use asm::{AsmError, assemble};
+pub use decode::{DecodedInstruction, Operand};
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum OpCode {
-    Return,
-    Constant,
-    ConstantLong,
-    Negate,
-    Add,
-    Subtract,
-    Multiply,
-    Divide,
-    Nil,
-    True,
-    False,
-    Not,
-    Equal,
-    Greater,
-    Less,
-    Print,
-    Pop,
-    DefineGlobal,
-    GetGlobal,
-    SetGlobal,
-    Unknown,
+    Return = 0,
+    Constant = 1,
+    ConstantLong = 2,
+    Negate = 3,
+    Add = 4,
+    Subtract = 5,
+    Multiply = 6,
+    Divide = 7,
+    Nil = 8,
+    True = 9,
+    False = 10,
+    Not = 11,
+    Equal = 12,
+    Greater = 13,
+    Less = 14,
+    Print = 15,
+    Pop = 16,
+    DefineGlobal = 17,
+    GetGlobal = 18,
+    SetGlobal = 19,
+    Modulo = 20,
+    GetLocal = 21,
+    SetLocal = 22,
+    Jump = 23,
+    JumpIfFalse = 24,
+    Loop = 25,
+    Dup = 26,
+    PopN = 27,
+    Call = 28,
+    Index = 29,
+    NotEqual = 30,
+    GreaterEqual = 31,
+    LessEqual = 32,
+    Zero = 33,
+    One = 34,
+    MinusOne = 35,
+    Assert = 36,
+}
+
+impl OpCode {
+    /// The highest opcode; discriminants run contiguously from 0 up to it
+    pub(crate) const LAST: u8 = OpCode::Assert as u8;
+
+    /// The opcode that pushes `value` without a constant, for the numbers
+    /// loops use most. -0 isn't one of them.
+    pub fn small_constant(value: &Value) -> Option<Self> {
+        let n = value.as_number()?;
+        match n.to_bits() {
+            bits if bits == 0f64.to_bits() => Some(OpCode::Zero),
+            bits if bits == 1f64.to_bits() => Some(OpCode::One),
+            bits if bits == (-1f64).to_bits() => Some(OpCode::MinusOne),
+            _ => None,
+        }
+    }
+
+    /// How many operand bytes follow the opcode
+    pub fn operand_len(self) -> usize {
+        match self {
+            OpCode::Constant
+            | OpCode::GetLocal
+            | OpCode::SetLocal
+            | OpCode::PopN
+            | OpCode::Call
+            | OpCode::Assert => 1,
+            OpCode::ConstantLong
+            | OpCode::DefineGlobal
+            | OpCode::GetGlobal
+            | OpCode::SetGlobal
+            | OpCode::Jump
+            | OpCode::JumpIfFalse
+            | OpCode::Loop => 2,
+            _ => 0,
+        }
+    }
 }
 
-impl From<u8> for OpCode {
-    fn from(value: u8) -> Self {
-        match value {
-            0 => Self::Return,
-            1 => Self::Constant,
-            2 => Self::ConstantLong,
-            3 => Self::Negate,
-            4 => Self::Add,
-            5 => Self::Subtract,
-            6 => Self::Multiply,
-            7 => Self::Divide,
-            8 => Self::Nil,
-            9 => Self::True,
-            10 => Self::False,
-            11 => Self::Not,
-            12 => Self::Equal,
-            13 => Self::Greater,
-            14 => Self::Less,
-            15 => Self::Print,
-            16 => Self::Pop,
-            17 => Self::DefineGlobal,
-            18 => Self::GetGlobal,
-            19 => Self::SetGlobal,
-            _ => Self::Unknown,
+impl TryFrom<u8> for OpCode {
+    type Error = u8;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        if value > Self::LAST {
+            return Err(value);
         }
+        // SAFETY: OpCode is repr(u8) and every value in 0..=LAST is a discriminant
+        Ok(unsafe { std::mem::transmute::<u8, OpCode>(value) })
     }
 }
 
 impl From<OpCode> for u8 {
     fn from(value: OpCode) -> Self {
-        match value {
-            OpCode::Return => 0,
-            OpCode::Constant => 1,
-            OpCode::ConstantLong => 2,
-            OpCode::Negate => 3,
-            OpCode::Add => 4,
-            OpCode::Subtract => 5,
-            OpCode::Multiply => 6,
-            OpCode::Divide => 7,
-            OpCode::Nil => 8,
-            OpCode::True => 9,
-            OpCode::False => 10,
-            OpCode::Not => 11,
-            OpCode::Equal => 12,
-            OpCode::Greater => 13,
-            OpCode::Less => 14,
-            OpCode::Print => 15,
-            OpCode::Pop => 16,
-            OpCode::DefineGlobal => 17,
-            OpCode::GetGlobal => 18,
-            OpCode::SetGlobal => 19,
-            OpCode::Unknown => 255,
-        }
+        value as u8
     }
 }
 
@@ -108,7 +136,23 @@ impl Display for OpCode {
                 OpCode::DefineGlobal => "OP_DEFINE_GLOBAL",
                 OpCode::GetGlobal => "OP_GET_GLOBAL",
                 OpCode::SetGlobal => "OP_SET_GLOBAL",
-                OpCode::Unknown => "UNKNOWN",
+                OpCode::Modulo => "OP_MODULO",
+                OpCode::GetLocal => "OP_GET_LOCAL",
+                OpCode::SetLocal => "OP_SET_LOCAL",
+                OpCode::Jump => "OP_JUMP",
+                OpCode::JumpIfFalse => "OP_JUMP_IF_FALSE",
+                OpCode::Loop => "OP_LOOP",
+                OpCode::Dup => "OP_DUP",
+                OpCode::PopN => "OP_POPN",
+                OpCode::Call => "OP_CALL",
+                OpCode::Index => "OP_INDEX",
+                OpCode::NotEqual => "OP_NOT_EQUAL",
+                OpCode::GreaterEqual => "OP_GREATER_EQUAL",
+                OpCode::LessEqual => "OP_LESS_EQUAL",
+                OpCode::Zero => "OP_ZERO",
+                OpCode::One => "OP_ONE",
+                OpCode::MinusOne => "OP_MINUS_ONE",
+                OpCode::Assert => "OP_ASSERT",
             }
         )
     }
@@ -122,24 +166,55 @@ pub fn break_index(idx: usize) -> [u8; 2] {
     [(idx >> 8) as u8, (idx & 255) as u8]
 }
 
-pub struct Chunk<'a> {
+/// Where in the source an instruction came from. Hand-built chunks can pass
+/// a bare line number, which leaves the column unknown (0).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Position {
+    pub line: usize,
+    /// 1-based, counted in characters
+    pub column: usize,
+}
+
+impl From<usize> for Position {
+    fn from(line: usize) -> Self {
+        Self { line, column: 0 }
+    }
+}
+
+impl Display for Position {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.column {
+            0 => write!(f, "{}", self.line),
+            column => write!(f, "{}:{}", self.line, column),
+        }
+    }
+}
+
+pub struct Chunk {
     pub(crate) code: Vec<u8>,
-    constants: Vec<Value<'a>>,
-    lines: Vec<usize>,
+    pub(crate) constants: Vec<Value>,
+    positions: Vec<Position>,
+    /// names of the global slots this chunk refers to, for disassembly
+    global_names: HashMap<usize, Rc<str>>,
+    /// index of the source it was compiled from in the program's
+    /// `SourceMap`; 0 for loaded and hand-built chunks
+    pub(crate) file: usize,
 }
 
-impl Default for Chunk<'_> {
+impl Default for Chunk {
     fn default() -> Self {
         Self::new()
     }
 }
 
-impl<'a> Chunk<'a> {
+impl Chunk {
     pub fn new() -> Self {
         Self {
             code: Vec::new(),
             constants: Vec::new(),
-            lines: Vec::new(),
+            positions: Vec::new(),
+            global_names: HashMap::new(),
+            file: 0,
         }
     }
 
@@ -147,106 +222,433 @@ impl<'a> Chunk<'a> {
         &self.code
     }
 
-    pub fn write(&mut self, byte: u8, line: usize) {
+    /// Appends a raw byte (an opcode or operand) from source `position`
+    pub fn write(&mut self, byte: u8, position: impl Into<Position>) {
         self.code.push(byte);
-        self.lines.push(line);
+        self.positions.push(position.into());
     }
 
-    pub fn write_constant<'p>(&'p mut self, value: Value<'a>, line: usize) {
+    /// Adds `value` to the constant pool and writes the instruction that loads
+    /// it, switching to `OP_CONSTANT_LONG` past 256 constants. 0, 1 and -1
+    /// get their own opcodes instead.
+    pub fn write_constant(&mut self, value: Value, position: impl Into<Position>) {
+        let position = position.into();
+        if let Some(op) = OpCode::small_constant(&value) {
+            self.write(op.into(), position);
+            return;
+        }
         let const_idx = self.add_constant(value);
         if const_idx < 256 {
-            self.write(OpCode::Constant.into(), line);
-            self.write(const_idx as u8, line);
+            self.write(OpCode::Constant.into(), position);
+            self.write(const_idx as u8, position);
         } else {
-            self.write(OpCode::ConstantLong.into(), line);
+            self.write(OpCode::ConstantLong.into(), position);
             let [const_idx_top, const_idx_bot] = break_index(const_idx);
-            self.write(const_idx_top, line);
-            self.write(const_idx_bot, line);
+            self.write(const_idx_top, position);
+            self.write(const_idx_bot, position);
+        }
+    }
+
+    /// Writes a global variable instruction with its 16-bit slot operand
+    pub(crate) fn write_global(
+        &mut self,
+        op: OpCode,
+        slot: usize,
+        name: &str,
+        position: impl Into<Position>,
+    ) {
+        let position = position.into();
+        self.write(op.into(), position);
+        let [slot_top, slot_bot] = break_index(slot);
+        self.write(slot_top, position);
+        self.write(slot_bot, position);
+        self.global_names
+            .entry(slot)
+            .or_insert_with(|| Rc::from(name));
+    }
+
+    /// Writes a forward jump with a placeholder operand and returns the
+    /// operand's offset for `patch_jump`
+    pub fn emit_jump(&mut self, op: OpCode, position: impl Into<Position>) -> usize {
+        let position = position.into();
+        self.write(op.into(), position);
+        self.write(0xff, position);
+        self.write(0xff, position);
+        self.code.len() - 2
+    }
+
+    /// Points the jump whose operand is at `offset` to the end of the code.
+    /// Fails with the distance if it doesn't fit in 16 bits.
+    pub fn patch_jump(&mut self, offset: usize) -> Result<(), usize> {
+        // -2 to account for the jump's own operand
+        let jump = self.code.len() - offset - 2;
+        if jump > u16::MAX as usize {
+            return Err(jump);
         }
+        let [top, bot] = break_index(jump);
+        self.code[offset] = top;
+        self.code[offset + 1] = bot;
+        Ok(())
     }
 
-    pub fn add_constant<'p>(&'p mut self, value: Value<'a>) -> usize {
+    /// Writes an `OP_LOOP` back to `loop_start`. Fails with the distance if it
+    /// doesn't fit in 16 bits, leaving a placeholder operand.
+    pub fn emit_loop(
+        &mut self,
+        loop_start: usize,
+        position: impl Into<Position>,
+    ) -> Result<(), usize> {
+        let position = position.into();
+        self.write(OpCode::Loop.into(), position);
+        // +2 to account for the loop's own operand
+        let jump = self.code.len() - loop_start + 2;
+        let [top, bot] = if jump > u16::MAX as usize {
+            [0xff, 0xff]
+        } else {
+            break_index(jump)
+        };
+        self.write(top, position);
+        self.write(bot, position);
+        if jump > u16::MAX as usize {
+            return Err(jump);
+        }
+        Ok(())
+    }
+
+    /// Adds `value` to the constant pool and returns its index
+    pub fn add_constant(&mut self, value: Value) -> usize {
         self.constants.push(value);
         self.constants.len() - 1
     }
 
+    /// Drops the code from `offset` on and the constants from `const_idx` on
+    pub(crate) fn truncate(&mut self, offset: usize, const_idx: usize) {
+        self.code.truncate(offset);
+        self.positions.truncate(offset);
+        self.constants.truncate(const_idx);
+    }
+
     pub fn free(self) {
         drop(self);
     }
 
     pub fn dissassemble(&self, name: &str) {
-        println!("== {name} ==");
+        let _ = self.dissassemble_to(&mut std::io::stdout(), name);
+    }
+
+    pub fn dissassemble_to(&self, out: &mut dyn Write, name: &str) -> std::io::Result<()> {
+        writeln!(out, "== {name} ==")?;
 
         let mut cursor = 0;
         while cursor < self.code.len() {
-            cursor = self.dissassemble_instruction(cursor)
+            cursor = self.dissassemble_instruction_to(out, cursor)?;
+        }
+        // functions declared in this chunk follow it
+        for function in self.constants.iter().filter_map(Value::as_function) {
+            function
+                .chunk
+                .dissassemble_to(out, function.name.as_deref().unwrap_or("script"))?;
         }
+        Ok(())
     }
 
     pub fn dissassemble_instruction(&self, index: usize) -> usize {
-        print!("{index:04} ",);
-        if index > 0 && self.lines[index] == self.lines[index - 1] {
-            print!("   | ");
+        self.dissassemble_instruction_to(&mut std::io::stdout(), index)
+            .unwrap_or(index + 1)
+    }
+
+    pub fn dissassemble_instruction_to(
+        &self,
+        out: &mut dyn Write,
+        index: usize,
+    ) -> std::io::Result<usize> {
+        write!(out, "{index:04} ",)?;
+        let line = self.read_line(index);
+        if index > 0 && line == self.read_line(index - 1) {
+            write!(out, "   | ")?;
         } else {
-            print!("{:4} ", self.lines[index]);
-        }
-        let op = OpCode::from(self.code[index]);
-        match op {
-            OpCode::Return => self.print_simple(OpCode::Return, index),
-            OpCode::Constant | OpCode::DefineGlobal | OpCode::GetGlobal | OpCode::SetGlobal => {
-                let const_idx = self.code[index + 1] as usize;
-                self.print_constant(op, const_idx, index)
+            write!(out, "{:4} ", line)?;
+        }
+        let instruction = match self.decode_instruction(index) {
+            Ok(instruction) => instruction,
+            Err(Error::InvalidBytecode { reason, .. }) => {
+                writeln!(out, "{reason}")?;
+                return Ok(index + 1);
             }
-            OpCode::ConstantLong => {
-                let const_idx = long_index(self.code[index + 1], self.code[index + 2]);
-                self.print_constant_long(const_idx, index)
+            Err(e) => unreachable!("decoding only fails as invalid bytecode: {e}"),
+        };
+        let op = instruction.op;
+        match instruction.operand {
+            Operand::None => writeln!(out, "{op}")?,
+            Operand::Constant { index, value } => {
+                writeln!(out, "{:16} {:4} '{}'", op, index, DebugValue(value))?
             }
-            OpCode::Negate
-            | OpCode::Add
-            | OpCode::Subtract
-            | OpCode::Multiply
-            | OpCode::Divide
-            | OpCode::Nil
-            | OpCode::True
-            | OpCode::False
-            | OpCode::Not
-            | OpCode::Equal
-            | OpCode::Greater
-            | OpCode::Less
-            | OpCode::Print
-            | OpCode::Pop => self.print_simple(op, index),
-            OpCode::Unknown => {
-                println!("Unknown OpCode: {}", self.code[index]);
-                index + 1
+            Operand::Global { slot, name } => {
+                writeln!(out, "{:16} {:4} '{}'", op, slot, name.unwrap_or("?"))?
             }
+            Operand::Local(byte) | Operand::Count(byte) => writeln!(out, "{:16} {:4}", op, byte)?,
+            Operand::Jump(target) => writeln!(out, "{:16} {:4} -> {}", op, index, target)?,
         }
+        Ok(instruction.next())
+    }
+
+    /// Checks that the code is a run of known instructions ending in
+    /// `OP_RETURN`, with constant indices in the pool, named global slots,
+    /// jumps that land on an instruction, and no instruction popping more
+    /// than the stack holds or reading a local slot above it. Functions in
+    /// the pool are checked too, with the error naming the function.
+    pub fn validate(&self) -> Result<(), Error> {
+        self.validate_frame(0)
     }
 
-    fn print_simple(&self, op: OpCode, cursor: usize) -> usize {
-        println!("{op}");
-        cursor + 1
+    /// `validate` for code whose frame starts with `slots` values: none for
+    /// the script, the callee and its arguments for a function
+    pub(crate) fn validate_frame(&self, slots: usize) -> Result<(), Error> {
+        let invalid = |reason: String, offset| Err(Error::InvalidBytecode { reason, offset });
+        if self.positions.len() != self.code.len() {
+            return invalid(
+                format!(
+                    "{} positions for {} bytes of code",
+                    self.positions.len(),
+                    self.code.len()
+                ),
+                0,
+            );
+        }
+        let mut starts = vec![false; self.code.len()];
+        let mut jumps = Vec::new();
+        let mut last = None;
+        let mut offset = 0;
+        while offset < self.code.len() {
+            starts[offset] = true;
+            let instruction = self.decode_instruction(offset)?;
+            let op = instruction.op;
+            match instruction.operand {
+                Operand::Global { slot, name: None } => {
+                    return invalid(format!("{op} uses unnamed global slot {slot}"), offset);
+                }
+                Operand::Count(n) if op == OpCode::Assert && n > 1 => {
+                    return invalid(format!("{op} expects 0 or 1 messages, not {n}"), offset);
+                }
+                Operand::Jump(target) => jumps.push((op, offset, target)),
+                _ => {}
+            }
+            last = Some(op);
+            offset = instruction.next();
+        }
+        if last != Some(OpCode::Return) {
+            return invalid("code doesn't end with OP_RETURN".to_string(), offset);
+        }
+        for (op, offset, target) in jumps {
+            if !starts.get(target).copied().unwrap_or(false) {
+                return invalid(
+                    format!("{op} jumps to {target}, which isn't an instruction"),
+                    offset,
+                );
+            }
+        }
+        self.validate_stack(slots)?;
+        for function in self.constants.iter().filter_map(Value::as_function) {
+            if let Err(Error::InvalidBytecode { reason, offset }) =
+                function.chunk.validate_frame(function.arity + 1)
+            {
+                return invalid(format!("in {function}: {reason}"), offset);
+            }
+        }
+        Ok(())
     }
 
-    fn print_constant(&self, op: OpCode, const_idx: usize, cursor: usize) -> usize {
-        println!("{:16} {:4} '{}'", op, const_idx, self.constants[const_idx]);
-        cursor + 2
+    /// Follows every path through the code from its start with `slots`
+    /// values on the stack, checking each instruction against the shallowest
+    /// stack any path reaches it with. Jumps must already land on
+    /// instructions.
+    fn validate_stack(&self, slots: usize) -> Result<(), Error> {
+        let invalid = |reason: String, offset| Err(Error::InvalidBytecode { reason, offset });
+        let mut depths = vec![None; self.code.len()];
+        let mut pending = vec![(0, slots)];
+        while let Some((offset, depth)) = pending.pop() {
+            if depths[offset].is_some_and(|known| known <= depth) {
+                continue;
+            }
+            depths[offset] = Some(depth);
+            let instruction = self.decode_instruction(offset)?;
+            let op = instruction.op;
+            let count = match instruction.operand {
+                Operand::Count(n) => n as usize,
+                _ => 0,
+            };
+            let (pops, pushes) = match op {
+                // the script may finish with nothing left to return
+                OpCode::Return => (usize::from(slots > 0), 0),
+                OpCode::Constant
+                | OpCode::ConstantLong
+                | OpCode::Nil
+                | OpCode::True
+                | OpCode::False
+                | OpCode::Zero
+                | OpCode::One
+                | OpCode::MinusOne
+                | OpCode::GetGlobal
+                | OpCode::GetLocal => (0, 1),
+                OpCode::Dup => (1, 2),
+                OpCode::Negate
+                | OpCode::Not
+                | OpCode::SetGlobal
+                | OpCode::SetLocal
+                | OpCode::JumpIfFalse => (1, 1),
+                OpCode::Add
+                | OpCode::Subtract
+                | OpCode::Multiply
+                | OpCode::Divide
+                | OpCode::Modulo
+                | OpCode::Equal
+                | OpCode::NotEqual
+                | OpCode::Greater
+                | OpCode::GreaterEqual
+                | OpCode::Less
+                | OpCode::LessEqual
+                | OpCode::Index => (2, 1),
+                OpCode::Print | OpCode::Pop | OpCode::DefineGlobal => (1, 0),
+                OpCode::PopN => (count, 0),
+                // the callee and its arguments, replaced by the result
+                OpCode::Call => (count + 1, 1),
+                OpCode::Assert => (count + 1, 0),
+                OpCode::Jump | OpCode::Loop => (0, 0),
+            };
+            if pops > depth {
+                return invalid(
+                    format!("{op} underflows the stack at depth {depth}"),
+                    offset,
+                );
+            }
+            if let Operand::Local(slot) = instruction.operand
+                && slot as usize >= depth
+            {
+                return invalid(
+                    format!("{op} uses local slot {slot} at stack depth {depth}"),
+                    offset,
+                );
+            }
+            let depth = depth - pops + pushes;
+            match (op, &instruction.operand) {
+                (OpCode::Return, _) => {}
+                (OpCode::Jump | OpCode::Loop, &Operand::Jump(target)) => {
+                    pending.push((target, depth))
+                }
+                (OpCode::JumpIfFalse, &Operand::Jump(target)) => {
+                    pending.push((target, depth));
+                    pending.push((instruction.next(), depth));
+                }
+                _ => pending.push((instruction.next(), depth)),
+            }
+        }
+        Ok(())
     }
 
-    fn print_constant_long(&self, const_idx: usize, cursor: usize) -> usize {
-        println!(
-            "{:16} {:4} '{}'",
-            OpCode::ConstantLong,
-            const_idx,
-            self.constants[const_idx]
-        );
-        cursor + 3
+    /// Rewrites global operands from the slots the chunk was built with to
+    /// `session`'s slots for the same names. Every global instruction must
+    /// have a name, as in a validated chunk.
+    pub(crate) fn relink_globals(&mut self, session: &mut CompilerSession) {
+        let names = std::mem::take(&mut self.global_names);
+        let mut offset = 0;
+        while offset < self.code.len() {
+            let op = OpCode::try_from(self.code[offset]).expect("validated");
+            if matches!(
+                op,
+                OpCode::DefineGlobal | OpCode::GetGlobal | OpCode::SetGlobal
+            ) {
+                let name = &names[&long_index(self.code[offset + 1], self.code[offset + 2])];
+                let slot = session.resolve_global(name);
+                [self.code[offset + 1], self.code[offset + 2]] = break_index(slot);
+                self.global_names.insert(slot, name.clone());
+            }
+            offset += 1 + op.operand_len();
+        }
     }
 
-    pub(crate) fn read_constant(&self, index: usize) -> &Value<'a> {
+    pub(crate) fn read_constant(&self, index: usize) -> &Value {
         &self.constants[index]
     }
 
     pub(crate) fn read_line(&self, index: usize) -> usize {
-        self.lines[index]
+        self.read_position(index).line
+    }
+
+    pub(crate) fn read_position(&self, index: usize) -> Position {
+        // an offset past the end (e.g. a truncated chunk) reports the last position
+        self.positions
+            .get(index)
+            .or(self.positions.last())
+            .copied()
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_opcode_round_trip() {
+        let mut names = std::collections::HashSet::new();
+        for byte in 0..=u8::MAX {
+            match OpCode::try_from(byte) {
+                Ok(op) => {
+                    assert!(byte <= OpCode::LAST);
+                    assert_eq!(u8::from(op), byte);
+                    assert!(names.insert(op.to_string()), "{op} repeated");
+                }
+                Err(e) => {
+                    assert!(byte > OpCode::LAST);
+                    assert_eq!(e, byte);
+                }
+            }
+        }
+        assert_eq!(names.len(), OpCode::LAST as usize + 1);
+    }
+
+    #[test]
+    fn test_patch_jump() {
+        let mut chunk = Chunk::new();
+        let offset = chunk.emit_jump(OpCode::JumpIfFalse, 1);
+        assert_eq!(offset, 1);
+        for _ in 0..3 {
+            chunk.write(OpCode::Nil.into(), 1);
+        }
+        assert_eq!(chunk.patch_jump(offset), Ok(()));
+        assert_eq!(chunk.code()[..3], [OpCode::JumpIfFalse.into(), 0, 3]);
+
+        let mut chunk = Chunk::new();
+        let offset = chunk.emit_jump(OpCode::Jump, 1);
+        for _ in 0..u16::MAX {
+            chunk.write(OpCode::Nil.into(), 1);
+        }
+        assert_eq!(chunk.patch_jump(offset), Ok(()));
+        assert_eq!(chunk.code()[1..3], [0xff, 0xff]);
+        chunk.write(OpCode::Nil.into(), 1);
+        assert_eq!(chunk.patch_jump(offset), Err(u16::MAX as usize + 1));
+    }
+
+    #[test]
+    fn test_emit_loop() {
+        let mut chunk = Chunk::new();
+        chunk.write(OpCode::Nil.into(), 1);
+        chunk.write(OpCode::Pop.into(), 1);
+        assert_eq!(chunk.emit_loop(0, 1), Ok(()));
+        assert_eq!(chunk.code()[2..], [OpCode::Loop.into(), 0, 5]);
+
+        // the operand counts the loop instruction itself
+        let mut chunk = Chunk::new();
+        for _ in 0..u16::MAX - 3 {
+            chunk.write(OpCode::Nil.into(), 1);
+        }
+        assert_eq!(chunk.emit_loop(0, 1), Ok(()));
+        assert_eq!(chunk.code()[chunk.code().len() - 2..], [0xff, 0xff]);
+
+        let mut chunk = Chunk::new();
+        for _ in 0..u16::MAX - 2 {
+            chunk.write(OpCode::Nil.into(), 1);
+        }
+        assert_eq!(chunk.emit_loop(0, 1), Err(u16::MAX as usize + 1));
     }
 }
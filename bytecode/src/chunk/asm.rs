@@ -0,0 +1,554 @@
+//! A textual assembler for building chunks by hand, mostly in tests. One
+//! instruction per line, named as the disassembler prints it, with or
+//! without the `OP_` prefix:
+//!
+//! ```text
+//! ; count down from 3
+//!     CONSTANT 3
+//!     DEFINE_GLOBAL n
+//! top:
+//!     GET_GLOBAL n
+//!     JUMP_IF_FALSE @done
+//!     POP
+//!     GET_GLOBAL n
+//!     PRINT
+//!     GET_GLOBAL n
+//!     CONSTANT 1
+//!     SUBTRACT
+//!     SET_GLOBAL n
+//!     POP
+//!     LOOP @top
+//! done:
+//!     POP
+//!     RETURN
+//! ```
+//!
+//! Constants are literals (numbers, `"strings"`, `true`, `false`, `nil`),
+//! pooled as the compiler pools them, with `OP_CONSTANT_LONG` past 256.
+//! Globals are names, given slots in order of first use; `VM::assemble`
+//! binds them to the VM's slots instead. Jumps and loops name a label. A
+//! `;` starts a comment. The result isn't validated, so a test can build a
+//! chunk the compiler never would.
+
+use std::collections::HashMap;
+
+use thiserror::Error;
+
+use super::{Chunk, OpCode, break_index};
+use crate::{Value, compiler::ConstantKey};
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[error("[line {line}] {message}")]
+pub struct AsmError {
+    pub message: String,
+    pub line: usize,
+}
+
+/// Assembles `src` into a chunk; see the module docs for the format
+pub fn assemble(src: &str) -> Result<Chunk, AsmError> {
+    let mut asm = Assembler::default();
+    for (i, text) in src.lines().enumerate() {
+        asm.line = i + 1;
+        asm.statement(text)?;
+    }
+    asm.finish()
+}
+
+/// A jump whose operand is written once its label is known
+struct Fixup<'s> {
+    op: OpCode,
+    /// offset of the jump's operand
+    offset: usize,
+    label: &'s str,
+    line: usize,
+}
+
+#[derive(Default)]
+struct Assembler<'s> {
+    chunk: Chunk,
+    line: usize,
+    labels: HashMap<&'s str, usize>,
+    fixups: Vec<Fixup<'s>>,
+    constants: HashMap<ConstantKey, usize>,
+    globals: HashMap<&'s str, usize>,
+}
+
+impl<'s> Assembler<'s> {
+    fn error<T>(&self, message: String) -> Result<T, AsmError> {
+        Err(AsmError {
+            message,
+            line: self.line,
+        })
+    }
+
+    fn statement(&mut self, text: &'s str) -> Result<(), AsmError> {
+        let text = strip_comment(text).trim();
+        if text.is_empty() {
+            return Ok(());
+        }
+        if let Some(label) = text.strip_suffix(':') {
+            if !is_name(label) {
+                return self.error(format!("'{label}' isn't a valid label"));
+            }
+            if self.labels.insert(label, self.chunk.code.len()).is_some() {
+                return self.error(format!("label '{label}' is already defined"));
+            }
+            return Ok(());
+        }
+        let (mnemonic, operand) = match text.split_once(char::is_whitespace) {
+            Some((mnemonic, operand)) => (mnemonic, Some(operand.trim())),
+            None => (text, None),
+        };
+        let Some(op) = opcode(mnemonic) else {
+            return self.error(format!("unknown instruction '{mnemonic}'"));
+        };
+        match (op, operand) {
+            (OpCode::Constant | OpCode::ConstantLong, Some(literal)) => {
+                let value = self.literal(literal)?;
+                self.constant(op, value)
+            }
+            (OpCode::DefineGlobal | OpCode::GetGlobal | OpCode::SetGlobal, Some(name))
+                if is_name(name) =>
+            {
+                let next = self.globals.len();
+                let slot = *self.globals.entry(name).or_insert(next);
+                self.chunk.write_global(op, slot, name, self.line);
+                Ok(())
+            }
+            (
+                OpCode::GetLocal | OpCode::SetLocal | OpCode::PopN | OpCode::Call | OpCode::Assert,
+                Some(byte),
+            ) => {
+                let Ok(byte) = byte.parse::<u8>() else {
+                    return self.error(format!("{op} takes a byte (0 to 255), not '{byte}'"));
+                };
+                self.chunk.write(op.into(), self.line);
+                self.chunk.write(byte, self.line);
+                Ok(())
+            }
+            (OpCode::Jump | OpCode::JumpIfFalse | OpCode::Loop, Some(target)) => {
+                let Some(label) = target.strip_prefix('@').filter(|label| is_name(label)) else {
+                    return self.error(format!("{op} takes a @label, not '{target}'"));
+                };
+                let offset = self.chunk.emit_jump(op, self.line);
+                self.fixups.push(Fixup {
+                    op,
+                    offset,
+                    label,
+                    line: self.line,
+                });
+                Ok(())
+            }
+            (op, None) if op.operand_len() == 0 => {
+                self.chunk.write(op.into(), self.line);
+                Ok(())
+            }
+            (op, None) => self.error(format!("{op} is missing its operand")),
+            (op, Some(operand)) if op.operand_len() == 0 => {
+                self.error(format!("{op} takes no operand, not '{operand}'"))
+            }
+            (op, Some(operand)) => self.error(format!("{op} takes a name, not '{operand}'")),
+        }
+    }
+
+    fn literal(&self, literal: &str) -> Result<Value, AsmError> {
+        let value = match literal {
+            "nil" => Value::Nil,
+            "true" => Value::Bool(true),
+            "false" => Value::Bool(false),
+            _ if literal.starts_with('"') => match literal[1..].strip_suffix('"') {
+                Some(s) if !s.contains('"') => Value::from(s),
+                _ => return self.error(format!("'{literal}' isn't a single string")),
+            },
+            _ => match literal.parse::<f64>() {
+                Ok(n) if literal.starts_with(|c: char| c.is_ascii_digit() || c == '-') => {
+                    Value::Number(n)
+                }
+                _ => {
+                    return self.error(format!(
+                        "'{literal}' isn't a number, string, true, false or nil"
+                    ));
+                }
+            },
+        };
+        Ok(value)
+    }
+
+    fn constant(&mut self, op: OpCode, value: Value) -> Result<(), AsmError> {
+        let key = ConstantKey::of(&value);
+        let index = match key.as_ref().and_then(|key| self.constants.get(key)) {
+            Some(index) => *index,
+            None => {
+                let index = self.chunk.add_constant(value);
+                if let Some(key) = key {
+                    self.constants.insert(key, index);
+                }
+                index
+            }
+        };
+        if index > u16::MAX as usize {
+            return self.error("too many constants in one chunk".to_string());
+        }
+        if op == OpCode::Constant && index < 256 {
+            self.chunk.write(op.into(), self.line);
+            self.chunk.write(index as u8, self.line);
+        } else {
+            self.chunk.write(OpCode::ConstantLong.into(), self.line);
+            for byte in break_index(index) {
+                self.chunk.write(byte, self.line);
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<Chunk, AsmError> {
+        for fixup in std::mem::take(&mut self.fixups) {
+            self.line = fixup.line;
+            let Some(&target) = self.labels.get(fixup.label) else {
+                return self.error(format!("undefined label '{}'", fixup.label));
+            };
+            // jumps count from the end of their own operand
+            let next = fixup.offset + 2;
+            let distance = match fixup.op {
+                OpCode::Loop => next.checked_sub(target),
+                _ => target.checked_sub(next),
+            };
+            let Some(distance) = distance else {
+                let direction = if fixup.op == OpCode::Loop {
+                    "backward"
+                } else {
+                    "forward"
+                };
+                return self.error(format!(
+                    "{} only jumps {direction}, and '{}' isn't",
+                    fixup.op, fixup.label
+                ));
+            };
+            if distance > u16::MAX as usize {
+                return self.error(format!("'{}' is too far to jump to", fixup.label));
+            }
+            [
+                self.chunk.code[fixup.offset],
+                self.chunk.code[fixup.offset + 1],
+            ] = break_index(distance);
+        }
+        Ok(self.chunk)
+    }
+}
+
+fn strip_comment(text: &str) -> &str {
+    let mut in_string = false;
+    for (i, c) in text.char_indices() {
+        match c {
+            '"' => in_string = !in_string,
+            ';' if !in_string => return &text[..i],
+            _ => {}
+        }
+    }
+    text
+}
+
+fn is_name(s: &str) -> bool {
+    s.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn opcode(mnemonic: &str) -> Option<OpCode> {
+    let mnemonic = mnemonic.to_ascii_uppercase();
+    let name = mnemonic.strip_prefix("OP_").unwrap_or(&mnemonic);
+    if name == "CONST" {
+        return Some(OpCode::Constant);
+    }
+    (0..=OpCode::LAST)
+        .filter_map(|byte| OpCode::try_from(byte).ok())
+        .find(|op| op.to_string().strip_prefix("OP_") == Some(name))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{DecodedInstruction, Error, Operand, VM, value::DebugValue};
+
+    fn asm_error(src: &str) -> (usize, String) {
+        let error = assemble(src).err().expect("assembled");
+        (error.line, error.message)
+    }
+
+    /// The chunk's code as assembly, with a label at every jump target
+    fn to_asm(chunk: &Chunk) -> String {
+        let decoded = chunk.decode().unwrap();
+        let targets: Vec<_> = decoded
+            .iter()
+            .filter_map(|i| match i.operand {
+                Operand::Jump(target) => Some(target),
+                _ => None,
+            })
+            .collect();
+        let mut asm = String::new();
+        for DecodedInstruction {
+            op,
+            operand,
+            offset,
+            ..
+        } in &decoded
+        {
+            if targets.contains(offset) {
+                asm += &format!("L{offset}:\n");
+            }
+            asm += &match operand {
+                Operand::None => format!("{op}\n"),
+                Operand::Constant { value, .. } if value.as_string().is_some() => {
+                    format!("{op} \"{value}\"\n")
+                }
+                Operand::Constant { value, .. } => format!("{op} {}\n", DebugValue(value)),
+                Operand::Local(n) | Operand::Count(n) => format!("{op} {n}\n"),
+                Operand::Global { name, .. } => format!("{op} {}\n", name.unwrap()),
+                Operand::Jump(target) => format!("{op} @L{target}\n"),
+            };
+        }
+        asm
+    }
+
+    /// Disassembly without the offset and line columns, which differ
+    /// between a compiled chunk and its assembled copy
+    fn listing(chunk: &Chunk) -> Vec<String> {
+        let mut out = Vec::new();
+        chunk.dissassemble_to(&mut out, "code").unwrap();
+        String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .skip(1)
+            .map(|line| line[10..].to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_assemble() {
+        let chunk = assemble(
+            "
+            ; a comment ; with \"quotes\"
+            CONST 1.5        ; trailing comment
+            OP_CONSTANT \"a;b\"
+            CONSTANT 1.5
+            define_global x
+            start:
+            GET_GLOBAL x
+            JUMP_IF_FALSE @end
+            LOOP @start
+            end:
+            POPN 2
+            RETURN
+            ",
+        )
+        .unwrap();
+        let decoded: Vec<_> = chunk
+            .decode()
+            .unwrap()
+            .into_iter()
+            .map(|i| (i.op, i.operand))
+            .collect();
+        let one_half = Value::Number(1.5);
+        let constant = |index, value| Operand::Constant { index, value };
+        let x = Operand::Global {
+            slot: 0,
+            name: Some("x"),
+        };
+        assert_eq!(
+            decoded,
+            [
+                (OpCode::Constant, constant(0, &one_half)),
+                (OpCode::Constant, constant(1, &Value::from("a;b"))),
+                (OpCode::Constant, constant(0, &one_half)),
+                (OpCode::DefineGlobal, x.clone()),
+                (OpCode::GetGlobal, x),
+                (OpCode::JumpIfFalse, Operand::Jump(18)),
+                (OpCode::Loop, Operand::Jump(9)),
+                (OpCode::PopN, Operand::Count(2)),
+                (OpCode::Return, Operand::None),
+            ]
+        );
+        assert_eq!(chunk.constants, [Value::Number(1.5), Value::from("a;b")]);
+        assert_eq!(chunk.read_line(0), 3);
+        assert_eq!(chunk.read_line(chunk.code().len() - 1), 13);
+        assert!(chunk.validate().is_ok());
+    }
+
+    #[test]
+    fn test_every_opcode() {
+        for byte in 0..=OpCode::LAST {
+            let op = OpCode::try_from(byte).unwrap();
+            let operand = match op {
+                OpCode::Constant | OpCode::ConstantLong => " 2.5",
+                OpCode::DefineGlobal | OpCode::GetGlobal | OpCode::SetGlobal => " g",
+                OpCode::Jump | OpCode::JumpIfFalse => " @end",
+                OpCode::Loop => " @start",
+                _ if op.operand_len() == 1 => " 1",
+                _ => "",
+            };
+            let chunk = assemble(&format!("start:\n{op}{operand}\nend:")).unwrap();
+            let decoded = chunk.decode().unwrap();
+            assert_eq!(decoded.len(), 1);
+            assert_eq!(decoded[0].op, op);
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut vm = VM::new();
+        let compiled = vm
+            .compile(
+                "var s = \"x\";
+                for (var i = 0; i < 3; i = i + 1) {
+                    if (i == 1) continue;
+                    s = s + i * 2 + 2.5;
+                }
+                print s;",
+            )
+            .unwrap();
+        let asm = to_asm(&compiled);
+        let assembled = vm.assemble(&asm).unwrap();
+        assert_eq!(assembled.code(), compiled.code());
+        assert_eq!(listing(&assembled), listing(&compiled));
+        assert_eq!(to_asm(&assembled), asm);
+    }
+
+    #[test]
+    fn test_run_assembled() {
+        let mut vm = VM::new();
+        vm.interpret("var n = 3;").unwrap();
+        let chunk = vm
+            .assemble(
+                "
+                CONSTANT \"\"
+                DEFINE_GLOBAL out
+            top:
+                GET_GLOBAL n
+                JUMP_IF_FALSE @done
+                POP
+                GET_GLOBAL out
+                GET_GLOBAL n
+                ADD
+                SET_GLOBAL out
+                POP
+                GET_GLOBAL n
+                CONSTANT 1
+                SUBTRACT
+                SET_GLOBAL n
+                ZERO
+                EQUAL
+                JUMP_IF_FALSE @keep
+                POP
+                NIL
+                SET_GLOBAL n
+            keep:
+                POP
+                LOOP @top
+            done:
+                POP
+                GET_GLOBAL out
+                RETURN
+                ",
+            )
+            .unwrap();
+        assert_eq!(vm.run(chunk).unwrap(), Some(Value::from("321")));
+    }
+
+    #[test]
+    fn test_runtime_errors() {
+        let run_error = |src: &str| {
+            let mut vm = VM::new();
+            let chunk = vm.assemble(src).unwrap();
+            match vm.run(chunk) {
+                Err(Error::Runtime(e)) => (e.line, e.message),
+                other => panic!("expected a runtime error, got {other:?}"),
+            }
+        };
+        assert_eq!(
+            run_error("CONSTANT 1\nCONSTANT \"a\"\nSUBTRACT\nRETURN"),
+            (3, "Operands must be numbers.".to_string())
+        );
+        assert_eq!(
+            run_error("TRUE\nNEGATE\nRETURN"),
+            (2, "Operand must be a number.".to_string())
+        );
+        assert_eq!(
+            run_error("NIL\nNIL\nCALL 0\nRETURN"),
+            (3, "Can only call functions and classes.".to_string())
+        );
+        assert_eq!(
+            run_error("GET_GLOBAL nope\nRETURN"),
+            (1, "Undefined variable nope".to_string())
+        );
+        assert_eq!(
+            run_error("NIL\nSET_GLOBAL nope\nRETURN"),
+            (2, "Undefined variable nope".to_string())
+        );
+        assert_eq!(
+            run_error("ONE\nZERO\nINDEX\nRETURN"),
+            (3, "Can only index strings; got number.".to_string())
+        );
+    }
+
+    #[test]
+    fn test_asm_errors() {
+        assert_eq!(
+            asm_error("NIL\nPUSH 1"),
+            (2, "unknown instruction 'PUSH'".to_string())
+        );
+        assert_eq!(
+            asm_error("ADD 1"),
+            (1, "OP_ADD takes no operand, not '1'".to_string())
+        );
+        assert_eq!(
+            asm_error("CONSTANT"),
+            (1, "OP_CONSTANT is missing its operand".to_string())
+        );
+        assert_eq!(
+            asm_error("CONSTANT 1x"),
+            (
+                1,
+                "'1x' isn't a number, string, true, false or nil".to_string()
+            )
+        );
+        assert_eq!(
+            asm_error("CONSTANT \"a\" \"b\""),
+            (1, "'\"a\" \"b\"' isn't a single string".to_string())
+        );
+        assert_eq!(
+            asm_error("GET_GLOBAL 1"),
+            (1, "OP_GET_GLOBAL takes a name, not '1'".to_string())
+        );
+        assert_eq!(
+            asm_error("POPN 256"),
+            (1, "OP_POPN takes a byte (0 to 255), not '256'".to_string())
+        );
+        assert_eq!(
+            asm_error("JUMP end"),
+            (1, "OP_JUMP takes a @label, not 'end'".to_string())
+        );
+        assert_eq!(
+            asm_error("a:\na:"),
+            (2, "label 'a' is already defined".to_string())
+        );
+        assert_eq!(
+            asm_error("NIL\nJUMP @nowhere"),
+            (2, "undefined label 'nowhere'".to_string())
+        );
+        assert_eq!(
+            asm_error("top:\nJUMP @top"),
+            (2, "OP_JUMP only jumps forward, and 'top' isn't".to_string())
+        );
+        assert_eq!(
+            asm_error("LOOP @end\nNIL\nend:"),
+            (
+                1,
+                "OP_LOOP only jumps backward, and 'end' isn't".to_string()
+            )
+        );
+        let far = format!("JUMP @end\n{}end:", "NIL\n".repeat(u16::MAX as usize + 1));
+        assert_eq!(
+            asm_error(&far),
+            (1, "'end' is too far to jump to".to_string())
+        );
+    }
+}
@@ -0,0 +1,217 @@
+//! Instructions read back out of a chunk with their operands interpreted,
+//! for the disassembler, `Chunk::validate` and other tooling.
+
+use super::{Chunk, OpCode, Position, long_index};
+use crate::{Error, Value};
+
+/// One instruction in a chunk
+#[derive(Debug, Clone, PartialEq)]
+pub struct DecodedInstruction<'c> {
+    pub op: OpCode,
+    pub operand: Operand<'c>,
+    /// where the opcode byte is; its operand bytes follow
+    pub offset: usize,
+    pub position: Position,
+}
+
+impl DecodedInstruction<'_> {
+    /// The offset of the instruction after this one
+    pub fn next(&self) -> usize {
+        self.offset + 1 + self.op.operand_len()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operand<'c> {
+    None,
+    Constant {
+        index: usize,
+        value: &'c Value,
+    },
+    /// a stack slot in the current frame
+    Local(u8),
+    /// `name` is `None` for a slot the chunk doesn't name
+    Global {
+        slot: usize,
+        name: Option<&'c str>,
+    },
+    /// the offset a jump or loop continues at
+    Jump(usize),
+    /// values popped by `OP_POPN`, arguments to `OP_CALL`, or messages to
+    /// `OP_ASSERT`
+    Count(u8),
+}
+
+impl Chunk {
+    /// Every instruction in the chunk, or the first that can't be decoded
+    pub fn decode(&self) -> Result<Vec<DecodedInstruction<'_>>, Error> {
+        let mut instructions = Vec::new();
+        let mut offset = 0;
+        while offset < self.code.len() {
+            let instruction = self.decode_instruction(offset)?;
+            offset = instruction.next();
+            instructions.push(instruction);
+        }
+        Ok(instructions)
+    }
+
+    /// The instruction starting at `offset`, failing on an unknown opcode, a
+    /// truncated operand, a constant outside the pool or a loop back past
+    /// the start
+    pub fn decode_instruction(&self, offset: usize) -> Result<DecodedInstruction<'_>, Error> {
+        let invalid = |reason: String| Err(Error::InvalidBytecode { reason, offset });
+        let Some(&byte) = self.code.get(offset) else {
+            return invalid("unexpected end of code".to_string());
+        };
+        let Ok(op) = OpCode::try_from(byte) else {
+            return invalid(format!("unknown opcode {byte}"));
+        };
+        let Some(bytes) = self.code.get(offset + 1..offset + 1 + op.operand_len()) else {
+            return invalid(format!("{op} is missing its operand"));
+        };
+        let operand = match bytes {
+            [] => 0,
+            [byte] => *byte as usize,
+            [top, bot] => long_index(*top, *bot),
+            _ => unreachable!("operands are at most 2 bytes"),
+        };
+        let next = offset + 1 + bytes.len();
+        let operand = match op {
+            OpCode::Constant | OpCode::ConstantLong => match self.constants.get(operand) {
+                Some(value) => Operand::Constant {
+                    index: operand,
+                    value,
+                },
+                None => {
+                    return invalid(format!(
+                        "{op} reads constant {operand} of {}",
+                        self.constants.len()
+                    ));
+                }
+            },
+            OpCode::GetLocal | OpCode::SetLocal => Operand::Local(operand as u8),
+            OpCode::DefineGlobal | OpCode::GetGlobal | OpCode::SetGlobal => Operand::Global {
+                slot: operand,
+                name: self.global_names.get(&operand).map(|name| name.as_ref()),
+            },
+            OpCode::Jump | OpCode::JumpIfFalse => Operand::Jump(next + operand),
+            OpCode::Loop => match next.checked_sub(operand) {
+                Some(target) => Operand::Jump(target),
+                None => return invalid(format!("{op} jumps before the chunk")),
+            },
+            OpCode::PopN | OpCode::Call | OpCode::Assert => Operand::Count(operand as u8),
+            _ => Operand::None,
+        };
+        Ok(DecodedInstruction {
+            op,
+            operand,
+            offset,
+            position: self.read_position(offset),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_decode() {
+        let mut chunk = Chunk::new();
+        chunk.write_constant(Value::Number(2.5), 1);
+        chunk.write_global(OpCode::DefineGlobal, 3, "a", 1);
+        let jump = chunk.emit_jump(OpCode::JumpIfFalse, 2);
+        chunk.write(OpCode::Nil.into(), 2);
+        chunk.patch_jump(jump).unwrap();
+        chunk.emit_loop(0, 3).unwrap();
+        chunk.write(OpCode::Return.into(), 4);
+
+        let decoded = chunk.decode().unwrap();
+        let ops: Vec<_> = decoded
+            .iter()
+            .map(|i| (i.op, i.offset, i.position.line))
+            .collect();
+        assert_eq!(
+            ops,
+            [
+                (OpCode::Constant, 0, 1),
+                (OpCode::DefineGlobal, 2, 1),
+                (OpCode::JumpIfFalse, 5, 2),
+                (OpCode::Nil, 8, 2),
+                (OpCode::Loop, 9, 3),
+                (OpCode::Return, 12, 4),
+            ]
+        );
+        assert_eq!(
+            decoded[0].operand,
+            Operand::Constant {
+                index: 0,
+                value: &Value::Number(2.5)
+            }
+        );
+        assert_eq!(
+            decoded[1].operand,
+            Operand::Global {
+                slot: 3,
+                name: Some("a")
+            }
+        );
+        assert_eq!(decoded[2].operand, Operand::Jump(9));
+        assert_eq!(decoded[3].operand, Operand::None);
+        assert_eq!(decoded[4].operand, Operand::Jump(0));
+        assert_eq!(decoded[4].next(), 12);
+    }
+
+    #[test]
+    fn test_decode_constant_long() {
+        let mut chunk = Chunk::new();
+        for i in 0..256 {
+            chunk.add_constant(Value::Number(i as f64));
+        }
+        chunk.write_constant(Value::from("long"), 1);
+        chunk.write(OpCode::Call.into(), 1);
+        chunk.write(2, 1);
+        let decoded = chunk.decode().unwrap();
+        assert_eq!(decoded[0].op, OpCode::ConstantLong);
+        assert_eq!(
+            decoded[0].operand,
+            Operand::Constant {
+                index: 256,
+                value: &Value::from("long")
+            }
+        );
+        assert_eq!(
+            (decoded[1].offset, &decoded[1].operand),
+            (3, &Operand::Count(2))
+        );
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        let error = |code: &[u8]| {
+            let mut chunk = Chunk::new();
+            chunk.add_constant(Value::Nil);
+            for byte in code {
+                chunk.write(*byte, 1);
+            }
+            match chunk.decode() {
+                Err(Error::InvalidBytecode { reason, offset }) => (reason, offset),
+                other => panic!("decoded {other:?}"),
+            }
+        };
+        let nil = OpCode::Nil.into();
+        assert_eq!(error(&[nil, 200]), ("unknown opcode 200".to_string(), 1));
+        assert_eq!(
+            error(&[nil, OpCode::GetGlobal.into(), 0]),
+            ("OP_GET_GLOBAL is missing its operand".to_string(), 1)
+        );
+        assert_eq!(
+            error(&[OpCode::Constant.into(), 1]),
+            ("OP_CONSTANT reads constant 1 of 1".to_string(), 0)
+        );
+        assert_eq!(
+            error(&[OpCode::Loop.into(), 0, 4]),
+            ("OP_LOOP jumps before the chunk".to_string(), 0)
+        );
+    }
+}
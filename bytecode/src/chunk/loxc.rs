@@ -0,0 +1,372 @@
+//! The `.loxc` format: a compiled script saved so it can run without its
+//! source. Loading trusts nothing it reads, since a file may be truncated,
+//! corrupt, or not bytecode at all.
+//!
+//! Everything is little-endian:
+//! - file: magic, version byte, chunk
+//! - chunk: code length (u32), code, a line and column (u32 each) per code
+//!   byte, constant count (u32), constants, global name count (u32), then a
+//!   slot (u16) and string for each
+//! - constant: a tag byte, then an f64, a string, or a function's name, arity
+//!   (u8) and chunk
+//! - string: length (u32), then UTF-8
+
+use std::rc::Rc;
+
+use super::{Chunk, Position};
+use crate::{Error, Value, object::Function, session::CompilerSession};
+
+const MAGIC: &[u8; 4] = b"LOXC";
+const VERSION: u8 = 1;
+/// deeper than the compiler can nest functions
+const MAX_NESTING: usize = 256;
+
+const TAG_NUMBER: u8 = 0;
+const TAG_STRING: u8 = 1;
+const TAG_FUNCTION: u8 = 2;
+const TAG_NIL: u8 = 3;
+const TAG_FALSE: u8 = 4;
+const TAG_TRUE: u8 = 5;
+
+impl Chunk {
+    /// Serializes the chunk, and the functions in its pool, as a `.loxc` file
+    pub fn to_loxc(&self) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        out.push(VERSION);
+        write_chunk(&mut out, self);
+        out
+    }
+
+    /// Loads a `.loxc` file, binding its globals to `session`'s slots by name
+    pub(crate) fn from_loxc(bytes: &[u8], session: &mut CompilerSession) -> Result<Self, Error> {
+        let mut reader = Reader {
+            bytes,
+            offset: 0,
+            depth: 0,
+            session,
+        };
+        if reader.take(MAGIC.len()).ok() != Some(MAGIC.as_slice()) {
+            return Err(Error::InvalidBytecode {
+                reason: "not a .loxc file".to_string(),
+                offset: 0,
+            });
+        }
+        let version = reader.u8()?;
+        if version != VERSION {
+            return reader.invalid(format!("format version {version}, expected {VERSION}"));
+        }
+        let chunk = reader.chunk(0)?;
+        if reader.offset != bytes.len() {
+            return reader.invalid("trailing bytes after the script".to_string());
+        }
+        Ok(chunk)
+    }
+}
+
+fn write_u32(out: &mut Vec<u8>, n: usize) {
+    out.extend_from_slice(&(n as u32).to_le_bytes());
+}
+
+fn write_str(out: &mut Vec<u8>, s: &str) {
+    write_u32(out, s.len());
+    out.extend_from_slice(s.as_bytes());
+}
+
+fn write_chunk(out: &mut Vec<u8>, chunk: &Chunk) {
+    write_u32(out, chunk.code.len());
+    out.extend_from_slice(&chunk.code);
+    for position in &chunk.positions {
+        write_u32(out, position.line);
+        write_u32(out, position.column);
+    }
+    write_u32(out, chunk.constants.len());
+    for constant in &chunk.constants {
+        write_constant(out, constant);
+    }
+    let mut names: Vec<_> = chunk.global_names.iter().collect();
+    names.sort();
+    write_u32(out, names.len());
+    for (slot, name) in names {
+        out.extend_from_slice(&(*slot as u16).to_le_bytes());
+        write_str(out, name);
+    }
+}
+
+fn write_constant(out: &mut Vec<u8>, value: &Value) {
+    if let Some(n) = value.as_number() {
+        out.push(TAG_NUMBER);
+        out.extend_from_slice(&n.to_le_bytes());
+    } else if let Some(s) = value.as_string() {
+        out.push(TAG_STRING);
+        write_str(out, &s);
+    } else if let Some(function) = value.as_function() {
+        out.push(TAG_FUNCTION);
+        write_str(out, function.name.as_deref().unwrap_or(""));
+        out.push(function.arity as u8);
+        write_chunk(out, &function.chunk);
+    } else if value.type_name() == "bool" {
+        out.push(if value.is_truthy() {
+            TAG_TRUE
+        } else {
+            TAG_FALSE
+        });
+    } else {
+        // natives are globals, never constants
+        out.push(TAG_NIL);
+    }
+}
+
+struct Reader<'b, 's> {
+    bytes: &'b [u8],
+    offset: usize,
+    /// functions being read, so a hostile file can't overflow the stack
+    depth: usize,
+    session: &'s mut CompilerSession,
+}
+
+impl<'b> Reader<'b, '_> {
+    fn invalid<T>(&self, reason: String) -> Result<T, Error> {
+        Err(Error::InvalidBytecode {
+            reason,
+            offset: self.offset,
+        })
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'b [u8], Error> {
+        let Some(bytes) = self.bytes.get(self.offset..self.offset.saturating_add(n)) else {
+            return self.invalid("unexpected end of file".to_string());
+        };
+        self.offset += n;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, Error> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<usize, Error> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]) as usize)
+    }
+
+    fn u32(&mut self) -> Result<usize, Error> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes(bytes.try_into().expect("took 4 bytes")) as usize)
+    }
+
+    fn string(&mut self) -> Result<Rc<str>, Error> {
+        let len = self.u32()?;
+        let start = self.offset;
+        match std::str::from_utf8(self.take(len)?) {
+            Ok(s) => Ok(self.session.intern(s)),
+            Err(e) => Err(Error::InvalidBytecode {
+                reason: "string isn't valid UTF-8".to_string(),
+                offset: start + e.valid_up_to(),
+            }),
+        }
+    }
+
+    /// A chunk whose frame starts with `slots` values, see `Chunk::validate`
+    fn chunk(&mut self, slots: usize) -> Result<Chunk, Error> {
+        let mut chunk = Chunk::new();
+        let len = self.u32()?;
+        let code_start = self.offset;
+        chunk.code = self.take(len)?.to_vec();
+        for _ in 0..len {
+            let line = self.u32()?;
+            let column = self.u32()?;
+            chunk.positions.push(Position { line, column });
+        }
+        for _ in 0..self.u32()? {
+            let constant = self.constant()?;
+            chunk.constants.push(constant);
+        }
+        for _ in 0..self.u32()? {
+            let slot = self.u16()?;
+            let name = self.string()?;
+            chunk.global_names.insert(slot, name);
+        }
+        if let Err(Error::InvalidBytecode { reason, offset }) = chunk.validate_frame(slots) {
+            return Err(Error::InvalidBytecode {
+                reason,
+                offset: code_start + offset,
+            });
+        }
+        chunk.relink_globals(self.session);
+        Ok(chunk)
+    }
+
+    fn constant(&mut self) -> Result<Value, Error> {
+        let value = match self.u8()? {
+            TAG_NUMBER => {
+                let bytes = self.take(8)?;
+                Value::Number(f64::from_le_bytes(bytes.try_into().expect("took 8 bytes")))
+            }
+            TAG_STRING => Value::from(self.string()?),
+            TAG_FUNCTION => {
+                if self.depth == MAX_NESTING {
+                    return self.invalid("functions nested too deeply".to_string());
+                }
+                let name = self.string()?;
+                let arity = self.u8()? as usize;
+                self.depth += 1;
+                let chunk = self.chunk(arity + 1)?;
+                self.depth -= 1;
+                Value::Function(Rc::new(Function {
+                    name: (!name.is_empty()).then_some(name),
+                    arity,
+                    chunk,
+                }))
+            }
+            TAG_NIL => Value::Nil,
+            TAG_FALSE => Value::Bool(false),
+            TAG_TRUE => Value::Bool(true),
+            tag => {
+                self.offset -= 1;
+                return self.invalid(format!("unknown constant tag {tag}"));
+            }
+        };
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{Error, VM, chunk::assemble};
+
+    fn compile(source: &str) -> Vec<u8> {
+        VM::new().compile(source).unwrap().to_loxc()
+    }
+
+    fn load_error(bytes: &[u8]) -> (String, usize) {
+        match VM::new().load(bytes) {
+            Err(Error::InvalidBytecode { reason, offset }) => (reason, offset),
+            Err(e) => panic!("expected invalid bytecode, got {e:?}"),
+            Ok(_) => panic!("loaded invalid bytecode"),
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let bytes = compile(
+            "var greeting = \"hi\";\nfun twice(x) { return x * 2; }\nvar n = twice(21);\nprint greeting;",
+        );
+        let mut vm = VM::new();
+        // globals are linked by name, so slots differ from the compiling VM's
+        vm.interpret("var unrelated = 1;").unwrap();
+        let chunk = vm.load(&bytes).unwrap();
+        vm.run(chunk).unwrap();
+        assert_eq!(
+            vm.interpret_line("n").unwrap(),
+            Some(crate::Value::Number(42.0))
+        );
+    }
+
+    #[test]
+    fn test_truncated() {
+        let bytes = compile("fun f(a) { print a; }\nf(\"s\");");
+        // every prefix fails cleanly
+        for end in 0..bytes.len() {
+            let (reason, _) = load_error(&bytes[..end]);
+            assert!(!reason.is_empty());
+        }
+        assert_eq!(
+            load_error(&bytes[..7]),
+            ("unexpected end of file".to_string(), 5)
+        );
+    }
+
+    #[test]
+    fn test_header() {
+        let mut bytes = compile("print 1;");
+        assert_eq!(
+            load_error(b"#!/usr/bin/env lox"),
+            ("not a .loxc file".to_string(), 0)
+        );
+        bytes[4] = 9;
+        assert_eq!(
+            load_error(&bytes),
+            ("format version 9, expected 1".to_string(), 5)
+        );
+        bytes[4] = 1;
+        bytes.push(0);
+        assert_eq!(load_error(&bytes).0, "trailing bytes after the script");
+    }
+
+    #[test]
+    fn test_constant_out_of_range() {
+        let mut bytes = compile("print 2;");
+        // code starts after the magic, version and length: OP_CONSTANT 0
+        assert_eq!(bytes[9..11], [1, 0]);
+        bytes[10] = 3;
+        assert_eq!(
+            load_error(&bytes),
+            ("OP_CONSTANT reads constant 3 of 1".to_string(), 9)
+        );
+    }
+
+    #[test]
+    fn test_jump_past_end() {
+        let mut bytes = compile("if (nil) print 1;");
+        // OP_NIL, then OP_JUMP_IF_FALSE with a 16-bit operand
+        assert_eq!(bytes[10], 24);
+        bytes[12] = 200;
+        let (reason, offset) = load_error(&bytes);
+        assert!(reason.starts_with("OP_JUMP_IF_FALSE jumps to"), "{reason}");
+        assert_eq!(offset, 10);
+    }
+
+    #[test]
+    fn test_bad_string_and_opcode() {
+        let mut bytes = compile("print \"abc\";");
+        let at = bytes.windows(3).position(|w| w == b"abc").unwrap();
+        bytes[at + 1] = 0xff;
+        assert_eq!(
+            load_error(&bytes),
+            ("string isn't valid UTF-8".to_string(), at + 1)
+        );
+
+        let mut bytes = compile("print 2;");
+        bytes[11] = 200;
+        assert_eq!(load_error(&bytes), ("unknown opcode 200".to_string(), 11));
+    }
+
+    #[test]
+    fn test_stack_checked() {
+        let load = |src: &str| load_error(&assemble(src).unwrap().to_loxc());
+        // code starts at offset 9
+        assert_eq!(
+            load("POP\nRETURN"),
+            ("OP_POP underflows the stack at depth 0".to_string(), 9)
+        );
+        assert_eq!(
+            load("ADD\nRETURN"),
+            ("OP_ADD underflows the stack at depth 0".to_string(), 9)
+        );
+        assert_eq!(
+            load("GET_LOCAL 250\nPRINT\nRETURN"),
+            (
+                "OP_GET_LOCAL uses local slot 250 at stack depth 0".to_string(),
+                9
+            )
+        );
+        assert_eq!(
+            load("NIL\nCALL 3\nRETURN"),
+            ("OP_CALL underflows the stack at depth 1".to_string(), 10)
+        );
+        // the shallowest path decides, here the one that skips the push
+        assert_eq!(
+            load("TRUE\nJUMP_IF_FALSE @skip\nNIL\nskip:\nPOPN 2\nRETURN"),
+            ("OP_POPN underflows the stack at depth 1".to_string(), 14)
+        );
+    }
+
+    #[test]
+    fn test_validate_compiled() {
+        let mut vm = VM::new();
+        let chunk = vm
+            .compile("fun f(n) { while (n > 0) { if (n % 2 == 0) print n; n = n - 1; } }\nf(4);")
+            .unwrap();
+        assert!(chunk.validate().is_ok());
+    }
+}
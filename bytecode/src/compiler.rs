@@ -1,47 +1,332 @@
-use core::panic;
+use std::{
+    collections::{HashMap, HashSet},
+    rc::Rc,
+};
+
+use thiserror::Error;
 
 use crate::{
-    Chunk, OpCode, Value,
-    scan::{Precedence, Scanner, Token, TokenType},
+    Chunk, OpCode, Position, Value,
+    chunk::break_index,
+    globals::MAX_GLOBALS,
+    object::Function,
+    scan::{Precedence, Scanner, Token, TokenType, UNTERMINATED_STRING},
+    session::CompilerSession,
 };
 
+/// Where in the source a compile error was reported
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ErrorAt {
+    /// the offending token's lexeme
+    Lexeme(String),
+    End,
+    /// the scanner couldn't make a token; the message says why
+    Scanner,
+}
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[error("[line {line}:{column}]{}: {message}", match .at {
+    ErrorAt::Lexeme(lexeme) => format!(" after '{lexeme}'"),
+    ErrorAt::End => " at end".to_string(),
+    ErrorAt::Scanner => String::new(),
+})]
+pub struct CompileError {
+    pub message: String,
+    pub at: ErrorAt,
+    pub line: usize,
+    pub column: usize,
+    /// index of the source in the program's `SourceMap`
+    pub file: usize,
+}
+
+impl CompileError {
+    fn new(token: Token, message: &str) -> Self {
+        let at = match token.ttype {
+            TokenType::EoF => ErrorAt::End,
+            TokenType::Error => ErrorAt::Scanner,
+            _ => ErrorAt::Lexeme(token.lexeme.to_string()),
+        };
+        Self {
+            message: message.to_string(),
+            at,
+            line: token.line,
+            column: token.column,
+            file: token.file,
+        }
+    }
+
+    /// Whether the source stopped too soon, inside a string or partway
+    /// through a statement, so a REPL can read another line to finish it
+    pub(crate) fn is_unfinished(&self) -> bool {
+        match self.at {
+            ErrorAt::End => true,
+            ErrorAt::Scanner => self.message.starts_with(UNTERMINATED_STRING),
+            ErrorAt::Lexeme(_) => false,
+        }
+    }
+}
+
 pub(crate) struct Compiler;
 
+/// Warnings on success, which don't stop the chunk from running
+type CompileResult = Result<Vec<CompileError>, Vec<CompileError>>;
+
 impl Compiler {
-    pub(crate) fn compile<'a>(source: &'a str, chunk: &mut Chunk<'a>) -> bool {
-        let scanner = Scanner::new(source);
-        let mut parser = Parser::new(scanner);
+    pub(crate) fn compile(
+        source: &str,
+        chunk: &mut Chunk,
+        session: &mut CompilerSession,
+    ) -> CompileResult {
+        Self::compile_with_mode(source, chunk, session, false)
+    }
+
+    /// In REPL mode the value of a trailing expression statement (whose ';' is
+    /// optional) is left on the stack for OP_RETURN to hand back
+    pub(crate) fn compile_repl(
+        source: &str,
+        chunk: &mut Chunk,
+        session: &mut CompilerSession,
+    ) -> CompileResult {
+        Self::compile_with_mode(source, chunk, session, true)
+    }
+
+    fn compile_with_mode(
+        source: &str,
+        chunk: &mut Chunk,
+        session: &mut CompilerSession,
+        repl: bool,
+    ) -> CompileResult {
+        let scanner = Scanner::for_file(source, session.file);
+        chunk.file = session.file;
+        let mut parser = Parser::new(scanner, session, repl);
         while !parser.match_token(TokenType::EoF) {
             parser.declaration(chunk);
         }
         parser.consume(TokenType::EoF, "Expected end of expression");
-        chunk.write(OpCode::Return.into(), parser.previous.line);
-        #[cfg(debug_assertions)]
-        {
-            if !parser.had_error {
-                chunk.dissassemble("code");
-            }
+        chunk.write(OpCode::Return.into(), parser.previous.position());
+        if parser.errors.is_empty() {
+            Ok(parser.undefined_globals())
+        } else {
+            Err(parser.errors)
+        }
+    }
+}
+
+const MAX_LOCALS: usize = 256;
+const MAX_ARGS: usize = 255;
+/// `OP_CONSTANT_LONG` has a 16-bit operand
+const MAX_CONSTANTS: usize = u16::MAX as usize + 1;
+/// deeper nesting is a compile error rather than a native stack overflow
+const MAX_DEPTH: usize = 256;
+
+struct Local<'a> {
+    name: &'a str,
+    /// `None` while the local's initializer is being compiled
+    depth: Option<usize>,
+}
+
+/// Bookkeeping for an enclosing loop, used by `break` and `continue`
+struct Loop<'a> {
+    /// offset `continue` jumps back to
+    start: usize,
+    /// scope depth the loop body is nested in; deeper locals are popped on exit
+    scope_depth: usize,
+    /// `break` jumps waiting to be patched once the loop's end is known
+    breaks: Vec<Jump<'a>>,
+}
+
+/// A forward jump waiting for its target
+#[derive(Clone, Copy)]
+struct Jump<'a> {
+    /// offset of the jump's operand
+    offset: usize,
+    /// the token it was emitted after, on the line of the statement or
+    /// expression that owns it
+    token: Token<'a>,
+}
+
+/// Where a literal's instruction sits, so the compiler can fold it
+#[derive(Clone)]
+struct LiteralSpan {
+    start: usize,
+    end: usize,
+    value: Value,
+    /// size of the constant pool before the literal, which may have reused
+    /// an existing constant or not needed one
+    pool_len: usize,
+}
+
+/// A constant's identity for deduplication; numbers by bits so 0 and -0 stay apart
+#[derive(PartialEq, Eq, Hash)]
+pub(crate) enum ConstantKey {
+    Number(u64),
+    String(Rc<str>),
+}
+
+impl ConstantKey {
+    pub(crate) fn of(value: &Value) -> Option<Self> {
+        if let Some(n) = value.as_number() {
+            return Some(Self::Number(n.to_bits()));
         }
-        !parser.had_error
+        value.as_string().map(Self::String)
     }
 }
 
-struct Parser<'a> {
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum FunctionKind {
+    Script,
+    Function,
+}
+
+/// The enclosing function's compiler state, set aside while a nested one compiles
+struct Enclosing<'a> {
+    kind: FunctionKind,
+    locals: Vec<Local<'a>>,
+    scope_depth: usize,
+    loops: Vec<Loop<'a>>,
+    literals: Vec<LiteralSpan>,
+    constants: HashMap<ConstantKey, usize>,
+    constants_full: bool,
+}
+
+struct Parser<'a, 'g> {
     scanner: Scanner<'a>,
+    session: &'g mut CompilerSession,
     current: Token<'a>,
     previous: Token<'a>,
-    had_error: bool,
+    /// everything reported so far; panic mode keeps cascades out of it
+    errors: Vec<CompileError>,
     panic_mode: bool,
+    repl: bool,
+    kind: FunctionKind,
+    locals: Vec<Local<'a>>,
+    scope_depth: usize,
+    loops: Vec<Loop<'a>>,
+    /// statements nested in a block or control-flow body can't be the REPL's result
+    nesting: usize,
+    /// the first read or assignment of each global, and the globals declared,
+    /// for `undefined_globals`
+    global_uses: HashMap<&'a str, Token<'a>>,
+    global_definitions: HashSet<&'a str>,
+    /// the target of the latest assignment, so the REPL can tell an
+    /// assignment statement from an expression worth echoing
+    assigned_at: Option<Position>,
+    literals: Vec<LiteralSpan>,
+    /// indices of the current chunk's constants, so repeated literals share one
+    constants: HashMap<ConstantKey, usize>,
+    /// the current chunk's pool overflowed, which is only reported once
+    constants_full: bool,
+    /// recursion depth of statements and expressions, across functions
+    depth: usize,
+    /// the rest of the input was skipped after nesting too deep, so any
+    /// further errors are cascades
+    too_deep: bool,
+}
+
+type ParseFn<'a, 'g> = fn(&mut Parser<'a, 'g>, &mut Chunk, bool);
+
+/// How a token parses at the start of an expression (`prefix`) and after an
+/// operand (`infix`); `precedence` is its binding power as an infix operator
+struct ParseRule<'a, 'g> {
+    prefix: Option<ParseFn<'a, 'g>>,
+    infix: Option<ParseFn<'a, 'g>>,
+    precedence: Precedence,
+}
+
+fn get_rule<'a, 'g>(ttype: TokenType) -> ParseRule<'a, 'g> {
+    macro_rules! rule {
+        ($prefix:expr, $infix:expr, $precedence:ident) => {
+            ParseRule {
+                prefix: $prefix,
+                infix: $infix,
+                precedence: Precedence::$precedence,
+            }
+        };
+    }
+    let grouping: ParseFn = |p, chunk, _| p.grouping(chunk);
+    let unary: ParseFn = |p, chunk, _| p.unary(chunk);
+    let binary: ParseFn = |p, chunk, _| p.binary(chunk);
+    let number: ParseFn = |p, chunk, _| p.number(chunk);
+    let string: ParseFn = |p, chunk, _| p.string(chunk);
+    let literal: ParseFn = |p, chunk, _| p.literal(chunk);
+    let variable: ParseFn = |p, chunk, can_assign| p.variable(chunk, can_assign);
+    let and: ParseFn = |p, chunk, _| p.and(chunk);
+    let or: ParseFn = |p, chunk, _| p.or(chunk);
+    let call: ParseFn = |p, chunk, _| p.call(chunk);
+    let index: ParseFn = |p, chunk, _| p.index(chunk);
+    match ttype {
+        TokenType::LeftParen => rule!(Some(grouping), Some(call), Call),
+        TokenType::LeftBracket => rule!(None, Some(index), Call),
+        TokenType::Minus => rule!(Some(unary), Some(binary), Term),
+        TokenType::Plus => rule!(None, Some(binary), Term),
+        TokenType::Slash | TokenType::Star | TokenType::Percent => {
+            rule!(None, Some(binary), Factor)
+        }
+        TokenType::Bang => rule!(Some(unary), None, None),
+        TokenType::BangEqual | TokenType::EqualEqual => rule!(None, Some(binary), Equality),
+        TokenType::Greater | TokenType::GreaterEqual | TokenType::Less | TokenType::LessEqual => {
+            rule!(None, Some(binary), Comparison)
+        }
+        TokenType::Identifier => rule!(Some(variable), None, None),
+        TokenType::String => rule!(Some(string), None, None),
+        TokenType::Number => rule!(Some(number), None, None),
+        TokenType::And => rule!(None, Some(and), And),
+        TokenType::Or => rule!(None, Some(or), Or),
+        TokenType::Nil | TokenType::True | TokenType::False => rule!(Some(literal), None, None),
+        TokenType::RightParen
+        | TokenType::LeftBrace
+        | TokenType::RightBrace
+        | TokenType::RightBracket
+        | TokenType::Comma
+        | TokenType::Colon
+        | TokenType::Dot
+        | TokenType::Semicolon
+        | TokenType::Equal
+        | TokenType::Assert
+        | TokenType::Break
+        | TokenType::Case
+        | TokenType::Class
+        | TokenType::Continue
+        | TokenType::Default
+        | TokenType::Else
+        | TokenType::For
+        | TokenType::Fun
+        | TokenType::If
+        | TokenType::Print
+        | TokenType::Return
+        | TokenType::Super
+        | TokenType::Switch
+        | TokenType::This
+        | TokenType::Var
+        | TokenType::While
+        | TokenType::Error
+        | TokenType::EoF => rule!(None, None, None),
+    }
 }
 
-impl<'a> Parser<'a> {
-    fn new(scanner: Scanner<'a>) -> Self {
+impl<'a, 'g> Parser<'a, 'g> {
+    fn new(scanner: Scanner<'a>, session: &'g mut CompilerSession, repl: bool) -> Self {
         let mut parser = Self {
             scanner,
+            session,
             current: Token::empty(),
             previous: Token::empty(),
-            had_error: false,
+            errors: Vec::new(),
             panic_mode: false,
+            repl,
+            kind: FunctionKind::Script,
+            locals: Vec::new(),
+            scope_depth: 0,
+            loops: Vec::new(),
+            nesting: 0,
+            assigned_at: None,
+            global_uses: HashMap::new(),
+            global_definitions: HashSet::new(),
+            depth: 0,
+            too_deep: false,
+            literals: Vec::new(),
+            constants: HashMap::new(),
+            constants_full: false,
         };
         // prime the pump
         parser.advance();
@@ -54,7 +339,16 @@ impl<'a> Parser<'a> {
             if try_token.ttype != TokenType::Error {
                 break try_token;
             }
-            self.error(try_token, "Unexpected token");
+            // an error token's lexeme is the scanner's message
+            if try_token.lexeme == UNTERMINATED_STRING {
+                let message = format!(
+                    "{} starting at line {}",
+                    UNTERMINATED_STRING, try_token.line
+                );
+                self.error(try_token, &message);
+            } else {
+                self.error(try_token, try_token.lexeme);
+            }
         };
         self.previous = self.current;
         self.current = new_current;
@@ -69,6 +363,10 @@ impl<'a> Parser<'a> {
         }
     }
 
+    fn check(&self, ttype: TokenType) -> bool {
+        self.current.ttype == ttype
+    }
+
     fn consume(&mut self, ttype: TokenType, message: &str) {
         if self.current.ttype != ttype {
             self.error(self.current, message);
@@ -78,26 +376,42 @@ impl<'a> Parser<'a> {
     }
 
     fn error(&mut self, token: Token, message: &str) {
-        if self.panic_mode {
+        if self.panic_mode || self.too_deep {
             return;
         }
         self.panic_mode = true;
-        eprint!("[line {}]", token.line);
-        match token.ttype {
-            TokenType::EoF => {
-                eprint!(" at end");
-            }
-            TokenType::Error => {}
-            _ => {
-                eprint!(" after '{}'", token.lexeme)
-            }
-        }
-        eprintln!(": {}", message);
-        self.had_error = true;
+        self.errors.push(CompileError::new(token, message));
     }
 
-    fn declaration(&mut self, chunk: &mut Chunk<'a>) {
-        if self.match_token(TokenType::Var) {
+    /// Warnings for globals used but never declared, first use first. Names
+    /// that already have a value, like natives or a REPL's earlier lines,
+    /// are fine.
+    fn undefined_globals(&self) -> Vec<CompileError> {
+        let globals = &self.session.globals;
+        let mut warnings: Vec<_> = self
+            .global_uses
+            .values()
+            .filter(|name| !self.global_definitions.contains(name.lexeme))
+            .filter(|name| {
+                let slot = globals.get_slot(name.lexeme);
+                slot.and_then(|slot| globals.get(slot)).is_none()
+            })
+            .map(|name| {
+                let message = format!("Global '{}' is used but never defined.", name.lexeme);
+                CompileError::new(*name, &message)
+            })
+            .collect();
+        warnings.sort_by_key(|warning| (warning.line, warning.column));
+        warnings
+    }
+
+    fn declaration(&mut self, chunk: &mut Chunk) {
+        if !self.enter() {
+            return;
+        }
+        if self.match_token(TokenType::Fun) {
+            self.fun_declaration(chunk);
+        } else if self.match_token(TokenType::Var) {
             self.var_declaration(chunk);
         } else {
             self.statement(chunk);
@@ -105,140 +419,689 @@ impl<'a> Parser<'a> {
         if self.panic_mode {
             self.synchronize();
         }
+        self.depth -= 1;
+    }
+
+    /// Counts a level of recursion, or reports an error and skips to the end
+    /// of the input if there are too many
+    fn enter(&mut self) -> bool {
+        if self.depth == MAX_DEPTH {
+            self.error(self.current, "Too much nesting.");
+            self.too_deep = true;
+            // nothing parses from here, and skipping guarantees progress
+            while !self.check(TokenType::EoF) {
+                self.advance();
+            }
+            return false;
+        }
+        self.depth += 1;
+        true
     }
 
-    fn statement(&mut self, chunk: &mut Chunk<'a>) {
+    fn statement(&mut self, chunk: &mut Chunk) {
         if self.match_token(TokenType::Print) {
             self.print_statement(chunk);
+        } else if self.match_token(TokenType::Assert) {
+            self.assert_statement(chunk);
+        } else if self.match_token(TokenType::If) {
+            self.if_statement(chunk);
+        } else if self.match_token(TokenType::While) {
+            self.while_statement(chunk);
+        } else if self.match_token(TokenType::For) {
+            self.for_statement(chunk);
+        } else if self.match_token(TokenType::Switch) {
+            self.switch_statement(chunk);
+        } else if self.match_token(TokenType::Break) {
+            self.break_statement(chunk);
+        } else if self.match_token(TokenType::Continue) {
+            self.continue_statement(chunk);
+        } else if self.match_token(TokenType::Return) {
+            self.return_statement(chunk);
+        } else if self.match_token(TokenType::LeftBrace) {
+            self.begin_scope();
+            self.block(chunk);
+            self.end_scope(chunk);
         } else {
-            // expression statement
-            self.expression(chunk);
+            self.expression_statement(chunk);
+        }
+    }
+
+    fn expression_statement(&mut self, chunk: &mut Chunk) {
+        let start = self.current.position();
+        self.expression(chunk);
+        let repl_line = self.repl && self.nesting == 0;
+        // `a = 1` at the prompt runs quietly; `(a = 1)` still shows 1
+        let repl_result = repl_line && self.assigned_at != Some(start);
+        if !(repl_line && self.check(TokenType::EoF)) {
             self.consume(TokenType::Semicolon, "Expect ';' after expression");
-            chunk.write(OpCode::Pop.into(), self.previous.line);
         }
+        if repl_result && self.check(TokenType::EoF) {
+            return;
+        }
+        chunk.write(OpCode::Pop.into(), self.previous.position());
     }
 
-    fn expression<'b: 'a>(&mut self, chunk: &mut Chunk<'a>) {
-        self.parse_precedence(Precedence::Assignment, chunk);
+    /// Compiles a statement that belongs to an enclosing construct
+    fn nested_statement(&mut self, chunk: &mut Chunk) {
+        if !self.enter() {
+            return;
+        }
+        self.nesting += 1;
+        self.statement(chunk);
+        self.nesting -= 1;
+        self.depth -= 1;
     }
 
-    fn parse_precedence(&mut self, prec: Precedence, chunk: &mut Chunk<'a>) {
-        self.advance();
+    fn block(&mut self, chunk: &mut Chunk) {
+        self.nesting += 1;
+        while !self.check(TokenType::RightBrace) && !self.check(TokenType::EoF) {
+            self.declaration(chunk);
+        }
+        self.nesting -= 1;
+        self.consume(TokenType::RightBrace, "Expect '}' after block.");
+    }
 
-        let can_assign = prec.can_assign();
-        match self.previous.ttype {
-            TokenType::LeftParen => {
-                // grouping
+    fn begin_scope(&mut self) {
+        self.scope_depth += 1;
+    }
+
+    fn end_scope(&mut self, chunk: &mut Chunk) {
+        self.scope_depth -= 1;
+        let count = self.pop_locals_above(chunk, self.scope_depth);
+        self.locals.truncate(self.locals.len() - count);
+    }
+
+    fn if_statement(&mut self, chunk: &mut Chunk) {
+        self.consume(TokenType::LeftParen, "Expect '(' after 'if'.");
+        self.expression(chunk);
+        self.consume(TokenType::RightParen, "Expect ')' after condition.");
+
+        let then_jump = self.emit_jump(chunk, OpCode::JumpIfFalse);
+        chunk.write(OpCode::Pop.into(), self.previous.position());
+        self.nested_statement(chunk);
+        let else_jump = self.emit_jump(chunk, OpCode::Jump);
+        self.patch_jump(chunk, then_jump);
+        chunk.write(OpCode::Pop.into(), self.previous.position());
+        if self.match_token(TokenType::Else) {
+            self.nested_statement(chunk);
+        }
+        self.patch_jump(chunk, else_jump);
+    }
+
+    fn while_statement(&mut self, chunk: &mut Chunk) {
+        let keyword = self.previous;
+        let loop_start = chunk.code.len();
+        self.consume(TokenType::LeftParen, "Expect '(' after 'while'.");
+        self.expression(chunk);
+        self.consume(TokenType::RightParen, "Expect ')' after condition.");
+
+        let exit_jump = self.emit_jump(chunk, OpCode::JumpIfFalse);
+        chunk.write(OpCode::Pop.into(), self.previous.position());
+        self.loop_body(chunk, loop_start);
+        self.emit_loop(chunk, loop_start, keyword);
+        self.patch_jump(chunk, exit_jump);
+        chunk.write(OpCode::Pop.into(), self.previous.position());
+        self.end_loop(chunk);
+    }
+
+    fn for_statement(&mut self, chunk: &mut Chunk) {
+        let keyword = self.previous;
+        self.begin_scope();
+        self.consume(TokenType::LeftParen, "Expect '(' after 'for'.");
+        if self.match_token(TokenType::Semicolon) {
+            // no initializer
+        } else if self.match_token(TokenType::Var) {
+            self.var_declaration(chunk);
+        } else {
+            self.nesting += 1;
+            self.expression_statement(chunk);
+            self.nesting -= 1;
+        }
+
+        let mut loop_start = chunk.code.len();
+        let mut exit_jump = None;
+        if !self.match_token(TokenType::Semicolon) {
+            self.expression(chunk);
+            self.consume(TokenType::Semicolon, "Expect ';' after loop condition.");
+            exit_jump = Some(self.emit_jump(chunk, OpCode::JumpIfFalse));
+            chunk.write(OpCode::Pop.into(), self.previous.position());
+        }
+
+        if !self.match_token(TokenType::RightParen) {
+            // the increment runs after the body, so jump over it on the way in
+            let body_jump = self.emit_jump(chunk, OpCode::Jump);
+            let increment_start = chunk.code.len();
+            self.expression(chunk);
+            chunk.write(OpCode::Pop.into(), self.previous.position());
+            self.consume(TokenType::RightParen, "Expect ')' after for clauses.");
+            self.emit_loop(chunk, loop_start, keyword);
+            loop_start = increment_start;
+            self.patch_jump(chunk, body_jump);
+        }
+
+        self.loop_body(chunk, loop_start);
+        self.emit_loop(chunk, loop_start, keyword);
+        if let Some(exit_jump) = exit_jump {
+            self.patch_jump(chunk, exit_jump);
+            chunk.write(OpCode::Pop.into(), self.previous.position());
+        }
+        self.end_loop(chunk);
+        self.end_scope(chunk);
+    }
+
+    fn switch_statement(&mut self, chunk: &mut Chunk) {
+        self.consume(TokenType::LeftParen, "Expect '(' after 'switch'.");
+        self.expression(chunk);
+        self.consume(TokenType::RightParen, "Expect ')' after value.");
+        self.consume(TokenType::LeftBrace, "Expect '{' before switch cases.");
+
+        // the scrutinee sits below any locals the cases declare, so give it a
+        // slot no identifier can name
+        self.begin_scope();
+        self.locals.push(Local {
+            name: "",
+            depth: Some(self.scope_depth),
+        });
+
+        let mut end_jumps = Vec::new();
+        let mut has_default = false;
+        while !self.check(TokenType::RightBrace) && !self.check(TokenType::EoF) {
+            if self.match_token(TokenType::Case) {
+                if has_default {
+                    self.error(self.previous, "Can't have a case after the default case.");
+                }
+                chunk.write(OpCode::Dup.into(), self.previous.position());
                 self.expression(chunk);
-                self.consume(TokenType::RightParen, "Expected ')' after expression");
-            }
-            TokenType::Minus | TokenType::Bang => {
-                self.unary(chunk);
-            }
-            TokenType::Number => self.number(chunk),
-            TokenType::String => self.string(chunk),
-            TokenType::Nil => {
-                chunk.write(OpCode::Nil.into(), self.previous.line);
-            }
-            TokenType::True => {
-                chunk.write(OpCode::True.into(), self.previous.line);
-            }
-            TokenType::False => {
-                chunk.write(OpCode::False.into(), self.previous.line);
-            }
-            TokenType::Identifier => {
-                let arg = chunk.add_constant(Value::ConstString(self.previous.lexeme));
-                if can_assign && self.match_token(TokenType::Equal) {
-                    self.expression(chunk);
-                    chunk.write(OpCode::SetGlobal.into(), self.previous.line);
-                    chunk.write(arg as u8, self.previous.line);
-                } else {
-                    chunk.write(OpCode::GetGlobal.into(), self.previous.line);
-                    chunk.write(arg as u8, self.previous.line);
+                self.consume(TokenType::Colon, "Expect ':' after case value.");
+                chunk.write(OpCode::Equal.into(), self.previous.position());
+                let next_case = self.emit_jump(chunk, OpCode::JumpIfFalse);
+                chunk.write(OpCode::Pop.into(), self.previous.position());
+                self.case_body(chunk);
+                end_jumps.push(self.emit_jump(chunk, OpCode::Jump));
+                self.patch_jump(chunk, next_case);
+                chunk.write(OpCode::Pop.into(), self.previous.position());
+            } else if self.match_token(TokenType::Default) {
+                if has_default {
+                    self.error(self.previous, "Can't have multiple default cases.");
                 }
+                has_default = true;
+                self.consume(TokenType::Colon, "Expect ':' after 'default'.");
+                self.case_body(chunk);
+            } else {
+                self.error(self.current, "Expect 'case' or 'default' in switch.");
+                break;
             }
-            _ => {
-                self.error(self.previous, "Expected expression");
-                return;
-            }
         }
+        self.consume(TokenType::RightBrace, "Expect '}' after switch cases.");
+
+        for jump in end_jumps {
+            self.patch_jump(chunk, jump);
+        }
+        self.end_scope(chunk);
+    }
+
+    fn case_body(&mut self, chunk: &mut Chunk) {
+        self.begin_scope();
+        self.nesting += 1;
+        while !matches!(
+            self.current.ttype,
+            TokenType::Case | TokenType::Default | TokenType::RightBrace | TokenType::EoF
+        ) {
+            self.declaration(chunk);
+        }
+        self.nesting -= 1;
+        self.end_scope(chunk);
+    }
+
+    fn loop_body(&mut self, chunk: &mut Chunk, start: usize) {
+        self.loops.push(Loop {
+            start,
+            scope_depth: self.scope_depth,
+            breaks: Vec::new(),
+        });
+        self.nested_statement(chunk);
+    }
 
-        while prec <= self.current.ttype.precendence() {
+    /// Lands the loop's pending `break` jumps at the current offset
+    fn end_loop(&mut self, chunk: &mut Chunk) {
+        let Some(loop_) = self.loops.pop() else {
+            return;
+        };
+        for jump in loop_.breaks {
+            self.patch_jump(chunk, jump);
+        }
+    }
+
+    fn break_statement(&mut self, chunk: &mut Chunk) {
+        let keyword = self.previous;
+        let Some(scope_depth) = self.loops.last().map(|l| l.scope_depth) else {
+            self.error(keyword, "Can't use 'break' outside of a loop.");
+            return;
+        };
+        self.consume(TokenType::Semicolon, "Expect ';' after 'break'.");
+        self.pop_locals_above(chunk, scope_depth);
+        let jump = self.emit_jump(chunk, OpCode::Jump);
+        if let Some(loop_) = self.loops.last_mut() {
+            loop_.breaks.push(jump);
+        }
+    }
+
+    fn continue_statement(&mut self, chunk: &mut Chunk) {
+        let keyword = self.previous;
+        let Some((start, scope_depth)) = self.loops.last().map(|l| (l.start, l.scope_depth)) else {
+            self.error(keyword, "Can't use 'continue' outside of a loop.");
+            return;
+        };
+        self.consume(TokenType::Semicolon, "Expect ';' after 'continue'.");
+        self.pop_locals_above(chunk, scope_depth);
+        self.emit_loop(chunk, start, keyword);
+    }
+
+    /// Emits pops for locals deeper than `depth` without ending their scope,
+    /// returning how many there are
+    fn return_statement(&mut self, chunk: &mut Chunk) {
+        if self.kind == FunctionKind::Script {
+            self.error(self.previous, "Can't return from top-level code.");
+        }
+        if self.match_token(TokenType::Semicolon) {
+            chunk.write(OpCode::Nil.into(), self.previous.position());
+        } else {
+            self.expression(chunk);
+            self.consume(TokenType::Semicolon, "Expect ';' after return value.");
+        }
+        // the callee's locals go with its frame, so nothing needs popping
+        chunk.write(OpCode::Return.into(), self.previous.position());
+    }
+
+    fn pop_locals_above(&mut self, chunk: &mut Chunk, depth: usize) -> usize {
+        let count = self
+            .locals
+            .iter()
+            .rev()
+            .take_while(|local| local.depth.is_none_or(|d| d > depth))
+            .count();
+        let mut remaining = count;
+        while remaining > 1 {
+            let n = remaining.min(u8::MAX as usize);
+            chunk.write(OpCode::PopN.into(), self.previous.position());
+            chunk.write(n as u8, self.previous.position());
+            remaining -= n;
+        }
+        if remaining == 1 {
+            chunk.write(OpCode::Pop.into(), self.previous.position());
+        }
+        count
+    }
+
+    fn emit_jump(&mut self, chunk: &mut Chunk, op: OpCode) -> Jump<'a> {
+        self.literals.clear();
+        Jump {
+            offset: chunk.emit_jump(op, self.previous.position()),
+            token: self.previous,
+        }
+    }
+
+    /// Lands `jump` at the current offset; one too far is reported where the
+    /// jump was emitted rather than wherever the parser has got to
+    fn patch_jump(&mut self, chunk: &mut Chunk, jump: Jump<'a>) {
+        // code before a jump target can't be folded into code after it
+        self.literals.clear();
+        if chunk.patch_jump(jump.offset).is_err() {
+            self.error(jump.token, "Too much code to jump over.");
+        }
+    }
+
+    /// `keyword` is the loop (or `continue`) an overlong jump is reported at
+    fn emit_loop(&mut self, chunk: &mut Chunk, loop_start: usize, keyword: Token<'a>) {
+        self.literals.clear();
+        if chunk
+            .emit_loop(loop_start, self.previous.position())
+            .is_err()
+        {
+            self.error(keyword, "Loop body too large.");
+        }
+    }
+
+    fn resolve_local(&mut self, name: Token) -> Option<usize> {
+        let slot = self
+            .locals
+            .iter()
+            .rposition(|local| local.name == name.lexeme)?;
+        if self.locals[slot].depth.is_none() {
+            self.error(name, "Can't read local variable in its own initializer.");
+        }
+        Some(slot)
+    }
+
+    fn expression<'b: 'a>(&mut self, chunk: &mut Chunk) {
+        self.parse_precedence(Precedence::Assignment, chunk);
+    }
+
+    fn parse_precedence(&mut self, prec: Precedence, chunk: &mut Chunk) {
+        if !self.enter() {
+            return;
+        }
+        self.advance();
+
+        let can_assign = prec.can_assign();
+        let Some(prefix) = get_rule(self.previous.ttype).prefix else {
+            self.error(self.previous, "Expected expression");
+            self.depth -= 1;
+            return;
+        };
+        prefix(self, chunk, can_assign);
+
+        while prec <= get_rule(self.current.ttype).precedence {
             self.advance();
-            match self.previous.ttype {
-                TokenType::Plus
-                | TokenType::Minus
-                | TokenType::Star
-                | TokenType::Slash
-                | TokenType::BangEqual
-                | TokenType::EqualEqual
-                | TokenType::Greater
-                | TokenType::GreaterEqual
-                | TokenType::Less
-                | TokenType::LessEqual => {
-                    self.binary(chunk);
-                }
-                _ => {}
-            }
+            let infix = get_rule(self.previous.ttype)
+                .infix
+                .expect("tokens with a precedence have an infix rule");
+            infix(self, chunk, can_assign);
         }
 
         if can_assign && self.match_token(TokenType::Equal) {
             self.error(self.previous, "Invalid assign target");
         }
+        self.depth -= 1;
+    }
+
+    fn grouping(&mut self, chunk: &mut Chunk) {
+        self.expression(chunk);
+        self.consume(TokenType::RightParen, "Expected ')' after expression");
+    }
+
+    fn literal(&mut self, chunk: &mut Chunk) {
+        let op = match self.previous.ttype {
+            TokenType::Nil => OpCode::Nil,
+            TokenType::True => OpCode::True,
+            _ => OpCode::False,
+        };
+        chunk.write(op.into(), self.previous.position());
+    }
+
+    fn variable(&mut self, chunk: &mut Chunk, can_assign: bool) {
+        let name = self.previous;
+        let local = self.resolve_local(name);
+        let assign = can_assign && self.match_token(TokenType::Equal);
+        if assign {
+            self.expression(chunk);
+            // after the value, so in `a = b = 1` the outer assignment wins
+            self.assigned_at = Some(name.position());
+        }
+        match local {
+            Some(slot) => {
+                let op = if assign {
+                    OpCode::SetLocal
+                } else {
+                    OpCode::GetLocal
+                };
+                chunk.write(op.into(), name.position());
+                chunk.write(slot as u8, name.position());
+            }
+            None => {
+                let op = if assign {
+                    OpCode::SetGlobal
+                } else {
+                    OpCode::GetGlobal
+                };
+                self.emit_global(chunk, op, name);
+            }
+        }
     }
 
-    fn number<'b: 'a>(&mut self, chunk: &mut Chunk<'a>) {
-        let val = self
-            .previous
-            .lexeme
-            .parse::<f64>()
-            .expect("Should be able to parse float");
-        chunk.write_constant(Value::Number(val), self.previous.line);
+    fn number<'b: 'a>(&mut self, chunk: &mut Chunk) {
+        match self.previous.lexeme.parse::<f64>() {
+            Ok(val) => self.emit_literal(chunk, Value::Number(val)),
+            Err(_) => self.error(self.previous, "Invalid number literal."),
+        }
     }
 
-    fn string<'b: 'a>(&mut self, chunk: &mut Chunk<'a>) {
+    fn string<'b: 'a>(&mut self, chunk: &mut Chunk) {
         let lexeme = self.previous.lexeme;
         let str = &lexeme[1..lexeme.len() - 1]; // remove quotes
-        chunk.write_constant(Value::ConstString(str), self.previous.line);
+        let str = self.session.intern(str);
+        self.emit_literal(chunk, Value::from(str));
     }
 
-    fn unary(&mut self, chunk: &mut Chunk<'a>) {
-        let op = self.previous.ttype;
+    /// Returns the pool index for `value`, reusing an equal number or string
+    fn make_constant(&mut self, chunk: &mut Chunk, value: Value) -> usize {
+        let key = ConstantKey::of(&value);
+        if let Some(index) = key.as_ref().and_then(|key| self.constants.get(key)) {
+            return *index;
+        }
+        if chunk.constants.len() == MAX_CONSTANTS {
+            if !std::mem::replace(&mut self.constants_full, true) {
+                self.error(self.previous, "Too many constants in one chunk.");
+            }
+            return 0;
+        }
+        let index = chunk.add_constant(value);
+        if let Some(key) = key {
+            self.constants.insert(key, index);
+        }
+        index
+    }
+
+    fn emit_constant(&mut self, chunk: &mut Chunk, value: Value) -> usize {
+        let index = self.make_constant(chunk, value);
+        if index < 256 {
+            chunk.write(OpCode::Constant.into(), self.previous.position());
+            chunk.write(index as u8, self.previous.position());
+        } else {
+            chunk.write(OpCode::ConstantLong.into(), self.previous.position());
+            let [index_top, index_bot] = break_index(index);
+            chunk.write(index_top, self.previous.position());
+            chunk.write(index_bot, self.previous.position());
+        }
+        index
+    }
+
+    fn emit_literal(&mut self, chunk: &mut Chunk, value: Value) {
+        let start = chunk.code.len();
+        let pool_len = chunk.constants.len();
+        match OpCode::small_constant(&value) {
+            Some(op) => chunk.write(op.into(), self.previous.position()),
+            None => {
+                self.emit_constant(chunk, value.clone());
+            }
+        }
+        self.literals.push(LiteralSpan {
+            start,
+            end: chunk.code.len(),
+            value,
+            pool_len,
+        });
+    }
+
+    fn literal_operands(&self, chunk: &Chunk, count: usize) -> Option<Vec<LiteralSpan>> {
+        let spans = self
+            .literals
+            .get(self.literals.len().checked_sub(count)?..)?;
+        let mut end = chunk.code.len();
+        for span in spans.iter().rev() {
+            if span.end != end {
+                return None;
+            }
+            end = span.start;
+        }
+        Some(spans.to_vec())
+    }
+
+    /// Replaces the literal operands at the end of the code with `value`
+    fn replace_literals(&mut self, chunk: &mut Chunk, operands: Vec<LiteralSpan>, value: Value) {
+        self.literals.truncate(self.literals.len() - operands.len());
+        let pool_len = operands[0].pool_len;
+        // forget constants the folded literals added, so nothing reuses them
+        for removed in &chunk.constants[pool_len..] {
+            if let Some(key) = ConstantKey::of(removed) {
+                self.constants.remove(&key);
+            }
+        }
+        chunk.truncate(operands[0].start, pool_len);
+        self.emit_literal(chunk, value);
+    }
+
+    /// Folds `-literal`, returning whether the negation was emitted
+    fn fold_unary(&mut self, chunk: &mut Chunk, op: Token) -> bool {
+        let Some(operands) = self.literal_operands(chunk, 1) else {
+            return false;
+        };
+        let value = match operands[0].value.negate() {
+            Ok(value) => value,
+            Err(message) => {
+                self.error(op, &message);
+                return false;
+            }
+        };
+        self.replace_literals(chunk, operands, value);
+        true
+    }
+
+    /// Folds arithmetic on two literals, returning whether the operation was emitted
+    fn fold_binary(&mut self, chunk: &mut Chunk, op: Token) -> bool {
+        let Some(operands) = self.literal_operands(chunk, 2) else {
+            return false;
+        };
+        let (a, b) = (&operands[0].value, &operands[1].value);
+        let value = match op.ttype {
+            TokenType::Plus => a.add(b),
+            TokenType::Minus => a.subtract(b),
+            TokenType::Star => a.multiply(b),
+            // leave division by zero for the VM
+            TokenType::Slash | TokenType::Percent if a.is_number() && *b == Value::Number(0.0) => {
+                return false;
+            }
+            TokenType::Slash => a.divide(b),
+            TokenType::Percent => a.modulo(b),
+            _ => return false,
+        };
+        match value {
+            Ok(value) => {
+                self.replace_literals(chunk, operands, value);
+                true
+            }
+            Err(message) => {
+                self.error(op, &message);
+                false
+            }
+        }
+    }
+
+    fn unary(&mut self, chunk: &mut Chunk) {
+        let op = self.previous;
         self.parse_precedence(Precedence::Unary, chunk);
-        let op_code = match op {
-            TokenType::Minus => OpCode::Negate,
+        let op_code = match op.ttype {
+            TokenType::Minus => {
+                if self.fold_unary(chunk, op) {
+                    return;
+                }
+                OpCode::Negate
+            }
             TokenType::Bang => OpCode::Not,
-            _ => panic!("Unary called on unexpected TokenType {}", op),
+            _ => {
+                self.error(op, "Expected unary operator.");
+                return;
+            }
         };
-        chunk.write(op_code.into(), self.previous.line);
-    }
-
-    fn binary(&mut self, chunk: &mut Chunk<'a>) {
-        let op = self.previous.ttype;
-        self.parse_precedence(op.precendence().next(), chunk);
-        let (op_code1, op_code2) = match op {
-            TokenType::Minus => (OpCode::Subtract, None),
-            TokenType::Plus => (OpCode::Add, None),
-            TokenType::Star => (OpCode::Multiply, None),
-            TokenType::Slash => (OpCode::Divide, None),
-            TokenType::BangEqual => (OpCode::Equal, Some(OpCode::Not)),
-            TokenType::EqualEqual => (OpCode::Equal, None),
-            TokenType::Greater => (OpCode::Greater, None),
-            TokenType::GreaterEqual => (OpCode::Greater, Some(OpCode::Not)),
-            TokenType::Less => (OpCode::Less, None),
-            TokenType::LessEqual => (OpCode::Less, Some(OpCode::Not)),
-            _ => panic!("Binay called on unexpected TokenType {}", op),
+        chunk.write(op_code.into(), op.position());
+    }
+
+    fn binary(&mut self, chunk: &mut Chunk) {
+        let op_token = self.previous;
+        let op = op_token.ttype;
+        self.parse_precedence(get_rule(op).precedence.next(), chunk);
+        if self.fold_binary(chunk, op_token) {
+            return;
+        }
+        let op_code = match op {
+            TokenType::Minus => OpCode::Subtract,
+            TokenType::Plus => OpCode::Add,
+            TokenType::Star => OpCode::Multiply,
+            TokenType::Slash => OpCode::Divide,
+            TokenType::Percent => OpCode::Modulo,
+            TokenType::BangEqual => OpCode::NotEqual,
+            TokenType::EqualEqual => OpCode::Equal,
+            TokenType::Greater => OpCode::Greater,
+            TokenType::GreaterEqual => OpCode::GreaterEqual,
+            TokenType::Less => OpCode::Less,
+            TokenType::LessEqual => OpCode::LessEqual,
+            _ => {
+                self.error(op_token, "Expected binary operator.");
+                return;
+            }
         };
-        chunk.write(op_code1.into(), self.previous.line);
-        if let Some(oc) = op_code2 {
-            chunk.write(oc.into(), self.previous.line);
+        // runtime errors point at the operator rather than the right operand
+        chunk.write(op_code.into(), op_token.position());
+    }
+
+    fn and(&mut self, chunk: &mut Chunk) {
+        // a falsey left operand is the result, so skip the right one
+        let end_jump = self.emit_jump(chunk, OpCode::JumpIfFalse);
+        chunk.write(OpCode::Pop.into(), self.previous.position());
+        self.parse_precedence(Precedence::And, chunk);
+        self.patch_jump(chunk, end_jump);
+    }
+
+    fn or(&mut self, chunk: &mut Chunk) {
+        // a truthy left operand is the result, so skip the right one
+        let else_jump = self.emit_jump(chunk, OpCode::JumpIfFalse);
+        let end_jump = self.emit_jump(chunk, OpCode::Jump);
+        self.patch_jump(chunk, else_jump);
+        chunk.write(OpCode::Pop.into(), self.previous.position());
+        self.parse_precedence(Precedence::Or, chunk);
+        self.patch_jump(chunk, end_jump);
+    }
+
+    fn call(&mut self, chunk: &mut Chunk) {
+        let paren = self.previous;
+        let arg_count = self.argument_list(chunk);
+        chunk.write(OpCode::Call.into(), paren.position());
+        chunk.write(arg_count, paren.position());
+    }
+
+    fn index(&mut self, chunk: &mut Chunk) {
+        let bracket = self.previous;
+        self.expression(chunk);
+        self.consume(TokenType::RightBracket, "Expect ']' after index.");
+        chunk.write(OpCode::Index.into(), bracket.position());
+    }
+
+    fn argument_list(&mut self, chunk: &mut Chunk) -> u8 {
+        let mut count = 0;
+        if !self.check(TokenType::RightParen) {
+            loop {
+                self.expression(chunk);
+                if count == MAX_ARGS {
+                    self.error(self.previous, "Can't have more than 255 arguments.");
+                }
+                count += 1;
+                if !self.match_token(TokenType::Comma) {
+                    break;
+                }
+            }
         }
+        self.consume(TokenType::RightParen, "Expect ')' after arguments.");
+        count.min(MAX_ARGS) as u8
     }
 
-    fn print_statement(&mut self, chunk: &mut Chunk<'a>) {
+    fn print_statement(&mut self, chunk: &mut Chunk) {
         self.expression(chunk);
         self.consume(TokenType::Semicolon, "Expect ; after value.");
-        chunk.write(OpCode::Print.into(), self.previous.line);
+        chunk.write(OpCode::Print.into(), self.previous.position());
+    }
+
+    /// `assert cond;` or `assert cond, message;`, failing at runtime when
+    /// `cond` is falsey. The operand says whether a message was pushed.
+    fn assert_statement(&mut self, chunk: &mut Chunk) {
+        let keyword = self.previous.position();
+        self.expression(chunk);
+        let has_message = self.match_token(TokenType::Comma);
+        if has_message {
+            self.expression(chunk);
+        }
+        self.consume(TokenType::Semicolon, "Expect ';' after assertion.");
+        chunk.write(OpCode::Assert.into(), keyword);
+        chunk.write(has_message.into(), keyword);
     }
 
     fn synchronize(&mut self) {
@@ -256,6 +1119,8 @@ impl<'a> Parser<'a> {
                 | TokenType::If
                 | TokenType::While
                 | TokenType::Print
+                | TokenType::Assert
+                | TokenType::Switch
                 | TokenType::Return => {
                     return;
                 }
@@ -266,20 +1131,775 @@ impl<'a> Parser<'a> {
         }
     }
 
-    fn var_declaration(&mut self, chunk: &mut Chunk<'a>) {
+    fn var_declaration(&mut self, chunk: &mut Chunk) {
         self.consume(TokenType::Identifier, "Expected variable name.");
-        let name = self.previous.lexeme;
+        self.declare_variable();
+        let name = self.previous;
         if self.match_token(TokenType::Equal) {
             self.expression(chunk);
         } else {
-            chunk.write(OpCode::Nil.into(), self.previous.line);
+            chunk.write(OpCode::Nil.into(), self.previous.position());
         }
         self.consume(
             TokenType::Semicolon,
             "Expected ';' after variable declaration.",
         );
-        let global = chunk.add_constant(Value::ConstString(name));
-        chunk.write(OpCode::DefineGlobal.into(), self.previous.line);
-        chunk.write(global as u8, self.previous.line);
+        if self.scope_depth > 0 {
+            // the initializer's value stays on the stack as the local's slot
+            self.mark_initialized();
+            return;
+        }
+        self.emit_global(chunk, OpCode::DefineGlobal, name);
+    }
+
+    fn fun_declaration(&mut self, chunk: &mut Chunk) {
+        self.consume(TokenType::Identifier, "Expect function name.");
+        self.declare_variable();
+        let name = self.previous;
+        // initialized before the body compiles, so the function can call itself
+        self.mark_initialized();
+        self.function(chunk, name);
+        if self.scope_depth == 0 {
+            self.emit_global(chunk, OpCode::DefineGlobal, name);
+        }
+    }
+
+    /// Compiles a function's parameters and body into a chunk of its own and
+    /// emits the finished function as a constant in `chunk`
+    fn function(&mut self, chunk: &mut Chunk, name: Token) {
+        let enclosing = self.begin_function();
+        let mut body = Chunk::new();
+        body.file = self.session.file;
+        self.begin_scope();
+        self.consume(TokenType::LeftParen, "Expect '(' after function name.");
+        let mut arity = 0;
+        if !self.check(TokenType::RightParen) {
+            loop {
+                if arity == MAX_ARGS {
+                    self.error(self.current, "Can't have more than 255 parameters.");
+                }
+                arity += 1;
+                self.consume(TokenType::Identifier, "Expect parameter name.");
+                self.declare_variable();
+                self.mark_initialized();
+                if !self.match_token(TokenType::Comma) {
+                    break;
+                }
+            }
+        }
+        self.consume(TokenType::RightParen, "Expect ')' after parameters.");
+        self.consume(TokenType::LeftBrace, "Expect '{' before function body.");
+        self.block(&mut body);
+        body.write(OpCode::Nil.into(), self.previous.position());
+        body.write(OpCode::Return.into(), self.previous.position());
+        self.end_function(enclosing);
+
+        let function = Function {
+            name: Some(self.session.intern(name.lexeme)),
+            arity,
+            chunk: body,
+        };
+        self.emit_constant(chunk, Value::Function(function.into()));
+    }
+
+    /// Starts a fresh set of locals whose slot zero holds the function being called
+    fn begin_function(&mut self) -> Enclosing<'a> {
+        let callee = Local {
+            name: "",
+            depth: Some(0),
+        };
+        Enclosing {
+            kind: std::mem::replace(&mut self.kind, FunctionKind::Function),
+            locals: std::mem::replace(&mut self.locals, vec![callee]),
+            scope_depth: std::mem::take(&mut self.scope_depth),
+            loops: std::mem::take(&mut self.loops),
+            literals: std::mem::take(&mut self.literals),
+            constants: std::mem::take(&mut self.constants),
+            constants_full: std::mem::take(&mut self.constants_full),
+        }
+    }
+
+    fn end_function(&mut self, enclosing: Enclosing<'a>) {
+        self.kind = enclosing.kind;
+        self.locals = enclosing.locals;
+        self.scope_depth = enclosing.scope_depth;
+        self.loops = enclosing.loops;
+        self.literals = enclosing.literals;
+        self.constants = enclosing.constants;
+        self.constants_full = enclosing.constants_full;
+    }
+
+    fn mark_initialized(&mut self) {
+        if self.scope_depth == 0 {
+            return;
+        }
+        if let Some(local) = self.locals.last_mut() {
+            local.depth = Some(self.scope_depth);
+        }
+    }
+
+    fn emit_global(&mut self, chunk: &mut Chunk, op: OpCode, name: Token<'a>) {
+        if op == OpCode::DefineGlobal {
+            self.global_definitions.insert(name.lexeme);
+        } else {
+            self.global_uses.entry(name.lexeme).or_insert(name);
+        }
+        let globals = &self.session.globals;
+        if globals.len() == MAX_GLOBALS && globals.get_slot(name.lexeme).is_none() {
+            self.error(name, "Too many global variables.");
+            return;
+        }
+        let slot = self.session.resolve_global(name.lexeme);
+        chunk.write_global(op, slot, name.lexeme, name.position());
+    }
+
+    /// Adds a new local, uninitialized until `var_declaration` finishes it,
+    /// rejecting one that shares its name with another in the same scope
+    fn declare_variable(&mut self) {
+        if self.scope_depth == 0 {
+            return;
+        }
+        let name = self.previous;
+        let duplicate = self
+            .locals
+            .iter()
+            .rev()
+            .take_while(|local| local.depth.is_none_or(|d| d >= self.scope_depth))
+            .any(|local| local.name == name.lexeme);
+        if duplicate {
+            self.error(name, "Already a variable with this name in this scope.");
+        }
+        if self.locals.len() == MAX_LOCALS {
+            self.error(name, "Too many local variables in function.");
+            return;
+        }
+        self.locals.push(Local {
+            name: name.lexeme,
+            depth: None,
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_compile_repl_keeps_final_value() {
+        let mut chunk = Chunk::new();
+        assert!(Compiler::compile_repl("2;", &mut chunk, &mut CompilerSession::default()).is_ok());
+        assert_eq!(
+            chunk.code(),
+            &[OpCode::Constant.into(), 0, OpCode::Return.into()]
+        );
+        let mut chunk = Chunk::new();
+        assert!(
+            Compiler::compile_repl("2; 3", &mut chunk, &mut CompilerSession::default()).is_ok()
+        );
+        assert_eq!(
+            chunk.code(),
+            &[
+                OpCode::Constant.into(),
+                0,
+                OpCode::Pop.into(),
+                OpCode::Constant.into(),
+                1,
+                OpCode::Return.into()
+            ]
+        );
+        let mut chunk = Chunk::new();
+        assert!(Compiler::compile("1", &mut chunk, &mut CompilerSession::default()).is_err());
+    }
+
+    #[test]
+    fn test_parse_rules() {
+        use TokenType::*;
+        let all = [
+            LeftParen,
+            RightParen,
+            LeftBrace,
+            RightBrace,
+            LeftBracket,
+            RightBracket,
+            Comma,
+            Colon,
+            Dot,
+            Minus,
+            Plus,
+            Semicolon,
+            Slash,
+            Star,
+            Percent,
+            Bang,
+            BangEqual,
+            Equal,
+            EqualEqual,
+            Greater,
+            GreaterEqual,
+            Less,
+            LessEqual,
+            Identifier,
+            String,
+            Number,
+            And,
+            Assert,
+            Break,
+            Case,
+            Class,
+            Continue,
+            Default,
+            Else,
+            False,
+            For,
+            Fun,
+            If,
+            Nil,
+            Or,
+            Print,
+            Return,
+            Super,
+            Switch,
+            This,
+            True,
+            Var,
+            While,
+            Error,
+            EoF,
+        ];
+        for ttype in all {
+            let rule: ParseRule = get_rule(ttype);
+            // parse_precedence relies on every binding token having an infix rule
+            assert_eq!(
+                rule.infix.is_some(),
+                rule.precedence != Precedence::None,
+                "{ttype}"
+            );
+        }
+        let precedence = |ttype| get_rule(ttype).precedence;
+        assert!(precedence(Or) < precedence(And));
+        assert!(precedence(And) < precedence(EqualEqual));
+        assert!(precedence(EqualEqual) < precedence(Less));
+        assert!(precedence(Less) < precedence(Plus));
+        assert!(precedence(Plus) < precedence(Star));
+        assert!(precedence(Star) < precedence(LeftParen));
+        assert_eq!(precedence(LeftParen), precedence(LeftBracket));
+        assert_eq!(precedence(Minus), precedence(Plus));
+    }
+
+    #[test]
+    fn test_compile_modulo() {
+        let mut chunk = Chunk::new();
+        assert!(Compiler::compile("a % 3;", &mut chunk, &mut CompilerSession::default()).is_ok());
+        assert_eq!(
+            chunk.code(),
+            &[
+                OpCode::GetGlobal.into(),
+                0,
+                0,
+                OpCode::Constant.into(),
+                0,
+                OpCode::Modulo.into(),
+                OpCode::Pop.into(),
+                OpCode::Return.into(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_compile_comparisons() {
+        for (op, code) in [
+            ("!=", OpCode::NotEqual),
+            ("<=", OpCode::LessEqual),
+            (">=", OpCode::GreaterEqual),
+        ] {
+            let mut chunk = Chunk::new();
+            let source = format!("a {op} 3;");
+            assert!(
+                Compiler::compile(&source, &mut chunk, &mut CompilerSession::default()).is_ok()
+            );
+            assert_eq!(
+                chunk.code()[3..],
+                [
+                    OpCode::Constant.into(),
+                    0,
+                    code.into(),
+                    OpCode::Pop.into(),
+                    OpCode::Return.into()
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn test_compile_assert() {
+        let mut chunk = Chunk::new();
+        let source = "assert nil;\nassert nil, \"m\";";
+        assert!(Compiler::compile(source, &mut chunk, &mut CompilerSession::default()).is_ok());
+        assert_eq!(
+            chunk.code(),
+            &[
+                OpCode::Nil.into(),
+                OpCode::Assert.into(),
+                0,
+                OpCode::Nil.into(),
+                OpCode::Constant.into(),
+                0,
+                OpCode::Assert.into(),
+                1,
+                OpCode::Return.into(),
+            ]
+        );
+        assert_eq!(chunk.read_line(6), 2);
+
+        let mut chunk = Chunk::new();
+        let errors = Compiler::compile(
+            "assert 1, 2, 3;",
+            &mut chunk,
+            &mut CompilerSession::default(),
+        )
+        .unwrap_err();
+        assert_eq!(errors[0].message, "Expect ';' after assertion.");
+    }
+
+    #[test]
+    fn test_break_continue_outside_loop() {
+        let mut chunk = Chunk::new();
+        assert!(Compiler::compile("break;", &mut chunk, &mut CompilerSession::default()).is_err());
+        let mut chunk = Chunk::new();
+        assert!(
+            Compiler::compile("{ continue; }", &mut chunk, &mut CompilerSession::default())
+                .is_err()
+        );
+        let mut chunk = Chunk::new();
+        assert!(
+            Compiler::compile(
+                "if (nil) break;",
+                &mut chunk,
+                &mut CompilerSession::default()
+            )
+            .is_err()
+        );
+        let mut chunk = Chunk::new();
+        assert!(
+            Compiler::compile(
+                "while (nil) { break; }",
+                &mut chunk,
+                &mut CompilerSession::default()
+            )
+            .is_ok()
+        );
+    }
+
+    #[test]
+    fn test_return_outside_function() {
+        let mut chunk = Chunk::new();
+        assert!(
+            Compiler::compile("return 1;", &mut chunk, &mut CompilerSession::default()).is_err()
+        );
+        let mut chunk = Chunk::new();
+        assert!(
+            Compiler::compile(
+                "fun f() {} { return; }",
+                &mut chunk,
+                &mut CompilerSession::default()
+            )
+            .is_err()
+        );
+        let mut chunk = Chunk::new();
+        assert!(
+            Compiler::compile(
+                "fun f() { if (true) return; return 1; }",
+                &mut chunk,
+                &mut CompilerSession::default()
+            )
+            .is_ok()
+        );
+    }
+
+    #[test]
+    fn test_compile_switch() {
+        let mut chunk = Chunk::new();
+        let source = "switch (1) {\ncase 1: print 10;\ncase 2: print 20;\n}";
+        assert!(Compiler::compile(source, &mut chunk, &mut CompilerSession::default()).is_ok());
+        let mut out = Vec::new();
+        chunk.dissassemble_to(&mut out, "switch").unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            concat!(
+                "== switch ==\n",
+                "0000    1 OP_ONE\n",
+                "0001    2 OP_DUP\n",
+                "0002    | OP_ONE\n",
+                "0003    | OP_EQUAL\n",
+                "0004    | OP_JUMP_IF_FALSE    4 -> 14\n",
+                "0007    | OP_POP\n",
+                "0008    | OP_CONSTANT    0 '10.0'\n",
+                "0010    | OP_PRINT\n",
+                "0011    | OP_JUMP   11 -> 30\n",
+                "0014    | OP_POP\n",
+                "0015    3 OP_DUP\n",
+                "0016    | OP_CONSTANT    1 '2.0'\n",
+                "0018    | OP_EQUAL\n",
+                "0019    | OP_JUMP_IF_FALSE   19 -> 29\n",
+                "0022    | OP_POP\n",
+                "0023    | OP_CONSTANT    2 '20.0'\n",
+                "0025    | OP_PRINT\n",
+                "0026    | OP_JUMP   26 -> 30\n",
+                "0029    | OP_POP\n",
+                "0030    4 OP_POP\n",
+                "0031    | OP_RETURN\n",
+            )
+        );
+    }
+
+    #[test]
+    fn test_switch_errors() {
+        let sources = [
+            "switch (1) { default: print 1; default: print 2; }",
+            "switch (1) { default: print 1; case 1: print 2; }",
+            "switch (1) { print 1; }",
+        ];
+        for source in sources {
+            let mut chunk = Chunk::new();
+            assert!(
+                Compiler::compile(source, &mut chunk, &mut CompilerSession::default()).is_err(),
+                "{source}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_constant_pool() {
+        // equal literals share a constant; 0 has its own opcode but -0 doesn't
+        let mut chunk = Chunk::new();
+        let source = "print 2; print \"a\"; print 2; print \"a\"; print 0; print -0;";
+        assert!(Compiler::compile(source, &mut chunk, &mut CompilerSession::default()).is_ok());
+        assert_eq!(chunk.constants.len(), 3);
+        assert_eq!(chunk.constants[2].to_string(), "-0");
+
+        // constants past 255 use the long form
+        let source: String = (2..302).map(|i| format!("print {i};")).collect();
+        assert!(disassemble(&source).contains("OP_CONSTANT_LONG  299 '301.0'"));
+
+        // folding gives back the constants of the literals it replaces
+        let mut chunk = Chunk::new();
+        let source = "print 4; print 2 + 3; print 2;";
+        assert!(Compiler::compile(source, &mut chunk, &mut CompilerSession::default()).is_ok());
+        assert_eq!(chunk.constants, [4.0, 5.0, 2.0].map(Value::Number));
+
+        // reported once per chunk, not for every constant after the limit
+        let source: String = (2..=MAX_CONSTANTS + 10).map(|i| format!("{i};")).collect();
+        let errors = compile_errors(&source);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].message, "Too many constants in one chunk.");
+        let source = format!("fun f() {{ {source} }} {source}");
+        let errors = compile_errors(&source);
+        let messages: Vec<_> = errors.iter().map(|e| e.message.as_str()).collect();
+        assert_eq!(messages, ["Too many constants in one chunk."; 2]);
+    }
+
+    #[test]
+    fn test_small_constants() {
+        // only the bound needs the pool
+        let mut chunk = Chunk::new();
+        let source = "for (var i = 0; i < 10; i = i + 1) { print i - 1; }";
+        assert!(Compiler::compile(source, &mut chunk, &mut CompilerSession::default()).is_ok());
+        assert_eq!(chunk.constants, [Value::Number(10.0)]);
+        let code = disassemble(source);
+        assert!(code.contains("OP_ZERO"));
+        assert_eq!(code.matches("OP_ONE").count(), 2);
+
+        // folding can produce them, and they fold like any other literal
+        assert_eq!(
+            disassemble("print -1; print 3 - 2; print -1 + 1;"),
+            concat!(
+                "== code ==\n",
+                "0000    1 OP_MINUS_ONE\n",
+                "0001    | OP_PRINT\n",
+                "0002    | OP_ONE\n",
+                "0003    | OP_PRINT\n",
+                "0004    | OP_ZERO\n",
+                "0005    | OP_PRINT\n",
+                "0006    | OP_RETURN\n",
+            )
+        );
+    }
+
+    fn compile_errors(source: &str) -> Vec<CompileError> {
+        let mut chunk = Chunk::new();
+        Compiler::compile(source, &mut chunk, &mut CompilerSession::default()).unwrap_err()
+    }
+
+    #[test]
+    fn test_structured_errors() {
+        assert_eq!(
+            compile_errors("print 1\nprint 2;"),
+            vec![CompileError {
+                message: "Expect ; after value.".to_string(),
+                at: ErrorAt::Lexeme("print".to_string()),
+                line: 2,
+                column: 1,
+                file: 0,
+            }]
+        );
+        assert_eq!(
+            compile_errors("var a;\n\na + 1 = 2;"),
+            vec![CompileError {
+                message: "Invalid assign target".to_string(),
+                at: ErrorAt::Lexeme("=".to_string()),
+                line: 3,
+                column: 7,
+                file: 0,
+            }]
+        );
+        assert_eq!(
+            compile_errors("1 +;"),
+            vec![CompileError {
+                message: "Expected expression".to_string(),
+                at: ErrorAt::Lexeme(";".to_string()),
+                line: 1,
+                column: 4,
+                file: 0,
+            }]
+        );
+        let errors = compile_errors("print 1 $;");
+        assert_eq!(errors[0].message, "Unexpected character");
+        assert_eq!(errors[0].at, ErrorAt::Scanner);
+        assert_eq!(compile_errors("{ print 1;")[0].at, ErrorAt::End);
+        let errors = compile_errors(&format!("print {};", "(".repeat(1000)));
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].message, "Too much nesting.");
+        assert_eq!(
+            compile_errors("var a;\nvar s = \"abc;\n\nprint s;\n")[0].to_string(),
+            "[line 2:9]: Unterminated string starting at line 2"
+        );
+
+        // synchronizing after an error still reports later statements
+        let errors = compile_errors("var = 1;\nprint 2;\nprint ;");
+        let lines: Vec<usize> = errors.iter().map(|e| e.line).collect();
+        assert_eq!(lines, [1, 3]);
+        assert_eq!(
+            errors[1].to_string(),
+            "[line 3:7] after ';': Expected expression"
+        );
+    }
+
+    #[test]
+    fn test_jump_too_far() {
+        let body = "print 2;\n".repeat(30_000);
+        let errors = compile_errors(&format!("var a;\nif (a) {{\n{body}}}"));
+        assert_eq!(
+            errors,
+            vec![CompileError {
+                message: "Too much code to jump over.".to_string(),
+                at: ErrorAt::Lexeme(")".to_string()),
+                line: 2,
+                column: 6,
+                file: 0,
+            }]
+        );
+        let errors = compile_errors(&format!("var a;\nwhile (a) {{\n{body}}}"));
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            errors[0].to_string(),
+            "[line 2:1] after 'while': Loop body too large."
+        );
+    }
+
+    fn disassemble(source: &str) -> String {
+        let mut chunk = Chunk::new();
+        assert!(
+            Compiler::compile(source, &mut chunk, &mut CompilerSession::default()).is_ok(),
+            "{source}"
+        );
+        let mut out = Vec::new();
+        chunk.dissassemble_to(&mut out, "code").unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_constant_folding() {
+        assert_eq!(
+            disassemble("print 2 * 3 + 4;"),
+            "== code ==\n0000    1 OP_CONSTANT    0 '10.0'\n0002    | OP_PRINT\n0003    | OP_RETURN\n"
+        );
+        assert!(disassemble("-(1 / 2);").contains("OP_CONSTANT    0 '-0.5'\n0002    | OP_POP"));
+        assert!(disassemble("\"a\" + \"b\" + 1;").contains("OP_CONSTANT    0 'ab1'\n"));
+        // operands that aren't all literals are left alone
+        assert!(disassemble("var a; 1 + a * 2;").contains("OP_MULTIPLY"));
+        assert!(disassemble("nil or 1 + 2;").contains("'3.0'"));
+        // division by zero keeps its runtime semantics
+        assert!(disassemble("1 / 0;").contains("OP_DIVIDE"));
+        assert!(disassemble("1 % 0;").contains("OP_MODULO"));
+    }
+
+    #[test]
+    fn test_constant_folding_type_errors() {
+        for source in ["\"a\" - 1;", "-\"a\";", "1 * \"a\";"] {
+            let mut chunk = Chunk::new();
+            assert!(
+                Compiler::compile(source, &mut chunk, &mut CompilerSession::default()).is_err(),
+                "{source}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_scope_exit_pops() {
+        let code = disassemble("{ var a = 1; var b = 2; var c = 3; var d = 4; var e = 5; }");
+        assert!(code.contains("OP_POPN    5\n"), "{code}");
+        assert!(!code.contains("OP_POP\n"), "{code}");
+
+        let code = disassemble("{ var a = 1; }");
+        assert!(code.contains("OP_POP\n"), "{code}");
+        assert!(!code.contains("OP_POPN"), "{code}");
+
+        let code = disassemble("while (nil) { var a = 1; var b = 2; break; }");
+        assert_eq!(code.matches("OP_POPN    2\n").count(), 2, "{code}");
+    }
+
+    #[test]
+    fn test_redeclare_local() {
+        let mut chunk = Chunk::new();
+        assert!(
+            Compiler::compile(
+                "{ var a = 1; var a = 2; }",
+                &mut chunk,
+                &mut CompilerSession::default()
+            )
+            .is_err()
+        );
+        let mut chunk = Chunk::new();
+        assert!(
+            Compiler::compile(
+                "{ var a = 1; { var b; } var a = 2; }",
+                &mut chunk,
+                &mut CompilerSession::default()
+            )
+            .is_err()
+        );
+        // shadowing in an inner scope and redefining globals are fine
+        let mut chunk = Chunk::new();
+        assert!(
+            Compiler::compile(
+                "{ var a = 1; { var a = 2; } }",
+                &mut chunk,
+                &mut CompilerSession::default()
+            )
+            .is_ok()
+        );
+        let mut chunk = Chunk::new();
+        assert!(
+            Compiler::compile(
+                "var a = 1; var a = 2;",
+                &mut chunk,
+                &mut CompilerSession::default()
+            )
+            .is_ok()
+        );
+    }
+
+    #[test]
+    fn test_function_declaration() {
+        let code = disassemble("fun add(a, b) { print a + b; } add(1, 2);");
+        assert!(code.contains("== add =="), "{code}");
+        assert!(code.contains("OP_GET_LOCAL    1"), "{code}");
+        assert!(code.contains("OP_GET_LOCAL    2"), "{code}");
+        assert!(code.contains("OP_CALL    2"), "{code}");
+        assert!(code.contains("<fn add>"), "{code}");
+
+        let mut chunk = Chunk::new();
+        assert!(
+            Compiler::compile(
+                "fun f(a, a) {}",
+                &mut chunk,
+                &mut CompilerSession::default()
+            )
+            .is_err()
+        );
+        let mut chunk = Chunk::new();
+        assert!(
+            Compiler::compile("fun f(a, b {}", &mut chunk, &mut CompilerSession::default())
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_local_in_own_initializer() {
+        let mut chunk = Chunk::new();
+        assert!(
+            Compiler::compile(
+                "{ var a = a; }",
+                &mut chunk,
+                &mut CompilerSession::default()
+            )
+            .is_err()
+        );
+        // the new local shadows the outer variable as soon as it's declared,
+        // as in the book and the treewalk resolver
+        let mut chunk = Chunk::new();
+        assert!(
+            Compiler::compile(
+                "{ var a = 1; { var a = a; } }",
+                &mut chunk,
+                &mut CompilerSession::default()
+            )
+            .is_err()
+        );
+        let mut chunk = Chunk::new();
+        assert!(
+            Compiler::compile(
+                "var a = 1; { var a = a + 1; }",
+                &mut chunk,
+                &mut CompilerSession::default()
+            )
+            .is_err()
+        );
+        // globals are looked up at runtime, so this compiles
+        let mut chunk = Chunk::new();
+        assert!(
+            Compiler::compile("var a = a;", &mut chunk, &mut CompilerSession::default()).is_ok()
+        );
+        let mut chunk = Chunk::new();
+        assert!(
+            Compiler::compile(
+                "{ var a = 1; var b = a; }",
+                &mut chunk,
+                &mut CompilerSession::default()
+            )
+            .is_ok()
+        );
+    }
+
+    #[test]
+    fn test_global_slots() {
+        let mut session = CompilerSession::default();
+        let mut chunk = Chunk::new();
+        assert!(Compiler::compile("var a = 1; var b = a;", &mut chunk, &mut session).is_ok());
+        let code = {
+            let mut out = Vec::new();
+            chunk.dissassemble_to(&mut out, "code").unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        assert!(code.contains("OP_DEFINE_GLOBAL    0 'a'"), "{code}");
+        assert!(code.contains("OP_GET_GLOBAL    0 'a'"), "{code}");
+        assert!(code.contains("OP_DEFINE_GLOBAL    1 'b'"), "{code}");
+
+        // a later chunk sees the same slots
+        let mut chunk = Chunk::new();
+        assert!(Compiler::compile("b = a;", &mut chunk, &mut session).is_ok());
+        assert_eq!(
+            chunk.code(),
+            &[
+                OpCode::GetGlobal.into(),
+                0,
+                0,
+                OpCode::SetGlobal.into(),
+                0,
+                1,
+                OpCode::Pop.into(),
+                OpCode::Return.into(),
+            ]
+        );
     }
 }
@@ -0,0 +1,176 @@
+use diagnostic::{Diagnostic, Phase, SourceMap, Span};
+
+use crate::{CompileError, Error, ErrorAt, RuntimeError, scan::UNTERMINATED_STRING};
+
+impl CompileError {
+    /// Stable name for the kind of error, see `Diagnostic::code`. The
+    /// compiler reports everything past the scanner as one kind.
+    pub fn code(&self) -> &'static str {
+        match self.at {
+            ErrorAt::Scanner if self.message.starts_with(UNTERMINATED_STRING) => {
+                "unterminated-string"
+            }
+            ErrorAt::Scanner if self.message == "Unexpected character" => "unexpected-character",
+            ErrorAt::Scanner => "scan",
+            ErrorAt::Lexeme(_) | ErrorAt::End => "compile",
+        }
+    }
+
+    pub fn span(&self) -> Span {
+        match &self.at {
+            ErrorAt::Lexeme(lexeme) => Span::of(self.line, self.column, lexeme).in_file(self.file),
+            ErrorAt::End | ErrorAt::Scanner => {
+                Span::point(self.line, self.column).in_file(self.file)
+            }
+        }
+    }
+}
+
+impl From<&CompileError> for Diagnostic {
+    fn from(e: &CompileError) -> Self {
+        let phase = match e.at {
+            ErrorAt::Scanner => Phase::Scan,
+            ErrorAt::Lexeme(_) | ErrorAt::End => Phase::Compile,
+        };
+        Diagnostic::error(phase, e.code(), e.message.clone(), Some(e.span()))
+    }
+}
+
+impl From<&RuntimeError> for Diagnostic {
+    fn from(e: &RuntimeError) -> Self {
+        // a hand-built chunk has lines but no columns
+        let span = Span::point(e.line, e.column.max(1)).in_file(e.file);
+        Diagnostic::error(Phase::Runtime, "runtime", e.message.clone(), Some(span))
+    }
+}
+
+impl Error {
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        match self {
+            Error::Compiler(errors) => errors.iter().map(Diagnostic::from).collect(),
+            Error::Runtime(error) => vec![error.into()],
+            Error::Io(error) => vec![Diagnostic::error(
+                Phase::Runtime,
+                "io",
+                error.to_string(),
+                None,
+            )],
+            Error::InvalidBytecode { reason, .. } => vec![Diagnostic::error(
+                Phase::Runtime,
+                "invalid-bytecode",
+                reason.clone(),
+                None,
+            )],
+        }
+    }
+
+    /// Each of `diagnostics` as `file:line:column: ...`, one per line, with
+    /// a runtime error's stack trace after it
+    pub fn render(&self, sources: &SourceMap) -> String {
+        let mut rendered: Vec<String> = self
+            .diagnostics()
+            .iter()
+            .map(|d| d.render(sources))
+            .collect();
+        if let Error::Runtime(error) = self {
+            rendered.extend(error.trace.iter().cloned());
+        }
+        rendered.join("\n")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use diagnostic::Severity;
+
+    use super::*;
+
+    fn compile_error(source: &str) -> Diagnostic {
+        match crate::VM::new().compile(source) {
+            Err(e) => e.diagnostics().remove(0),
+            Ok(_) => panic!("{source:?} compiled"),
+        }
+    }
+
+    #[test]
+    fn test_compile_errors() {
+        let diagnostic = compile_error("print 1 +;");
+        assert_eq!(diagnostic.severity, Severity::Error);
+        assert_eq!(diagnostic.phase, Phase::Compile);
+        assert_eq!(diagnostic.code, "compile");
+        assert_eq!(diagnostic.message, "Expected expression");
+        assert_eq!(diagnostic.span, Some(Span::of(1, 10, ";")));
+
+        let diagnostic = compile_error("print 1");
+        assert_eq!(diagnostic.phase, Phase::Compile);
+        assert_eq!(diagnostic.span, Some(Span::point(1, 8)));
+
+        let diagnostic = compile_error("print @;");
+        assert_eq!(diagnostic.phase, Phase::Scan);
+        assert_eq!(diagnostic.code, "unexpected-character");
+        assert_eq!(diagnostic.span, Some(Span::point(1, 7)));
+
+        let diagnostic = compile_error("print\n \"abc");
+        assert_eq!(diagnostic.code, "unterminated-string");
+        assert_eq!(diagnostic.span, Some(Span::point(2, 2)));
+    }
+
+    #[test]
+    fn test_runtime_error() {
+        let error = RuntimeError {
+            message: "Operands must be numbers.".to_string(),
+            line: 3,
+            column: 9,
+            file: 0,
+            trace: vec!["[line 3] in script".to_string()],
+        };
+        let diagnostic = Diagnostic::from(&error);
+        assert_eq!(diagnostic.phase, Phase::Runtime);
+        assert_eq!(diagnostic.code, "runtime");
+        assert_eq!(diagnostic.message, "Operands must be numbers.");
+        assert_eq!(diagnostic.span, Some(Span::point(3, 9)));
+
+        let error = RuntimeError { column: 0, ..error };
+        assert_eq!(Diagnostic::from(&error).span, Some(Span::point(3, 1)));
+    }
+
+    #[test]
+    fn test_errors_name_their_file() {
+        let mut sources = SourceMap::default();
+        let mut vm = crate::VM::new();
+        for (name, line) in [
+            ("<repl:1>", "fun f(x) {\n  return -x;\n}"),
+            ("<repl:2>", "print 1 +;"),
+        ] {
+            vm.set_file(sources.add(name, line));
+            let _ = vm.interpret_line(line);
+        }
+        let error = vm.interpret_line("print 1 +;").unwrap_err();
+        assert_eq!(
+            error.render(&sources),
+            "<repl:2>:1:10: compile error: Expected expression"
+        );
+        // the runtime error is in the function, not the line that called it
+        vm.set_file(sources.add("<repl:3>", "f(nil);"));
+        let error = vm.interpret_line("f(nil);").unwrap_err();
+        assert_eq!(
+            error.render(&sources),
+            "<repl:1>:2:10: runtime error: Operand must be a number.\n\
+             [line 2:10] in f()\n\
+             [line 1:2] in script"
+        );
+    }
+
+    #[test]
+    fn test_other_errors() {
+        let io = Error::Io(std::io::Error::other("closed"));
+        assert_eq!(io.diagnostics()[0].code, "io");
+        assert_eq!(io.diagnostics()[0].span, None);
+        let invalid = Error::InvalidBytecode {
+            reason: "bad magic".to_string(),
+            offset: 0,
+        };
+        assert_eq!(invalid.diagnostics()[0].code, "invalid-bytecode");
+        assert_eq!(invalid.diagnostics()[0].message, "bad magic");
+    }
+}
@@ -0,0 +1,81 @@
+use std::{collections::HashMap, rc::Rc};
+
+use crate::Value;
+
+pub(crate) const MAX_GLOBALS: usize = u16::MAX as usize + 1;
+
+/// Global variables by slot. The compiler assigns each name a slot once, and
+/// the VM reads and writes slots directly, so REPL chunks share one table.
+#[derive(Default)]
+pub(crate) struct Globals {
+    slots: HashMap<Rc<str>, usize>,
+    names: Vec<Rc<str>>,
+    values: Vec<Option<Value>>,
+}
+
+impl Globals {
+    pub(crate) fn get_slot(&self, name: &str) -> Option<usize> {
+        self.slots.get(name).copied()
+    }
+
+    /// Returns the slot for `name`, assigning the next free one if it has none
+    pub(crate) fn resolve(&mut self, name: Rc<str>) -> usize {
+        if let Some(slot) = self.get_slot(&name) {
+            return slot;
+        }
+        let slot = self.names.len();
+        self.slots.insert(name.clone(), slot);
+        self.names.push(name);
+        self.values.push(None);
+        slot
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    /// The name behind `slot`, for diagnostics
+    pub(crate) fn name(&self, slot: usize) -> &str {
+        self.try_name(slot).unwrap_or("<unknown>")
+    }
+
+    pub(crate) fn try_name(&self, slot: usize) -> Option<&str> {
+        self.names.get(slot).map(|name| name.as_ref())
+    }
+
+    pub(crate) fn get(&self, slot: usize) -> Option<&Value> {
+        self.values.get(slot)?.as_ref()
+    }
+
+    pub(crate) fn get_mut(&mut self, slot: usize) -> Option<&mut Value> {
+        self.values.get_mut(slot)?.as_mut()
+    }
+
+    pub(crate) fn define(&mut self, slot: usize, value: Value) {
+        if slot >= self.values.len() {
+            self.values.resize(slot + 1, None);
+        }
+        self.values[slot] = Some(value);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_resolve_reuses_slots() {
+        let mut globals = Globals::default();
+        assert_eq!(globals.resolve("a".into()), 0);
+        assert_eq!(globals.resolve("b".into()), 1);
+        assert_eq!(globals.resolve("a".into()), 0);
+        assert_eq!(globals.name(1), "b");
+        assert_eq!(globals.get(0), None);
+        globals.define(0, Value::Number(1.0));
+        assert_eq!(globals.get(0), Some(&Value::Number(1.0)));
+        // slots without a name (e.g. from a hand-built chunk) still work
+        globals.define(5, Value::Nil);
+        assert_eq!(globals.get(5), Some(&Value::Nil));
+        assert_eq!(globals.name(5), "<unknown>");
+    }
+}
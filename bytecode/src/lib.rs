@@ -1,54 +1,752 @@
 #![allow(dead_code)]
 mod chunk;
 mod compiler;
+mod diagnostic;
+mod globals;
+mod object;
 mod scan;
+mod session;
 mod value;
 mod vm;
 
-pub use chunk::{Chunk, OpCode};
+pub use ::diagnostic::{Diagnostic, Phase, Severity, SourceFile, SourceMap, Span};
+pub use chunk::{AsmError, Chunk, DecodedInstruction, OpCode, Operand, Position, assemble};
+pub use compiler::{CompileError, ErrorAt};
 pub use value::Value;
-pub use vm::VM;
+pub use vm::{
+    Debugger, InstructionEvent, InstructionHook, RuntimeError, Stats, StepInfo, VM, trace_hook,
+};
 
-use std::io::Write;
+use cli::{Timings, timed};
+use repl::ReplOutcome;
+use std::{
+    cell::{Ref, RefCell},
+    fmt::Debug,
+    io::Write,
+    rc::Rc,
+};
 use thiserror::Error;
+use value::ValueVec;
 
-#[derive(Error, Debug)]
+#[derive(Error)]
 pub enum Error {
-    #[error("Compiler Error")]
-    Compiler,
+    #[error("{}Compilation failed, see errors above.", .0.iter().fold(String::new(), |acc, e| acc + &e.to_string() + "\n"))]
+    Compiler(Vec<CompileError>),
 
-    #[error("Runtime Error")]
-    Runtime,
+    #[error(transparent)]
+    Runtime(#[from] RuntimeError),
 
-    #[error("IO Error")]
-    Io,
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+
+    /// `offset` is into the file for a `.loxc` that failed to load, or into
+    /// the chunk's code for `Chunk::validate`
+    #[error("Invalid bytecode at offset {offset}: {reason}")]
+    InvalidBytecode { reason: String, offset: usize },
+}
+
+impl Debug for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self)
+    }
 }
 
-pub struct Lox();
+impl Error {
+    /// The `sysexits.h` code for a binary to exit with: 65 for a program that
+    /// doesn't compile or load, 70 for one that fails while running, and 74
+    /// for failing to read or write
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Compiler(_) | Error::InvalidBytecode { .. } => 65,
+            Error::Runtime(_) => 70,
+            Error::Io(_) => 74,
+        }
+    }
+}
+
+// by hand because `io::Error` isn't Clone; a step debugger repeats the error
+// that ended its script
+impl Clone for Error {
+    fn clone(&self) -> Self {
+        match self {
+            Self::Compiler(errors) => Self::Compiler(errors.clone()),
+            Self::Runtime(error) => Self::Runtime(error.clone()),
+            Self::Io(error) => Self::Io(std::io::Error::new(error.kind(), error.to_string())),
+            Self::InvalidBytecode { reason, offset } => Self::InvalidBytecode {
+                reason: reason.clone(),
+                offset: *offset,
+            },
+        }
+    }
+}
+
+/// The phases `LoxOptions::timings` times, in the order they run
+const PHASES: [&str; 2] = ["compile", "execute"];
+
+const DEBUG_HELP: &str = "commands: [s]tep (or empty), [u]ntil <line>, [c]ontinue, [p]rint, [q]uit";
+
+/// Runs scripts and the REPL as the `lox` binary does, with a fresh VM for each
+#[derive(Default)]
+pub struct Lox {
+    stack_size: Option<usize>,
+    strict_globals: bool,
+    /// print `VM::stats` after a script runs, or after each REPL line
+    stats: bool,
+    budget: Option<u64>,
+    trace: bool,
+    /// see `VM::deterministic`
+    deterministic: bool,
+    /// what errors in a script call it, `<script>` by default
+    script_name: Option<String>,
+    output: Option<SharedWriter>,
+    trace_output: Option<SharedWriter>,
+    /// every source compiled, which errors' spans index
+    sources: RefCell<SourceMap>,
+    /// how long compiling and running scripts has taken, when they're timed
+    timings: Option<RefCell<Timings>>,
+}
+
+/// A writer every VM a `Lox` creates can share
+#[derive(Clone)]
+struct SharedWriter(Rc<RefCell<Box<dyn Write>>>);
+
+impl Write for SharedWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.0.borrow_mut().flush()
+    }
+}
+
+/// Configures a `Lox`, e.g.
+/// `Lox::builder().trace(true).max_steps(1_000_000).output(sink).build()`
+#[derive(Default)]
+pub struct LoxOptions(Lox);
+
+impl LoxOptions {
+    /// See `VM::trace_execution` and `VM::print_code`
+    pub fn trace(mut self, trace: bool) -> Self {
+        self.0.trace = trace;
+        self
+    }
+
+    /// See `VM::with_budget`
+    pub fn max_steps(mut self, instructions: u64) -> Self {
+        self.0.budget = Some(instructions);
+        self
+    }
+
+    /// See `VM::with_stack_size`
+    pub fn stack_size(mut self, slots: usize) -> Self {
+        self.0.stack_size = Some(slots);
+        self
+    }
+
+    /// See `VM::strict_globals`
+    pub fn strict_globals(mut self, strict: bool) -> Self {
+        self.0.strict_globals = strict;
+        self
+    }
+
+    /// See `VM::deterministic`
+    pub fn deterministic(mut self, deterministic: bool) -> Self {
+        self.0.deterministic = deterministic;
+        self
+    }
+
+    /// Print the VM's stats to stderr after a script runs, or after each
+    /// REPL line
+    pub fn stats(mut self, stats: bool) -> Self {
+        self.0.stats = stats;
+        self
+    }
+
+    /// Time compiling and running scripts, for `Lox::timings`
+    pub fn timings(mut self, enabled: bool) -> Self {
+        self.0.timings = enabled.then(|| RefCell::new(Timings::new(&PHASES)));
+        self
+    }
+
+    /// See `VM::with_output`
+    pub fn output(mut self, out: Box<dyn Write>) -> Self {
+        self.0.output = Some(SharedWriter(Rc::new(RefCell::new(out))));
+        self
+    }
+
+    /// See `VM::with_trace_output`
+    pub fn trace_output(mut self, out: Box<dyn Write>) -> Self {
+        self.0.trace_output = Some(SharedWriter(Rc::new(RefCell::new(out))));
+        self
+    }
+
+    pub fn build(self) -> Lox {
+        self.0
+    }
+}
 
 impl Lox {
-    pub fn run(file: String) -> Result<(), Error> {
-        VM::new().interpret(&file)
+    pub fn builder() -> LoxOptions {
+        LoxOptions::default()
+    }
+
+    /// See `VM::with_stack_size`
+    pub fn with_stack_size(mut self, slots: usize) -> Self {
+        self.stack_size = Some(slots);
+        self
+    }
+
+    /// See `VM::strict_globals`
+    pub fn with_strict_globals(mut self) -> Self {
+        self.strict_globals = true;
+        self
+    }
+
+    /// See `VM::with_budget`
+    pub fn with_budget(mut self, instructions: u64) -> Self {
+        self.budget = Some(instructions);
+        self
+    }
+
+    /// Print the VM's stats to stderr after a script runs, or after each
+    /// REPL line
+    pub fn with_stats(mut self) -> Self {
+        self.stats = true;
+        self
+    }
+
+    /// See `VM::deterministic`
+    pub fn with_deterministic(mut self) -> Self {
+        self.deterministic = true;
+        self
+    }
+
+    pub fn is_deterministic(&self) -> bool {
+        self.deterministic
+    }
+
+    /// Time compiling and running scripts, for `timings`
+    pub fn with_timings(mut self) -> Self {
+        self.timings = Some(RefCell::new(Timings::new(&PHASES)));
+        self
+    }
+
+    /// How long compiling and running scripts has taken so far, if they're
+    /// timed
+    pub fn timings(&self) -> Option<Timings> {
+        self.timings
+            .as_ref()
+            .map(|timings| timings.borrow().clone())
+    }
+
+    /// Name scripts `name` in errors, like their path or `<stdin>`
+    pub fn with_script_name(mut self, name: impl Into<String>) -> Self {
+        self.script_name = Some(name.into());
+        self
+    }
+
+    fn vm(&self) -> VM {
+        let mut vm = match self.stack_size {
+            Some(slots) => VM::new().with_stack_size(slots),
+            None => VM::new(),
+        };
+        if let Some(instructions) = self.budget {
+            vm = vm.with_budget(instructions);
+        }
+        if self.deterministic {
+            vm = vm.deterministic();
+        }
+        if let Some(out) = &self.output {
+            vm = vm.with_output(Box::new(out.clone()));
+        }
+        if let Some(out) = &self.trace_output {
+            vm = vm.with_trace_output(Box::new(out.clone()));
+        }
+        vm.strict_globals = self.strict_globals;
+        vm.trace_execution = self.trace;
+        vm.print_code = self.trace;
+        vm
+    }
+
+    /// A VM whose compiles say they came from `source`, added under `name`
+    fn vm_for(&self, name: String, source: &str) -> VM {
+        let mut vm = self.vm();
+        vm.set_file(self.sources.borrow_mut().add(name, source));
+        vm
+    }
+
+    /// `vm_for` a whole script
+    fn vm_for_script(&self, source: &str) -> VM {
+        let name = self.script_name.as_deref().unwrap_or("<script>");
+        self.vm_for(name.to_string(), source)
+    }
+
+    /// The sources compiled so far, for rendering errors with `Error::render`
+    pub fn sources(&self) -> Ref<'_, SourceMap> {
+        self.sources.borrow()
+    }
+
+    /// Compiles a script, rendering any warnings to stderr
+    fn compile_script(&self, vm: &mut VM, file: &str) -> Result<Chunk, Error> {
+        let chunk = vm.compile(file)?;
+        for warning in warnings(vm) {
+            eprintln!("{}", warning.render(&self.sources()));
+        }
+        Ok(chunk)
+    }
+
+    /// Finds every problem in a program that can be found without running
+    /// it; compile warnings come back with `Severity::Warning`
+    pub fn check(&self, source: &str) -> Vec<Diagnostic> {
+        let mut vm = self.vm_for_script(source);
+        match vm.compile(source) {
+            Ok(_) => warnings(&vm),
+            Err(e) => e.diagnostics(),
+        }
+    }
+
+    pub fn run(&self, file: String, trace: bool) -> Result<(), Error> {
+        let mut vm = self.vm_for_script(&file);
+        vm.trace_execution |= trace;
+        vm.print_code |= trace;
+        self.compile_and_run(&mut vm, &file)
     }
 
-    pub fn run_prompt() -> Result<(), Error> {
-        let mut vm = VM::new();
+    /// Runs a script, writing its output to `out` instead of stdout
+    pub fn run_source(&self, source: &str, out: Box<dyn Write>) -> Result<(), Error> {
+        let mut vm = self.vm_for_script(source).with_output(out);
+        self.compile_and_run(&mut vm, source)
+    }
+
+    /// Timing each, if `timings` is on
+    fn compile_and_run(&self, vm: &mut VM, file: &str) -> Result<(), Error> {
+        let timings = self.timings.as_ref();
+        let chunk = timed(timings, "compile", || self.compile_script(vm, file))?;
+        self.run_chunk(vm, chunk)
+    }
+
+    /// Prints the stats afterwards, even if it fails, if `stats` is on
+    fn run_chunk(&self, vm: &mut VM, chunk: Chunk) -> Result<(), Error> {
+        let result = timed(self.timings.as_ref(), "execute", || vm.run(chunk)).map(|_| ());
+        if self.stats {
+            eprintln!("{}", vm.stats());
+        }
+        result
+    }
+
+    /// Errors name the entry they're in as `<repl:N>`
+    pub fn run_prompt(&self, trace: bool) -> Result<(), Error> {
+        let mut vm = self.vm();
+        vm.trace_execution |= trace;
+        vm.print_code |= trace;
+        let mut number = 1;
+        let mut result = Ok(());
+        repl::run(|entry| {
+            let file = self
+                .sources
+                .borrow_mut()
+                .add(format!("<repl:{number}>"), entry);
+            vm.set_file(file);
+            match self.run_line(&mut vm, entry) {
+                Ok(ReplOutcome::Done) => {
+                    number += 1;
+                    if self.stats {
+                        eprintln!("{}", vm.stats());
+                    }
+                    ReplOutcome::Done
+                }
+                Ok(outcome) => outcome,
+                // only writing output fails the session
+                Err(e) => {
+                    result = Err(e);
+                    ReplOutcome::Quit
+                }
+            }
+        })?;
+        result
+    }
+
+    /// Runs a script, then prints how often each opcode ran and for how long
+    pub fn run_profiled(&self, file: String) -> Result<(), Error> {
+        let mut vm = self.vm_for_script(&file);
+        let profile = vm.profile();
+        let chunk = self.compile_script(&mut vm, &file)?;
+        let result = vm.run(chunk).map(|_| ());
+        eprint!("{}", profile.borrow());
+        result
+    }
+
+    /// Runs a script saved by `compile`
+    pub fn run_loxc(&self, bytes: &[u8], trace: bool) -> Result<(), Error> {
+        let mut vm = self.vm();
+        vm.trace_execution |= trace;
+        vm.print_code |= trace;
+        let chunk = vm.load(bytes)?;
+        self.run_chunk(&mut vm, chunk)
+    }
+
+    /// Compiles a script and saves it to `out` as a `.loxc` file
+    pub fn compile(&self, file: String, out: &std::path::Path) -> Result<(), Error> {
+        let chunk = self.compile_script(&mut self.vm_for_script(&file), &file)?;
+        Ok(std::fs::write(out, chunk.to_loxc())?)
+    }
+
+    /// Compiles a script and prints its bytecode instead of running it
+    pub fn disassemble(&self, file: String) -> Result<(), Error> {
+        let chunk = self.compile_script(&mut self.vm_for_script(&file), &file)?;
+        chunk.dissassemble_to(&mut std::io::stdout(), "script")?;
+        Ok(())
+    }
+
+    /// Runs a script under a small prompt that steps through its bytecode
+    pub fn debug(&self, file: String) -> Result<(), Error> {
+        let mut vm = self.vm_for_script(&file);
+        let mut debugger = vm.debug(&file)?;
+        println!("{DEBUG_HELP}");
         loop {
-            print!(">");
-            std::io::stdout().flush().map_err(|_| Error::Io)?;
+            print!("[line {}]> ", debugger.position());
+            std::io::stdout().flush()?;
             let mut line = String::new();
-            if std::io::stdin()
-                .read_line(&mut line)
-                .map_err(|_| Error::Io)?
-                > 0
-            {
-                // lines need to be leaked because global variables persist
-                let line = line.leak();
-                let res = vm.interpret(line);
-                if let Err(e) = res {
-                    println!("Error: {}", e);
+            if std::io::stdin().read_line(&mut line)? == 0 {
+                return Ok(());
+            }
+            let mut words = line.split_whitespace();
+            let info = match (words.next(), words.next().map(str::parse::<usize>)) {
+                (None | Some("s" | "step"), None) => debugger.step()?,
+                (Some("u" | "until"), Some(Ok(line))) => debugger.run_until_line(line)?,
+                (Some("c" | "continue"), None) => {
+                    debugger.continue_run()?;
+                    return Ok(());
+                }
+                (Some("p" | "print"), None) => {
+                    println!("stack: {}", ValueVec(debugger.stack()));
+                    for (name, value) in debugger.globals() {
+                        println!("{name} = {value}");
+                    }
+                    continue;
                 }
+                (Some("q" | "quit"), None) => return Ok(()),
+                _ => {
+                    println!("{DEBUG_HELP}");
+                    continue;
+                }
+            };
+            println!("{}", info.instruction);
+            println!("          {}", ValueVec(&info.stack));
+            if info.finished {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Runs a REPL line, echoing its value to the VM's output
+    fn run_line(&self, vm: &mut VM, line: &str) -> Result<ReplOutcome, Error> {
+        match vm.interpret_line(line) {
+            Ok(Some(value)) => writeln!(vm.output(), "{}", value)?,
+            Ok(None) => {}
+            Err(Error::Compiler(errors))
+                if errors.first().is_some_and(CompileError::is_unfinished) =>
+            {
+                return Ok(ReplOutcome::Incomplete);
             }
+            Err(e) => eprintln!("{}", e.render(&self.sources())),
+        }
+        Ok(ReplOutcome::Done)
+    }
+}
+
+/// The latest compile's warnings as diagnostics
+fn warnings(vm: &VM) -> Vec<Diagnostic> {
+    vm.warnings()
+        .iter()
+        .map(|warning| {
+            Diagnostic::warning(
+                Phase::Compile,
+                warning.code(),
+                warning.message.clone(),
+                Some(warning.span()),
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// A writer tests can read back after handing it to a VM
+    #[derive(Clone, Default)]
+    pub(crate) struct SharedBuf(Rc<RefCell<Vec<u8>>>);
+
+    impl SharedBuf {
+        pub(crate) fn contents(&self) -> String {
+            String::from_utf8(self.0.borrow().clone()).unwrap()
+        }
+    }
+
+    impl Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().write(buf)
         }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn output(source: &str) -> String {
+        let buf = SharedBuf::default();
+        let mut vm = VM::new().with_output(Box::new(buf.clone()));
+        vm.interpret(source).unwrap();
+        buf.contents()
+    }
+
+    #[test]
+    fn test_run_source() {
+        let buf = SharedBuf::default();
+        let result = Lox::default().run_source("print 1;\nprint -nil;", Box::new(buf.clone()));
+        assert!(matches!(result, Err(Error::Runtime(_))));
+        assert_eq!(buf.contents(), "1\n");
+    }
+
+    #[test]
+    fn test_check() {
+        let lox = Lox::default();
+        assert_eq!(lox.check("var a = 1; print a;"), []);
+        let diagnostics = lox.check("print missing;");
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].severity, Severity::Warning);
+        assert_eq!(
+            diagnostics[0].message,
+            "Global 'missing' is used but never defined."
+        );
+        // each check adds its source to `sources`
+        assert_eq!(
+            diagnostics[0].span,
+            Some(Span::of(1, 7, "missing").in_file(1))
+        );
+        assert_eq!(lox.sources().name(1), "<script>");
+        let diagnostics = lox.check("print (1;\nprint 2");
+        assert!(diagnostics.len() == 2, "{diagnostics:?}");
+        assert!(diagnostics.iter().all(|d| d.severity == Severity::Error));
+        // strict globals make the warning an error
+        let strict = Lox::builder().strict_globals(true).build();
+        let diagnostics = strict.check("print missing;");
+        assert_eq!(diagnostics[0].severity, Severity::Error);
+        assert_eq!(
+            diagnostics[0].message,
+            "Global 'missing' is used but never defined."
+        );
+    }
+
+    #[test]
+    fn test_builder() {
+        let out = SharedBuf::default();
+        let lox = Lox::builder().output(Box::new(out.clone())).build();
+        lox.run("print 1;".to_string(), false).unwrap();
+        // every run shares the sink
+        lox.run("print 2;".to_string(), false).unwrap();
+        assert_eq!(out.contents(), "1\n2\n");
+
+        let lox = Lox::builder().max_steps(100).build();
+        let error = lox.run("while (true) {}".to_string(), false).unwrap_err();
+        assert!(error.to_string().contains("budget"), "{error}");
+
+        let lox = Lox::builder().stack_size(4).build();
+        let source = "fun f(n) { return f(n + 1); } f(0);";
+        assert!(matches!(
+            lox.run(source.to_string(), false),
+            Err(Error::Runtime(_))
+        ));
+
+        let lox = Lox::builder().strict_globals(true).build();
+        assert!(matches!(
+            lox.run("print missing;".to_string(), false),
+            Err(Error::Compiler(_))
+        ));
+
+        let (out, trace) = (SharedBuf::default(), SharedBuf::default());
+        let lox = Lox::builder()
+            .trace(true)
+            .output(Box::new(out.clone()))
+            .trace_output(Box::new(trace.clone()))
+            .build();
+        lox.run("print 3;".to_string(), false).unwrap();
+        assert_eq!(out.contents(), "3\n");
+        assert!(
+            trace.contents().contains("OP_PRINT"),
+            "{}",
+            trace.contents()
+        );
+        // the default keeps tracing off
+        let trace = SharedBuf::default();
+        let lox = Lox::builder()
+            .output(Box::new(SharedBuf::default()))
+            .trace_output(Box::new(trace.clone()))
+            .build();
+        lox.run("print 3;".to_string(), false).unwrap();
+        assert_eq!(trace.contents(), "");
+    }
+
+    #[test]
+    fn test_print_output() {
+        // integral numbers print without a fraction, others as short as
+        // they can while still reading back the same
+        assert_eq!(
+            output("print 1;\nprint 2.5;\nprint -0;\nprint 3 - 5;"),
+            "1\n2.5\n-0\n-2\n"
+        );
+        assert_eq!(output("print 1 / 3;"), "0.3333333333333333\n");
+        assert_eq!(output("print 0.1 + 0.2;"), "0.30000000000000004\n");
+        assert_eq!(
+            output("print 0 / 0;\nprint 1 / 0;\nprint -1 / 0;"),
+            "NaN\ninf\n-inf\n"
+        );
+        // past i64 and f64's exact integers, still no exponent or fraction
+        assert_eq!(
+            output("print 1111111111111111111111;"),
+            "1111111111111111100000\n"
+        );
+        assert_eq!(
+            output("print 123456789012.5;\nprint 0.000001;"),
+            "123456789012.5\n0.000001\n"
+        );
+        assert_eq!(
+            output("print \"a\" + 1;\nprint nil;\nprint 1 < 2;"),
+            "a1\nnil\ntrue\n"
+        );
+        assert_eq!(
+            output("fun f() {}\nprint f;\nprint clock;"),
+            "<fn f>\n<native fn>\n"
+        );
+        assert_eq!(output("print \"a\nb\";\nprint 1;"), "a\nb\n1\n");
+        // output before a runtime error is kept; the error itself is returned
+        let buf = SharedBuf::default();
+        let mut vm = VM::new().with_output(Box::new(buf.clone()));
+        assert!(vm.interpret("print 1;\nprint -nil;\nprint 2;").is_err());
+        assert_eq!(buf.contents(), "1\n");
+    }
+
+    #[test]
+    fn test_repl_prints_expression_value() {
+        let lox = Lox::default();
+        let buf = SharedBuf::default();
+        let mut vm = VM::new().with_output(Box::new(buf.clone()));
+        lox.run_line(&mut vm, "1 + 2").unwrap();
+        assert_eq!(buf.contents(), "3\n");
+
+        lox.run_line(&mut vm, "var a = 3;").unwrap();
+        lox.run_line(&mut vm, "print a;").unwrap();
+        assert_eq!(buf.contents(), "3\n3\n");
+
+        // assignments run quietly, with or without a semicolon
+        for line in ["a = 4", "a = 5;", "var b; b = a = 6"] {
+            lox.run_line(&mut vm, line).unwrap();
+        }
+        lox.run_line(&mut vm, "(a = 7)").unwrap();
+        lox.run_line(&mut vm, "a + b").unwrap();
+        assert_eq!(buf.contents(), "3\n3\n7\n13\n");
+    }
+
+    #[test]
+    fn test_repl_and_script_agree() {
+        let lox = Lox::default();
+        let source =
+            "var a = 1;\nprint a;\na = a + 1;\nfun f(x) { x; return x * 2; }\nprint f(a);\na + 1;";
+        let script = SharedBuf::default();
+        let mut vm = VM::new().with_output(Box::new(script.clone()));
+        assert_eq!(vm.interpret(source).unwrap(), None);
+        let line = SharedBuf::default();
+        let mut repl = VM::new().with_output(Box::new(line.clone()));
+        lox.run_line(&mut repl, source).unwrap();
+        // only the REPL shows the trailing expression
+        assert_eq!(script.contents(), "1\n4\n");
+        assert_eq!(line.contents(), "1\n4\n3\n");
+        assert_eq!(
+            vm.interpret_line("a").unwrap(),
+            repl.interpret_line("a").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_repl_lines_need_not_outlive_session() {
+        let lox = Lox::default();
+        let buf = SharedBuf::default();
+        let mut vm = VM::new().with_output(Box::new(buf.clone()));
+        for i in 0..5000 {
+            // each line is dropped at the end of the iteration
+            let line = format!("var x{} = \"v{}\";", i % 10, i);
+            lox.run_line(&mut vm, &line).unwrap();
+        }
+        lox.run_line(&mut vm, &String::from("x3")).unwrap();
+        assert_eq!(buf.contents(), "v4993\n");
+    }
+
+    fn runtime_error(source: &str) -> RuntimeError {
+        match Lox::default().run(source.to_string(), false) {
+            Err(Error::Runtime(error)) => error,
+            other => panic!("expected a runtime error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_runtime_errors() {
+        let error = runtime_error("var s = \"a\";\nprint -s;");
+        assert!(error.message.contains("must be a number"));
+        assert_eq!(error.line, 2);
+
+        let error = runtime_error("var a = 1;\n\nprint a + nil;");
+        assert!(error.message.contains("'+'"), "{}", error.message);
+        assert!(error.message.contains("nil"), "{}", error.message);
+        assert_eq!((error.line, error.column), (3, 9));
+
+        let error = runtime_error("print 1 < nil;");
+        assert_eq!(
+            error.message,
+            "Operands must be two numbers or two strings; got number and nil."
+        );
+        assert_eq!(error.line, 1);
+
+        let error = runtime_error("\nprint missing;");
+        assert!(error.message.contains("Undefined variable missing"));
+        assert_eq!(error.line, 2);
+
+        let error = runtime_error("fun f() {\n  undefined = 1;\n}\nf();");
+        assert_eq!(error.line, 2);
+        assert_eq!(error.column, 3);
+        assert_eq!(error.trace, ["[line 2:3] in f()", "[line 4:2] in script"]);
+
+        assert!(matches!(
+            Lox::default().run("print (".to_string(), false),
+            Err(Error::Compiler(_))
+        ));
+    }
+
+    #[test]
+    fn test_error_rendering() {
+        let error = Lox::default()
+            .run("var = 1;\nprint 2;\nprint ;".to_string(), false)
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "[line 1:5] after '=': Expected variable name.\n\
+             [line 3:7] after ';': Expected expression\n\
+             Compilation failed, see errors above."
+        );
+        // Debug renders the same, so `main` returning an error prints all of it
+        assert_eq!(format!("{error:?}"), error.to_string());
+
+        let error = Lox::default()
+            .run("print -nil;".to_string(), false)
+            .unwrap_err();
+        assert!(
+            error.to_string().starts_with("Operand must be a number."),
+            "{error}"
+        );
+
+        let error: Error = std::io::Error::new(std::io::ErrorKind::NotFound, "no script").into();
+        assert_eq!(error.to_string(), "no script");
+        assert_eq!(error.clone().to_string(), "no script");
     }
 }
@@ -1,18 +1,154 @@
 use bytecode::{Error, Lox};
-use std::fs::read_to_string;
+use cli::{Cli, Flag, Script};
+use std::{
+    fs::{read, read_to_string},
+    io::{IsTerminal, stdin},
+};
 
-pub fn main() -> Result<(), Error> {
-    let args: Vec<String> = std::env::args().collect();
+const CLI: Cli = Cli {
+    name: env!("CARGO_PKG_NAME"),
+    version: env!("CARGO_PKG_VERSION"),
+    about: "Compiles a Lox script to bytecode and runs it",
+    flags: &[
+        Flag::switch(
+            "--trace",
+            "Print each instruction as it runs; or set LOX_TRACE",
+        ),
+        Flag::option("--stack-size", "<slots>", "How many values the stack holds"),
+        Flag::switch(
+            "--strict-globals",
+            "Make reading an undefined global a compile error",
+        ),
+        Flag::switch("--stats", "Print instruction, stack and string counts"),
+        Flag::switch(
+            "--deterministic",
+            "Make `clock()` count calls and `random()` the same each run",
+        ),
+        Flag::option(
+            "--max-instructions",
+            "<n>",
+            "Stop the script after this many instructions",
+        ),
+        Flag::switch("--timings", "Print how long compiling and running took"),
+        Flag::switch("--debug", "Step through the script"),
+        Flag::switch(
+            "--profile",
+            "Print how often each opcode ran and for how long",
+        ),
+        Flag::switch("--disassemble", "Print the bytecode instead of running it").short("-d"),
+        Flag::switch(
+            "--compile",
+            "Save the bytecode next to the script as a .loxc file",
+        ),
+        Flag::switch("--repl", "Start the REPL even when stdin isn't a terminal"),
+    ],
+    script_args: false,
+    many_scripts: false,
+    without_script: "starts a REPL",
+};
 
-    #[allow(clippy::comparison_chain)]
-    if args.len() > 2 {
-        println!("Usage: {} [script]", args[0]);
-        std::process::exit(64);
-    } else if args.len() == 2 {
-        let contents = read_to_string(&args[1]).map_err(|_| Error::Io)?;
-        // because lexemes are stored as &static str to reduce allocations, leak the contents
-        Lox::run(contents)
-    } else {
-        Lox::run_prompt()
+pub fn main() {
+    let args = CLI.args();
+    let trace = args.flag("--trace") || std::env::var_os("LOX_TRACE").is_some();
+    let (debug, profile) = (args.flag("--debug"), args.flag("--profile"));
+    let (disassemble, compile) = (args.flag("--disassemble"), args.flag("--compile"));
+    let mut lox = match args.parse("--stack-size") {
+        Ok(None) => Lox::default(),
+        Ok(Some(slots)) => Lox::default().with_stack_size(slots),
+        Err(e) => CLI.usage_error(e),
+    };
+    if args.flag("--strict-globals") {
+        lox = lox.with_strict_globals();
+    }
+    if args.flag("--stats") {
+        lox = lox.with_stats();
+    }
+    if args.flag("--deterministic") {
+        lox = lox.with_deterministic();
+    }
+    if args.flag("--timings") {
+        lox = lox.with_timings();
+    }
+    match args.parse("--max-instructions") {
+        Ok(None) => {}
+        Ok(Some(instructions)) => lox = lox.with_budget(instructions),
+        Err(e) => CLI.usage_error(e),
+    }
+
+    let modes = [debug, profile, disassemble, compile];
+    if modes.into_iter().filter(|&mode| mode).count() > 1 {
+        CLI.usage_error(
+            "`--debug`, `--profile`, `--disassemble` and `--compile` can't be combined",
+        );
+    }
+    if args.flag("--timings") && (modes.contains(&true) || args.flag("--repl")) {
+        CLI.usage_error("`--timings` only times running a script");
+    }
+    if args.flag("--repl") {
+        if args.script().is_some() || modes.contains(&true) {
+            CLI.usage_error("`--repl` can't be given a script or another mode");
+        }
+        return exit_on_error(&lox, lox.run_prompt(trace));
+    }
+    // a program piped in without `-` is still a program, not REPL input
+    let script = match args.script() {
+        Some(script) => script.clone(),
+        None if !stdin().is_terminal() => Script::Stdin,
+        None if modes.contains(&true) => {
+            CLI.usage_error("`--debug`, `--profile`, `--disassemble` and `--compile` need a script")
+        }
+        None => return exit_on_error(&lox, lox.run_prompt(trace)),
+    };
+    let lox = lox.with_script_name(match &script {
+        Script::Path(path) => path.display().to_string(),
+        Script::Stdin => "<stdin>".to_string(),
+    });
+    // compiled scripts can only be run
+    let loxc =
+        matches!(&script, Script::Path(path) if path.extension().is_some_and(|ext| ext == "loxc"));
+    if loxc && modes.contains(&true) {
+        CLI.usage_error("a compiled .loxc script can only be run");
+    }
+    let result = match &script {
+        Script::Path(path) if loxc => read(path)
+            .map_err(Error::from)
+            .and_then(|bytes| lox.run_loxc(&bytes, trace)),
+        Script::Path(path) if compile => read_to_string(path)
+            .map_err(Error::from)
+            .and_then(|contents| lox.compile(contents, &path.with_extension("loxc"))),
+        Script::Stdin if compile => {
+            CLI.usage_error("`--compile` needs a script file to save next to")
+        }
+        Script::Stdin if debug => {
+            CLI.usage_error("`--debug` reads its commands from stdin, so needs a script file")
+        }
+        _ => {
+            let contents = match &script {
+                Script::Path(path) => read_to_string(path),
+                Script::Stdin => std::io::read_to_string(stdin()),
+            };
+            contents.map_err(Error::from).and_then(|contents| {
+                if disassemble {
+                    lox.disassemble(contents)
+                } else if debug {
+                    lox.debug(contents)
+                } else if profile {
+                    lox.run_profiled(contents)
+                } else {
+                    lox.run(contents, trace)
+                }
+            })
+        }
+    };
+    if let Some(timings) = lox.timings() {
+        eprintln!("{timings}");
+    }
+    exit_on_error(&lox, result);
+}
+
+fn exit_on_error(lox: &Lox, result: Result<(), Error>) {
+    if let Err(error) = result {
+        eprintln!("{}", error.render(&lox.sources()));
+        std::process::exit(error.exit_code());
     }
 }
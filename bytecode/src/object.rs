@@ -0,0 +1,86 @@
+use std::{
+    cell::{Cell, RefCell},
+    fmt::Display,
+    rc::Rc,
+};
+
+use random::Random;
+
+use crate::{Chunk, Value};
+
+/// A compiled Lox function; the top-level script is one with no name
+pub struct Function {
+    pub(crate) name: Option<Rc<str>>,
+    pub(crate) arity: usize,
+    pub(crate) chunk: Chunk,
+}
+
+impl Function {
+    pub(crate) fn script(chunk: Chunk) -> Self {
+        Self {
+            name: None,
+            arity: 0,
+            chunk,
+        }
+    }
+}
+
+impl Display for Function {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.name {
+            Some(name) => write!(f, "<fn {}>", name),
+            None => write!(f, "<script>"),
+        }
+    }
+}
+
+impl std::fmt::Debug for Function {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+pub type NativeFn = Box<dyn Fn(&[Value]) -> Value>;
+
+/// A function implemented in Rust and exposed to Lox as a global
+pub struct Native {
+    pub(crate) name: &'static str,
+    pub(crate) arity: usize,
+    pub(crate) function: NativeFn,
+}
+
+impl Display for Native {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<native fn>")
+    }
+}
+
+impl std::fmt::Debug for Native {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<native fn {}>", self.name)
+    }
+}
+
+/// Seconds since the Unix epoch
+pub(crate) fn clock_native(_args: &[Value]) -> Value {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default();
+    Value::Number(now.as_secs_f64())
+}
+
+/// `clock()` in a deterministic VM, which counts milliseconds by calls
+/// instead: 0.001 for the first, 0.002 for the next, and so on
+pub(crate) fn counting_clock() -> NativeFn {
+    let calls = Cell::new(0u64);
+    Box::new(move |_| {
+        calls.set(calls.get() + 1);
+        Value::Number(calls.get() as f64 / 1000.0)
+    })
+}
+
+/// `random()`, from 0 up to but not including 1
+pub(crate) fn random_native(seed: u64) -> NativeFn {
+    let random = RefCell::new(Random::new(seed));
+    Box::new(move |_| Value::Number(random.borrow_mut().next_f64()))
+}
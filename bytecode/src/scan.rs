@@ -1,19 +1,25 @@
 use std::fmt::Display;
 
-#[derive(Clone, Copy, PartialEq, Eq)]
+use crate::chunk::Position;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum TokenType {
     // Single-character tokens.
     LeftParen,
     RightParen,
     LeftBrace,
     RightBrace,
+    LeftBracket,
+    RightBracket,
     Comma,
+    Colon,
     Dot,
     Minus,
     Plus,
     Semicolon,
     Slash,
     Star,
+    Percent,
     // One or two character tokens.
     Bang,
     BangEqual,
@@ -29,7 +35,12 @@ pub enum TokenType {
     Number,
     // Keywords.
     And,
+    Assert,
+    Break,
+    Case,
     Class,
+    Continue,
+    Default,
     Else,
     False,
     For,
@@ -40,6 +51,7 @@ pub enum TokenType {
     Print,
     Return,
     Super,
+    Switch,
     This,
     True,
     Var,
@@ -56,13 +68,17 @@ impl Display for TokenType {
             TokenType::RightParen => write!(f, "RightParen"),
             TokenType::LeftBrace => write!(f, "LeftBrace"),
             TokenType::RightBrace => write!(f, "RightBrace"),
+            TokenType::LeftBracket => write!(f, "LeftBracket"),
+            TokenType::RightBracket => write!(f, "RightBracket"),
             TokenType::Comma => write!(f, "Comma"),
+            TokenType::Colon => write!(f, "Colon"),
             TokenType::Dot => write!(f, "Dot"),
             TokenType::Minus => write!(f, "Minus"),
             TokenType::Plus => write!(f, "Plus"),
             TokenType::Semicolon => write!(f, "Semicolon"),
             TokenType::Slash => write!(f, "Slash"),
             TokenType::Star => write!(f, "Star"),
+            TokenType::Percent => write!(f, "Percent"),
             TokenType::Bang => write!(f, "Bang"),
             TokenType::BangEqual => write!(f, "Bangequal"),
             TokenType::Equal => write!(f, "Equal"),
@@ -75,7 +91,12 @@ impl Display for TokenType {
             TokenType::String => write!(f, "String"),
             TokenType::Number => write!(f, "Number"),
             TokenType::And => write!(f, "And"),
+            TokenType::Assert => write!(f, "Assert"),
+            TokenType::Break => write!(f, "Break"),
+            TokenType::Case => write!(f, "Case"),
             TokenType::Class => write!(f, "Class"),
+            TokenType::Continue => write!(f, "Continue"),
+            TokenType::Default => write!(f, "Default"),
             TokenType::Else => write!(f, "Else"),
             TokenType::False => write!(f, "False"),
             TokenType::For => write!(f, "For"),
@@ -86,6 +107,7 @@ impl Display for TokenType {
             TokenType::Print => write!(f, "Print"),
             TokenType::Return => write!(f, "Return"),
             TokenType::Super => write!(f, "Super"),
+            TokenType::Switch => write!(f, "Switch"),
             TokenType::This => write!(f, "This"),
             TokenType::True => write!(f, "True"),
             TokenType::Var => write!(f, "Var"),
@@ -96,21 +118,7 @@ impl Display for TokenType {
     }
 }
 
-impl TokenType {
-    pub fn precendence(&self) -> Precedence {
-        match self {
-            Self::Minus | Self::Plus => Precedence::Term,
-            Self::Slash | Self::Star => Precedence::Factor,
-            Self::BangEqual | Self::EqualEqual => Precedence::Equality,
-            Self::Greater | Self::Less | Self::GreaterEqual | Self::LessEqual => {
-                Precedence::Comparison
-            }
-            _ => Precedence::None,
-        }
-    }
-}
-
-#[derive(PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub enum Precedence {
     None,
     Assignment, // =
@@ -119,9 +127,9 @@ pub enum Precedence {
     Equality,   // == !=
     Comparison, // < > <= >=
     Term,       // + -
-    Factor,     // * /
+    Factor,     // * / %
     Unary,      // ! -
-    Call,       // . ()
+    Call,       // . () []
     Primary,
 }
 
@@ -152,6 +160,10 @@ pub struct Token<'a> {
     pub(crate) ttype: TokenType,
     pub(crate) lexeme: &'a str,
     pub(crate) line: usize,
+    /// 1-based character column of the token's first character
+    pub(crate) column: usize,
+    /// index of the source in the program's `SourceMap`
+    pub(crate) file: usize,
 }
 
 impl Token<'_> {
@@ -160,6 +172,15 @@ impl Token<'_> {
             ttype: TokenType::Error,
             lexeme: "",
             line: 0,
+            column: 0,
+            file: 0,
+        }
+    }
+
+    pub(crate) fn position(&self) -> Position {
+        Position {
+            line: self.line,
+            column: self.column,
         }
     }
 }
@@ -169,17 +190,24 @@ macro_rules! token {
         Token {
             ttype: $ttype,
             lexeme: str::from_utf8(&$self.source[$self.start..$self.current]).unwrap(),
-            line: $self.line,
+            line: $self.start_line,
+            column: $self.column,
+            file: $self.file,
         }
     };
 }
 
+/// Error token message; the compiler adds the line the string started on
+pub(crate) const UNTERMINATED_STRING: &str = "Unterminated string";
+
 macro_rules! error_token {
     ($self:ident, $message:expr) => {
         Token {
             ttype: TokenType::Error,
             lexeme: $message,
-            line: $self.line,
+            line: $self.start_line,
+            column: $self.column,
+            file: $self.file,
         }
     };
 }
@@ -189,21 +217,50 @@ pub struct Scanner<'a> {
     start: usize,
     current: usize,
     line: usize,
+    /// line the token being scanned starts on, for tokens spanning lines
+    start_line: usize,
+    /// offset of the first byte on the current line
+    line_start: usize,
+    /// `column` is the column at byte offset `column_offset`; kept between
+    /// tokens so long lines aren't recounted from the start for every token
+    column_offset: usize,
+    column: usize,
+    /// the EoF token has been yielded, ending iteration
+    finished: bool,
+    file: usize,
 }
 
 impl<'a> Scanner<'a> {
     pub(crate) fn new(source: &'a str) -> Self {
+        Self::for_file(source, 0)
+    }
+
+    /// A scanner whose tokens say they came from `file`
+    pub(crate) fn for_file(source: &'a str, file: usize) -> Self {
         Self {
             source: source.as_bytes(),
             start: 0,
             current: 0,
             line: 1,
+            start_line: 1,
+            line_start: 0,
+            column_offset: 0,
+            column: 1,
+            finished: false,
+            file,
         }
     }
 
+    /// Every token in `source`, errors included, ending with EoF
+    pub(crate) fn tokenize(source: &'a str) -> Vec<Token<'a>> {
+        Scanner::new(source).collect()
+    }
+
     pub(crate) fn scan_token<'b>(&'b mut self) -> Token<'a> {
         self.skip_whitespace();
         self.start = self.current;
+        self.start_line = self.line;
+        self.advance_column(self.start);
         if self.is_at_end() {
             return token!(self, TokenType::EoF);
         }
@@ -213,13 +270,17 @@ impl<'a> Scanner<'a> {
             b')' => token!(self, TokenType::RightParen),
             b'{' => token!(self, TokenType::LeftBrace),
             b'}' => token!(self, TokenType::RightBrace),
+            b'[' => token!(self, TokenType::LeftBracket),
+            b']' => token!(self, TokenType::RightBracket),
             b';' => token!(self, TokenType::Semicolon),
             b',' => token!(self, TokenType::Comma),
+            b':' => token!(self, TokenType::Colon),
             b'.' => token!(self, TokenType::Dot),
             b'-' => token!(self, TokenType::Minus),
             b'+' => token!(self, TokenType::Plus),
             b'/' => token!(self, TokenType::Slash),
             b'*' => token!(self, TokenType::Star),
+            b'%' => token!(self, TokenType::Percent),
             b'!' => {
                 if self.match_advance(b'=') {
                     token!(self, TokenType::BangEqual)
@@ -278,11 +339,15 @@ impl<'a> Scanner<'a> {
     }
 
     fn peek(&self) -> u8 {
-        self.source[self.current]
+        if self.is_at_end() {
+            b'\0'
+        } else {
+            self.source[self.current]
+        }
     }
 
     fn peek_next(&self) -> u8 {
-        if self.is_at_end() {
+        if self.current + 1 >= self.source.len() {
             b'\0'
         } else {
             self.source[self.current + 1]
@@ -291,20 +356,39 @@ impl<'a> Scanner<'a> {
 
     fn string<'b>(&'b mut self) -> Token<'a> {
         while self.peek() != b'"' && !self.is_at_end() {
-            if self.peek() == b'\n' {
-                self.current += 1;
+            if *self.advance() == b'\n' {
+                self.new_line();
             }
-            self.advance();
         }
 
         if self.is_at_end() {
-            error_token!(self, "Unterminated string")
+            error_token!(self, UNTERMINATED_STRING)
         } else {
             self.advance();
             token!(self, TokenType::String)
         }
     }
 
+    /// Moves the column count up to byte `offset`
+    fn advance_column(&mut self, offset: usize) {
+        if self.column_offset < self.line_start {
+            self.column_offset = self.line_start;
+            self.column = 1;
+        }
+        // count characters rather than bytes by skipping UTF-8 continuation bytes
+        self.column += self.source[self.column_offset..offset]
+            .iter()
+            .filter(|b| **b & 0xC0 != 0x80)
+            .count();
+        self.column_offset = offset;
+    }
+
+    /// Call after consuming a '\n'
+    fn new_line(&mut self) {
+        self.line += 1;
+        self.line_start = self.current;
+    }
+
     fn skip_whitespace(&mut self) {
         loop {
             if self.is_at_end() {
@@ -315,16 +399,12 @@ impl<'a> Scanner<'a> {
                     self.advance();
                 }
                 b'\n' => {
-                    self.line += 1;
                     self.advance();
+                    self.new_line();
                 }
-                b'/' => {
-                    if self.peek_next() == b'/' {
-                        while self.peek() != b'\n' && !self.is_at_end() {
-                            self.advance();
-                        }
-                    } else {
-                        break;
+                b'/' if self.peek_next() == b'/' => {
+                    while self.peek() != b'\n' && !self.is_at_end() {
+                        self.advance();
                     }
                 }
                 _ => {
@@ -338,8 +418,8 @@ impl<'a> Scanner<'a> {
         while self.peek().is_ascii_digit() {
             self.advance();
         }
-        // fraction
-        if self.peek() == b'.' {
+        // fraction - a trailing '.' is left for the next token
+        if self.peek() == b'.' && self.peek_next().is_ascii_digit() {
             self.advance();
             while self.peek().is_ascii_digit() {
                 self.advance();
@@ -358,15 +438,48 @@ impl<'a> Scanner<'a> {
             }
         }
         match self.source[self.start] {
-            b'a' => self.keyword_if_match(1, 2, "nd", TokenType::And),
-            b'c' => self.keyword_if_match(1, 4, "lass", TokenType::Class),
+            b'a' => {
+                if self.current - self.start > 1 {
+                    match self.source[self.start + 1] {
+                        b'n' => self.keyword_if_match(2, 1, "d", TokenType::And),
+                        b's' => self.keyword_if_match(2, 4, "sert", TokenType::Assert),
+                        _ => token!(self, TokenType::Identifier),
+                    }
+                } else {
+                    token!(self, TokenType::Identifier)
+                }
+            }
+            b'b' => self.keyword_if_match(1, 4, "reak", TokenType::Break),
+            b'c' => {
+                if self.current - self.start > 1 {
+                    match self.source[self.start + 1] {
+                        b'a' => self.keyword_if_match(2, 2, "se", TokenType::Case),
+                        b'l' => self.keyword_if_match(2, 3, "ass", TokenType::Class),
+                        b'o' => self.keyword_if_match(2, 6, "ntinue", TokenType::Continue),
+                        _ => token!(self, TokenType::Identifier),
+                    }
+                } else {
+                    token!(self, TokenType::Identifier)
+                }
+            }
+            b'd' => self.keyword_if_match(1, 6, "efault", TokenType::Default),
             b'e' => self.keyword_if_match(1, 3, "lse", TokenType::Else),
             b'i' => self.keyword_if_match(1, 1, "f", TokenType::If),
             b'n' => self.keyword_if_match(1, 2, "il", TokenType::Nil),
             b'o' => self.keyword_if_match(1, 1, "r", TokenType::Or),
             b'p' => self.keyword_if_match(1, 4, "rint", TokenType::Print),
             b'r' => self.keyword_if_match(1, 5, "eturn", TokenType::Return),
-            b's' => self.keyword_if_match(1, 4, "uper", TokenType::Super),
+            b's' => {
+                if self.current - self.start > 1 {
+                    match self.source[self.start + 1] {
+                        b'u' => self.keyword_if_match(2, 3, "per", TokenType::Super),
+                        b'w' => self.keyword_if_match(2, 4, "itch", TokenType::Switch),
+                        _ => token!(self, TokenType::Identifier),
+                    }
+                } else {
+                    token!(self, TokenType::Identifier)
+                }
+            }
             b'v' => self.keyword_if_match(1, 2, "ar", TokenType::Var),
             b'w' => self.keyword_if_match(1, 4, "hile", TokenType::While),
             b'f' => {
@@ -384,8 +497,8 @@ impl<'a> Scanner<'a> {
             b't' => {
                 if self.current - self.start > 1 {
                     match self.source[self.start + 1] {
-                        b'h' => self.keyword_if_match(2, 3, "is", TokenType::This),
-                        b'r' => self.keyword_if_match(2, 1, "ue", TokenType::True),
+                        b'h' => self.keyword_if_match(2, 2, "is", TokenType::This),
+                        b'r' => self.keyword_if_match(2, 2, "ue", TokenType::True),
                         _ => token!(self, TokenType::Identifier),
                     }
                 } else {
@@ -412,3 +525,289 @@ impl<'a> Scanner<'a> {
         }
     }
 }
+
+/// Yields tokens up to and including EoF; error tokens don't stop it
+impl<'a> Iterator for Scanner<'a> {
+    type Item = Token<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+        let token = self.scan_token();
+        self.finished = token.ttype == TokenType::EoF;
+        Some(token)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn scan_types(source: &str) -> Vec<TokenType> {
+        Scanner::tokenize(source).iter().map(|t| t.ttype).collect()
+    }
+
+    fn scan_lexemes(source: &str) -> Vec<&str> {
+        Scanner::tokenize(source).iter().map(|t| t.lexeme).collect()
+    }
+
+    fn scan_positions(source: &str) -> Vec<(usize, usize)> {
+        Scanner::tokenize(source)
+            .iter()
+            .filter(|t| t.ttype != TokenType::EoF)
+            .map(|t| (t.line, t.column))
+            .collect()
+    }
+
+    #[test]
+    fn test_scanner_iterator() {
+        let mut scanner = Scanner::new("var x = 5;");
+        let types: Vec<_> = scanner.by_ref().map(|t| t.ttype).collect();
+        assert_eq!(
+            types,
+            [
+                TokenType::Var,
+                TokenType::Identifier,
+                TokenType::Equal,
+                TokenType::Number,
+                TokenType::Semicolon,
+                TokenType::EoF,
+            ]
+        );
+        // iteration stays over, though scan_token would keep returning EoF
+        assert!(scanner.next().is_none());
+        assert_eq!(scan_types(""), [TokenType::EoF]);
+    }
+
+    #[test]
+    fn test_scanner_numbers() {
+        assert_eq!(
+            scan_lexemes("5 5.5 0.25 12."),
+            ["5", "5.5", "0.25", "12", ".", ""]
+        );
+        assert_eq!(
+            scan_types("-3"),
+            [TokenType::Minus, TokenType::Number, TokenType::EoF]
+        );
+    }
+
+    #[test]
+    fn test_scanner_strings() {
+        assert_eq!(
+            scan_lexemes("var x = \"hello world\";"),
+            ["var", "x", "=", "\"hello world\"", ";", ""]
+        );
+        assert_eq!(scan_types("\"\""), [TokenType::String, TokenType::EoF]);
+    }
+
+    #[test]
+    fn test_scanner_keywords() {
+        let keywords = [
+            ("and", TokenType::And),
+            ("assert", TokenType::Assert),
+            ("break", TokenType::Break),
+            ("case", TokenType::Case),
+            ("class", TokenType::Class),
+            ("continue", TokenType::Continue),
+            ("default", TokenType::Default),
+            ("else", TokenType::Else),
+            ("false", TokenType::False),
+            ("for", TokenType::For),
+            ("fun", TokenType::Fun),
+            ("if", TokenType::If),
+            ("nil", TokenType::Nil),
+            ("or", TokenType::Or),
+            ("print", TokenType::Print),
+            ("return", TokenType::Return),
+            ("super", TokenType::Super),
+            ("switch", TokenType::Switch),
+            ("this", TokenType::This),
+            ("true", TokenType::True),
+            ("var", TokenType::Var),
+            ("while", TokenType::While),
+        ];
+        for (keyword, ttype) in keywords {
+            assert_eq!(scan_types(keyword), [ttype, TokenType::EoF], "{keyword}");
+            // a prefix or an extension of a keyword is just a name
+            for name in [&keyword[..keyword.len() - 1], &format!("{keyword}s")] {
+                assert_eq!(
+                    scan_types(name),
+                    [TokenType::Identifier, TokenType::EoF],
+                    "{name}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_scanner_identifiers() {
+        assert_eq!(
+            scan_lexemes("_a a_1 A9 truth"),
+            ["_a", "a_1", "A9", "truth", ""]
+        );
+        assert!(
+            scan_types("_a a_1 A9 truth")[..4]
+                .iter()
+                .all(|t| *t == TokenType::Identifier)
+        );
+    }
+
+    #[test]
+    fn test_scanner_comments() {
+        assert_eq!(scan_lexemes("a // b c\nd // e"), ["a", "d", ""]);
+        assert_eq!(scan_positions("// one\n// two\n  x"), [(3, 3)]);
+    }
+
+    #[test]
+    fn test_scanner_errors_inline() {
+        let tokens = Scanner::tokenize("a @ b \"open");
+        let types: Vec<_> = tokens.iter().map(|t| t.ttype).collect();
+        assert_eq!(
+            types,
+            [
+                TokenType::Identifier,
+                TokenType::Error,
+                TokenType::Identifier,
+                TokenType::Error,
+                TokenType::EoF,
+            ]
+        );
+        assert_eq!(tokens[1].lexeme, "Unexpected character");
+        assert_eq!(tokens[3].lexeme, UNTERMINATED_STRING);
+    }
+
+    #[test]
+    fn test_scanner_columns() {
+        assert_eq!(
+            scan_positions("var ab = 1;\n  print ab;"),
+            vec![
+                (1, 1),
+                (1, 5),
+                (1, 8),
+                (1, 10),
+                (1, 11),
+                (2, 3),
+                (2, 9),
+                (2, 11)
+            ]
+        );
+        // columns count characters, not bytes
+        assert_eq!(scan_positions("\"é\" + x"), vec![(1, 1), (1, 5), (1, 7)]);
+        // a multi-line string is at its opening quote; columns restart after it
+        assert_eq!(
+            scan_positions("x = \"a\nbc\" + y;\n z"),
+            vec![(1, 1), (1, 3), (1, 5), (2, 5), (2, 7), (2, 8), (3, 2)]
+        );
+    }
+
+    #[test]
+    fn test_scanner_multiline_string_lines() {
+        // an unterminated string is reported where it opened, not at EOF
+        let mut scanner = Scanner::new("print 1;\nprint \"abc\n\n\nprint 2;\n");
+        let token = scanner.find(|t| t.ttype == TokenType::Error).unwrap();
+        assert_eq!((token.line, token.column), (2, 7));
+        assert_eq!(scanner.next().unwrap().line, 6);
+
+        // newlines inside a string count toward later tokens' lines
+        let lines: Vec<usize> = scan_positions("\"a\nb\nc\"\nx\ny")
+            .into_iter()
+            .map(|(line, _)| line)
+            .collect();
+        assert_eq!(lines, [1, 4, 5]);
+    }
+
+    #[test]
+    fn test_scanner_string_with_newline() {
+        let mut scanner = Scanner::new("\"one\ntwo\" x");
+        let string = scanner.scan_token();
+        assert_eq!(string.ttype, TokenType::String);
+        // every character is kept, quotes included
+        assert_eq!(string.lexeme, "\"one\ntwo\"");
+        assert_eq!(string.line, 1);
+        let next = scanner.scan_token();
+        assert_eq!((next.lexeme, next.line, next.column), ("x", 2, 6));
+        assert_eq!(scanner.scan_token().ttype, TokenType::EoF);
+    }
+
+    #[test]
+    fn test_scanner_end_of_input() {
+        assert_eq!(scan_types("5"), vec![TokenType::Number, TokenType::EoF]);
+        assert_eq!(
+            scan_types("5."),
+            vec![TokenType::Number, TokenType::Dot, TokenType::EoF]
+        );
+        assert_eq!(scan_types("5.5"), vec![TokenType::Number, TokenType::EoF]);
+        assert_eq!(scan_types("\"abc"), vec![TokenType::Error, TokenType::EoF]);
+        assert_eq!(scan_types("/"), vec![TokenType::Slash, TokenType::EoF]);
+        assert_eq!(scan_types("// comment"), vec![TokenType::EoF]);
+        assert_eq!(
+            scan_types("abc"),
+            vec![TokenType::Identifier, TokenType::EoF]
+        );
+    }
+
+    #[test]
+    fn test_scanner_brackets() {
+        assert_eq!(
+            scan_types("s[0]"),
+            vec![
+                TokenType::Identifier,
+                TokenType::LeftBracket,
+                TokenType::Number,
+                TokenType::RightBracket,
+                TokenType::EoF,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_scanner_operators() {
+        assert_eq!(
+            scan_types("! != = == < <= > >="),
+            vec![
+                TokenType::Bang,
+                TokenType::BangEqual,
+                TokenType::Equal,
+                TokenType::EqualEqual,
+                TokenType::Less,
+                TokenType::LessEqual,
+                TokenType::Greater,
+                TokenType::GreaterEqual,
+                TokenType::EoF,
+            ]
+        );
+        assert_eq!(
+            scan_types("!=!<=<>=> ==="),
+            vec![
+                TokenType::BangEqual,
+                TokenType::Bang,
+                TokenType::LessEqual,
+                TokenType::Less,
+                TokenType::GreaterEqual,
+                TokenType::Greater,
+                TokenType::EqualEqual,
+                TokenType::Equal,
+                TokenType::EoF,
+            ]
+        );
+        assert_eq!(
+            scan_types("(){};,.-+/*%"),
+            vec![
+                TokenType::LeftParen,
+                TokenType::RightParen,
+                TokenType::LeftBrace,
+                TokenType::RightBrace,
+                TokenType::Semicolon,
+                TokenType::Comma,
+                TokenType::Dot,
+                TokenType::Minus,
+                TokenType::Plus,
+                TokenType::Slash,
+                TokenType::Star,
+                TokenType::Percent,
+                TokenType::EoF,
+            ]
+        );
+    }
+}
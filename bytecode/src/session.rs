@@ -0,0 +1,56 @@
+use std::{collections::HashSet, rc::Rc};
+
+use crate::globals::Globals;
+
+/// Compiler state that outlives a single `interpret` call, so REPL lines
+/// share strings and global slots instead of starting over. The globals'
+/// values ride along for the VM.
+#[derive(Default)]
+pub(crate) struct CompilerSession {
+    pub(crate) globals: Globals,
+    /// string constants, global names and function names seen so far
+    strings: HashSet<Rc<str>>,
+    /// index in the program's `SourceMap` of the source being compiled
+    pub(crate) file: usize,
+}
+
+impl CompilerSession {
+    /// Returns the session's copy of `s`, adding it the first time
+    pub(crate) fn intern(&mut self, s: &str) -> Rc<str> {
+        if let Some(interned) = self.strings.get(s) {
+            return interned.clone();
+        }
+        let interned: Rc<str> = s.into();
+        self.strings.insert(interned.clone());
+        interned
+    }
+
+    /// The slot for global `name`, assigning one if it's new
+    pub(crate) fn resolve_global(&mut self, name: &str) -> usize {
+        if let Some(slot) = self.globals.get_slot(name) {
+            return slot;
+        }
+        let name = self.intern(name);
+        self.globals.resolve(name)
+    }
+
+    pub(crate) fn strings(&self) -> impl Iterator<Item = &str> {
+        self.strings.iter().map(|s| s.as_ref())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_intern() {
+        let mut session = CompilerSession::default();
+        let a = session.intern("x");
+        let b = session.intern("x");
+        assert!(Rc::ptr_eq(&a, &b));
+        let slot = session.resolve_global("x");
+        assert_eq!(session.resolve_global("x"), slot);
+        assert_eq!(session.strings().count(), 1);
+    }
+}
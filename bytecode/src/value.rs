@@ -1,114 +1,428 @@
-use std::{fmt::Display, rc::Rc};
+use std::fmt::Display;
+#[cfg(not(feature = "nan_boxing"))]
+use std::rc::Rc;
 
+#[cfg(not(feature = "nan_boxing"))]
+use crate::object::{Function, Native};
+
+/// Fails an arithmetic operation whose operands aren't numbers, with the
+/// message the VM reports
 macro_rules! non_number {
-    ($op:expr, $self:ident, $other:ident) => {
-        panic!(
-            "{} called on non-Number Values {} <> {}",
-            $op, $self, $other
-        )
+    ($self:ident, $other:ident) => {
+        return Err("Operands must be numbers.".to_string())
+    };
+    ($self:ident) => {
+        return Err("Operand must be a number.".to_string())
+    };
+}
+
+/// Fails a comparison between anything but two numbers or two strings
+macro_rules! incomparable {
+    ($self:ident, $other:ident) => {
+        return Err(format!(
+            "Operands must be two numbers or two strings; got {} and {}.",
+            $self.type_name(),
+            $other.type_name()
+        ))
     };
-    ($op:expr, $self:ident) => {
-        panic!("{} called on non-Number Value {}", $op, $self)
+}
+
+/// Fails `+` on operands that are neither two numbers nor involve a string
+macro_rules! non_addable {
+    ($self:ident, $other:ident) => {
+        return Err(format!(
+            "Operands to '+' must be two numbers or involve a string; got {} and {}",
+            $self.type_name(),
+            $other.type_name()
+        ))
     };
 }
 
-#[derive(Debug, Clone, PartialEq)]
-pub enum Value<'a> {
+#[cfg(feature = "nan_boxing")]
+mod nan_boxed;
+#[cfg(feature = "nan_boxing")]
+pub use nan_boxed::Value;
+
+#[cfg(not(feature = "nan_boxing"))]
+#[derive(Debug, Clone, Default)]
+pub enum Value {
     Number(f64),
     Bool(bool),
-    ConstString(&'a str), // points to source code
-    String(Rc<String>),   // Rc instead of Garbage collector
+    String(Rc<str>), // Rc instead of Garbage collector
+    Function(Rc<Function>),
+    Native(Rc<Native>),
+    #[default]
     Nil,
 }
 
-impl<'a> Value<'a> {
-    pub fn negate(&self) -> Self {
+#[cfg(not(feature = "nan_boxing"))]
+impl Value {
+    pub fn negate(&self) -> Result<Self, String> {
         match self {
-            Value::Number(x) => Value::Number(-x),
-            _ => non_number!("Negate", self),
+            Value::Number(x) => Ok(Value::Number(-x)),
+            _ => non_number!(self),
         }
     }
 
-    pub fn add(&self, other: &Self) -> Self {
-        match (self, other) {
+    pub fn add(&self, other: &Self) -> Result<Self, String> {
+        Ok(match (self, other) {
             (Self::Number(a), Self::Number(b)) => Self::Number(a + b),
-            (Self::String(a), b) => Self::String(Rc::new(format!("{}{}", a, b))),
-            (a, Self::String(b)) => Self::String(Rc::new(format!("{}{}", a, b))),
-            (Self::ConstString(a), b) => Self::String(Rc::new(format!("{}{}", a, b))),
-            (a, Self::ConstString(b)) => Self::String(Rc::new(format!("{}{}", a, b))),
-            _ => non_number!("Add", self, other),
+            (Self::String(a), Self::String(b)) => Self::String(concat(a, b)),
+            (Self::String(a), b) => Self::String(concat(a, &b.to_string())),
+            (a, Self::String(b)) => Self::String(concat(&a.to_string(), b)),
+            _ => non_addable!(self, other),
+        })
+    }
+
+    pub fn subtract(&self, other: &Self) -> Result<Self, String> {
+        match (self, other) {
+            (Self::Number(a), Self::Number(b)) => Ok(Self::Number(a - b)),
+            _ => non_number!(self, other),
         }
     }
 
-    pub fn subtract(&self, other: &Self) -> Self {
+    pub fn multiply(&self, other: &Self) -> Result<Self, String> {
         match (self, other) {
-            (Self::Number(a), Self::Number(b)) => Self::Number(a - b),
-            _ => non_number!("Subtract", self, other),
+            (Self::Number(a), Self::Number(b)) => Ok(Self::Number(a * b)),
+            _ => non_number!(self, other),
         }
     }
 
-    pub fn multiply(&self, other: &Self) -> Self {
+    pub fn divide(&self, other: &Self) -> Result<Self, String> {
         match (self, other) {
-            (Self::Number(a), Self::Number(b)) => Self::Number(a * b),
-            _ => non_number!("Multiply", self, other),
+            (Self::Number(a), Self::Number(b)) => Ok(Self::Number(a / b)),
+            _ => non_number!(self, other),
         }
     }
 
-    pub fn divide(&self, other: &Self) -> Self {
+    pub fn modulo(&self, other: &Self) -> Result<Self, String> {
         match (self, other) {
-            (Self::Number(a), Self::Number(b)) => Self::Number(a / b),
-            _ => non_number!("Divide", self, other),
+            (Self::Number(a), Self::Number(b)) => Ok(Self::Number(a % b)),
+            _ => non_number!(self, other),
         }
     }
 
-    pub fn greater(&self, other: &Self) -> Self {
+    pub fn greater(&self, other: &Self) -> Result<Self, String> {
         match (self, other) {
-            (Self::Number(a), Self::Number(b)) => Self::Bool(a > b),
-            _ => non_number!("Greater", self, other),
+            (Self::Number(a), Self::Number(b)) => Ok(Self::Bool(a > b)),
+            (Self::String(a), Self::String(b)) => Ok(Self::Bool(a > b)),
+            _ => incomparable!(self, other),
         }
     }
 
-    pub fn less(&self, other: &Self) -> Self {
+    pub fn less(&self, other: &Self) -> Result<Self, String> {
         match (self, other) {
-            (Self::Number(a), Self::Number(b)) => Self::Bool(a < b),
-            _ => non_number!("Less", self, other),
+            (Self::Number(a), Self::Number(b)) => Ok(Self::Bool(a < b)),
+            (Self::String(a), Self::String(b)) => Ok(Self::Bool(a < b)),
+            _ => incomparable!(self, other),
         }
     }
 
-    pub fn is_truthy(&self) -> bool {
-        matches!(self, Value::Bool(false) | Value::Nil)
+    pub fn greater_equal(&self, other: &Self) -> Result<Self, String> {
+        match (self, other) {
+            (Self::Number(a), Self::Number(b)) => Ok(Self::Bool(a >= b)),
+            (Self::String(a), Self::String(b)) => Ok(Self::Bool(a >= b)),
+            _ => incomparable!(self, other),
+        }
+    }
+
+    pub fn less_equal(&self, other: &Self) -> Result<Self, String> {
+        match (self, other) {
+            (Self::Number(a), Self::Number(b)) => Ok(Self::Bool(a <= b)),
+            (Self::String(a), Self::String(b)) => Ok(Self::Bool(a <= b)),
+            _ => incomparable!(self, other),
+        }
     }
 
-    pub(crate) fn as_str(&self) -> &'a str {
+    pub fn is_number(&self) -> bool {
+        matches!(self, Value::Number(_))
+    }
+
+    pub fn as_number(&self) -> Option<f64> {
+        match self {
+            Value::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    pub fn is_string(&self) -> bool {
+        matches!(self, Value::String(_))
+    }
+
+    pub fn as_string(&self) -> Option<Rc<str>> {
         match self {
-            Self::ConstString(s) => s,
-            _ => panic!("AsString called on non-String Value {}", self),
+            Value::String(s) => Some(s.clone()),
+            _ => None,
         }
     }
+
+    pub fn as_function(&self) -> Option<Rc<Function>> {
+        match self {
+            Value::Function(f) => Some(f.clone()),
+            _ => None,
+        }
+    }
+
+    pub fn as_native(&self) -> Option<Rc<Native>> {
+        match self {
+            Value::Native(n) => Some(n.clone()),
+            _ => None,
+        }
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Number(_) => "number",
+            Value::Bool(_) => "bool",
+            Value::String(_) => "string",
+            Value::Function(_) | Value::Native(_) => "function",
+            Value::Nil => "nil",
+        }
+    }
+
+    pub fn is_truthy(&self) -> bool {
+        !matches!(self, Value::Bool(false) | Value::Nil)
+    }
+}
+
+/// Joins two strings; an `Rc<str>` can't grow in place, so the result is
+/// built in a buffer sized for both and copied into its `Rc` once
+#[cfg(not(feature = "nan_boxing"))]
+fn concat(a: &str, b: &str) -> Rc<str> {
+    let mut joined = String::with_capacity(a.len() + b.len());
+    joined.push_str(a);
+    joined.push_str(b);
+    Rc::from(joined)
 }
 
-impl Default for Value<'_> {
-    fn default() -> Self {
-        Self::Nil
+#[cfg(not(feature = "nan_boxing"))]
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Number(a), Self::Number(b)) => a == b,
+            (Self::Bool(a), Self::Bool(b)) => a == b,
+            (Self::String(a), Self::String(b)) => a == b,
+            (Self::Function(a), Self::Function(b)) => Rc::ptr_eq(a, b),
+            (Self::Native(a), Self::Native(b)) => Rc::ptr_eq(a, b),
+            (Self::Nil, Self::Nil) => true,
+            _ => false,
+        }
     }
 }
 
-impl Display for Value<'_> {
+#[cfg(not(feature = "nan_boxing"))]
+impl From<&str> for Value {
+    fn from(s: &str) -> Self {
+        Self::String(s.into())
+    }
+}
+
+#[cfg(not(feature = "nan_boxing"))]
+impl From<Rc<str>> for Value {
+    fn from(s: Rc<str>) -> Self {
+        Self::String(s)
+    }
+}
+
+#[cfg(not(feature = "nan_boxing"))]
+impl Display for Value {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Value::Number(d) => write!(f, "{d:?}"),
-            Value::Nil => write!(f, "Nil"),
+            // integral values without a fraction, like the treewalk backend
+            Value::Number(d) => write!(f, "{d}"),
+            Value::Nil => write!(f, "nil"),
             Value::Bool(b) => write!(f, "{}", b),
-            Value::ConstString(s) => write!(f, "{}", *s),
             Value::String(s) => write!(f, "{}", *s),
+            Value::Function(function) => write!(f, "{}", function),
+            Value::Native(native) => write!(f, "{}", native),
         }
     }
 }
 
-pub(crate) struct ValueVec<'a>(pub &'a Vec<Value<'a>>);
+pub(crate) struct ValueVec<'a>(pub &'a [Value]);
 
 impl Display for ValueVec<'_> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        self.0.iter().try_for_each(|v| write!(f, "[{v}]"))
+        self.0
+            .iter()
+            .try_for_each(|v| write!(f, "[{}]", DebugValue(v)))
+    }
+}
+
+/// A value as disassembly and traces show it: numbers keep their fractional
+/// part, so the number 1 doesn't look like the string "1"
+pub(crate) struct DebugValue<'a>(pub &'a Value);
+
+impl Display for DebugValue<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.0.as_number() {
+            Some(n) => write!(f, "{n:?}"),
+            None => write!(f, "{}", self.0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_string_equality() {
+        let constant = Value::from("hi");
+        let concatenated = Value::from("h").add(&Value::from("i")).unwrap();
+        assert_eq!(constant, concatenated);
+        assert_eq!(concatenated, constant);
+        assert_ne!(Value::from("ho"), constant);
+        assert_ne!(constant, Value::from("ho"));
+        assert_ne!(constant, Value::Nil);
+        assert_ne!(Value::Number(1.0), Value::Bool(true));
+        assert_eq!(Value::Nil, Value::Nil);
+        assert_eq!(Value::Number(0.0), Value::Number(-0.0));
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(Value::Number(3.0).to_string(), "3");
+        assert_eq!(Value::Number(-0.5).to_string(), "-0.5");
+        assert_eq!(Value::Number(-0.0).to_string(), "-0");
+        assert_eq!(Value::Number(1e300).to_string().len(), 301);
+        assert_eq!(Value::Number(f64::INFINITY).to_string(), "inf");
+        assert_eq!(Value::Number(f64::NAN).to_string(), "NaN");
+        // traces and disassembly keep the fraction
+        assert_eq!(DebugValue(&Value::Number(3.0)).to_string(), "3.0");
+        assert_eq!(DebugValue(&Value::from("3")).to_string(), "3");
+        assert_eq!(Value::Bool(true).to_string(), "true");
+        assert_eq!(Value::Nil.to_string(), "nil");
+        assert_eq!(
+            Value::from("a")
+                .add(&Value::Number(1.0))
+                .unwrap()
+                .to_string(),
+            "a1"
+        );
+        assert_eq!(
+            Value::Nil.add(&Value::from("a")).unwrap().to_string(),
+            "nila"
+        );
+    }
+
+    #[test]
+    fn test_concat() {
+        let ab = Value::from("ab");
+        assert_eq!(ab.add(&Value::from("cd")).unwrap(), Value::from("abcd"));
+        assert_eq!(ab.add(&Value::from("")).unwrap(), ab);
+        assert_eq!(
+            Value::from("").add(&Value::from("")).unwrap(),
+            Value::from("")
+        );
+        assert_eq!(
+            Value::from("é").add(&Value::from("ü")).unwrap().to_string(),
+            "éü"
+        );
+        assert_eq!(ab.add(&Value::Number(2.5)).unwrap().to_string(), "ab2.5");
+        assert_eq!(Value::Number(-1.0).add(&ab).unwrap().to_string(), "-1ab");
+        assert_eq!(Value::Bool(false).add(&ab).unwrap().to_string(), "falseab");
+        // the operands are untouched
+        assert_eq!(ab.to_string(), "ab");
+    }
+
+    #[test]
+    fn test_comparisons() {
+        let (a, b) = (Value::from("apple"), Value::from("banana"));
+        assert_eq!(a.less(&b), Ok(Value::Bool(true)));
+        assert_eq!(a.less_equal(&a), Ok(Value::Bool(true)));
+        assert_eq!(a.greater(&b), Ok(Value::Bool(false)));
+        assert_eq!(b.greater_equal(&a), Ok(Value::Bool(true)));
+        // concatenated strings compare like constants
+        let app = Value::from("app").add(&Value::from("le")).unwrap();
+        assert_eq!(app.greater_equal(&a), Ok(Value::Bool(true)));
+        assert_eq!(
+            a.less(&Value::Number(1.0)),
+            Err("Operands must be two numbers or two strings; got string and number.".to_string())
+        );
+    }
+
+    #[test]
+    fn test_type_errors_dont_panic() {
+        let values = [
+            Value::Number(1.0),
+            Value::Bool(true),
+            Value::from("a"),
+            Value::Nil,
+        ];
+        let arithmetic = [
+            Value::subtract,
+            Value::multiply,
+            Value::divide,
+            Value::modulo,
+        ];
+        let comparisons = [
+            Value::greater,
+            Value::greater_equal,
+            Value::less,
+            Value::less_equal,
+        ];
+        for a in &values {
+            assert_eq!(a.negate().is_ok(), a.is_number());
+            for b in &values {
+                let numbers = a.is_number() && b.is_number();
+                let strings = a.is_string() && b.is_string();
+                for op in arithmetic {
+                    assert_eq!(op(a, b).is_ok(), numbers);
+                }
+                for op in comparisons {
+                    assert_eq!(op(a, b).is_ok(), numbers || strings);
+                }
+                let any_string = a.is_string() || b.is_string();
+                assert_eq!(a.add(b).is_ok(), numbers || any_string);
+            }
+        }
+        assert_eq!(
+            Value::Nil.add(&Value::Bool(true)),
+            Err(
+                "Operands to '+' must be two numbers or involve a string; got nil and bool"
+                    .to_string()
+            )
+        );
+        assert_eq!(
+            Value::from("a").negate(),
+            Err("Operand must be a number.".to_string())
+        );
+    }
+
+    #[test]
+    fn test_predicates() {
+        let values = [
+            Value::Number(0.0),
+            Value::Number(f64::NAN),
+            Value::Bool(true),
+            Value::Bool(false),
+            Value::from(""),
+            Value::Nil,
+        ];
+        let truthy: Vec<bool> = values.iter().map(Value::is_truthy).collect();
+        assert_eq!(truthy, [true, true, true, false, true, false]);
+        let types: Vec<&str> = values.iter().map(Value::type_name).collect();
+        assert_eq!(types, ["number", "number", "bool", "bool", "string", "nil"]);
+        assert_eq!(Value::Number(2.0).as_number(), Some(2.0));
+        assert_eq!(Value::Bool(true).as_number(), None);
+        assert!(Value::from("x").is_string());
+        assert!(!Value::Nil.is_number());
+        assert_eq!(Value::default(), Value::Nil);
+    }
+
+    #[test]
+    fn test_modulo() {
+        assert_eq!(
+            Value::Number(10.0).modulo(&Value::Number(3.0)),
+            Ok(Value::Number(1.0))
+        );
+        assert_eq!(
+            Value::Number(7.5).modulo(&Value::Number(2.0)),
+            Ok(Value::Number(1.5))
+        );
+        let n = Value::Number(4.0).modulo(&Value::Number(0.0)).unwrap();
+        assert!(n.as_number().is_some_and(f64::is_nan));
+        assert_ne!(n, n);
     }
 }
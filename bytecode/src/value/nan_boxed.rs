@@ -0,0 +1,330 @@
+//! A `Value` packed into the bits of an f64. Numbers are stored as-is; nil,
+//! the booleans, strings, and functions (as indices into per-thread tables)
+//! live in the payload of a quiet NaN.
+
+use std::{cell::RefCell, collections::HashMap, fmt::Display, rc::Rc};
+
+use crate::object::{Function, Native};
+
+const QNAN: u64 = 0x7ffc_0000_0000_0000;
+const SIGN_BIT: u64 = 0x8000_0000_0000_0000;
+
+const TAG_NIL: u64 = 1;
+const TAG_FALSE: u64 = 2;
+const TAG_TRUE: u64 = 3;
+
+const NIL: u64 = QNAN | TAG_NIL;
+const FALSE: u64 = QNAN | TAG_FALSE;
+const TRUE: u64 = QNAN | TAG_TRUE;
+const STRING: u64 = SIGN_BIT | QNAN;
+const OBJECT_BIT: u64 = 1 << 49;
+const OBJECT: u64 = STRING | OBJECT_BIT;
+
+/// Interned strings, so equal strings share an index and compare by bits.
+/// Nothing is ever freed until there is a garbage collector.
+#[derive(Default)]
+struct Strings {
+    indices: HashMap<Rc<str>, u64>,
+    strings: Vec<Rc<str>>,
+}
+
+thread_local! {
+    static STRINGS: RefCell<Strings> = RefCell::default();
+}
+
+fn intern(s: &str) -> u64 {
+    STRINGS.with_borrow_mut(|table| {
+        if let Some(index) = table.indices.get(s) {
+            return *index;
+        }
+        let s: Rc<str> = s.into();
+        let index = table.strings.len() as u64;
+        table.indices.insert(s.clone(), index);
+        table.strings.push(s);
+        index
+    })
+}
+
+fn lookup(index: u64) -> Rc<str> {
+    STRINGS.with_borrow(|table| table.strings[index as usize].clone())
+}
+
+#[derive(Clone)]
+enum Object {
+    Function(Rc<Function>),
+    Native(Rc<Native>),
+}
+
+thread_local! {
+    /// Heap objects by index; like strings, never freed
+    static OBJECTS: RefCell<Vec<Object>> = RefCell::default();
+}
+
+fn allocate(object: Object) -> u64 {
+    OBJECTS.with_borrow_mut(|objects| {
+        objects.push(object);
+        (objects.len() - 1) as u64
+    })
+}
+
+// not Copy, so code written against the enum representation compiles unchanged
+#[derive(Clone)]
+pub struct Value(u64);
+
+#[allow(non_snake_case, non_upper_case_globals)]
+impl Value {
+    pub const Nil: Self = Self(NIL);
+
+    pub fn Number(n: f64) -> Self {
+        Self(n.to_bits())
+    }
+
+    pub fn Bool(b: bool) -> Self {
+        if b { Self(TRUE) } else { Self(FALSE) }
+    }
+
+    pub fn Function(function: Rc<Function>) -> Self {
+        Self(OBJECT | allocate(Object::Function(function)))
+    }
+
+    pub fn Native(native: Rc<Native>) -> Self {
+        Self(OBJECT | allocate(Object::Native(native)))
+    }
+}
+
+impl Value {
+    pub fn negate(&self) -> Result<Self, String> {
+        match self.as_number() {
+            Some(x) => Ok(Self::Number(-x)),
+            None => non_number!(self),
+        }
+    }
+
+    fn numbers(&self, other: &Self) -> Option<(f64, f64)> {
+        Some((self.as_number()?, other.as_number()?))
+    }
+
+    pub fn add(&self, other: &Self) -> Result<Self, String> {
+        if let Some((a, b)) = self.numbers(other) {
+            return Ok(Self::Number(a + b));
+        }
+        if !self.is_string() && !other.is_string() {
+            non_addable!(self, other);
+        }
+        let text = |v: &Self| v.as_string().unwrap_or_else(|| v.to_string().into());
+        // concat sizes the buffer once; the result is copied into the intern table
+        Ok(Self::from([text(self), text(other)].concat().as_str()))
+    }
+
+    pub fn subtract(&self, other: &Self) -> Result<Self, String> {
+        match self.numbers(other) {
+            Some((a, b)) => Ok(Self::Number(a - b)),
+            None => non_number!(self, other),
+        }
+    }
+
+    pub fn multiply(&self, other: &Self) -> Result<Self, String> {
+        match self.numbers(other) {
+            Some((a, b)) => Ok(Self::Number(a * b)),
+            None => non_number!(self, other),
+        }
+    }
+
+    pub fn divide(&self, other: &Self) -> Result<Self, String> {
+        match self.numbers(other) {
+            Some((a, b)) => Ok(Self::Number(a / b)),
+            None => non_number!(self, other),
+        }
+    }
+
+    pub fn modulo(&self, other: &Self) -> Result<Self, String> {
+        match self.numbers(other) {
+            Some((a, b)) => Ok(Self::Number(a % b)),
+            None => non_number!(self, other),
+        }
+    }
+
+    pub fn greater(&self, other: &Self) -> Result<Self, String> {
+        if let Some((a, b)) = self.numbers(other) {
+            return Ok(Self::Bool(a > b));
+        }
+        match (self.as_string(), other.as_string()) {
+            (Some(a), Some(b)) => Ok(Self::Bool(a > b)),
+            _ => incomparable!(self, other),
+        }
+    }
+
+    pub fn less(&self, other: &Self) -> Result<Self, String> {
+        if let Some((a, b)) = self.numbers(other) {
+            return Ok(Self::Bool(a < b));
+        }
+        match (self.as_string(), other.as_string()) {
+            (Some(a), Some(b)) => Ok(Self::Bool(a < b)),
+            _ => incomparable!(self, other),
+        }
+    }
+
+    pub fn greater_equal(&self, other: &Self) -> Result<Self, String> {
+        if let Some((a, b)) = self.numbers(other) {
+            return Ok(Self::Bool(a >= b));
+        }
+        match (self.as_string(), other.as_string()) {
+            (Some(a), Some(b)) => Ok(Self::Bool(a >= b)),
+            _ => incomparable!(self, other),
+        }
+    }
+
+    pub fn less_equal(&self, other: &Self) -> Result<Self, String> {
+        if let Some((a, b)) = self.numbers(other) {
+            return Ok(Self::Bool(a <= b));
+        }
+        match (self.as_string(), other.as_string()) {
+            (Some(a), Some(b)) => Ok(Self::Bool(a <= b)),
+            _ => incomparable!(self, other),
+        }
+    }
+
+    pub fn is_number(&self) -> bool {
+        self.0 & QNAN != QNAN
+    }
+
+    pub fn as_number(&self) -> Option<f64> {
+        self.is_number().then(|| f64::from_bits(self.0))
+    }
+
+    pub fn is_string(&self) -> bool {
+        self.0 & OBJECT == STRING
+    }
+
+    pub fn as_string(&self) -> Option<Rc<str>> {
+        self.is_string().then(|| lookup(self.0 & !STRING))
+    }
+
+    fn as_object(&self) -> Option<Object> {
+        if self.0 & OBJECT != OBJECT {
+            return None;
+        }
+        let index = (self.0 & !OBJECT) as usize;
+        OBJECTS.with_borrow(|objects| Some(objects[index].clone()))
+    }
+
+    pub fn as_function(&self) -> Option<Rc<Function>> {
+        match self.as_object()? {
+            Object::Function(f) => Some(f),
+            _ => None,
+        }
+    }
+
+    pub fn as_native(&self) -> Option<Rc<Native>> {
+        match self.as_object()? {
+            Object::Native(n) => Some(n),
+            _ => None,
+        }
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self.0 {
+            _ if self.is_number() => "number",
+            _ if self.is_string() => "string",
+            _ if self.0 & OBJECT == OBJECT => "function",
+            TRUE | FALSE => "bool",
+            _ => "nil",
+        }
+    }
+
+    pub fn is_truthy(&self) -> bool {
+        !matches!(self.0, FALSE | NIL)
+    }
+}
+
+impl Default for Value {
+    fn default() -> Self {
+        Self::Nil
+    }
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        match self.numbers(other) {
+            Some((a, b)) => a == b,
+            // strings are interned and objects allocated once per value,
+            // so everything else compares by bits
+            None => self.0 == other.0,
+        }
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Self {
+        Self(STRING | intern(s))
+    }
+}
+
+impl From<Rc<str>> for Value {
+    fn from(s: Rc<str>) -> Self {
+        Self::from(&*s)
+    }
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(d) = self.as_number() {
+            return write!(f, "{d}");
+        }
+        if let Some(s) = self.as_string() {
+            return write!(f, "{}", s);
+        }
+        match self.as_object() {
+            Some(Object::Function(function)) => return write!(f, "{}", function),
+            Some(Object::Native(native)) => return write!(f, "{}", native),
+            None => {}
+        }
+        match self.0 {
+            TRUE => write!(f, "true"),
+            FALSE => write!(f, "false"),
+            _ => write!(f, "nil"),
+        }
+    }
+}
+
+impl std::fmt::Debug for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(d) = self.as_number() {
+            return write!(f, "Number({d:?})");
+        }
+        if let Some(s) = self.as_string() {
+            return write!(f, "String({s:?})");
+        }
+        match self.as_object() {
+            Some(Object::Function(function)) => return write!(f, "Function({function:?})"),
+            Some(Object::Native(native)) => return write!(f, "Native({native:?})"),
+            None => {}
+        }
+        match self.0 {
+            TRUE => write!(f, "Bool(true)"),
+            FALSE => write!(f, "Bool(false)"),
+            _ => write!(f, "Nil"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_size() {
+        assert_eq!(std::mem::size_of::<Value>(), 8);
+    }
+
+    #[test]
+    fn test_nan_is_a_number() {
+        let nan = Value::Number(0.0).divide(&Value::Number(0.0)).unwrap();
+        assert!(nan.is_number());
+        assert!(Value::Number(-f64::NAN).is_number());
+        assert!(Value::Number(f64::INFINITY).is_number());
+        assert!(!Value::Nil.is_number());
+        assert!(!Value::Bool(false).is_number());
+        assert!(!Value::from("nan").is_number());
+    }
+}
@@ -1,221 +1,1623 @@
-use std::collections::HashMap;
+use std::{cell::RefCell, io::Write, rc::Rc};
 
-use crate::{Chunk, Error, OpCode, Value, chunk::long_index, compiler::Compiler, value::ValueVec};
+use random::{DETERMINISTIC_SEED, random_seed};
 
-static MAX_STACK: usize = 256;
+use crate::{
+    AsmError, Chunk, Error, OpCode, Value, assemble,
+    chunk::long_index,
+    compiler::{CompileError, Compiler},
+    globals::Globals,
+    object::{Function, Native, NativeFn, clock_native, counting_clock, random_native},
+    session::CompilerSession,
+};
 
-pub struct VM<'a> {
-    globals: HashMap<&'a str, Value<'a>>,
+mod debugger;
+mod hook;
+mod profile;
+mod stats;
+
+pub use debugger::{Debugger, StepInfo};
+use hook::HookFn;
+pub use hook::{InstructionEvent, InstructionHook, trace_hook};
+pub use profile::Profile;
+pub use stats::Stats;
+
+/// Value slots one call can use: a function addresses its locals and
+/// temporaries with a one-byte operand
+const FRAME_SLOTS: usize = 256;
+const DEFAULT_STACK_SIZE: usize = 64 * FRAME_SLOTS;
+
+pub struct VM {
+    /// global names and values, and strings shared by every compiled line
+    session: CompilerSession,
+    /// reused by every run, so a REPL line doesn't allocate a fresh stack
+    stack: Box<[Value]>,
+    /// Dump the stack and disassemble each instruction before it executes, via
+    /// `trace_hook` alongside any installed hook
+    pub trace_execution: bool,
+    /// Disassemble each chunk after it compiles successfully
+    pub print_code: bool,
+    /// Fail to compile a script (but not a REPL line) that uses a global it
+    /// never defines, instead of only warning
+    pub strict_globals: bool,
+    /// from the latest compile
+    warnings: Vec<CompileError>,
+    /// where `print` writes
+    out: Box<dyn Write>,
+    trace_out: Box<dyn Write>,
+    instruction_hook: Option<InstructionHook>,
+    stats: Stats,
+    /// instructions each run may execute
+    budget: Option<u64>,
 }
 
-impl Default for VM<'_> {
+impl Default for VM {
     fn default() -> Self {
         Self::new()
     }
 }
 
-impl<'a> VM<'a> {
+impl VM {
     pub fn new() -> Self {
-        Self {
-            globals: HashMap::new(),
+        let mut vm = Self {
+            session: CompilerSession::default(),
+            stack: vec![Value::Nil; DEFAULT_STACK_SIZE].into_boxed_slice(),
+            trace_execution: false,
+            print_code: false,
+            strict_globals: false,
+            warnings: Vec::new(),
+            out: Box::new(std::io::stdout()),
+            trace_out: Box::new(std::io::stdout()),
+            instruction_hook: None,
+            stats: Stats::default(),
+            budget: None,
+        };
+        vm.define_native("clock", 0, Box::new(clock_native));
+        vm.define_native("random", 0, random_native(random_seed()));
+        vm
+    }
+
+    fn define_native(&mut self, name: &'static str, arity: usize, function: NativeFn) {
+        let slot = self.session.resolve_global(name);
+        let native = Native {
+            name,
+            arity,
+            function,
+        };
+        self.session
+            .globals
+            .define(slot, Value::Native(Rc::new(native)));
+    }
+
+    /// Makes `clock()` go up a millisecond per call and `random()` start
+    /// from a fixed seed, so a program prints the same thing every run
+    pub fn deterministic(mut self) -> Self {
+        self.define_native("clock", 0, counting_clock());
+        self.define_native("random", 0, random_native(DETERMINISTIC_SEED));
+        self
+    }
+
+    /// Sizes the value stack to `slots` values instead of the default 16384.
+    /// Calls are limited to one frame per 256 slots (and at least the
+    /// script's own), so this bounds recursion depth as well as memory.
+    pub fn with_stack_size(mut self, slots: usize) -> Self {
+        self.stack = vec![Value::Nil; slots].into_boxed_slice();
+        self
+    }
+
+    /// Fails each run that executes more than `instructions` instructions,
+    /// so a script that never ends can't hang its host
+    pub fn with_budget(mut self, instructions: u64) -> Self {
+        self.budget = Some(instructions);
+        self
+    }
+
+    /// Send the program's `print` output to `out` instead of stdout
+    pub fn with_output(mut self, out: Box<dyn Write>) -> Self {
+        self.out = out;
+        self
+    }
+
+    pub fn output(&mut self) -> &mut dyn Write {
+        &mut self.out
+    }
+
+    /// Send trace and code dumps to `out` instead of stdout
+    pub fn with_trace_output(mut self, out: Box<dyn Write>) -> Self {
+        self.trace_out = out;
+        self
+    }
+
+    /// Calls `hook` before every instruction executed from now on
+    pub fn set_instruction_hook(&mut self, hook: InstructionHook) {
+        self.instruction_hook = Some(hook);
+    }
+
+    pub fn clear_instruction_hook(&mut self) {
+        self.instruction_hook = None;
+    }
+
+    /// Counts and times every instruction executed from now on, in place of
+    /// any installed hook
+    pub fn profile(&mut self) -> Rc<RefCell<Profile>> {
+        let profile = Rc::new(RefCell::new(Profile::default()));
+        self.set_instruction_hook(Profile::hook(profile.clone()));
+        profile
+    }
+
+    /// Counts since the VM was created or `reset_stats` was last called
+    pub fn stats(&self) -> Stats {
+        Stats {
+            globals: self.session.globals.len(),
+            ..self.stats.clone()
         }
     }
 
-    pub fn run(&mut self, chunk: Chunk<'a>) -> Result<(), Error> {
-        let vmi = VMInterpreter {
-            stack: Vec::with_capacity(MAX_STACK),
+    pub fn reset_stats(&mut self) {
+        self.stats = Stats::default();
+    }
+
+    /// Executes a compiled or hand-built chunk against this VM's globals,
+    /// returning whatever is left on top of the stack at `OP_RETURN`
+    pub fn run(&mut self, chunk: Chunk) -> Result<Option<Value>, Error> {
+        let vmi = VMInterpreter::new(
+            &mut self.stack,
+            &mut self.out,
+            &mut self.stats,
+            Rc::new(Function::script(chunk)),
+        );
+        let installed = self.instruction_hook.as_mut();
+        let mut hook: Option<Box<HookFn>> = match (self.trace_execution, installed) {
+            (false, None) => None,
+            (false, Some(hook)) => Some(Box::new(hook)),
+            (true, None) => Some(Box::new(trace_hook(&mut self.trace_out))),
+            (true, Some(hook)) => {
+                let mut trace = trace_hook(&mut self.trace_out);
+                Some(Box::new(move |event: &InstructionEvent| {
+                    trace(event);
+                    hook(event);
+                }))
+            }
         };
-        vmi.run(&chunk, &mut self.globals)
+        let result = vmi.run(&mut self.session.globals, hook.as_deref_mut(), self.budget);
+        drop(hook);
+        if result.is_err() {
+            self.reset();
+        }
+        result
+    }
+
+    /// Empties the value stack so the next run starts clean. `run` does this
+    /// after a runtime error. Globals are deliberately kept: a failed line
+    /// keeps every definition and assignment it made before the error.
+    pub fn reset(&mut self) {
+        self.stack.fill(Value::Nil);
+    }
+
+    /// Compiles and runs a whole script
+    pub fn interpret(&mut self, source: &str) -> Result<Option<Value>, Error> {
+        let chunk = self.compile(source)?;
+        self.run(chunk)
     }
 
-    pub(crate) fn interpret(&mut self, source: &'a str) -> Result<(), Error> {
+    /// Compiles a script without running it. Its globals are declared in
+    /// this VM, so a later `interpret` can still use them.
+    pub fn compile(&mut self, source: &str) -> Result<Chunk, Error> {
         let mut chunk = Chunk::new();
-        if !Compiler::compile(source, &mut chunk) {
-            return Err(Error::Compiler);
+        let warnings =
+            Compiler::compile(source, &mut chunk, &mut self.session).map_err(Error::Compiler)?;
+        if self.strict_globals && !warnings.is_empty() {
+            return Err(Error::Compiler(warnings));
         }
+        self.warnings = warnings;
+        self.check_compiled(&chunk);
+        Ok(chunk)
+    }
+
+    /// Later compiles record that their code came from `file` in the
+    /// program's `SourceMap`, for the spans of their errors
+    pub fn set_file(&mut self, file: usize) {
+        self.session.file = file;
+    }
+
+    /// Warnings from the latest successful compile, like a global that's
+    /// used but never defined
+    pub fn warnings(&self) -> &[CompileError] {
+        &self.warnings
+    }
+
+    /// Loads a script saved with `Chunk::to_loxc`, rejecting anything that
+    /// isn't valid bytecode. Its globals share this VM's slots by name.
+    pub fn load(&mut self, bytes: &[u8]) -> Result<Chunk, Error> {
+        let chunk = Chunk::from_loxc(bytes, &mut self.session)?;
+        if self.print_code {
+            let _ = chunk.dissassemble_to(&mut self.trace_out, "code");
+        }
+        Ok(chunk)
+    }
+
+    /// Assembles a chunk (see `assemble`) whose globals share this
+    /// VM's slots by name, as `load` does
+    pub fn assemble(&mut self, src: &str) -> Result<Chunk, AsmError> {
+        let mut chunk = assemble(src)?;
+        chunk.relink_globals(&mut self.session);
+        Ok(chunk)
+    }
+
+    fn check_compiled(&mut self, chunk: &Chunk) {
+        if cfg!(debug_assertions)
+            && let Err(error) = chunk.validate()
+        {
+            panic!("compiler emitted invalid bytecode: {error}");
+        }
+        if self.print_code {
+            let _ = chunk.dissassemble_to(&mut self.trace_out, "code");
+        }
+    }
+
+    /// Compiles a script and pauses it before its first instruction
+    pub fn debug(&mut self, source: &str) -> Result<Debugger<'_>, Error> {
+        let chunk = self.compile(source)?;
+        let vmi = VMInterpreter::new(
+            &mut self.stack,
+            &mut self.out,
+            &mut self.stats,
+            Rc::new(Function::script(chunk)),
+        );
+        Ok(Debugger::new(vmi, &mut self.session.globals))
+    }
+
+    /// Like `interpret`, but returns the value of a trailing expression statement
+    pub fn interpret_line(&mut self, source: &str) -> Result<Option<Value>, Error> {
+        let mut chunk = Chunk::new();
+        // a later line may well define what this one uses
+        self.warnings = Compiler::compile_repl(source, &mut chunk, &mut self.session)
+            .map_err(Error::Compiler)?;
+        self.check_compiled(&chunk);
         self.run(chunk)
     }
 }
 
 macro_rules! peek {
     ($self:ident, $idx:expr) => {
-        $self.stack[$self.stack.len() - $idx - 1]
+        $self.stack[$self.stack_top - $idx - 1]
     };
 }
 
 macro_rules! pop {
-    ($self:ident) => {
-        $self.stack.pop().unwrap()
-    };
+    ($self:ident, $ip:ident) => {{
+        debug_assert!($self.stack_top > 0, "Stack underflow at offset {}", $ip);
+        $self.stack_top -= 1;
+        std::mem::take(&mut $self.stack[$self.stack_top])
+    }};
 }
 
 macro_rules! push {
-    ($self:ident, $value:ident) => {
-        $self.stack.push($value)
-    };
-    ($self:ident, $value:expr) => {
-        $self.stack.push($value)
+    ($self:ident, $chunk:ident, $ip:ident, $value:expr) => {{
+        let value = $value;
+        if $self.stack_top == $self.stack.len() {
+            return Err($self.error("Stack overflow.", $ip));
+        }
+        $self.stack[$self.stack_top] = value;
+        $self.stack_top += 1;
+        count!($self.stats.peak_stack = $self.stats.peak_stack.max($self.stack_top));
+    }};
+}
+
+/// Updates `Stats`, or nothing without the `stats` feature
+macro_rules! count {
+    ($update:expr) => {
+        #[cfg(feature = "stats")]
+        {
+            $update;
+        }
     };
 }
 
 macro_rules! read {
-    ($chunk:ident, $idx:expr) => {
-        $chunk.code[$idx]
+    ($self:ident, $chunk:ident, $idx:expr) => {
+        match $chunk.code.get($idx) {
+            Some(byte) => *byte,
+            None => {
+                let message = format!("Unexpected end of bytecode at offset {}.", $idx);
+                return Err($self.error(&message, $idx));
+            }
+        }
+    };
+}
+
+/// The result of a `Value` operation, or its error as a runtime error
+macro_rules! try_op {
+    ($self:ident, $result:expr, $ip:ident) => {
+        match $result {
+            Ok(value) => value,
+            Err(message) => return Err($self.error(&message, $ip)),
+        }
     };
 }
 
 macro_rules! binary_op {
     ($self:ident, $chunk:ident, $op:ident, $ip:ident) => {{
-        if !matches!(peek!($self, 0), Value::Number(_))
-            || !matches!(peek!($self, 1), Value::Number(_))
-        {
-            $self.print_error($chunk, "Operands must be numbers.", $ip);
-            return Err(Error::Runtime);
-        }
-        let b = pop!($self);
-        let a = pop!($self);
-        let res = a.$op(&b);
-        push!($self, res);
+        let b = pop!($self, $ip);
+        let a = pop!($self, $ip);
+        let res = try_op!($self, a.$op(&b), $ip);
+        push!($self, $chunk, $ip, res);
     }};
 }
 
 macro_rules! binary_op_supp_str {
     ($self:ident, $chunk:ident, $op:ident, $ip:ident) => {{
-        let none_are_string = !matches!(peek!($self, 0), Value::String(_) | Value::ConstString(_))
-            && !matches!(peek!($self, 1), Value::String(_) | Value::ConstString(_));
-        let not_both_numbers = !matches!(peek!($self, 0), Value::Number(_))
-            || !matches!(peek!($self, 1), Value::Number(_));
-        if none_are_string && not_both_numbers {
-            $self.print_error($chunk, "Operands must be numbers.", $ip);
-            return Err(Error::Runtime);
-        }
-        let b = pop!($self);
-        let a = pop!($self);
-        let res = a.$op(&b);
-        push!($self, res);
+        let b = pop!($self, $ip);
+        let a = pop!($self, $ip);
+        let res = try_op!($self, a.$op(&b), $ip);
+        count!(if a.is_string() || b.is_string() {
+            $self.count_string(&res)
+        });
+        push!($self, $chunk, $ip, res);
     }};
 }
 
-struct VMInterpreter<'a> {
-    stack: Vec<Value<'a>>,
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+#[error("{message}{}", .trace.iter().map(|frame| format!("\n{frame}")).collect::<String>())]
+pub struct RuntimeError {
+    pub message: String,
+    /// line of the instruction that failed
+    pub line: usize,
+    /// its column, or 0 for a hand-built chunk without one
+    pub column: usize,
+    /// index in the program's `SourceMap` of the source it was compiled from
+    pub file: usize,
+    /// `[line N] in name()` for each live frame, innermost first
+    pub trace: Vec<String>,
+}
+
+/// A function invocation in progress; the top-level script is the outermost frame
+struct CallFrame {
+    function: Rc<Function>,
+    /// offset of the instruction in progress; the `OP_CALL` while a frame above runs
+    ip: usize,
+    /// index of the frame's slot zero in the value stack
+    slots: usize,
+}
+
+/// What the run loop does after an instruction
+enum Flow {
+    /// carry on in the same frame at this offset
+    Next(usize),
+    /// a call or return made another frame the innermost
+    Switch,
+    /// the script returned, with whatever was left on top of the stack
+    Return(Option<Value>),
+}
+
+struct VMInterpreter<'s> {
+    stack: &'s mut [Value],
+    out: &'s mut dyn Write,
+    stats: &'s mut Stats,
+    /// the next free slot in `stack`
+    stack_top: usize,
+    frames: Vec<CallFrame>,
+    /// the most frames `stack` is sized for
+    max_frames: usize,
 }
 
-impl<'a> VMInterpreter<'a> {
+impl Drop for VMInterpreter<'_> {
+    fn drop(&mut self) {
+        // leave the shared stack empty for the next run, even after an error
+        self.stack[..self.stack_top].fill(Value::Nil);
+    }
+}
+
+impl<'s> VMInterpreter<'s> {
+    fn new(
+        stack: &'s mut [Value],
+        out: &'s mut dyn Write,
+        stats: &'s mut Stats,
+        script: Rc<Function>,
+    ) -> Self {
+        Self {
+            max_frames: (stack.len() / FRAME_SLOTS).max(1),
+            stack,
+            out,
+            stats,
+            stack_top: 0,
+            frames: vec![CallFrame {
+                function: script,
+                ip: 0,
+                slots: 0,
+            }],
+        }
+    }
+
     fn run(
         mut self,
-        chunk: &Chunk<'a>,
-        globals: &mut HashMap<&'a str, Value<'a>>,
-    ) -> Result<(), Error> {
-        let mut ip = 0;
+        globals: &mut Globals,
+        mut hook: Option<&mut HookFn>,
+        mut budget: Option<u64>,
+    ) -> Result<Option<Value>, Error> {
         loop {
-            #[cfg(debug_assertions)]
-            {
-                println!("          {}", ValueVec(&self.stack));
-                let _ = chunk.dissassemble_instruction(ip);
-            }
-            match OpCode::from(read!(chunk, ip)) {
-                OpCode::Return => {
-                    return Ok(());
-                }
-                OpCode::Constant => {
-                    let value = chunk.read_constant(read!(chunk, ip + 1) as usize);
-                    push!(self, value.to_owned());
-                    ip += 1;
-                }
-                OpCode::ConstantLong => {
-                    let value = chunk
-                        .read_constant(long_index(read!(chunk, ip + 1), read!(chunk, ip + 2)))
-                        .to_owned();
-                    println!("{}", value);
-                    push!(self, value);
-                    ip += 2;
-                }
-                OpCode::Negate => {
-                    if !matches!(peek!(self, 0), Value::Number(_)) {
-                        self.print_error(chunk, "Operand must be a number.", ip);
-                        return Err(Error::Runtime);
+            let frame = self
+                .frames
+                .last()
+                .expect("the script frame is never popped");
+            let function = frame.function.clone();
+            let slots = frame.slots;
+            let mut ip = frame.ip;
+            // runs the current frame until a call or return switches to another
+            loop {
+                if let Some(left) = budget.as_mut() {
+                    if *left == 0 {
+                        return Err(self.error("Execution budget exceeded.", ip));
                     }
-                    let value = pop!(self);
-                    push!(self, value.negate());
-                }
-                OpCode::Add => {
-                    binary_op_supp_str!(self, chunk, add, ip);
+                    *left -= 1;
                 }
-                OpCode::Subtract => {
-                    binary_op!(self, chunk, subtract, ip);
-                }
-                OpCode::Multiply => {
-                    binary_op!(self, chunk, multiply, ip);
+                if let Some(hook) = hook.as_deref_mut() {
+                    let stack = &self.stack[..self.stack_top];
+                    if let Some(event) = InstructionEvent::new(&function.chunk, ip, stack) {
+                        hook(&event);
+                    }
                 }
-                OpCode::Divide => {
-                    binary_op!(self, chunk, divide, ip);
+                match self.step(&function.chunk, ip, slots, globals)? {
+                    Flow::Next(next) => ip = next,
+                    Flow::Switch => break,
+                    Flow::Return(value) => return Ok(value),
                 }
-                OpCode::Nil => {
-                    push!(self, Value::Nil);
+            }
+        }
+    }
+
+    /// Executes the instruction at `ip` in the innermost frame, whose chunk
+    /// and first slot are passed in so the hot loop keeps them in locals
+    #[inline(always)]
+    fn step(
+        &mut self,
+        chunk: &Chunk,
+        mut ip: usize,
+        slots: usize,
+        globals: &mut Globals,
+    ) -> Result<Flow, Error> {
+        count!(self.stats.instructions += 1);
+        let byte = read!(self, chunk, ip);
+        let Ok(op) = OpCode::try_from(byte) else {
+            let message = format!("Unknown opcode {} at offset {}.", byte, ip);
+            return Err(self.error(&message, ip));
+        };
+        match op {
+            OpCode::Return => {
+                if self.frames.len() == 1 {
+                    if self.stack_top == 0 {
+                        return Ok(Flow::Return(None));
+                    }
+                    return Ok(Flow::Return(Some(pop!(self, ip))));
                 }
-                OpCode::True => {
-                    push!(self, Value::Bool(true));
+                let result = pop!(self, ip);
+                if let Some(frame) = self.frames.pop() {
+                    self.stack[frame.slots..self.stack_top].fill(Value::Nil);
+                    self.stack_top = frame.slots;
                 }
-                OpCode::False => {
-                    push!(self, Value::Bool(false));
+                push!(self, chunk, ip, result);
+                if let Some(caller) = self.frames.last_mut() {
+                    // resume after the caller's OP_CALL and its operand
+                    caller.ip += 2;
                 }
-                OpCode::Not => {
-                    let value = pop!(self);
-                    push!(self, Value::Bool(!value.is_truthy()))
+                return Ok(Flow::Switch);
+            }
+            OpCode::Call => {
+                let arg_count = read!(self, chunk, ip + 1) as usize;
+                let callee = peek!(self, arg_count).clone();
+                if let Some(native) = callee.as_native() {
+                    self.check_arity(native.name, native.arity, arg_count, ip)?;
+                    let args = self.stack_top - arg_count;
+                    let result = (native.function)(&self.stack[args..self.stack_top]);
+                    self.stack[args - 1..self.stack_top].fill(Value::Nil);
+                    self.stack_top = args - 1;
+                    push!(self, chunk, ip, result);
+                    ip += 1;
+                } else if let Some(callee) = callee.as_function() {
+                    let name = callee.name.as_deref().unwrap_or("script");
+                    self.check_arity(name, callee.arity, arg_count, ip)?;
+                    if self.frames.len() == self.max_frames {
+                        return Err(self.error("Stack overflow.", ip));
+                    }
+                    if let Some(caller) = self.frames.last_mut() {
+                        caller.ip = ip;
+                    }
+                    self.frames.push(CallFrame {
+                        function: callee,
+                        ip: 0,
+                        slots: self.stack_top - arg_count - 1,
+                    });
+                    return Ok(Flow::Switch);
+                } else {
+                    return Err(self.error("Can only call functions and classes.", ip));
                 }
-                OpCode::Equal => {
-                    let b = pop!(self);
-                    let a = pop!(self);
-                    let res = a == b;
-                    push!(self, Value::Bool(res));
+            }
+            OpCode::Constant => {
+                let value = chunk.read_constant(read!(self, chunk, ip + 1) as usize);
+                push!(self, chunk, ip, value.to_owned());
+                ip += 1;
+            }
+            OpCode::ConstantLong => {
+                let value = chunk
+                    .read_constant(long_index(
+                        read!(self, chunk, ip + 1),
+                        read!(self, chunk, ip + 2),
+                    ))
+                    .to_owned();
+                push!(self, chunk, ip, value);
+                ip += 2;
+            }
+            OpCode::Negate => {
+                let value = try_op!(self, pop!(self, ip).negate(), ip);
+                push!(self, chunk, ip, value);
+            }
+            OpCode::Add => {
+                binary_op_supp_str!(self, chunk, add, ip);
+            }
+            OpCode::Subtract => {
+                binary_op!(self, chunk, subtract, ip);
+            }
+            OpCode::Multiply => {
+                binary_op!(self, chunk, multiply, ip);
+            }
+            OpCode::Divide => {
+                binary_op!(self, chunk, divide, ip);
+            }
+            OpCode::Modulo => {
+                binary_op!(self, chunk, modulo, ip);
+            }
+            OpCode::Nil => {
+                push!(self, chunk, ip, Value::Nil);
+            }
+            OpCode::Zero => {
+                push!(self, chunk, ip, Value::Number(0.0));
+            }
+            OpCode::One => {
+                push!(self, chunk, ip, Value::Number(1.0));
+            }
+            OpCode::MinusOne => {
+                push!(self, chunk, ip, Value::Number(-1.0));
+            }
+            OpCode::True => {
+                push!(self, chunk, ip, Value::Bool(true));
+            }
+            OpCode::False => {
+                push!(self, chunk, ip, Value::Bool(false));
+            }
+            OpCode::Not => {
+                let value = pop!(self, ip);
+                push!(self, chunk, ip, Value::Bool(!value.is_truthy()))
+            }
+            OpCode::Equal => {
+                let b = pop!(self, ip);
+                let a = pop!(self, ip);
+                let res = a == b;
+                push!(self, chunk, ip, Value::Bool(res));
+            }
+            OpCode::NotEqual => {
+                let b = pop!(self, ip);
+                let a = pop!(self, ip);
+                let res = a != b;
+                push!(self, chunk, ip, Value::Bool(res));
+            }
+            OpCode::Greater => {
+                binary_op!(self, chunk, greater, ip)
+            }
+            OpCode::GreaterEqual => {
+                binary_op!(self, chunk, greater_equal, ip)
+            }
+            OpCode::Less => {
+                binary_op!(self, chunk, less, ip)
+            }
+            OpCode::LessEqual => {
+                binary_op!(self, chunk, less_equal, ip)
+            }
+            OpCode::Print => {
+                let value = pop!(self, ip);
+                writeln!(self.out, "{}", value)?;
+            }
+            OpCode::Pop => {
+                pop!(self, ip);
+            }
+            OpCode::PopN => {
+                let count = read!(self, chunk, ip + 1) as usize;
+                let new_top = self.stack_top.saturating_sub(count);
+                self.stack[new_top..self.stack_top].fill(Value::Nil);
+                self.stack_top = new_top;
+                ip += 1;
+            }
+            OpCode::Dup => {
+                push!(self, chunk, ip, peek!(self, 0).clone());
+            }
+            OpCode::Assert => {
+                let message = (read!(self, chunk, ip + 1) == 1).then(|| pop!(self, ip));
+                if !pop!(self, ip).is_truthy() {
+                    let message = match message {
+                        Some(message) => format!("Assertion failed: {message}"),
+                        None => "Assertion failed.".to_string(),
+                    };
+                    return Err(self.error(&message, ip));
                 }
-                OpCode::Greater => {
-                    binary_op!(self, chunk, greater, ip)
+                ip += 1;
+            }
+            OpCode::Index => {
+                let index = pop!(self, ip);
+                let receiver = pop!(self, ip);
+                let Some(s) = receiver.as_string() else {
+                    let message = format!("Can only index strings; got {}.", receiver.type_name());
+                    return Err(self.error(&message, ip));
+                };
+                let Some(i) = index.as_number().filter(|i| i.fract() == 0.0) else {
+                    let message = format!("String index must be an integer; got {index}.");
+                    return Err(self.error(&message, ip));
+                };
+                // indices count characters, not bytes
+                let c = if i < 0.0 {
+                    None
+                } else {
+                    s.chars().nth(i as usize)
+                };
+                let Some(c) = c else {
+                    let message = format!(
+                        "String index {i} out of range for length {}.",
+                        s.chars().count()
+                    );
+                    return Err(self.error(&message, ip));
+                };
+                let indexed = Value::from(c.encode_utf8(&mut [0; 4]) as &str);
+                count!(self.count_string(&indexed));
+                push!(self, chunk, ip, indexed);
+            }
+            OpCode::DefineGlobal => {
+                let slot = long_index(read!(self, chunk, ip + 1), read!(self, chunk, ip + 2));
+                globals.define(slot, pop!(self, ip));
+                ip += 2;
+            }
+            OpCode::GetGlobal => {
+                let slot = long_index(read!(self, chunk, ip + 1), read!(self, chunk, ip + 2));
+                let val = globals
+                    .get(slot)
+                    .ok_or_else(|| self.undefined_global(globals, slot, ip))?;
+                push!(self, chunk, ip, val.clone());
+                ip += 2;
+            }
+            OpCode::GetLocal => {
+                let slot = read!(self, chunk, ip + 1) as usize;
+                push!(self, chunk, ip, self.stack[slots + slot].clone());
+                ip += 1;
+            }
+            OpCode::SetLocal => {
+                let slot = read!(self, chunk, ip + 1) as usize;
+                self.stack[slots + slot] = peek!(self, 0).clone();
+                ip += 1;
+            }
+            OpCode::Jump => {
+                let offset = long_index(read!(self, chunk, ip + 1), read!(self, chunk, ip + 2));
+                return Ok(Flow::Next(ip + 3 + offset));
+            }
+            OpCode::JumpIfFalse => {
+                let offset = long_index(read!(self, chunk, ip + 1), read!(self, chunk, ip + 2));
+                if !peek!(self, 0).is_truthy() {
+                    return Ok(Flow::Next(ip + 3 + offset));
                 }
-                OpCode::Less => {
-                    binary_op!(self, chunk, less, ip)
+                return Ok(Flow::Next(ip + 3));
+            }
+            OpCode::Loop => {
+                let offset = long_index(read!(self, chunk, ip + 1), read!(self, chunk, ip + 2));
+                let Some(target) = (ip + 3).checked_sub(offset) else {
+                    let message = format!("Loop target before start of bytecode at offset {}.", ip);
+                    return Err(self.error(&message, ip));
+                };
+                return Ok(Flow::Next(target));
+            }
+            OpCode::SetGlobal => {
+                let slot = long_index(read!(self, chunk, ip + 1), read!(self, chunk, ip + 2));
+                let Some(value) = globals.get_mut(slot) else {
+                    return Err(self.undefined_global(globals, slot, ip));
+                };
+                *value = peek!(self, 0).clone();
+                ip += 2;
+            }
+        };
+        Ok(Flow::Next(ip + 1))
+    }
+
+    /// A chunk that wasn't compiled in this session (hand-built, or corrupt)
+    /// can name a slot no global was ever declared in
+    fn undefined_global(&self, globals: &Globals, slot: usize, ip: usize) -> Error {
+        let message = match globals.try_name(slot) {
+            Some(name) => format!("Undefined variable {name}"),
+            None => format!("global slot {slot} has no name at offset {ip}"),
+        };
+        self.error(&message, ip)
+    }
+
+    #[cfg(feature = "stats")]
+    fn count_string(&mut self, value: &Value) {
+        if let Some(s) = value.as_string() {
+            self.stats.strings += 1;
+            self.stats.string_bytes += s.len() as u64;
+        }
+    }
+
+    fn check_arity(
+        &self,
+        name: &str,
+        arity: usize,
+        arg_count: usize,
+        ip: usize,
+    ) -> Result<(), Error> {
+        if arity == arg_count {
+            return Ok(());
+        }
+        let message = format!("Expected {arity} arguments but got {arg_count} calling {name}().");
+        Err(self.error(&message, ip))
+    }
+
+    /// Builds the error for a failure at `ip` in the innermost frame, with a
+    /// trace line per live frame
+    fn error(&self, message: &str, ip: usize) -> Error {
+        // the innermost frame is at the current ip rather than its saved one
+        let ips = std::iter::once(ip).chain(self.frames.iter().rev().skip(1).map(|f| f.ip));
+        let trace: Vec<String> = self
+            .frames
+            .iter()
+            .rev()
+            .zip(ips)
+            .map(|(frame, ip)| {
+                let position = frame.function.chunk.read_position(ip);
+                match &frame.function.name {
+                    Some(name) => format!("[line {position}] in {name}()"),
+                    None => format!("[line {position}] in script"),
                 }
-                OpCode::Print => {
-                    let value = pop!(self);
-                    println!("{}", value);
+            })
+            .collect();
+        let (position, file) = self
+            .frames
+            .last()
+            .map(|frame| {
+                let chunk = &frame.function.chunk;
+                (chunk.read_position(ip), chunk.file)
+            })
+            .unwrap_or_default();
+        Error::Runtime(RuntimeError {
+            message: message.to_string(),
+            line: position.line,
+            column: position.column,
+            file,
+            trace,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test::SharedBuf;
+
+    fn trace(source: &str) -> String {
+        let buf = SharedBuf::default();
+        let mut vm = VM::new()
+            .with_trace_output(Box::new(buf.clone()))
+            .with_output(Box::new(std::io::sink()));
+        vm.trace_execution = true;
+        vm.interpret(source).unwrap();
+        buf.contents()
+    }
+
+    #[test]
+    fn test_trace_execution() {
+        let trace = trace("var a = 2; a + 2;");
+        let ops: Vec<&str> = trace
+            .split_whitespace()
+            .filter(|w| w.starts_with("OP_"))
+            .collect();
+        assert_eq!(
+            ops,
+            vec![
+                "OP_CONSTANT",
+                "OP_DEFINE_GLOBAL",
+                "OP_GET_GLOBAL",
+                "OP_CONSTANT",
+                "OP_ADD",
+                "OP_POP",
+                "OP_RETURN"
+            ]
+        );
+        assert!(trace.contains("[4.0]"));
+    }
+
+    #[test]
+    fn test_modulo() {
+        assert!(trace("10 % 3;").contains("[1.0]"));
+        assert!(trace("7.5 % 2;").contains("[1.5]"));
+        assert!(trace("5 % 0;").contains("[NaN]"));
+    }
+
+    #[test]
+    fn test_comparisons() {
+        assert!(trace("1 <= 2;").contains("[true]"));
+        assert!(trace("2 <= 2;").contains("[true]"));
+        assert!(trace("3 <= 2;").contains("[false]"));
+        assert!(trace("1 >= 2;").contains("[false]"));
+        assert!(trace("2 >= 2;").contains("[true]"));
+        assert!(trace("1 != 2;").contains("[true]"));
+        assert!(trace("1 == 1;").contains("[true]"));
+        assert!(trace("!nil;").contains("[true]"));
+        assert!(trace("1 != 1;").contains("[false]"));
+        assert!(trace("\"a\" != 1;").contains("[true]"));
+        assert!(VM::new().interpret("1 <= nil;").is_err());
+    }
+
+    #[test]
+    fn test_string_comparisons() {
+        let mut vm = VM::new();
+        vm.interpret("var a = \"apple\"; var b = \"banana\";")
+            .unwrap();
+        for (source, expected) in [
+            ("a < b", true),
+            ("a <= b", true),
+            ("a > b", false),
+            ("a >= b", false),
+            ("b > a", true),
+            ("a <= a", true),
+            ("a >= a", true),
+            ("a < a", false),
+            // compared by bytes, so shorter prefixes come first and
+            // uppercase before lowercase
+            ("\"app\" < a", true),
+            ("\"Zebra\" < a", true),
+            // a concatenated string compares like a constant
+            ("\"app\" + \"le\" >= a", true),
+        ] {
+            let result = vm.interpret_line(source).unwrap();
+            assert_eq!(result, Some(Value::Bool(expected)), "{source}");
+        }
+        for source in ["a < 1", "2 >= b", "a > nil", "true <= b"] {
+            let Err(Error::Runtime(error)) = vm.interpret_line(source) else {
+                panic!("{source} should fail at runtime");
+            };
+            assert!(
+                error
+                    .message
+                    .starts_with("Operands must be two numbers or two strings;"),
+                "{source}: {}",
+                error.message
+            );
+        }
+    }
+
+    #[test]
+    fn test_nan_comparisons() {
+        // every ordered comparison with NaN is false, so `<=` can't be `!(>)`
+        let nan = "var n = 0 / 0;\n";
+        for (op, expected) in [
+            ("<", "false"),
+            ("<=", "false"),
+            (">", "false"),
+            (">=", "false"),
+            ("==", "false"),
+            ("!=", "true"),
+        ] {
+            let mut vm = VM::new();
+            vm.interpret(nan).unwrap();
+            assert_eq!(
+                vm.interpret_line(&format!("n {op} n"))
+                    .unwrap()
+                    .unwrap()
+                    .to_string(),
+                expected,
+                "{op}"
+            );
+            assert_eq!(
+                vm.interpret_line(&format!("n {op} 1"))
+                    .unwrap()
+                    .unwrap()
+                    .to_string(),
+                expected,
+                "{op}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_small_constants() {
+        assert!(trace("0 == 0.0;").contains("[true]"));
+        assert!(trace("var a = 0;\na == -0;").contains("[true]"));
+        assert!(trace("var a = 1;\na == 2 - 1;").contains("[true]"));
+        assert!(trace("var a = -1;\na == 0 - 1;").contains("[true]"));
+        assert!(trace("var a = -1;\n-a;").contains("[1.0]"));
+        assert!(trace("1 / -0;").contains("[-inf]"));
+    }
+
+    #[test]
+    fn test_assert() {
+        let mut vm = VM::new();
+        assert!(
+            vm.interpret("assert 1 < 2;\nassert \"\", \"strings are truthy\";")
+                .is_ok()
+        );
+        let failure = |vm: &mut VM, source| match vm.interpret(source) {
+            Err(Error::Runtime(e)) => (e.message, e.line),
+            other => panic!("expected a runtime error, got {other:?}"),
+        };
+        assert_eq!(
+            failure(&mut vm, "var a = 2;\nassert a == 3;"),
+            ("Assertion failed.".to_string(), 2)
+        );
+        assert_eq!(
+            failure(&mut vm, "assert false, \"a is \" + a;"),
+            ("Assertion failed: a is 2".to_string(), 1)
+        );
+        assert_eq!(
+            failure(&mut vm, "assert nil, nil;").0,
+            "Assertion failed: nil"
+        );
+    }
+
+    #[test]
+    fn test_concatenated_string_equality() {
+        assert!(trace("\"hi\" + \"\" == \"hi\";").contains("[true]"));
+        assert!(trace("\"hi\" == \"h\" + \"i\";").contains("[true]"));
+        assert!(trace("\"hi\" + \"\" == \"ho\";").contains("[false]"));
+    }
+
+    #[test]
+    fn test_add_operand_types() {
+        let ok = [
+            ("1 + 2;", "[3.0]"),
+            ("\"a\" + \"b\";", "[ab]"),
+            ("\"a\" + 1;", "[a1]"),
+            ("1 + \"a\";", "[1a]"),
+            ("\"a\" + 0.5;", "[a0.5]"),
+            ("\"a\" + false;", "[afalse]"),
+            ("nil + \"a\";", "[nila]"),
+        ];
+        for (source, expected) in ok {
+            assert!(trace(source).contains(expected), "{source}");
+        }
+        let err = ["false + 1;", "1 + nil;", "false + false;", "nil + nil;"];
+        for source in err {
+            assert!(VM::new().interpret(source).is_err(), "{source}");
+        }
+    }
+
+    #[test]
+    fn test_invalid_chunk() {
+        let mut chunk = Chunk::new();
+        chunk.write(200, 1);
+        assert!(VM::new().run(chunk).is_err());
+
+        // operand missing
+        let mut chunk = Chunk::new();
+        chunk.write(OpCode::Constant.into(), 1);
+        assert!(VM::new().run(chunk).is_err());
+
+        // no OP_RETURN
+        assert!(VM::new().run(assemble("NIL").unwrap()).is_err());
+
+        // a global slot no name was declared for
+        for op in [OpCode::GetGlobal, OpCode::SetGlobal] {
+            let mut chunk = Chunk::new();
+            chunk.write(OpCode::Nil.into(), 1);
+            chunk.write(op.into(), 1);
+            for byte in crate::chunk::break_index(999) {
+                chunk.write(byte, 1);
+            }
+            chunk.write(OpCode::Return.into(), 1);
+            match VM::new().run(chunk) {
+                Err(Error::Runtime(e)) => {
+                    assert_eq!(e.message, "global slot 999 has no name at offset 1")
                 }
-                OpCode::Pop => {
-                    pop!(self);
+                other => panic!("expected a runtime error, got {other:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_interpret_returns_final_value() {
+        let mut vm = VM::new();
+        assert_eq!(
+            vm.interpret_line("1 + 2").unwrap(),
+            Some(Value::Number(3.0))
+        );
+        assert_eq!(
+            vm.interpret_line("1 + 2;").unwrap(),
+            Some(Value::Number(3.0))
+        );
+        assert_eq!(vm.interpret_line("var a = 1;").unwrap(), None);
+        assert_eq!(vm.interpret("1 + 2;").unwrap(), None);
+    }
+
+    fn global(vm: &mut VM, name: &str) -> Value {
+        vm.interpret_line(name).unwrap().unwrap()
+    }
+
+    #[test]
+    fn test_locals_and_control_flow() {
+        let mut vm = VM::new();
+        let source = "
+            var a = 0;
+            var b = 0;
+            {
+                var x = 1;
+                { var x = 2; a = x; }
+                b = x;
+            }
+            var c;
+            if (a > b) c = \"then\"; else c = \"else\";
+            var d = nil or 0 and 7;
+            var e = false and 1;
+        ";
+        assert_eq!(vm.interpret(source).unwrap(), None);
+        assert_eq!(global(&mut vm, "a"), Value::Number(2.0));
+        assert_eq!(global(&mut vm, "b"), Value::Number(1.0));
+        assert_eq!(global(&mut vm, "c"), Value::from("then"));
+        assert_eq!(global(&mut vm, "d"), Value::Number(7.0));
+        assert_eq!(global(&mut vm, "e"), Value::Bool(false));
+    }
+
+    #[test]
+    fn test_break() {
+        let mut vm = VM::new();
+        let source = "
+            var i = 0;
+            while (i < 10) {
+                var x = i;
+                if (x == 5) break;
+                i = i + 1;
+            }
+        ";
+        // the stack is empty at the end, so nothing is returned
+        assert_eq!(vm.interpret(source).unwrap(), None);
+        assert_eq!(global(&mut vm, "i"), Value::Number(5.0));
+    }
+
+    #[test]
+    fn test_continue() {
+        let mut vm = VM::new();
+        let source = "
+            var sum = 0;
+            for (var i = 0; i < 10; i = i + 1) {
+                var odd = i % 2;
+                if (odd == 0) continue;
+                sum = sum + i;
+            }
+        ";
+        assert_eq!(vm.interpret(source).unwrap(), None);
+        assert_eq!(global(&mut vm, "sum"), Value::Number(25.0));
+    }
+
+    #[test]
+    fn test_break_nested_loops() {
+        let mut vm = VM::new();
+        let source = "
+            var count = 0;
+            var outer = 0;
+            for (var i = 0; i < 3; i = i + 1) {
+                for (var j = 0; j < 10; j = j + 1) {
+                    if (j == 2) break;
+                    count = count + 1;
                 }
-                OpCode::DefineGlobal => {
-                    let name = chunk.read_constant(read!(chunk, ip + 1) as usize).as_str();
-                    println!("Defining: {}", name);
-                    globals.insert(name, pop!(self));
-                    ip += 1;
+                outer = outer + 1;
+            }
+        ";
+        assert_eq!(vm.interpret(source).unwrap(), None);
+        assert_eq!(global(&mut vm, "count"), Value::Number(6.0));
+        assert_eq!(global(&mut vm, "outer"), Value::Number(3.0));
+    }
+
+    #[test]
+    fn test_switch() {
+        let source = "
+            var result;
+            switch (n) {
+                case 1:
+                    var s = \"one\";
+                    result = s;
+                case 1 + 1:
+                    result = \"two\";
+                default:
+                    result = \"other\";
+            }
+        ";
+        for (input, expected) in [(1, "one"), (2, "two"), (3, "other")] {
+            let mut vm = VM::new();
+            let source = format!("var n = {input};{source}");
+            assert_eq!(vm.interpret(&source).unwrap(), None);
+            assert_eq!(global(&mut vm, "result"), Value::from(expected));
+        }
+    }
+
+    #[test]
+    fn test_break_from_switch_in_loop() {
+        let mut vm = VM::new();
+        let source = "
+            var i = 0;
+            while (i < 10) {
+                var n = i;
+                switch (n) {
+                    case 3:
+                        var unused = n;
+                        break;
+                    default:
+                        i = i + 1;
                 }
-                OpCode::GetGlobal => {
-                    let name = chunk.read_constant(read!(chunk, ip + 1) as usize).as_str();
-                    println!("Getting: {}", name);
-                    let val = globals.get(name).ok_or_else(|| {
-                        self.print_error(chunk, &format!("Undefined variable {}", name), ip);
-                        Error::Runtime
-                    })?;
-                    push!(self, val.clone());
-                    ip += 1;
+            }
+        ";
+        assert_eq!(vm.interpret(source).unwrap(), None);
+        assert_eq!(global(&mut vm, "i"), Value::Number(3.0));
+    }
+
+    #[test]
+    fn test_constant_folding_keeps_behavior() {
+        let programs = [
+            "var r = 2 * 3 + 4;",
+            "var r = -(1 / 2) * 4;",
+            "var r = \"a\" + 1 + 2;",
+            "var r = 1 + 2 + \"a\";",
+            "var r = 7 % 4 - 9 / 4;",
+            "var r = (1 + 2) * (3 - 4) == -3;",
+            "var r = nil or 1 + 2;",
+            "var r = 1 + 2 > 2 and \"x\" + \"y\";",
+        ];
+        for program in programs {
+            let mut folded = VM::new();
+            folded.interpret(program).unwrap();
+            // reading the literals through a global blocks folding
+            let unfolded_program = format!("var one = 1; {}", program.replace('1', "one"));
+            let mut unfolded = VM::new();
+            unfolded.interpret(&unfolded_program).unwrap();
+            assert_eq!(
+                global(&mut folded, "r"),
+                global(&mut unfolded, "r"),
+                "{program}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_scope_exit_balances_stack() {
+        let trace = trace(
+            "{ var a = 1; var b = 2; { var c = 3; var d = 4; var e = 5; } var f = 6; }
+            for (var i = 0; i < 3; i = i + 1) { var x = i; var y = i; if (i == 1) break; }",
+        );
+        let mut lines = trace.lines();
+        let mut stacks = Vec::new();
+        while let Some(stack) = lines.next() {
+            stacks.push(stack.trim());
+            lines.next();
+        }
+        // an empty stack traces as a blank line
+        assert_eq!(stacks.last(), Some(&""), "{trace}");
+        assert!(trace.contains("OP_POPN"), "{trace}");
+    }
+
+    #[test]
+    fn test_globals() {
+        let mut vm = VM::new();
+        vm.interpret("var a = 1; var b = 2; a = a + b;").unwrap();
+        assert_eq!(global(&mut vm, "a"), Value::Number(3.0));
+        vm.interpret_line("var b = \"x\";").unwrap();
+        assert_eq!(global(&mut vm, "b"), Value::from("x"));
+        assert!(vm.interpret("c;").is_err());
+        assert!(vm.interpret("c = 1;").is_err());
+        // the failed lines reserved a slot for c, but it stays undefined
+        assert!(vm.interpret("c;").is_err());
+        vm.interpret("var c = 3;").unwrap();
+        assert_eq!(global(&mut vm, "c"), Value::Number(3.0));
+
+        // the same get site sees a redefinition made mid-program
+        let source = "var seen = \"\";\nfun look() { seen = seen + d; }\nvar d = \"1\";\nlook();\nvar d = \"2\";\nlook();";
+        vm.interpret(source).unwrap();
+        assert_eq!(global(&mut vm, "seen"), Value::from("12"));
+    }
+
+    #[test]
+    fn test_undefined_global_warnings() {
+        let mut vm = VM::new();
+        let source = "var counter = 0;\nfun bump() { countr = counter + 1; }\nfun later() { return helper(); }\nfun helper() { return clock(); }";
+        vm.compile(source).unwrap();
+        // `helper` is defined after its use and `clock` is a native
+        let warnings: Vec<_> = vm.warnings().iter().map(ToString::to_string).collect();
+        assert_eq!(
+            warnings,
+            ["[line 2:14] after 'countr': Global 'countr' is used but never defined."]
+        );
+
+        vm.strict_globals = true;
+        let Err(Error::Compiler(errors)) = vm.compile(source) else {
+            panic!("strict globals should reject the typo");
+        };
+        assert_eq!(errors.len(), 1);
+        assert!(
+            vm.interpret("var counter = 1;\ncounter = counter + 1;")
+                .is_ok()
+        );
+
+        // a REPL line can use what a later line defines, even when strict
+        assert!(vm.interpret_line("fun f() { return g(); }").is_ok());
+        assert_eq!(vm.warnings().len(), 1);
+        vm.interpret_line("fun g() { return 2; }").unwrap();
+        assert_eq!(vm.interpret_line("f()").unwrap(), Some(Value::Number(2.0)));
+        assert!(vm.warnings().is_empty());
+    }
+
+    #[test]
+    fn test_session_shares_strings() {
+        let mut vm = VM::new();
+        vm.interpret_line("var x = \"x\";").unwrap();
+        assert_eq!(
+            vm.interpret_line("x + \"x\"").unwrap(),
+            Some(Value::from("xx"))
+        );
+        // the global's name and both string constants are one interned "x"
+        let count = vm.session.strings().filter(|s| *s == "x").count();
+        assert_eq!(count, 1);
+        #[cfg(not(feature = "nan_boxing"))]
+        {
+            let value = global(&mut vm, "x").as_string().unwrap();
+            assert!(Rc::ptr_eq(&value, &vm.session.intern("x")));
+        }
+    }
+
+    #[test]
+    fn test_global_loop() {
+        let mut vm = VM::new();
+        let source = "
+            var sum = 0;
+            for (var i = 0; i < 100000; i = i + 1) {
+                sum = sum + i;
+            }
+        ";
+        vm.interpret(source).unwrap();
+        assert_eq!(global(&mut vm, "sum"), Value::Number(4999950000.0));
+    }
+
+    #[test]
+    fn test_stack_overflow() {
+        let nils = |n| assemble(&format!("{}RETURN", "NIL\n".repeat(n))).unwrap();
+        assert_eq!(
+            VM::new().run(nils(DEFAULT_STACK_SIZE)).unwrap(),
+            Some(Value::Nil)
+        );
+        assert!(VM::new().run(nils(DEFAULT_STACK_SIZE + 1)).is_err());
+    }
+
+    #[test]
+    fn test_arithmetic_loop() {
+        let mut vm = VM::new();
+        let source = "
+            var result = 0;
+            {
+                var acc = 1;
+                for (var i = 0; i < 100000; i = i + 1) {
+                    acc = (acc * 7 + i) % 997;
                 }
-                OpCode::SetGlobal => {
-                    let name = chunk.read_constant(read!(chunk, ip + 1) as usize).as_str();
-                    println!("Checking: {}", name);
-                    globals.get(name).ok_or_else(|| {
-                        self.print_error(chunk, &format!("Undefined variable {}", name), ip);
-                        Error::Runtime
-                    })?;
-                    println!("Setting: {}", name);
-                    globals.insert(name, peek!(self, 0).clone());
-                    ip += 1;
+                result = acc;
+            }
+        ";
+        vm.interpret(source).unwrap();
+        assert_eq!(global(&mut vm, "result"), Value::Number(240.0));
+    }
+
+    /// Run with `cargo test --release -- --ignored` to time the dispatch loop
+    #[test]
+    #[ignore]
+    fn test_dispatch_loop_timing() {
+        let source = "
+            {
+                var acc = 0;
+                for (var i = 0; i < 10000000; i = i + 1) {
+                    acc = acc + i * 2 - 1;
                 }
-                OpCode::Unknown => todo!(),
-            };
-            ip += 1;
+            }
+        ";
+        let start = std::time::Instant::now();
+        VM::new().interpret(source).unwrap();
+        let elapsed = start.elapsed();
+        println!("10M iterations in {elapsed:?}");
+        assert!(elapsed.as_secs() < 30);
+
+        let start = std::time::Instant::now();
+        VM::new().with_budget(u64::MAX).interpret(source).unwrap();
+        let budgeted = start.elapsed();
+        println!("with a budget: {budgeted:?}");
+        // loosely: a budget costs a little, and not having one costs nothing
+        assert!(elapsed < budgeted * 2);
+    }
+
+    #[test]
+    fn test_budget() {
+        let error = VM::new()
+            .with_budget(1000)
+            .interpret("var i = 0;\nwhile (true) { i = i + 1; }")
+            .unwrap_err();
+        let Error::Runtime(error) = error else {
+            panic!("expected a runtime error, got {error:?}");
+        };
+        assert_eq!(error.message, "Execution budget exceeded.");
+        assert_eq!(error.line, 2);
+
+        let fib =
+            "fun fib(n) { if (n < 2) return n; return fib(n - 1) + fib(n - 2); }\nvar f = fib(15);";
+        let mut vm = VM::new().with_budget(1_000_000);
+        vm.interpret(fib).unwrap();
+        assert_eq!(global(&mut vm, "f"), Value::Number(610.0));
+        // each run gets the whole budget
+        vm.interpret(fib).unwrap();
+        assert!(VM::new().with_budget(1000).interpret(fib).is_err());
+    }
+
+    #[test]
+    fn test_error_trace() {
+        let mut chunk = Chunk::new();
+        chunk.write(OpCode::Nil.into(), 1);
+        chunk.write(OpCode::Nil.into(), 2);
+        chunk.write(OpCode::Add.into(), 3);
+        let function = |name: Option<&str>| {
+            let mut chunk = Chunk::new();
+            chunk.write(OpCode::Call.into(), 10);
+            chunk.write(0, 10);
+            chunk.write(OpCode::Add.into(), 11);
+            Rc::new(Function {
+                name: name.map(Into::into),
+                arity: 0,
+                chunk,
+            })
+        };
+        let mut stack = [const { Value::Nil }; 4];
+        let mut out = std::io::sink();
+        let mut stats = Stats::default();
+        let mut vmi = VMInterpreter::new(
+            &mut stack,
+            &mut out,
+            &mut stats,
+            Rc::new(Function::script(chunk)),
+        );
+        assert_eq!(
+            vmi.error("Oops.", 2).to_string(),
+            "Oops.\n[line 3] in script"
+        );
+
+        vmi.frames[0].ip = 0;
+        vmi.frames.push(CallFrame {
+            function: function(Some("outer")),
+            ip: 0,
+            slots: 0,
+        });
+        vmi.frames.push(CallFrame {
+            function: function(Some("inner")),
+            ip: 0,
+            slots: 0,
+        });
+        let Error::Runtime(error) = vmi.error("Oops.", 2) else {
+            panic!("expected a runtime error");
+        };
+        assert_eq!(error.line, 11);
+        assert_eq!(
+            error.trace,
+            [
+                "[line 11] in inner()",
+                "[line 10] in outer()",
+                "[line 1] in script"
+            ]
+        );
+        assert_eq!(
+            error.to_string(),
+            "Oops.\n[line 11] in inner()\n[line 10] in outer()\n[line 1] in script"
+        );
+    }
+
+    #[test]
+    fn test_string_building() {
+        let mut vm = VM::new();
+        vm.interpret(
+            "
+            var s = \"\";
+            for (var i = 0; i < 5; i = i + 1) {
+                var piece = \"<\" + i + \">\";
+                s = s + piece;
+            }
+            ",
+        )
+        .unwrap();
+        assert_eq!(global(&mut vm, "s"), Value::from("<0><1><2><3><4>"));
+    }
+
+    #[test]
+    fn test_string_indexing() {
+        let mut vm = VM::new();
+        vm.interpret("var s = \"héllo\"; var n = 4;").unwrap();
+        assert_eq!(vm.interpret_line("s[0]").unwrap(), Some(Value::from("h")));
+        assert_eq!(vm.interpret_line("s[1]").unwrap(), Some(Value::from("é")));
+        assert_eq!(vm.interpret_line("s[n]").unwrap(), Some(Value::from("o")));
+        assert_eq!(
+            vm.interpret_line("(s + \"!\")[2 + 3]").unwrap(),
+            Some(Value::from("!"))
+        );
+
+        let runtime_error = |vm: &mut VM, source: &str| match vm.interpret(source) {
+            Err(Error::Runtime(error)) => error,
+            other => panic!("expected a runtime error, got {other:?}"),
+        };
+        let error = runtime_error(&mut vm, "print s[0];\nprint s[-1];");
+        assert!(error.message.contains("out of range"), "{}", error.message);
+        assert_eq!(error.line, 2);
+        let error = runtime_error(&mut vm, "print s[5];");
+        assert!(error.message.contains("out of range"), "{}", error.message);
+        let error = runtime_error(&mut vm, "print s[1.5];");
+        assert!(error.message.contains("integer"), "{}", error.message);
+        let error = runtime_error(&mut vm, "print n[0];");
+        assert!(error.message.contains("number"), "{}", error.message);
+        assert!(vm.interpret("s[0] = \"x\";").is_err());
+    }
+
+    #[test]
+    fn test_many_constants() {
+        let mut vm = VM::new();
+        let mut source = String::from("var sum = 0; var s = \"\";");
+        for i in 1..=300 {
+            source.push_str(&format!("sum = sum + {i}; s = \"{i}\";"));
         }
+        vm.interpret(&source).unwrap();
+        assert_eq!(global(&mut vm, "sum"), Value::Number(45150.0));
+        assert_eq!(global(&mut vm, "s"), Value::from("300"));
+    }
+
+    #[test]
+    fn test_calls() {
+        let mut vm = VM::new();
+        vm.interpret(
+            "
+            var result;
+            fun add(a, b) { result = a + b; }
+            add(1, 2);
+            ",
+        )
+        .unwrap();
+        assert_eq!(global(&mut vm, "result"), Value::Number(3.0));
+
+        // locals are relative to the frame, and a call leaves nil behind
+        vm.interpret(
+            "
+            fun scale(n) { var k = 2; result = n * k; }
+            {
+                var x = 10;
+                var returned = scale(x);
+                result = result + x;
+                if (returned == nil) result = result + 1;
+            }
+            ",
+        )
+        .unwrap();
+        assert_eq!(global(&mut vm, "result"), Value::Number(31.0));
+        assert!(vm.interpret("var x = 1; x();").is_err());
+    }
+
+    #[test]
+    fn test_returns() {
+        let mut vm = VM::new();
+        vm.interpret(
+            "
+            fun square(n) { return n * n; }
+            var computed = square(3) + square(4);
+            fun nothing() { 1 + 1; }
+            var implicit = nothing();
+            fun bare() { return; }
+            var empty = bare();
+            fun first_over(limit) {
+                for (var i = 0; i < 100; i = i + 1) {
+                    var doubled = i * 2;
+                    if (doubled > limit) return i;
+                }
+                return -1;
+            }
+            var early = first_over(7);
+            fun fib(n) { if (n < 2) return n; return fib(n - 1) + fib(n - 2); }
+            var fib10 = fib(10);
+            ",
+        )
+        .unwrap();
+        assert_eq!(global(&mut vm, "computed"), Value::Number(25.0));
+        assert_eq!(global(&mut vm, "implicit"), Value::Nil);
+        assert_eq!(global(&mut vm, "empty"), Value::Nil);
+        assert_eq!(global(&mut vm, "early"), Value::Number(4.0));
+        assert_eq!(global(&mut vm, "fib10"), Value::Number(55.0));
+        // the caller's locals are intact after a return from inside a loop
+        vm.interpret("{ var x = 5; var y = first_over(3) + x; computed = y; }")
+            .unwrap();
+        assert_eq!(global(&mut vm, "computed"), Value::Number(7.0));
+        assert_eq!(
+            vm.interpret_line("1 + first_over(3)").unwrap(),
+            Some(Value::Number(3.0))
+        );
+    }
+
+    #[test]
+    fn test_call_arity() {
+        let mut vm = VM::new();
+        vm.interpret("var calls = 0; fun add(a, b) { calls = calls + 1; }")
+            .unwrap();
+        assert!(vm.interpret("add(1);").is_err());
+        assert!(vm.interpret("add(1, 2, 3);").is_err());
+        assert!(vm.interpret("add(1, 2);").is_ok());
+        assert_eq!(global(&mut vm, "calls"), Value::Number(1.0));
+
+        assert!(vm.interpret("clock(1);").is_err());
+        vm.interpret("var now = clock();").unwrap();
+        assert!(global(&mut vm, "now").as_number().is_some_and(|n| n > 0.0));
+    }
+
+    #[test]
+    fn test_deterministic_natives() {
+        let program = "var a = random(); var b = random(); var start = clock(); var end = clock();";
+        let run = |vm: VM| {
+            let mut vm = vm;
+            vm.interpret(program).unwrap();
+            ["a", "b", "start", "end"].map(|name| global(&mut vm, name).as_number().unwrap())
+        };
+        let [a, b, start, end] = run(VM::new().deterministic());
+        assert_eq!([a, b, start, end], run(VM::new().deterministic()));
+        assert!((0.0..1.0).contains(&a) && a != b);
+        assert_eq!((start, end), (0.001, 0.002));
+        assert_ne!(run(VM::new())[0], run(VM::new())[0]);
+    }
+
+    #[test]
+    fn test_deep_recursion_overflows() {
+        let mut vm = VM::new();
+        assert!(vm.interpret("fun f() { f(); } f();").is_err());
+    }
+
+    #[test]
+    fn test_reset_after_error() {
+        let depths = Rc::new(RefCell::new(Vec::new()));
+        let mut vm = VM::new();
+        let recorded = depths.clone();
+        vm.set_instruction_hook(Box::new(move |event| {
+            if event.offset == 0 {
+                recorded.borrow_mut().push(event.stack.len());
+            }
+        }));
+        vm.interpret_line("var a = 1;").unwrap();
+        // fails with operands of the outer additions still on the stack
+        assert!(vm.interpret_line("a = 2; 1 + (2 + (3 + -nil));").is_err());
+        // the assignment before the error stands
+        assert_eq!(
+            vm.interpret_line("a + 1").unwrap(),
+            Some(Value::Number(3.0))
+        );
+        assert_eq!(*depths.borrow(), [0, 0, 0]);
+        assert!(vm.stack.iter().all(|value| *value == Value::Nil));
+    }
+
+    #[test]
+    fn test_stack_size() {
+        let source =
+            "fun depth(n) { if (n == 0) return 0; return 1 + depth(n - 1); }\nprint depth(100);";
+        let overflow = |vm: &mut VM| match vm.interpret(source) {
+            Err(Error::Runtime(e)) => e.message == "Stack overflow.",
+            _ => false,
+        };
+        // 64 frames
+        assert!(overflow(&mut VM::new()));
+        let mut vm = VM::new()
+            .with_stack_size(128 * FRAME_SLOTS)
+            .with_output(Box::new(std::io::sink()));
+        assert!(vm.interpret(source).is_ok());
+
+        // too few slots for the values, with frames to spare
+        let mut vm = VM::new().with_stack_size(4);
+        assert!(vm.interpret("print 1 + (2 + (3 + 4));").is_ok());
+        assert!(overflow(&mut vm.with_stack_size(3)));
     }
 
-    fn print_error(&self, chunk: &Chunk<'a>, message: &str, ip: usize) {
-        eprintln!("{} [line {}] in script", message, chunk.read_line(ip));
+    #[test]
+    fn test_trace_disabled() {
+        let buf = SharedBuf::default();
+        let mut vm = VM::new().with_trace_output(Box::new(buf.clone()));
+        vm.interpret("1 + 2;").unwrap();
+        assert!(buf.contents().is_empty());
     }
 }
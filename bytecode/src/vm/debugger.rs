@@ -0,0 +1,213 @@
+//! Single-stepping through a script, one instruction at a time, using the
+//! same `step` the VM's run loop is built on.
+
+use std::rc::Rc;
+
+use super::{Flow, VMInterpreter};
+use crate::{Error, OpCode, Position, Value, globals::Globals, object::Function};
+
+/// One instruction executed by a `Debugger`
+#[derive(Debug, Clone, PartialEq)]
+pub struct StepInfo {
+    pub op: OpCode,
+    /// offset of the instruction in its function's chunk
+    pub offset: usize,
+    /// its disassembly, as `--trace` prints it
+    pub instruction: String,
+    pub position: Position,
+    /// the value stack after it ran, bottom first
+    pub stack: Vec<Value>,
+    /// the script returned; stepping again does nothing
+    pub finished: bool,
+}
+
+/// A script paused between instructions; see `VM::debug`
+pub struct Debugger<'vm> {
+    interpreter: VMInterpreter<'vm>,
+    globals: &'vm mut Globals,
+    /// the innermost frame's function, next instruction and first slot
+    function: Rc<Function>,
+    ip: usize,
+    slots: usize,
+    /// set once the script returns or fails; later steps repeat it
+    end: Option<Result<StepInfo, Error>>,
+    result: Option<Value>,
+}
+
+impl<'vm> Debugger<'vm> {
+    pub(super) fn new(interpreter: VMInterpreter<'vm>, globals: &'vm mut Globals) -> Self {
+        let function = interpreter.frames[0].function.clone();
+        Self {
+            interpreter,
+            globals,
+            function,
+            ip: 0,
+            slots: 0,
+            end: None,
+            result: None,
+        }
+    }
+
+    /// Executes the next instruction
+    pub fn step(&mut self) -> Result<StepInfo, Error> {
+        if let Some(end) = &self.end {
+            return end.clone();
+        }
+        let function = self.function.clone();
+        let offset = self.ip;
+        let flow = self
+            .interpreter
+            .step(&function.chunk, offset, self.slots, self.globals);
+        let finished = match flow {
+            Ok(Flow::Next(next)) => {
+                self.ip = next;
+                false
+            }
+            Ok(Flow::Switch) => {
+                self.load_frame();
+                false
+            }
+            Ok(Flow::Return(value)) => {
+                self.result = value;
+                true
+            }
+            Err(error) => {
+                self.end = Some(Err(error.clone()));
+                return Err(error);
+            }
+        };
+        let chunk = &function.chunk;
+        let op = OpCode::try_from(chunk.code()[offset]).expect("the instruction just ran");
+        let mut instruction = Vec::new();
+        let _ = chunk.dissassemble_instruction_to(&mut instruction, offset);
+        let info = StepInfo {
+            op,
+            offset,
+            instruction: String::from_utf8_lossy(&instruction).trim_end().to_string(),
+            position: chunk.read_position(offset),
+            stack: self.stack().to_vec(),
+            finished,
+        };
+        if finished {
+            self.end = Some(Ok(info.clone()));
+        }
+        Ok(info)
+    }
+
+    /// Steps until the next instruction is the first of a run on `line`, or
+    /// the script ends, returning the last step taken
+    pub fn run_until_line(&mut self, line: usize) -> Result<StepInfo, Error> {
+        loop {
+            let info = self.step()?;
+            if info.finished || (info.position.line != line && self.position().line == line) {
+                return Ok(info);
+            }
+        }
+    }
+
+    /// Runs the rest of the script, returning what `VM::run` would
+    pub fn continue_run(&mut self) -> Result<Option<Value>, Error> {
+        while !self.step()?.finished {}
+        Ok(self.result.clone())
+    }
+
+    /// Where the next instruction came from
+    pub fn position(&self) -> Position {
+        self.function.chunk.read_position(self.ip)
+    }
+
+    pub fn stack(&self) -> &[Value] {
+        &self.interpreter.stack[..self.interpreter.stack_top]
+    }
+
+    /// Defined globals, in the order they were first declared
+    pub fn globals(&self) -> Vec<(&str, &Value)> {
+        (0..self.globals.len())
+            .filter_map(|slot| Some((self.globals.name(slot), self.globals.get(slot)?)))
+            .collect()
+    }
+
+    fn load_frame(&mut self) {
+        let frame = self
+            .interpreter
+            .frames
+            .last()
+            .expect("the script frame is never popped");
+        self.function = frame.function.clone();
+        self.ip = frame.ip;
+        self.slots = frame.slots;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{Error, OpCode, VM, Value};
+
+    #[test]
+    fn test_step() {
+        let mut vm = VM::new();
+        let mut debugger = vm.debug("var a = 1;\nvar b = a + 2;\nprint b;").unwrap();
+        let one = Value::Number(1.0);
+        let expected = [
+            (OpCode::One, vec![one.clone()]),
+            (OpCode::DefineGlobal, vec![]),
+            (OpCode::GetGlobal, vec![one.clone()]),
+            (OpCode::Constant, vec![one.clone(), Value::Number(2.0)]),
+            (OpCode::Add, vec![Value::Number(3.0)]),
+            (OpCode::DefineGlobal, vec![]),
+        ];
+        for (op, stack) in expected {
+            let info = debugger.step().unwrap();
+            assert_eq!((info.op, info.stack), (op, stack));
+            assert!(!info.finished);
+        }
+        assert_eq!(debugger.position().line, 3);
+        assert_eq!(
+            debugger.globals()[2..],
+            [("a", &one), ("b", &Value::Number(3.0))]
+        );
+
+        let info = debugger.step().unwrap();
+        assert_eq!(info.op, OpCode::GetGlobal);
+        assert!(info.instruction.contains("OP_GET_GLOBAL"));
+        assert_eq!(debugger.step().unwrap().op, OpCode::Print);
+        let end = debugger.step().unwrap();
+        assert_eq!(end.op, OpCode::Return);
+        assert!(end.finished);
+        // stepping a finished script repeats its last step
+        assert_eq!(debugger.step().unwrap(), end);
+    }
+
+    #[test]
+    fn test_run_until_line() {
+        let mut vm = VM::new();
+        let source = "fun double(x) {\n  return x * 2;\n}\nvar r = double(3);\nprint r;";
+        let mut debugger = vm.debug(source).unwrap();
+        let info = debugger.run_until_line(2).unwrap();
+        assert_eq!(info.op, OpCode::Call);
+        assert_eq!(debugger.position().line, 2);
+        // the callee and its argument
+        assert_eq!(debugger.stack().len(), 2);
+        assert_eq!(debugger.stack()[1], Value::Number(3.0));
+
+        assert_eq!(debugger.continue_run().unwrap(), None);
+        let globals = debugger.globals();
+        assert_eq!(globals.last(), Some(&("r", &Value::Number(6.0))));
+    }
+
+    #[test]
+    fn test_step_error() {
+        let mut vm = VM::new();
+        let mut debugger = vm.debug("var a = nil;\nprint -a;").unwrap();
+        let error = loop {
+            if let Err(error) = debugger.step() {
+                break error;
+            }
+        };
+        let Error::Runtime(runtime) = &error else {
+            panic!("expected a runtime error, got {error:?}");
+        };
+        assert_eq!(runtime.line, 2);
+        assert!(matches!(debugger.step(), Err(Error::Runtime(again)) if again == *runtime));
+    }
+}
@@ -0,0 +1,99 @@
+//! Callbacks run before each instruction, for tracing, metrics and other
+//! tooling that shouldn't need its own copy of the dispatch loop.
+
+use std::io::Write;
+
+use crate::{Chunk, OpCode, Position, Value, value::ValueVec};
+
+/// The instruction about to execute
+pub struct InstructionEvent<'a> {
+    pub op: OpCode,
+    /// offset of the instruction in `chunk`
+    pub offset: usize,
+    pub position: Position,
+    /// the value stack, bottom first; its length is the stack depth
+    pub stack: &'a [Value],
+    /// the running function's chunk
+    pub chunk: &'a Chunk,
+}
+
+impl<'a> InstructionEvent<'a> {
+    /// `None` for an offset that isn't a valid instruction, which the VM
+    /// reports as an error instead of running it
+    pub(super) fn new(chunk: &'a Chunk, offset: usize, stack: &'a [Value]) -> Option<Self> {
+        let op = OpCode::try_from(*chunk.code().get(offset)?).ok()?;
+        Some(Self {
+            op,
+            offset,
+            position: chunk.read_position(offset),
+            stack,
+            chunk,
+        })
+    }
+}
+
+pub(super) type HookFn<'h> = dyn FnMut(&InstructionEvent) + 'h;
+
+pub type InstructionHook = Box<HookFn<'static>>;
+
+/// The hook behind `VM::trace_execution`: dumps the stack, then disassembles
+/// the instruction
+pub fn trace_hook(mut out: impl Write) -> impl FnMut(&InstructionEvent) {
+    move |event| {
+        let _ = writeln!(out, "          {}", ValueVec(event.stack));
+        let _ = event
+            .chunk
+            .dissassemble_instruction_to(&mut out, event.offset);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{cell::RefCell, rc::Rc};
+
+    use crate::{OpCode, VM};
+
+    #[test]
+    fn test_counting_hook() {
+        let count = Rc::new(RefCell::new(0));
+        let mut vm = VM::new();
+        let counter = count.clone();
+        vm.set_instruction_hook(Box::new(move |_| *counter.borrow_mut() += 1));
+        // 1 to set up, 13 per iteration, then 7 for the failing check and exit
+        vm.interpret("for (var i = 0; i < 3; i = i + 1) {}")
+            .unwrap();
+        assert_eq!(*count.borrow(), 1 + 3 * 13 + 7);
+
+        vm.clear_instruction_hook();
+        vm.interpret("var a = 1;").unwrap();
+        assert_eq!(*count.borrow(), 47);
+    }
+
+    #[test]
+    fn test_hook_events() {
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let mut vm = VM::new();
+        let recorded = events.clone();
+        vm.set_instruction_hook(Box::new(move |event| {
+            recorded.borrow_mut().push((
+                event.op,
+                event.offset,
+                event.position.line,
+                event.stack.len(),
+            ));
+        }));
+        vm.interpret("var a = 1;\nprint a + 2;").unwrap();
+        assert_eq!(
+            *events.borrow(),
+            [
+                (OpCode::One, 0, 1, 0),
+                (OpCode::DefineGlobal, 1, 1, 1),
+                (OpCode::GetGlobal, 4, 2, 0),
+                (OpCode::Constant, 7, 2, 1),
+                (OpCode::Add, 9, 2, 2),
+                (OpCode::Print, 10, 2, 1),
+                (OpCode::Return, 11, 2, 0),
+            ]
+        );
+    }
+}
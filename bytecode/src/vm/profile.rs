@@ -0,0 +1,134 @@
+//! Execution counts and wall time per opcode, gathered through the
+//! instruction hook.
+
+use std::{
+    cell::RefCell,
+    fmt::Display,
+    rc::Rc,
+    time::{Duration, Instant},
+};
+
+use super::{InstructionEvent, InstructionHook};
+use crate::OpCode;
+
+const OPCODES: usize = OpCode::LAST as usize + 1;
+
+#[derive(Debug, Clone)]
+pub struct Profile {
+    counts: [u64; OPCODES],
+    times: [Duration; OPCODES],
+}
+
+// arrays only derive Default up to 32 elements
+impl Default for Profile {
+    fn default() -> Self {
+        Self {
+            counts: [0; OPCODES],
+            times: [Duration::ZERO; OPCODES],
+        }
+    }
+}
+
+impl Profile {
+    pub fn count(&self, op: OpCode) -> u64 {
+        self.counts[op as usize]
+    }
+
+    /// Time from the start of each `op` to the start of the next instruction
+    pub fn time(&self, op: OpCode) -> Duration {
+        self.times[op as usize]
+    }
+
+    /// Every opcode that ran, with its count and time, slowest first
+    pub fn rows(&self) -> Vec<(OpCode, u64, Duration)> {
+        let mut rows: Vec<_> = (0..=OpCode::LAST)
+            .filter_map(|byte| OpCode::try_from(byte).ok())
+            .filter(|op| self.count(*op) > 0)
+            .map(|op| (op, self.count(op), self.time(op)))
+            .collect();
+        rows.sort_by(|a, b| b.2.cmp(&a.2).then(b.1.cmp(&a.1)));
+        rows
+    }
+
+    /// A hook that adds every instruction it sees to `profile`
+    pub(super) fn hook(profile: Rc<RefCell<Profile>>) -> InstructionHook {
+        let mut last: Option<(OpCode, Instant)> = None;
+        Box::new(move |event: &InstructionEvent| {
+            let now = Instant::now();
+            let mut profile = profile.borrow_mut();
+            // a new run starts at offset 0 with an empty stack; the time
+            // since the previous run's last instruction isn't that instruction's
+            let new_run = event.offset == 0 && event.stack.is_empty();
+            if let Some((op, start)) = last.filter(|_| !new_run) {
+                profile.times[op as usize] += now - start;
+            }
+            profile.counts[event.op as usize] += 1;
+            last = Some((event.op, now));
+        })
+    }
+}
+
+impl Display for Profile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let total: Duration = self.times.iter().sum();
+        writeln!(
+            f,
+            "{:<18} {:>12} {:>14} {:>7}",
+            "opcode", "count", "time", "%"
+        )?;
+        for (op, count, time) in self.rows() {
+            let percent = if total.is_zero() {
+                0.0
+            } else {
+                time.as_secs_f64() / total.as_secs_f64() * 100.0
+            };
+            writeln!(
+                f,
+                "{:<18} {:>12} {:>14} {:>6.2}%",
+                op.to_string(),
+                count,
+                format!("{time:.2?}"),
+                percent
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{OpCode, VM};
+
+    #[test]
+    fn test_profile_counts() {
+        let mut vm = VM::new();
+        let profile = vm.profile();
+        vm.interpret("var total = 0;\nfor (var i = 0; i < 5; i = i + 1) total = total + i;")
+            .unwrap();
+        let profile = profile.borrow();
+        // one for each increment and one for each addition to total
+        assert_eq!(profile.count(OpCode::Add), 10);
+        // the loop condition runs once more than the body
+        assert_eq!(profile.count(OpCode::Less), 6);
+        assert_eq!(profile.count(OpCode::SetGlobal), 5);
+        assert_eq!(profile.count(OpCode::Return), 1);
+        assert_eq!(profile.count(OpCode::Call), 0);
+
+        let rows = profile.rows();
+        assert!(rows.iter().all(|(_, count, _)| *count > 0));
+        assert!(rows.windows(2).all(|pair| pair[0].2 >= pair[1].2));
+        let table = profile.to_string();
+        assert!(table.contains("OP_ADD"));
+        assert!(!table.contains("OP_CALL"));
+    }
+
+    #[test]
+    fn test_profile_accumulates_across_runs() {
+        let mut vm = VM::new();
+        let profile = vm.profile();
+        vm.interpret("var b = 2; print b + 2;").unwrap();
+        vm.interpret_line("var a = 1; a + a").unwrap();
+        assert_eq!(profile.borrow().count(OpCode::Add), 2);
+        assert_eq!(profile.borrow().count(OpCode::Return), 2);
+    }
+}
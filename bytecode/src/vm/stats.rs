@@ -0,0 +1,62 @@
+//! Counters for sizing the VM for embedded scripts. They're kept only with
+//! the `stats` feature (on by default); without it every count stays 0.
+
+use std::fmt::Display;
+
+/// What a VM has done since it was created or `VM::reset_stats` was called
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Stats {
+    pub instructions: u64,
+    /// the most values on the stack at once
+    pub peak_stack: usize,
+    /// strings made while running, by concatenation or indexing; constants
+    /// are made by the compiler and don't count
+    pub strings: u64,
+    pub string_bytes: u64,
+    /// globals declared so far, natives included
+    pub globals: usize,
+}
+
+impl Display for Stats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "instructions: {}, peak stack: {}, strings: {} ({} bytes), globals: {}",
+            self.instructions, self.peak_stack, self.strings, self.string_bytes, self.globals
+        )
+    }
+}
+
+#[cfg(all(test, feature = "stats"))]
+mod test {
+    use crate::VM;
+
+    #[test]
+    fn test_stats() {
+        let mut vm = VM::new();
+        vm.interpret("var a = 1;\nvar b = a + 2;").unwrap();
+        let stats = vm.stats();
+        // OP_ONE, OP_DEFINE_GLOBAL, OP_GET_GLOBAL, OP_CONSTANT, OP_ADD,
+        // OP_DEFINE_GLOBAL, OP_RETURN
+        assert_eq!(stats.instructions, 7);
+        assert_eq!(stats.peak_stack, 2);
+        assert_eq!((stats.strings, stats.string_bytes), (0, 0));
+        // clock, random, a and b
+        assert_eq!(stats.globals, 4);
+
+        vm.interpret("var s = \"ab\";\nvar t = s + \"cd\";\nvar c = t[1] + s;")
+            .unwrap();
+        let stats = vm.stats();
+        assert_eq!(stats.instructions, 7 + 13);
+        assert_eq!((stats.strings, stats.string_bytes), (3, 4 + 1 + 3));
+        assert_eq!(stats.globals, 7);
+
+        vm.reset_stats();
+        assert_eq!(vm.stats().instructions, 0);
+        assert_eq!(vm.stats().globals, 7);
+        vm.interpret_line("fun f(x) { return x; } f(f(1))").unwrap();
+        // the outer callee, the inner callee, its argument, and the copy of
+        // it `return x` pushes
+        assert_eq!(vm.stats().peak_stack, 4);
+    }
+}
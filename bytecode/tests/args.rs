@@ -0,0 +1,150 @@
+use std::{
+    io::Write,
+    process::{Command, Output, Stdio},
+};
+
+/// The fixtures `rlox`'s tests run both backends against
+fn fixture(name: &str) -> String {
+    format!(
+        "{}/../rlox/tests/fixtures/{name}",
+        env!("CARGO_MANIFEST_DIR")
+    )
+}
+
+fn run(args: &[&str], stdin: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_bytecode"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(stdin.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+fn exit_code(args: &[&str]) -> Option<i32> {
+    let output = run(args, "");
+    assert!(output.status.success() || !output.stderr.is_empty());
+    output.status.code()
+}
+
+#[test]
+fn test_exit_codes() {
+    assert_eq!(exit_code(&[&fixture("shared.lox")]), Some(0));
+    assert_eq!(exit_code(&[&fixture("syntax_error.lox")]), Some(65));
+    assert_eq!(exit_code(&[&fixture("runtime_error.lox")]), Some(70));
+    assert_eq!(exit_code(&[&fixture("missing.lox")]), Some(74));
+    assert_eq!(exit_code(&[&fixture("missing.loxc")]), Some(74));
+    assert_eq!(exit_code(&["a.lox", "b.lox"]), Some(64));
+    assert_eq!(exit_code(&["--stack-size"]), Some(64));
+    assert_eq!(exit_code(&["--debug"]), Some(64));
+}
+
+#[test]
+fn test_invalid_bytecode_exit_code() {
+    let path = std::env::temp_dir().join(format!("exit-codes-{}.loxc", std::process::id()));
+    std::fs::write(&path, b"not bytecode").unwrap();
+    let code = exit_code(&[path.to_str().unwrap()]);
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(code, Some(65));
+}
+
+#[test]
+fn test_help_and_version() {
+    let output = run(&["--help"], "");
+    assert_eq!(output.status.code(), Some(0));
+    let help = String::from_utf8(output.stdout).unwrap();
+    assert!(help.contains("Usage: "), "{help}");
+    assert!(help.contains("-d, --disassemble"), "{help}");
+
+    let output = run(&["--version"], "");
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        concat!("bytecode ", env!("CARGO_PKG_VERSION"), "\n")
+    );
+
+    let output = run(&["--bogus", "a.lox"], "");
+    assert_eq!(output.status.code(), Some(64));
+    assert!(output.stdout.is_empty());
+    assert!(
+        String::from_utf8(output.stderr)
+            .unwrap()
+            .starts_with("unknown flag `--bogus`\nUsage: ")
+    );
+}
+
+#[test]
+fn test_script_from_stdin() {
+    // with `-`, or by piping it in without a script
+    for args in [&["-"][..], &[]] {
+        let output = run(args, "print 1 + 2;\nvar s = \"a\";\nprint -s;");
+        assert_eq!(output.status.code(), Some(70), "{args:?}");
+        assert_eq!(output.stdout, b"3\n", "{args:?}");
+        assert_eq!(
+            String::from_utf8(output.stderr).unwrap(),
+            "<stdin>:3:7: runtime error: Operand must be a number.\n[line 3:7] in script\n",
+            "{args:?}"
+        );
+
+        let output = run(args, "print 1 +;");
+        assert_eq!(output.status.code(), Some(65), "{args:?}");
+        assert_eq!(
+            String::from_utf8(output.stderr).unwrap(),
+            "<stdin>:1:10: compile error: Expected expression\n",
+            "{args:?}"
+        );
+    }
+    let output = run(&["--disassemble"], "print 1;");
+    assert_eq!(output.status.code(), Some(0));
+    assert!(!output.stdout.is_empty());
+    assert_eq!(exit_code(&["--debug", "-"]), Some(64));
+    assert_eq!(exit_code(&["--repl", "a.lox"]), Some(64));
+    assert_eq!(
+        exit_code(&["--timings", "--disassemble", "a.lox"]),
+        Some(64)
+    );
+}
+
+#[test]
+fn test_errors_name_the_script() {
+    let output = run(&[&fixture("syntax_error.lox")], "");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.starts_with(&format!(
+            "{}:1:7: scan error: Unterminated string",
+            fixture("syntax_error.lox")
+        )),
+        "{stderr}"
+    );
+
+    // warnings too, without stopping the script
+    let output = run(&["-"], "fun f() { return x; }\nprint 1;");
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "<stdin>:1:18: compile warning: Global 'x' is used but never defined.\n"
+    );
+}
+
+#[test]
+fn test_stats_after_script() {
+    let output = run(&["--stats", "-"], "print 1;");
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(output.stdout, b"1\n");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.starts_with("instructions: "), "{stderr}");
+    assert_eq!(stderr.lines().count(), 1, "{stderr}");
+
+    // and when it fails at runtime
+    let output = run(&["--stats", "-"], "print -nil;");
+    assert_eq!(output.status.code(), Some(70));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.starts_with("instructions: "), "{stderr}");
+}
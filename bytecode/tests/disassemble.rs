@@ -0,0 +1,47 @@
+use std::{fs, process::Command};
+
+use test_support::write_files;
+
+fn disassemble(args: &[&str]) -> std::process::Output {
+    Command::new(env!("CARGO_BIN_EXE_bytecode"))
+        .args(args)
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn test_disassemble_fixture() {
+    let expected = fs::read_to_string(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/disassemble/fixture.expected"
+    ))
+    .unwrap();
+    for flag in ["--disassemble", "-d"] {
+        let output = disassemble(&[flag, "tests/disassemble/fixture.lox"]);
+        assert_eq!(output.status.code(), Some(0));
+        // the fixture prints when run, so any output beyond the dump means it ran
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), expected);
+        assert!(output.stderr.is_empty());
+    }
+}
+
+#[test]
+fn test_disassemble_compile_error() {
+    let dir = write_files(
+        "bytecode-disassemble-error",
+        &[("error.lox", "print 1;\nprint ;")],
+    );
+    let path = dir.join("error.lox");
+    let output = disassemble(&["--disassemble", path.to_str().unwrap()]);
+    fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(output.status.code(), Some(65));
+    assert!(output.stdout.is_empty());
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        format!(
+            "{}:2:7: compile error: Expected expression\n",
+            path.display()
+        )
+    );
+}
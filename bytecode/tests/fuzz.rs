@@ -0,0 +1,91 @@
+//! Malformed programs must come back as `Err`, never as a panic. Every file
+//! in `tests/corpus` runs whole and truncated at each character, alongside
+//! seeded random ASCII and token soup.
+
+use std::{fs, panic};
+
+use bytecode::Lox;
+use random::Random;
+
+const TOKENS: &[&str] = &[
+    "(", ")", "{", "}", "[", "]", ",", ":", ".", "-", "+", ";", "/", "*", "%", "!", "!=", "=",
+    "==", ">", ">=", "<", "<=", "x", "y", "f", "\"s\"", "\"", "0", "1", "2.5", "and", "assert",
+    "break", "case", "class", "continue", "default", "else", "false", "fun", "if", "nil", "or",
+    "print", "return", "super", "switch", "this", "true", "var", "\n", "//",
+];
+
+fn assert_no_panic(source: &str) {
+    let owned = source.to_string();
+    if panic::catch_unwind(|| Lox::default().run(owned, false)).is_err() {
+        panic!("panicked on input:\n{source}");
+    }
+}
+
+fn corpus() -> Vec<String> {
+    let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/corpus");
+    let mut paths: Vec<_> = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    paths.sort();
+    paths
+        .into_iter()
+        .map(|path| fs::read_to_string(path).unwrap())
+        .collect()
+}
+
+#[test]
+fn test_corpus_truncations() {
+    let corpus = corpus();
+    assert!(!corpus.is_empty());
+    for source in corpus {
+        for (end, _) in source.char_indices() {
+            assert_no_panic(&source[..end]);
+        }
+        assert_no_panic(&source);
+    }
+}
+
+#[test]
+fn test_deep_nesting() {
+    // each would overflow the native stack without the compiler's depth limit
+    let patterns = [
+        ("print ", "(", "1"),
+        ("print ", "-", "1;"),
+        ("print ", "!", "nil;"),
+        ("print a", "[1", ""),
+        ("print ", "a(", ""),
+        ("", "{", ""),
+        ("", "if (1) ", "print 1;"),
+        ("", "while (nil) ", ";"),
+        ("", "fun f() {", ""),
+    ];
+    for (prefix, repeated, suffix) in patterns {
+        let source = format!("{prefix}{}{suffix}", repeated.repeat(10_000));
+        assert!(Lox::default().run(source, false).is_err(), "{repeated}");
+    }
+}
+
+#[test]
+fn test_random_ascii() {
+    let mut rng = Random::new(0x2545_f491_4f6c_dd1d);
+    for _ in 0..2000 {
+        let len = rng.below(64);
+        let source: String = (0..len).map(|_| rng.below(128) as u8 as char).collect();
+        assert_no_panic(&source);
+    }
+}
+
+#[test]
+fn test_token_soup() {
+    // no loop keywords, so nothing generated can run forever
+    let mut rng = Random::new(0x9e37_79b9_7f4a_7c15);
+    for _ in 0..2000 {
+        let len = rng.below(32);
+        let source = (0..len)
+            .map(|_| TOKENS[rng.below(TOKENS.len())])
+            .collect::<Vec<_>>()
+            .join(" ");
+        assert_no_panic(&source);
+    }
+}
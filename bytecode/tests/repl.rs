@@ -0,0 +1,57 @@
+use std::{
+    io::Write,
+    process::{Command, Stdio},
+};
+
+fn repl(input: &str) -> (String, String) {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_bytecode"))
+        // piped input would otherwise be run as a script
+        .arg("--repl")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    (
+        String::from_utf8(output.stdout).unwrap(),
+        String::from_utf8(output.stderr).unwrap(),
+    )
+}
+
+#[test]
+fn test_repl_exits_on_eof() {
+    let (stdout, stderr) = repl("print 1;\n\n   \nprint 2;");
+    assert_eq!(stdout, ">1\n>>>2\n>");
+    assert!(stderr.is_empty());
+}
+
+#[test]
+fn test_repl_continues_unfinished_entries() {
+    let (stdout, stderr) = repl(
+        "fun add(a,
+  b) {
+  return a + b;
+}
+print \"one
+two\";
+add(1,
+
+2)
+print 1 +;
+add(3, 4)",
+    );
+    // the entry is only run once it's finished, and counts as one
+    assert_eq!(stdout, ">......>..one\ntwo\n>....3\n>>7\n>");
+    assert_eq!(
+        stderr,
+        "<repl:4>:1:10: compile error: Expected expression\n"
+    );
+}
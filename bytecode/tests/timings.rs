@@ -0,0 +1,37 @@
+use std::{
+    io::Write,
+    process::{Command, Stdio},
+};
+
+#[test]
+fn test_timings() {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_bytecode"))
+        .args(["--timings", "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(b"fun f(n) { return n * 2; }\nprint f(21);\n")
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "42\n");
+
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    let mut lines = stderr.lines();
+    assert_eq!(lines.next(), Some("phase            ms"));
+    let rows: Vec<(&str, f64)> = lines
+        .map(|line| {
+            let (phase, ms) = line.split_once(' ').unwrap();
+            (phase, ms.trim().parse().unwrap())
+        })
+        .collect();
+    let phases: Vec<_> = rows.iter().map(|(phase, _)| *phase).collect();
+    assert_eq!(phases, ["compile", "execute", "total"]);
+    assert!(rows.iter().all(|(_, ms)| *ms >= 0.0), "{stderr}");
+}
@@ -0,0 +1,439 @@
+//! The command-line parsing the binaries share: each declares its flags, and
+//! gets `--help`, `--version`, a script path or `-` for stdin, and exit code
+//! 64 for anything it doesn't understand
+#![feature(duration_millis_float)]
+use std::{fmt::Display, path::PathBuf, str::FromStr};
+
+mod timings;
+
+pub use timings::{Timings, timed};
+
+/// A flag a binary accepts, like `--trace` or `--stack-size <slots>`
+#[derive(Debug, Clone, Copy)]
+pub struct Flag {
+    pub name: &'static str,
+    /// another name for it, like `-d`
+    pub short: Option<&'static str>,
+    /// what help calls the value for flags that take one, like `<slots>`
+    pub value: Option<&'static str>,
+    pub help: &'static str,
+}
+
+impl Flag {
+    /// A flag that's either given or not
+    pub const fn switch(name: &'static str, help: &'static str) -> Self {
+        Self {
+            name,
+            short: None,
+            value: None,
+            help,
+        }
+    }
+
+    /// A flag with a value, given as `--name value` or `--name=value`
+    pub const fn option(name: &'static str, value: &'static str, help: &'static str) -> Self {
+        Self {
+            value: Some(value),
+            ..Self::switch(name, help)
+        }
+    }
+
+    pub const fn short(self, short: &'static str) -> Self {
+        Self {
+            short: Some(short),
+            ..self
+        }
+    }
+
+    fn usage(&self) -> String {
+        match self.value {
+            Some(value) => format!("{} {value}", self.name),
+            None => self.name.to_string(),
+        }
+    }
+}
+
+/// What a binary accepts
+#[derive(Debug, Clone, Copy)]
+pub struct Cli {
+    /// `CARGO_PKG_NAME`, for `--version`
+    pub name: &'static str,
+    /// `CARGO_PKG_VERSION`
+    pub version: &'static str,
+    /// a line for `--help` saying what the binary does
+    pub about: &'static str,
+    pub flags: &'static [Flag],
+    /// whether the script can be followed by `--` and arguments for it
+    pub script_args: bool,
+    /// whether more than one script can be given, to run one after another
+    pub many_scripts: bool,
+    /// what the binary does when given no script, for `--help`
+    pub without_script: &'static str,
+}
+
+/// Where the program to run comes from
+#[derive(Debug, Clone, PartialEq)]
+pub enum Script {
+    Path(PathBuf),
+    /// `-`
+    Stdin,
+}
+
+/// The arguments a binary was run with, once `--help` and `--version` are
+/// handled
+#[derive(Debug, Default, PartialEq)]
+pub struct CliArgs {
+    /// in the order given; none to start the REPL
+    pub scripts: Vec<Script>,
+    /// everything after `--`, even things that look like flags
+    pub script_args: Vec<String>,
+    /// the switches given, by name
+    switches: Vec<&'static str>,
+    /// the options given, by name, with their values
+    values: Vec<(&'static str, String)>,
+}
+
+impl CliArgs {
+    /// Whether the switch `name` was given
+    pub fn flag(&self, name: &str) -> bool {
+        self.switches.contains(&name)
+    }
+
+    /// The value of the option `name`, the last one if it's given twice
+    pub fn value(&self, name: &str) -> Option<&str> {
+        self.values
+            .iter()
+            .rev()
+            .find(|(flag, _)| *flag == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// The value of the option `name` as a `T`
+    pub fn parse<T: FromStr>(&self, name: &str) -> Result<Option<T>, UsageError> {
+        self.value(name)
+            .map(|value| {
+                value
+                    .parse()
+                    .map_err(|_| UsageError(format!("invalid value `{value}` for `{name}`")))
+            })
+            .transpose()
+    }
+}
+
+/// What the command line asked for
+#[derive(Debug, PartialEq)]
+pub enum Parsed {
+    Run(CliArgs),
+    Help,
+    Version,
+}
+
+/// Arguments the binary doesn't understand
+#[derive(Debug, PartialEq)]
+pub struct UsageError(pub String);
+
+impl UsageError {
+    /// `EX_USAGE` from `sysexits.h`
+    pub const EXIT_CODE: i32 = 64;
+}
+
+impl Display for UsageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Cli {
+    /// Parses arguments, not including the program name
+    pub fn parse(&self, args: impl IntoIterator<Item = String>) -> Result<Parsed, UsageError> {
+        let mut parsed = CliArgs::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--help" | "-h" => return Ok(Parsed::Help),
+                "--version" | "-V" => return Ok(Parsed::Version),
+                "--" if self.script_args && !parsed.scripts.is_empty() => {
+                    parsed.script_args = args.by_ref().collect();
+                }
+                "--" => return Err(UsageError("unexpected `--`".to_string())),
+                "-" => parsed.add_script(self, Script::Stdin)?,
+                flag if flag.starts_with('-') => {
+                    let (name, inline) = match flag.split_once('=') {
+                        Some((name, value)) => (name, Some(value.to_string())),
+                        None => (flag, None),
+                    };
+                    let Some(flag) = self
+                        .flags
+                        .iter()
+                        .find(|f| f.name == name || f.short == Some(name))
+                    else {
+                        return Err(UsageError(format!("unknown flag `{name}`")));
+                    };
+                    match (flag.value, inline) {
+                        (None, None) => parsed.switches.push(flag.name),
+                        (None, Some(_)) => {
+                            return Err(UsageError(format!(
+                                "`{}` doesn't take a value",
+                                flag.name
+                            )));
+                        }
+                        (Some(_), Some(value)) => parsed.values.push((flag.name, value)),
+                        (Some(value), None) => match args.next() {
+                            Some(next) => parsed.values.push((flag.name, next)),
+                            None => {
+                                return Err(UsageError(format!("`{}` needs a {value}", flag.name)));
+                            }
+                        },
+                    }
+                }
+                path => parsed.add_script(self, Script::Path(PathBuf::from(path)))?,
+            }
+        }
+        Ok(Parsed::Run(parsed))
+    }
+
+    /// Parses the process's arguments, printing help or the version and
+    /// exiting if they were asked for, or the usage and exiting with 64 if
+    /// the arguments are wrong
+    pub fn args(&self) -> CliArgs {
+        match self.parse(std::env::args().skip(1)) {
+            Ok(Parsed::Run(args)) => args,
+            Ok(Parsed::Help) => {
+                print!("{}", self.help(&program()));
+                std::process::exit(0);
+            }
+            Ok(Parsed::Version) => {
+                println!("{} {}", self.name, self.version);
+                std::process::exit(0);
+            }
+            Err(e) => self.usage_error(e),
+        }
+    }
+
+    /// Prints `error` and the usage, then exits with 64; for combinations
+    /// of arguments the binary itself rejects
+    pub fn usage_error(&self, error: impl Display) -> ! {
+        eprintln!("{error}");
+        eprintln!("{}", self.usage(&program()));
+        std::process::exit(UsageError::EXIT_CODE);
+    }
+
+    /// `Usage: program [flags] [script]`
+    pub fn usage(&self, program: &str) -> String {
+        let mut usage = format!("Usage: {program}");
+        for flag in self.flags {
+            usage.push_str(&format!(" [{}]", flag.usage()));
+        }
+        let scripts = if self.many_scripts {
+            "script..."
+        } else {
+            "script"
+        };
+        if self.script_args {
+            usage.push_str(&format!(" [{scripts} [-- args...]]"));
+        } else {
+            usage.push_str(&format!(" [{scripts}]"));
+        }
+        usage
+    }
+
+    /// The usage, then what each flag does
+    pub fn help(&self, program: &str) -> String {
+        let mut help = format!("{}\n\n{}\n\n", self.about, self.usage(program));
+        help.push_str(&format!(
+            "With no script, {}; `-` reads the script from stdin.\n\n",
+            self.without_script
+        ));
+        let mut rows = vec![
+            ("-h, --help".to_string(), "Print this help"),
+            ("-V, --version".to_string(), "Print the version"),
+        ];
+        rows.extend(self.flags.iter().map(|flag| {
+            let names = match flag.short {
+                Some(short) => format!("{short}, {}", flag.usage()),
+                None => flag.usage(),
+            };
+            (names, flag.help)
+        }));
+        let width = rows.iter().map(|(names, _)| names.len()).max().unwrap_or(0);
+        help.push_str("Options:\n");
+        for (names, about) in rows {
+            help.push_str(&format!("  {names:<width$}  {about}\n"));
+        }
+        help
+    }
+}
+
+impl CliArgs {
+    /// The first script, for binaries that only take one
+    pub fn script(&self) -> Option<&Script> {
+        self.scripts.first()
+    }
+
+    /// Stdin can only be read once, so `-` can only be given once
+    fn add_script(&mut self, cli: &Cli, script: Script) -> Result<(), UsageError> {
+        let many = cli.many_scripts && !(script == Script::Stdin && self.scripts.contains(&script));
+        if !many && !self.scripts.is_empty() {
+            return Err(UsageError(format!(
+                "unexpected argument `{}`",
+                match script {
+                    Script::Path(path) => path.display().to_string(),
+                    Script::Stdin => "-".to_string(),
+                }
+            )));
+        }
+        self.scripts.push(script);
+        Ok(())
+    }
+}
+
+fn program() -> String {
+    std::env::args().next().unwrap_or_default()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const CLI: Cli = Cli {
+        name: "lox",
+        version: "1.2.3",
+        about: "Runs Lox",
+        flags: &[
+            Flag::switch("--tokens", "Print the tokens"),
+            Flag::switch("--disassemble", "Print the bytecode").short("-d"),
+            Flag::switch("--trace", "Trace execution"),
+            Flag::option("--backend", "<name>", "Which interpreter to use"),
+        ],
+        script_args: true,
+        many_scripts: false,
+        without_script: "starts a REPL",
+    };
+
+    fn parse(args: &[&str]) -> Result<Parsed, UsageError> {
+        CLI.parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    fn run(args: &[&str]) -> CliArgs {
+        match parse(args) {
+            Ok(Parsed::Run(args)) => args,
+            other => panic!("expected arguments to run with, got {other:?}"),
+        }
+    }
+
+    fn error(args: &[&str]) -> String {
+        match parse(args) {
+            Err(UsageError(message)) => message,
+            other => panic!("expected a usage error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_scripts() {
+        assert_eq!(run(&[]), CliArgs::default());
+        assert_eq!(
+            run(&["main.lox"]).script(),
+            Some(&Script::Path("main.lox".into()))
+        );
+        assert_eq!(run(&["-"]).script(), Some(&Script::Stdin));
+        let args = run(&["main.lox", "--", "--trace", "-", "x"]);
+        assert_eq!(args.script_args, ["--trace", "-", "x"]);
+        assert!(!args.flag("--trace"));
+    }
+
+    #[test]
+    fn test_flags() {
+        let args = run(&["--trace", "-d", "main.lox", "--tokens"]);
+        assert!(args.flag("--trace") && args.flag("--disassemble") && args.flag("--tokens"));
+        assert_eq!(args.script(), Some(&Script::Path("main.lox".into())));
+
+        let args = run(&["--backend", "bytecode"]);
+        assert_eq!(args.value("--backend"), Some("bytecode"));
+        assert_eq!(args.script(), None);
+        assert_eq!(
+            run(&["--backend=treewalk", "-"]).value("--backend"),
+            Some("treewalk")
+        );
+        assert_eq!(
+            run(&["--backend=a", "--backend", "b"]).value("--backend"),
+            Some("b")
+        );
+        assert_eq!(run(&["--trace"]).value("--backend"), None);
+    }
+
+    #[test]
+    fn test_parse_values() {
+        let args = run(&["--backend", "64"]);
+        assert_eq!(args.parse::<usize>("--backend"), Ok(Some(64)));
+        assert_eq!(args.parse::<usize>("--missing"), Ok(None));
+        let args = run(&["--backend", "many"]);
+        assert_eq!(
+            args.parse::<usize>("--backend"),
+            Err(UsageError(
+                "invalid value `many` for `--backend`".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_help_and_version() {
+        for args in [&["--help"][..], &["-h"], &["main.lox", "--help", "--bogus"]] {
+            assert_eq!(parse(args), Ok(Parsed::Help), "{args:?}");
+        }
+        assert_eq!(parse(&["--version"]), Ok(Parsed::Version));
+        assert_eq!(parse(&["-V", "--trace"]), Ok(Parsed::Version));
+        // after `--`, they're the script's
+        assert_eq!(run(&["a.lox", "--", "--help"]).script_args, ["--help"]);
+
+        let help = CLI.help("lox");
+        assert!(help.starts_with(
+            "Runs Lox\n\nUsage: lox [--tokens] [--disassemble] [--trace] [--backend <name>] [script [-- args...]]\n"
+        ));
+        assert!(help.contains("\n  -d, --disassemble  Print the bytecode\n"));
+        assert!(help.contains("\n  --backend <name>   Which interpreter to use\n"));
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(error(&["--bogus"]), "unknown flag `--bogus`");
+        assert_eq!(error(&["-x", "main.lox"]), "unknown flag `-x`");
+        assert_eq!(error(&["--trace=yes"]), "`--trace` doesn't take a value");
+        assert_eq!(error(&["--backend"]), "`--backend` needs a <name>");
+        assert_eq!(error(&["a.lox", "b.lox"]), "unexpected argument `b.lox`");
+        assert_eq!(error(&["a.lox", "-"]), "unexpected argument `-`");
+        // script arguments need a script to go to
+        assert_eq!(error(&["--", "x"]), "unexpected `--`");
+        let cli = Cli {
+            script_args: false,
+            ..CLI
+        };
+        assert!(cli.parse(["a.lox", "--", "x"].map(String::from)).is_err());
+        assert_eq!(cli.usage("lox").rsplit_once(' ').unwrap().1, "[script]");
+    }
+
+    #[test]
+    fn test_many_scripts() {
+        let cli = Cli {
+            many_scripts: true,
+            ..CLI
+        };
+        let parse = |args: &[&str]| cli.parse(args.iter().map(|arg| arg.to_string()));
+        let Ok(Parsed::Run(args)) = parse(&["a.lox", "-", "--trace", "b.lox", "--", "x"]) else {
+            panic!("expected arguments to run with");
+        };
+        assert_eq!(
+            args.scripts,
+            [
+                Script::Path("a.lox".into()),
+                Script::Stdin,
+                Script::Path("b.lox".into())
+            ]
+        );
+        assert_eq!(args.script_args, ["x"]);
+        assert_eq!(
+            parse(&["-", "a.lox", "-"]),
+            Err(UsageError("unexpected argument `-`".to_string()))
+        );
+        assert!(cli.usage("lox").ends_with(" [script... [-- args...]]"));
+    }
+}
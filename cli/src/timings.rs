@@ -0,0 +1,100 @@
+//! How long each phase of a run took, for the binaries' `--timings`
+use std::{
+    cell::RefCell,
+    fmt::Display,
+    time::{Duration, Instant},
+};
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Timings {
+    phases: Vec<(&'static str, Duration)>,
+}
+
+impl Timings {
+    /// No time spent yet in each of `phases`, listed in the order they run
+    pub fn new(phases: &[&'static str]) -> Self {
+        Self {
+            phases: phases
+                .iter()
+                .map(|&phase| (phase, Duration::ZERO))
+                .collect(),
+        }
+    }
+
+    /// Adds to the time spent in `phase`, so a phase that runs more than
+    /// once, like parsing each imported file, adds up
+    pub fn add(&mut self, phase: &'static str, duration: Duration) {
+        match self.phases.iter_mut().find(|(name, _)| *name == phase) {
+            Some((_, total)) => *total += duration,
+            None => self.phases.push((phase, duration)),
+        }
+    }
+
+    pub fn get(&self, phase: &str) -> Option<Duration> {
+        self.phases
+            .iter()
+            .find(|(name, _)| *name == phase)
+            .map(|(_, duration)| *duration)
+    }
+
+    pub fn total(&self) -> Duration {
+        self.phases.iter().map(|(_, duration)| *duration).sum()
+    }
+}
+
+/// A table of the phases and their times in milliseconds, then the total
+impl Display for Timings {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{:<8} {:>10}", "phase", "ms")?;
+        for (phase, duration) in &self.phases {
+            writeln!(f, "{phase:<8} {:>10.3}", duration.as_millis_f64())?;
+        }
+        write!(f, "{:<8} {:>10.3}", "total", self.total().as_millis_f64())
+    }
+}
+
+/// Runs `f`, adding how long it took to `phase` if there are `timings`;
+/// without them, it doesn't even read the clock
+pub fn timed<T>(
+    timings: Option<&RefCell<Timings>>,
+    phase: &'static str,
+    f: impl FnOnce() -> T,
+) -> T {
+    let Some(timings) = timings else {
+        return f();
+    };
+    let start = Instant::now();
+    let result = f();
+    timings.borrow_mut().add(phase, start.elapsed());
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_timings() {
+        let timings = RefCell::new(Timings::new(&["scan", "parse"]));
+        timings
+            .borrow_mut()
+            .add("parse", Duration::from_micros(1500));
+        timings
+            .borrow_mut()
+            .add("parse", Duration::from_micros(500));
+        assert_eq!(timed(Some(&timings), "run", || 1 + 1), 2);
+        assert_eq!(timed(None, "run", || 3), 3);
+
+        let timings = timings.into_inner();
+        assert_eq!(timings.get("scan"), Some(Duration::ZERO));
+        assert_eq!(timings.get("parse"), Some(Duration::from_millis(2)));
+        assert!(timings.get("run").is_some());
+        assert_eq!(timings.get("resolve"), None);
+
+        let table = Timings::new(&["scan"]);
+        assert_eq!(
+            table.to_string(),
+            "phase            ms\nscan          0.000\ntotal         0.000"
+        );
+    }
+}
@@ -0,0 +1,118 @@
+//! Times the programs in `bench/` on both backends and prints a table.
+//!
+//! `cargo run --release -p conformance --example bench -- [--runs N] [--save <file>] [--baseline <file>]`
+//!
+//! `--save` records the timings so a later run with `--baseline` can show how
+//! much each program sped up or slowed down.
+#![feature(duration_millis_float)]
+use std::{collections::HashMap, fs, path::Path, time::Instant};
+
+use conformance::{BACKENDS, Backend, run};
+
+fn main() {
+    let mut args: Vec<String> = std::env::args().collect();
+    let runs = match take_option(&mut args, "--runs").map(|n| n?.parse().ok()) {
+        None => 5,
+        Some(Some(runs)) if runs > 0 => runs,
+        Some(_) => usage(&args[0]),
+    };
+    let save = take_option(&mut args, "--save").map(|path| path.unwrap_or_else(|| usage(&args[0])));
+    let baseline = match take_option(&mut args, "--baseline") {
+        None => HashMap::new(),
+        Some(None) => usage(&args[0]),
+        Some(Some(path)) => read_baseline(Path::new(&path)),
+    };
+    if args.len() > 1 {
+        usage(&args[0]);
+    }
+
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("bench");
+    let mut programs: Vec<_> = fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "lox"))
+        .collect();
+    programs.sort();
+
+    println!(
+        "{:<12}{:>20}{:>20}{:>10}",
+        "program", "treewalk", "bytecode", "speedup"
+    );
+    let mut recorded = String::new();
+    for path in programs {
+        let name = path.file_stem().unwrap().to_string_lossy().into_owned();
+        let source = fs::read_to_string(&path).unwrap();
+        // a program the backends disagree on isn't measuring the same thing
+        let outcomes = BACKENDS.map(|backend| run(backend, &source));
+        if outcomes[0] != outcomes[1] || outcomes[0].failure.is_some() {
+            eprintln!("{name}: backends disagree: {outcomes:?}");
+            std::process::exit(1);
+        }
+        let millis = BACKENDS.map(|backend| median_millis(backend, &source, runs));
+        let mut row = format!("{name:<12}");
+        for (backend, millis) in BACKENDS.iter().zip(millis) {
+            let change = baseline
+                .get(&(name.clone(), backend.to_string()))
+                .map(|before| format!(" ({:+.0}%)", (millis / before - 1.0) * 100.0))
+                .unwrap_or_default();
+            row.push_str(&format!("{:>20}", format!("{millis:.2}ms{change}")));
+            recorded.push_str(&format!("{name} {backend} {millis}\n"));
+        }
+        println!("{row}{:>9.2}x", millis[0] / millis[1]);
+    }
+    if let Some(path) = save {
+        fs::write(&path, recorded).unwrap();
+        println!("saved timings to {path}");
+    }
+}
+
+/// The median of `runs` runs, with output thrown away so printing isn't
+/// part of the measurement
+fn median_millis(backend: Backend, source: &str, runs: usize) -> f64 {
+    let mut times: Vec<f64> = (0..runs)
+        .map(|_| {
+            let start = Instant::now();
+            match backend {
+                Backend::Treewalk => treewalk::Lox::default()
+                    .run_source(source, &mut std::io::sink())
+                    .unwrap(),
+                Backend::Bytecode => bytecode::Lox::default()
+                    .run_source(source, Box::new(std::io::sink()))
+                    .unwrap(),
+            }
+            start.elapsed().as_millis_f64()
+        })
+        .collect();
+    times.sort_by(f64::total_cmp);
+    times[times.len() / 2]
+}
+
+/// Timings saved by `--save`, by program and backend
+fn read_baseline(path: &Path) -> HashMap<(String, String), f64> {
+    let contents = fs::read_to_string(path).unwrap_or_else(|e| {
+        eprintln!("can't read baseline {}: {e}", path.display());
+        std::process::exit(1);
+    });
+    contents
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let name = fields.next()?.to_string();
+            let backend = fields.next()?.to_string();
+            Some(((name, backend), fields.next()?.parse().ok()?))
+        })
+        .collect()
+}
+
+fn usage(program: &str) -> ! {
+    println!("Usage: {program} [--runs N] [--save <file>] [--baseline <file>]");
+    std::process::exit(64);
+}
+
+/// Removes `flag` and the value after it from `args`. `Some(None)` means the
+/// flag was given without a value.
+fn take_option(args: &mut Vec<String>, flag: &str) -> Option<Option<String>> {
+    let pos = args.iter().position(|a| a == flag)?;
+    args.remove(pos);
+    Some((pos < args.len()).then(|| args.remove(pos)))
+}
@@ -0,0 +1,37 @@
+//! Entry points for the fuzz targets in `fuzz/`, which `tests/fuzz.rs` also
+//! runs over the corpora. Each takes any bytes at all and must return rather
+//! than panic.
+
+/// loop iterations and calls `treewalk_run` allows, so generated programs
+/// that loop forever still finish
+const STEPS: u64 = 10_000;
+
+/// Scans, parses and resolves with the tree-walker
+pub fn treewalk_parse(data: &[u8]) {
+    if let Ok(source) = std::str::from_utf8(data) {
+        treewalk::Lox::check(source);
+    }
+}
+
+/// Runs a whole program with the tree-walker, within a step budget
+pub fn treewalk_run(data: &[u8]) {
+    let Ok(source) = std::str::from_utf8(data) else {
+        return;
+    };
+    // an import could read any file, or block on one like /dev/stdin
+    if source.contains("import") {
+        return;
+    }
+    let lox = treewalk::Lox::builder()
+        .output(Box::new(std::io::sink()))
+        .max_steps(STEPS)
+        .build();
+    let _ = lox.interpret(source);
+}
+
+/// Compiles with the bytecode compiler without running the result
+pub fn bytecode_compile(data: &[u8]) {
+    if let Ok(source) = std::str::from_utf8(data) {
+        let _ = bytecode::VM::new().compile(source);
+    }
+}
@@ -0,0 +1,179 @@
+//! Runs Lox scripts on both backends and checks them against expectations
+//! written in their comments:
+//!
+//! - `// expect: <line>` for each line the script prints, in order
+//! - `// expect compile error` if scanning, parsing or compiling fails
+//! - `// expect runtime error` if it fails while running, after its output
+//! - `// backend: treewalk-only` (or `bytecode-only`) to skip the other one
+use std::{cell::RefCell, fmt::Display, io::Write, rc::Rc};
+
+pub mod fuzz;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Backend {
+    Treewalk,
+    Bytecode,
+}
+
+pub const BACKENDS: [Backend; 2] = [Backend::Treewalk, Backend::Bytecode];
+
+impl Display for Backend {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Backend::Treewalk => write!(f, "treewalk"),
+            Backend::Bytecode => write!(f, "bytecode"),
+        }
+    }
+}
+
+/// How a script failed; messages differ between the backends so only the
+/// phase is compared
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Failure {
+    Compile,
+    Runtime,
+}
+
+impl Failure {
+    /// Only 65 is for errors found before the program runs; failing to write
+    /// its output counts as a runtime error
+    fn from_exit_code(code: i32) -> Self {
+        if code == 65 {
+            Failure::Compile
+        } else {
+            Failure::Runtime
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Outcome {
+    pub output: String,
+    pub failure: Option<Failure>,
+}
+
+/// What a script's comments say it should do
+#[derive(Debug, PartialEq)]
+pub struct Expectation {
+    pub outcome: Outcome,
+    /// the only backend to run it on
+    pub only: Option<Backend>,
+}
+
+impl Expectation {
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let mut output = String::new();
+        let mut failure = None;
+        let mut only = None;
+        for (line, text) in source.lines().enumerate() {
+            let Some(comment) = comment(text) else {
+                continue;
+            };
+            if let Some(expected) = comment.strip_prefix("expect:") {
+                output.push_str(expected.trim());
+                output.push('\n');
+            } else if comment == "expect compile error" {
+                failure = Some(Failure::Compile);
+            } else if comment == "expect runtime error" {
+                failure = Some(Failure::Runtime);
+            } else if let Some(backend) = comment.strip_prefix("backend:") {
+                only = Some(match backend.trim() {
+                    "treewalk-only" => Backend::Treewalk,
+                    "bytecode-only" => Backend::Bytecode,
+                    other => return Err(format!("line {}: unknown backend `{other}`", line + 1)),
+                });
+            }
+        }
+        Ok(Self {
+            outcome: Outcome { output, failure },
+            only,
+        })
+    }
+
+    pub fn runs_on(&self, backend: Backend) -> bool {
+        self.only.is_none_or(|only| only == backend)
+    }
+}
+
+/// The first `//` comment on `line` that holds a marker, so a `//` in
+/// the code before it, like one in a string, isn't taken for it
+fn comment(line: &str) -> Option<&str> {
+    line.match_indices("//")
+        .map(|(i, _)| line[i + 2..].trim())
+        .find(|comment| comment.starts_with("expect") || comment.starts_with("backend:"))
+}
+
+/// A writer that can be read back after a VM takes ownership of it
+#[derive(Clone, Default)]
+struct SharedBuf(Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedBuf {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Runs `source` deterministically, so scripts can use `clock()` and
+/// `random()` and still expect the same output
+pub fn run(backend: Backend, source: &str) -> Outcome {
+    let mut out = Vec::new();
+    let failure = match backend {
+        // warnings don't change what a script does, and the expectations
+        // only cover that
+        Backend::Treewalk => match treewalk::Lox::builder()
+            .deterministic(true)
+            .build()
+            .interpret_reporting(source, &mut out, &mut |_| {})
+        {
+            Ok(()) => None,
+            Err(e) => Some(Failure::from_exit_code(e.exit_code())),
+        },
+        Backend::Bytecode => {
+            let buf = SharedBuf::default();
+            let result = bytecode::Lox::builder()
+                .deterministic(true)
+                .build()
+                .run_source(source, Box::new(buf.clone()));
+            out = buf.0.take();
+            match result {
+                Ok(()) => None,
+                Err(e) => Some(Failure::from_exit_code(e.exit_code())),
+            }
+        }
+    };
+    Outcome {
+        output: String::from_utf8_lossy(&out).into_owned(),
+        failure,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_expectation() {
+        let expectation = Expectation::parse(
+            "print 1; // expect: 1\n// backend: treewalk-only\nprint -\"a\"; // expect runtime error",
+        )
+        .unwrap();
+        assert_eq!(
+            expectation,
+            Expectation {
+                outcome: Outcome {
+                    output: "1\n".to_string(),
+                    failure: Some(Failure::Runtime),
+                },
+                only: Some(Backend::Treewalk),
+            }
+        );
+        assert!(!expectation.runs_on(Backend::Bytecode));
+        assert!(Expectation::parse("// backend: jit-only").is_err());
+        let expectation = Expectation::parse("print \"http://x\"; // expect: http://x").unwrap();
+        assert_eq!(expectation.outcome.output, "http://x\n");
+    }
+}
@@ -0,0 +1,51 @@
+use std::{fs, path::Path};
+
+use conformance::{BACKENDS, Expectation, run};
+
+#[test]
+fn test_corpus() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/corpus");
+    let mut scripts: Vec<_> = fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "lox"))
+        .collect();
+    scripts.sort();
+    assert!(!scripts.is_empty(), "no scripts in {}", dir.display());
+
+    let mut failures = Vec::new();
+    for script in &scripts {
+        let name = script.file_name().unwrap().to_string_lossy();
+        let source = fs::read_to_string(script).unwrap();
+        let expectation = match Expectation::parse(&source) {
+            Ok(expectation) => expectation,
+            Err(e) => {
+                failures.push(format!("{name}: {e}"));
+                continue;
+            }
+        };
+        for backend in BACKENDS {
+            if !expectation.runs_on(backend) {
+                continue;
+            }
+            let outcome = run(backend, &source);
+            if outcome != expectation.outcome {
+                failures.push(format!(
+                    "{name} on {backend}:\n  expected {:?}\n  got      {:?}",
+                    expectation.outcome, outcome
+                ));
+            }
+        }
+    }
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
+
+#[test]
+fn test_random_agrees() {
+    // more of the sequence than a corpus script would spell out
+    let source = "for (var i = 0; i < 1000; i = i + 1) print random();";
+    let [treewalk, bytecode] = BACKENDS.map(|backend| run(backend, source));
+    assert_eq!(treewalk.failure, None);
+    assert_eq!(treewalk.output.lines().count(), 1000);
+    assert_eq!(treewalk, bytecode);
+}
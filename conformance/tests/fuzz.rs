@@ -0,0 +1,103 @@
+//! Runs each fuzz target over the test programs of every crate, whole, cut
+//! short at each character, and with seeded random mutations, so panics are
+//! caught without the fuzzing tools.
+
+use std::{fs, panic, path::Path};
+
+use conformance::fuzz::{bytecode_compile, treewalk_parse, treewalk_run};
+use random::Random;
+
+type Target = fn(&[u8]);
+
+const TARGETS: [(&str, Target); 3] = [
+    ("treewalk_parse", treewalk_parse),
+    ("treewalk_run", treewalk_run),
+    ("bytecode_compile", bytecode_compile),
+];
+
+const MUTATIONS: usize = 1000;
+
+fn corpus() -> Vec<Vec<u8>> {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let mut paths = Vec::new();
+    for dir in ["tests/corpus", "bench", "../bytecode/tests/corpus"] {
+        for entry in fs::read_dir(root.join(dir)).unwrap() {
+            paths.push(entry.unwrap().path());
+        }
+    }
+    paths.sort();
+    paths
+        .into_iter()
+        .map(|path| fs::read(path).unwrap())
+        .collect()
+}
+
+fn assert_no_panic(target: &str, run: Target, data: &[u8]) {
+    if panic::catch_unwind(|| run(data)).is_err() {
+        panic!(
+            "{target} panicked on input:\n{}",
+            String::from_utf8_lossy(data)
+        );
+    }
+}
+
+#[test]
+fn test_corpus_truncations() {
+    let corpus = corpus();
+    assert!(!corpus.is_empty());
+    for (target, run) in TARGETS {
+        for data in &corpus {
+            for end in 0..=data.len() {
+                assert_no_panic(target, run, &data[..end]);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_corpus_mutations() {
+    let corpus = corpus();
+    for (target, run) in TARGETS {
+        let mut rng = Random::new(0x2545_f491_4f6c_dd1d);
+        for _ in 0..MUTATIONS {
+            let mut data = corpus[rng.below(corpus.len())].clone();
+            for _ in 0..1 + rng.below(4) {
+                let at = rng.below(data.len() + 1);
+                match rng.below(3) {
+                    0 => data.insert(at, rng.below(256) as u8),
+                    1 if at < data.len() => {
+                        data.remove(at);
+                    }
+                    _ => {
+                        // a copy of another stretch of the program
+                        let from = rng.below(data.len() + 1);
+                        let len = rng.below(16);
+                        let copy = data[from..(from + len).min(data.len())].to_vec();
+                        data.splice(at..at, copy);
+                    }
+                }
+            }
+            assert_no_panic(target, run, &data);
+        }
+    }
+}
+
+#[test]
+fn test_deep_nesting() {
+    // each would overflow the native stack without the parsers' depth limits
+    let patterns = [
+        ("print ", "(", "1"),
+        ("print ", "-", "1;"),
+        ("", "{", ""),
+        ("", "if (true) ", "print 1;"),
+        ("", "a = ", "1;"),
+        ("print ", "a(", ""),
+        ("", "fun f() {", ""),
+    ];
+    for (prefix, repeated, suffix) in patterns {
+        let source = format!("{prefix}{}{suffix}", repeated.repeat(10_000));
+        for (target, run) in TARGETS {
+            assert_no_panic(target, run, source.as_bytes());
+        }
+    }
+}
@@ -0,0 +1,320 @@
+//! Problems found in a Lox program, in a shape both interpreters share
+use std::fmt::{Display, Write};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// The stage of the pipeline that found a problem
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    Scan,
+    Parse,
+    Resolve,
+    /// bytecode parses and resolves in the same pass that emits code
+    Compile,
+    Runtime,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+impl Display for Phase {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Phase::Scan => write!(f, "scan"),
+            Phase::Parse => write!(f, "parse"),
+            Phase::Resolve => write!(f, "resolve"),
+            Phase::Compile => write!(f, "compile"),
+            Phase::Runtime => write!(f, "runtime"),
+        }
+    }
+}
+
+/// The files a program was loaded from, so spans can stay `Copy` and name
+/// their file with an index
+#[derive(Debug, Clone, Default)]
+pub struct SourceMap {
+    files: Vec<SourceFile>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SourceFile {
+    /// a path, or a made-up name like `<repl:3>`
+    pub name: String,
+    pub text: String,
+}
+
+impl SourceMap {
+    /// Adds a file, returning the index its spans use
+    pub fn add(&mut self, name: impl Into<String>, text: impl Into<String>) -> usize {
+        self.files.push(SourceFile {
+            name: name.into(),
+            text: text.into(),
+        });
+        self.files.len() - 1
+    }
+
+    pub fn get(&self, file: usize) -> Option<&SourceFile> {
+        self.files.get(file)
+    }
+
+    /// The file's name, or `<unknown>` for an index from another map
+    pub fn name(&self, file: usize) -> &str {
+        self.get(file).map_or("<unknown>", |f| &f.name)
+    }
+
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+}
+
+/// A range of source text. Lines and columns count from 1, and the end is
+/// exclusive, so a span that only marks a position has `end == start`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    /// index into the program's `SourceMap`
+    pub file: usize,
+    pub line: usize,
+    pub column: usize,
+    pub end_line: usize,
+    pub end_column: usize,
+}
+
+impl Span {
+    /// A position in the first file
+    pub fn point(line: usize, column: usize) -> Self {
+        Self {
+            file: 0,
+            line,
+            column,
+            end_line: line,
+            end_column: column,
+        }
+    }
+
+    pub fn in_file(self, file: usize) -> Self {
+        Self { file, ..self }
+    }
+
+    /// `file:line:column`
+    pub fn render(&self, sources: &SourceMap) -> String {
+        format!("{}:{}:{}", sources.name(self.file), self.line, self.column)
+    }
+
+    /// Covers `text`, starting at `line` and `column`
+    pub fn of(line: usize, column: usize, text: &str) -> Self {
+        let mut span = Self::point(line, column);
+        for c in text.chars() {
+            if c == '\n' {
+                span.end_line += 1;
+                span.end_column = 1;
+            } else {
+                span.end_column += 1;
+            }
+        }
+        span
+    }
+}
+
+impl Display for Span {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}:{}", self.line, self.column)
+    }
+}
+
+/// A problem kept as data until something renders it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub phase: Phase,
+    /// stable, kebab-case name for the kind of problem, like `unterminated-string`
+    pub code: &'static str,
+    pub message: String,
+    /// `None` for problems not tied to the source, like failing to write output
+    pub span: Option<Span>,
+}
+
+impl Diagnostic {
+    pub fn error(phase: Phase, code: &'static str, message: String, span: Option<Span>) -> Self {
+        Self {
+            severity: Severity::Error,
+            phase,
+            code,
+            message,
+            span,
+        }
+    }
+
+    pub fn warning(phase: Phase, code: &'static str, message: String, span: Option<Span>) -> Self {
+        Self {
+            severity: Severity::Warning,
+            ..Self::error(phase, code, message, span)
+        }
+    }
+
+    /// A single-line JSON object with `severity`, `phase`, `code`, `message`,
+    /// `file`, `line`, `column`, `end_line` and `end_column`; the positions
+    /// are null without a span
+    pub fn to_json(&self, sources: &SourceMap) -> String {
+        let mut json = format!(
+            "{{\"severity\":\"{}\",\"phase\":\"{}\",\"code\":\"{}\",\"message\":",
+            self.severity, self.phase, self.code
+        );
+        json_string(&mut json, &self.message);
+        match self.span {
+            Some(span) => {
+                json.push_str(",\"file\":");
+                json_string(&mut json, sources.name(span.file));
+                write!(
+                    json,
+                    ",\"line\":{},\"column\":{},\"end_line\":{},\"end_column\":{}}}",
+                    span.line, span.column, span.end_line, span.end_column
+                )
+            }
+            None => write!(
+                json,
+                ",\"file\":null,\"line\":null,\"column\":null,\"end_line\":null,\"end_column\":null}}"
+            ),
+        }
+        .unwrap();
+        json
+    }
+
+    /// `file:line:column: phase severity: message`, without the position if
+    /// there's no span
+    pub fn render(&self, sources: &SourceMap) -> String {
+        let message = format!("{} {}: {}", self.phase, self.severity, self.message);
+        match self.span {
+            Some(span) => format!("{}: {}", span.render(sources), message),
+            None => message,
+        }
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.span {
+            Some(span) => write!(f, "{} at {}", self.message, span),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+fn json_string(json: &mut String, s: &str) {
+    json.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if c.is_control() => write!(json, "\\u{:04x}", c as u32).unwrap(),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_span_of() {
+        assert_eq!(
+            Span::of(2, 5, "abc"),
+            Span {
+                file: 0,
+                line: 2,
+                column: 5,
+                end_line: 2,
+                end_column: 8
+            }
+        );
+        assert_eq!(
+            Span::of(1, 7, "\"a\nbc\""),
+            Span {
+                file: 0,
+                line: 1,
+                column: 7,
+                end_line: 2,
+                end_column: 4
+            }
+        );
+        assert_eq!(Span::of(3, 1, ""), Span::point(3, 1));
+    }
+
+    #[test]
+    fn test_render() {
+        let mut sources = SourceMap::default();
+        sources.add("main.lox", "print 1;");
+        let lib = sources.add("lib/helpers.lox", "print x;");
+        let diagnostic = Diagnostic::error(
+            Phase::Resolve,
+            "access-undefined",
+            "Access undeclared variable 'x'".to_string(),
+            Some(Span::point(1, 7).in_file(lib)),
+        );
+        assert_eq!(
+            diagnostic.render(&sources),
+            "lib/helpers.lox:1:7: resolve error: Access undeclared variable 'x'"
+        );
+        let diagnostic = Diagnostic {
+            span: Some(Span::point(2, 1).in_file(5)),
+            ..diagnostic
+        };
+        assert_eq!(
+            diagnostic.render(&sources),
+            "<unknown>:2:1: resolve error: Access undeclared variable 'x'"
+        );
+        let diagnostic = Diagnostic {
+            span: None,
+            ..diagnostic
+        };
+        assert_eq!(
+            diagnostic.render(&sources),
+            "resolve error: Access undeclared variable 'x'"
+        );
+    }
+
+    #[test]
+    fn test_to_json() {
+        let mut sources = SourceMap::default();
+        sources.add("a \"quoted\" name.lox", "");
+        let diagnostic = Diagnostic::error(
+            Phase::Scan,
+            "unexpected-character",
+            "Unexpected character `\"`".to_string(),
+            Some(Span::of(2, 4, "\"")),
+        );
+        assert_eq!(
+            diagnostic.to_json(&sources),
+            r#"{"severity":"error","phase":"scan","code":"unexpected-character","message":"Unexpected character `\"`","file":"a \"quoted\" name.lox","line":2,"column":4,"end_line":2,"end_column":5}"#
+        );
+        let diagnostic = Diagnostic::warning(
+            Phase::Runtime,
+            "native",
+            "tab\there\nline".to_string(),
+            None,
+        );
+        let json: serde_json::Value = serde_json::from_str(&diagnostic.to_json(&sources)).unwrap();
+        assert_eq!(json["severity"], "warning");
+        assert!(json["file"].is_null());
+        assert_eq!(json["message"], "tab\there\nline");
+        assert!(json["end_column"].is_null());
+    }
+}
@@ -0,0 +1,89 @@
+use std::{fs, io};
+
+use cli::{Cli, Flag, Script};
+use treewalk::{FormatError, SourceMap};
+
+const CLI: Cli = Cli {
+    name: env!("CARGO_PKG_NAME"),
+    version: env!("CARGO_PKG_VERSION"),
+    about: "Rewrites Lox scripts in place in the canonical layout",
+    flags: &[Flag::switch(
+        "--check",
+        "Change nothing, but list the scripts that aren't formatted and exit with 1",
+    )],
+    script_args: false,
+    many_scripts: true,
+    without_script: "formats stdin to stdout",
+};
+
+fn main() {
+    let args = CLI.args();
+    let check = args.flag("--check");
+    let scripts = if args.scripts.is_empty() {
+        vec![Script::Stdin]
+    } else {
+        args.scripts
+    };
+    // the most serious problem decides the exit code, but doesn't stop the
+    // other scripts from being formatted
+    let mut code = 0;
+    for script in &scripts {
+        if let Err(e) = format(script, check) {
+            code = code.max(e);
+        }
+    }
+    std::process::exit(code);
+}
+
+/// Formats `script`, reporting why not and returning the exit code for it
+/// if it can't be
+fn format(script: &Script, check: bool) -> Result<(), i32> {
+    let name = match script {
+        Script::Path(path) => path.display().to_string(),
+        Script::Stdin => "<stdin>".to_string(),
+    };
+    let source = match script {
+        Script::Path(path) => fs::read_to_string(path),
+        Script::Stdin => io::read_to_string(io::stdin()),
+    }
+    .map_err(|e| {
+        eprintln!("{name}: {e}");
+        74
+    })?;
+    let formatted = treewalk::format(&source).map_err(|e| {
+        report(&name, &source, &e);
+        e.exit_code()
+    })?;
+
+    if check {
+        if formatted != source {
+            println!("{name}");
+            return Err(1);
+        }
+        return Ok(());
+    }
+    match script {
+        Script::Stdin => print!("{formatted}"),
+        // left alone when already formatted, so its modification time only
+        // changes with its contents
+        Script::Path(path) if formatted != source => fs::write(path, formatted).map_err(|e| {
+            eprintln!("{name}: {e}");
+            74
+        })?,
+        Script::Path(_) => {}
+    }
+    Ok(())
+}
+
+fn report(name: &str, source: &str, e: &FormatError) {
+    match e {
+        FormatError::Invalid(e) => {
+            let mut sources = SourceMap::default();
+            sources.add(name, source);
+            for d in e.diagnostics() {
+                eprintln!("{}", d.render(&sources));
+            }
+        }
+        FormatError::Changed => eprintln!("{name}: {e}"),
+    }
+}
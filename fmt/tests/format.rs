@@ -0,0 +1,88 @@
+use std::{
+    fs,
+    io::Write,
+    path::Path,
+    process::{Command, Output, Stdio},
+};
+
+use test_support::write_files;
+
+fn lox_fmt(dir: &Path, args: &[&str], stdin: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_lox-fmt"))
+        .current_dir(dir)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(stdin.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn test_golden() {
+    let golden = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+    for name in ["spacing", "control", "comments"] {
+        let input = fs::read_to_string(golden.join(format!("{name}.lox"))).unwrap();
+        let expected = fs::read_to_string(golden.join(format!("{name}.expected"))).unwrap();
+        let output = lox_fmt(&golden, &[], &input);
+        assert_eq!(output.status.code(), Some(0), "{name}");
+        assert_eq!(
+            String::from_utf8(output.stdout).unwrap(),
+            expected,
+            "{name}"
+        );
+
+        // formatting again changes nothing
+        let output = lox_fmt(&golden, &["--check", "-"], &expected);
+        assert_eq!(output.status.code(), Some(0), "{name}");
+        assert!(output.stdout.is_empty(), "{name}");
+    }
+}
+
+#[test]
+fn test_check() {
+    let dir = write_files(
+        "lox-fmt-check",
+        &[("good.lox", "print 1;\n"), ("messy.lox", "print  1 ;")],
+    );
+    let output = lox_fmt(&dir, &["--check", "good.lox", "messy.lox"], "");
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "messy.lox\n");
+    // checking writes nothing
+    assert_eq!(
+        fs::read_to_string(dir.join("messy.lox")).unwrap(),
+        "print  1 ;"
+    );
+}
+
+#[test]
+fn test_rewrites_in_place() {
+    let dir = write_files(
+        "lox-fmt-write",
+        &[("messy.lox", "print  1 ;"), ("broken.lox", "print (1;\n")],
+    );
+    let output = lox_fmt(&dir, &["messy.lox", "broken.lox"], "");
+    // a script that doesn't parse is left alone, but the others are still
+    // formatted
+    assert_eq!(output.status.code(), Some(65));
+    assert!(output.stdout.is_empty());
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "broken.lox:1:7: parse error: Expected ')' after expression\n"
+    );
+    assert_eq!(
+        fs::read_to_string(dir.join("messy.lox")).unwrap(),
+        "print 1;\n"
+    );
+    assert_eq!(
+        fs::read_to_string(dir.join("broken.lox")).unwrap(),
+        "print (1;\n"
+    );
+}
@@ -0,0 +1,63 @@
+//! The generator behind `random()` in both interpreters, so a seed gives the
+//! same numbers on either backend
+use std::hash::{BuildHasher, RandomState};
+
+/// What `random()` starts from in deterministic runs
+pub const DETERMINISTIC_SEED: u64 = 42;
+
+/// A different seed each run
+pub fn random_seed() -> u64 {
+    RandomState::new().hash_one(0)
+}
+
+/// splitmix64, giving numbers from 0 up to but not including 1
+#[derive(Debug, Clone)]
+pub struct Random {
+    state: u64,
+}
+
+impl Random {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    pub fn next_f64(&mut self) -> f64 {
+        // the top 53 bits, as many as an f64 holds exactly
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// A number from 0 up to but not including `n`, for tests that pick
+    /// from a list
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_sequence() {
+        let numbers = |seed| {
+            let mut random = Random::new(seed);
+            (0..100).map(|_| random.next_f64()).collect::<Vec<_>>()
+        };
+        let first = numbers(DETERMINISTIC_SEED);
+        // what scripts in the conformance corpus expect
+        assert_eq!(first[..2], [0.7415648787718233, 0.1599103928769201]);
+        assert!(first.iter().all(|n| (0.0..1.0).contains(n)));
+        assert_eq!(first, numbers(DETERMINISTIC_SEED));
+        assert_ne!(first, numbers(DETERMINISTIC_SEED + 1));
+        let mut random = Random::new(DETERMINISTIC_SEED);
+        assert!((0..100).all(|_| random.below(3) < 3));
+    }
+}
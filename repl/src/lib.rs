@@ -0,0 +1,301 @@
+//! The prompt loop both interpreters run as their REPL, with line editing and
+//! history when stdin is a terminal
+use std::io::{self, BufRead, IsTerminal, Write};
+
+use rustyline::{DefaultEditor, error::ReadlineError};
+
+/// Shown when the loop is waiting for a new entry
+pub const PROMPT: &str = ">";
+/// Shown when the lines so far are an unfinished entry
+pub const CONTINUATION: &str = "..";
+
+/// What a backend did with an entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplOutcome {
+    /// The entry ran, or its errors were reported, so the next line starts a
+    /// new one
+    Done,
+    /// The entry stopped too soon, like inside a block or string, so the next
+    /// line is added to it
+    Incomplete,
+    /// End the session
+    Quit,
+}
+
+/// One read from a `LineSource`
+#[derive(Debug, PartialEq)]
+pub enum Input {
+    /// A line, without its line ending
+    Line(String),
+    /// Ctrl-C, which throws away the entry being typed
+    Interrupted,
+    /// Ctrl-D or the end of piped input, which ends the session
+    Eof,
+}
+
+/// An entry like `:save session.txt`, for the REPL itself rather than the
+/// program, since no Lox code starts with `:`
+#[derive(Debug, PartialEq)]
+pub struct Command<'a> {
+    /// the word after the `:`
+    pub name: &'a str,
+    /// the rest of the entry, trimmed
+    pub arg: &'a str,
+}
+
+impl<'a> Command<'a> {
+    pub fn parse(entry: &'a str) -> Option<Self> {
+        let rest = entry.trim().strip_prefix(':')?;
+        let (name, arg) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+        Some(Self {
+            name,
+            arg: arg.trim(),
+        })
+    }
+}
+
+/// Where the loop gets its lines from
+pub trait LineSource {
+    /// Shows `prompt` and reads a line
+    fn read_line(&mut self, prompt: &str) -> io::Result<Input>;
+
+    /// Remembers a finished entry so it can be recalled later
+    fn add_history(&mut self, _entry: &str) {}
+}
+
+/// A terminal, with raw-mode editing and history
+pub struct Terminal(DefaultEditor);
+
+impl Terminal {
+    pub fn new() -> io::Result<Self> {
+        DefaultEditor::new().map(Self).map_err(io::Error::other)
+    }
+}
+
+impl LineSource for Terminal {
+    fn read_line(&mut self, prompt: &str) -> io::Result<Input> {
+        match self.0.readline(prompt) {
+            Ok(line) => Ok(Input::Line(line)),
+            Err(ReadlineError::Interrupted) => Ok(Input::Interrupted),
+            Err(ReadlineError::Eof) => Ok(Input::Eof),
+            Err(ReadlineError::Io(e)) => Err(e),
+            Err(e) => Err(io::Error::other(e)),
+        }
+    }
+
+    fn add_history(&mut self, entry: &str) {
+        // a failure only means the entry can't be recalled
+        let _ = self.0.add_history_entry(entry);
+    }
+}
+
+/// Input that isn't a terminal, like a pipe, read a line at a time with the
+/// prompts still written to `prompts`
+pub struct Piped<R, W> {
+    input: R,
+    prompts: W,
+}
+
+impl<R: BufRead, W: Write> Piped<R, W> {
+    pub fn new(input: R, prompts: W) -> Self {
+        Self { input, prompts }
+    }
+}
+
+impl<R: BufRead, W: Write> LineSource for Piped<R, W> {
+    fn read_line(&mut self, prompt: &str) -> io::Result<Input> {
+        write!(self.prompts, "{prompt}")?;
+        self.prompts.flush()?;
+        let mut line = String::new();
+        if self.input.read_line(&mut line)? == 0 {
+            return Ok(Input::Eof);
+        }
+        let len = line.trim_end_matches(['\n', '\r']).len();
+        line.truncate(len);
+        Ok(Input::Line(line))
+    }
+}
+
+/// Reads entries from a `LineSource` and hands each one to a backend
+pub struct Repl<S> {
+    source: S,
+}
+
+impl<S: LineSource> Repl<S> {
+    pub fn new(source: S) -> Self {
+        Self { source }
+    }
+
+    /// Runs until the input ends or `eval` quits. Entries are passed to
+    /// `eval` with a newline after each of their lines; blank ones are
+    /// skipped.
+    pub fn run(&mut self, mut eval: impl FnMut(&str) -> ReplOutcome) -> io::Result<()> {
+        let mut entry = String::new();
+        loop {
+            let prompt = if entry.is_empty() {
+                PROMPT
+            } else {
+                CONTINUATION
+            };
+            let line = match self.source.read_line(prompt)? {
+                Input::Line(line) => line,
+                Input::Interrupted => {
+                    entry.clear();
+                    continue;
+                }
+                Input::Eof => return Ok(()),
+            };
+            entry.push_str(&line);
+            entry.push('\n');
+            if entry.trim().is_empty() {
+                entry.clear();
+                continue;
+            }
+            match eval(&entry) {
+                ReplOutcome::Incomplete => continue,
+                ReplOutcome::Done => {}
+                ReplOutcome::Quit => return Ok(()),
+            }
+            self.source.add_history(entry.trim_end());
+            entry.clear();
+        }
+    }
+}
+
+/// Runs `eval` on entries from stdin, editing them in the terminal if there
+/// is one
+pub fn run(eval: impl FnMut(&str) -> ReplOutcome) -> io::Result<()> {
+    if io::stdin().is_terminal() {
+        Repl::new(Terminal::new()?).run(eval)
+    } else {
+        Repl::new(Piped::new(io::stdin().lock(), io::stdout())).run(eval)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::VecDeque;
+
+    use super::*;
+
+    /// Plays back inputs, recording the prompts shown and history kept
+    #[derive(Default)]
+    struct Script {
+        inputs: VecDeque<Input>,
+        prompts: Vec<String>,
+        history: Vec<String>,
+    }
+
+    impl Script {
+        fn new(inputs: impl IntoIterator<Item = Input>) -> Self {
+            Self {
+                inputs: inputs.into_iter().collect(),
+                ..Self::default()
+            }
+        }
+    }
+
+    impl LineSource for &mut Script {
+        fn read_line(&mut self, prompt: &str) -> io::Result<Input> {
+            self.prompts.push(prompt.to_string());
+            Ok(self.inputs.pop_front().unwrap_or(Input::Eof))
+        }
+
+        fn add_history(&mut self, entry: &str) {
+            self.history.push(entry.to_string());
+        }
+    }
+
+    fn line(s: &str) -> Input {
+        Input::Line(s.to_string())
+    }
+
+    /// Treats an entry as unfinished while it has more `{` than `}`
+    fn braces(entries: &mut Vec<String>) -> impl FnMut(&str) -> ReplOutcome + '_ {
+        |entry| {
+            if entry.matches('{').count() > entry.matches('}').count() {
+                return ReplOutcome::Incomplete;
+            }
+            entries.push(entry.to_string());
+            ReplOutcome::Done
+        }
+    }
+
+    #[test]
+    fn test_continuation() {
+        let mut script = Script::new([
+            line("fun f() {"),
+            line("  return 1;"),
+            line("}"),
+            line("print f();"),
+        ]);
+        let mut entries = Vec::new();
+        Repl::new(&mut script).run(braces(&mut entries)).unwrap();
+        assert_eq!(entries, ["fun f() {\n  return 1;\n}\n", "print f();\n"]);
+        assert_eq!(script.prompts, [">", "..", "..", ">", ">"]);
+        assert_eq!(script.history, ["fun f() {\n  return 1;\n}", "print f();"]);
+    }
+
+    #[test]
+    fn test_interrupt_and_blank_lines() {
+        let mut script = Script::new([
+            line("{"),
+            Input::Interrupted,
+            line(""),
+            line("   "),
+            line("print 1;"),
+        ]);
+        let mut entries = Vec::new();
+        Repl::new(&mut script).run(braces(&mut entries)).unwrap();
+        // the interrupted entry is gone, not carried into the next one
+        assert_eq!(entries, ["print 1;\n"]);
+        assert_eq!(script.prompts, [">", "..", ">", ">", ">", ">"]);
+    }
+
+    #[test]
+    fn test_termination() {
+        // the end of input ends the session, even partway through an entry
+        let mut script = Script::new([line("{")]);
+        let mut entries = Vec::new();
+        Repl::new(&mut script).run(braces(&mut entries)).unwrap();
+        assert!(entries.is_empty());
+        assert_eq!(script.prompts, [">", ".."]);
+
+        // quitting stops reading, so the lines after it are never seen
+        let mut script = Script::new([line("quit"), line("print 1;")]);
+        Repl::new(&mut script).run(|_| ReplOutcome::Quit).unwrap();
+        assert_eq!(script.prompts, [">"]);
+        assert_eq!(script.inputs, [line("print 1;")]);
+    }
+
+    #[test]
+    fn test_command() {
+        assert_eq!(
+            Command::parse(" :save  my session.txt \n"),
+            Some(Command {
+                name: "save",
+                arg: "my session.txt"
+            })
+        );
+        assert_eq!(
+            Command::parse(":quit\n"),
+            Some(Command {
+                name: "quit",
+                arg: ""
+            })
+        );
+        assert_eq!(Command::parse("print \":\";\n"), None);
+    }
+
+    #[test]
+    fn test_piped() {
+        let mut prompts = Vec::new();
+        let mut entries = Vec::new();
+        let input = "{\r\n}\nprint 1;".as_bytes();
+        Repl::new(Piped::new(input, &mut prompts))
+            .run(braces(&mut entries))
+            .unwrap();
+        assert_eq!(entries, ["{\n}\n", "print 1;\n"]);
+        assert_eq!(String::from_utf8(prompts).unwrap(), ">..>>");
+    }
+}
@@ -0,0 +1,116 @@
+use std::{
+    fs::read_to_string,
+    io::{stdin, stdout},
+};
+
+use cli::{Cli, Flag, Script};
+use treewalk::Diagnostic;
+
+const CLI: Cli = Cli {
+    name: env!("CARGO_PKG_NAME"),
+    version: env!("CARGO_PKG_VERSION"),
+    about: "Runs a Lox script with either interpreter",
+    flags: &[
+        Flag::option(
+            "--backend",
+            "<treewalk|bytecode>",
+            "Which interpreter to use, the tree-walker by default",
+        ),
+        Flag::switch(
+            "--deterministic",
+            "Make `clock()` count calls and `random()` the same each run",
+        ),
+    ],
+    script_args: false,
+    many_scripts: false,
+    without_script: "starts a REPL",
+};
+
+/// Which interpreter runs the script or REPL
+#[derive(Clone, Copy)]
+enum Backend {
+    Treewalk,
+    Bytecode,
+}
+
+fn main() {
+    let args = CLI.args();
+    let backend = match args.value("--backend") {
+        None | Some("treewalk") => Backend::Treewalk,
+        Some("bytecode") => Backend::Bytecode,
+        Some(name) => CLI.usage_error(format!("unknown backend `{name}`")),
+    };
+    let deterministic = args.flag("--deterministic");
+    let code = match args.script() {
+        Some(script) => run(backend, script, deterministic),
+        None => prompt(backend, deterministic),
+    };
+    std::process::exit(code);
+}
+
+/// Runs a script, returning the exit code, see each backend's
+/// `Error::exit_code`
+fn run(backend: Backend, script: &Script, deterministic: bool) -> i32 {
+    match backend {
+        Backend::Treewalk => {
+            let lox = treewalk::Lox::builder()
+                .deterministic(deterministic)
+                .build();
+            // resolver warnings go to stderr, as the bytecode backend's do
+            let out = &mut stdout();
+            let warn = &mut |d: Diagnostic| eprintln!("{}", d.render(&lox.sources()));
+            let result = match script {
+                Script::Path(path) => lox.run_file_reporting(path, out, warn),
+                Script::Stdin => lox.run_stdin_reporting(out, warn),
+            };
+            match result {
+                Ok(()) => 0,
+                Err(e) => report(&e.render(&lox.sources()), e.exit_code()),
+            }
+        }
+        Backend::Bytecode => {
+            let lox = bytecode::Lox::builder()
+                .deterministic(deterministic)
+                .build()
+                .with_script_name(match script {
+                    Script::Path(path) => path.display().to_string(),
+                    Script::Stdin => "<stdin>".to_string(),
+                });
+            let contents = match script {
+                Script::Path(path) => read_to_string(path),
+                Script::Stdin => std::io::read_to_string(stdin()),
+            };
+            let result = contents
+                .map_err(bytecode::Error::from)
+                .and_then(|contents| lox.run(contents, false));
+            match result {
+                Ok(()) => 0,
+                Err(e) => report(&e.render(&lox.sources()), e.exit_code()),
+            }
+        }
+    }
+}
+
+fn prompt(backend: Backend, deterministic: bool) -> i32 {
+    let result = match backend {
+        Backend::Treewalk => treewalk::Lox::builder()
+            .deterministic(deterministic)
+            .build()
+            .run_prompt()
+            .map_err(|e| (e.to_string(), e.exit_code())),
+        Backend::Bytecode => bytecode::Lox::builder()
+            .deterministic(deterministic)
+            .build()
+            .run_prompt(false)
+            .map_err(|e| (e.to_string(), e.exit_code())),
+    };
+    match result {
+        Ok(()) => 0,
+        Err((e, code)) => report(&e, code),
+    }
+}
+
+fn report(error: &impl std::fmt::Display, code: i32) -> i32 {
+    eprintln!("{error}");
+    code
+}
@@ -0,0 +1,116 @@
+use std::process::{Command, Output};
+
+const BACKENDS: [&str; 2] = ["--backend=treewalk", "--backend=bytecode"];
+
+fn rlox(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_rlox"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+fn fixture(name: &str) -> String {
+    format!("{}/tests/fixtures/{name}", env!("CARGO_MANIFEST_DIR"))
+}
+
+#[test]
+fn test_backends_agree_on_shared_script() {
+    let script = fixture("shared.lox");
+    let expected = "hello world\n7\n3.5\n-0.5\ntrue\ntrue\ntrue\n17\n3\n2\n1\n16\n55\ndefault\nfalse\nshadow\nhello\n";
+    for backend in BACKENDS {
+        let output = rlox(&[backend, &script]);
+        assert_eq!(output.status.code(), Some(0), "{backend}");
+        assert_eq!(
+            String::from_utf8(output.stdout).unwrap(),
+            expected,
+            "{backend}"
+        );
+    }
+    // the tree-walker is the default
+    assert_eq!(rlox(&[&script]).stdout, expected.as_bytes());
+}
+
+#[test]
+fn test_backends_agree_on_exit_codes() {
+    for backend in BACKENDS {
+        assert_eq!(
+            rlox(&[backend, &fixture("syntax_error.lox")]).status.code(),
+            Some(65),
+            "{backend}"
+        );
+        let output = rlox(&[backend, &fixture("runtime_error.lox")]);
+        assert_eq!(output.status.code(), Some(70), "{backend}");
+        assert_eq!(output.stdout, b"before\n", "{backend}");
+        assert!(!output.stderr.is_empty(), "{backend}");
+        assert_eq!(
+            rlox(&[backend, &fixture("missing.lox")]).status.code(),
+            Some(74),
+            "{backend}"
+        );
+    }
+    assert_eq!(rlox(&["--backend=jit"]).status.code(), Some(64));
+    assert_eq!(rlox(&["--backend"]).status.code(), Some(64));
+}
+
+#[test]
+fn test_backends_name_the_script_in_errors() {
+    for backend in BACKENDS {
+        let script = fixture("syntax_error.lox");
+        let stderr = String::from_utf8(rlox(&[backend, &script]).stderr).unwrap();
+        assert!(
+            stderr.starts_with(&format!("{script}:1:7: scan error: Unterminated string")),
+            "{backend}: {stderr}"
+        );
+        let script = fixture("runtime_error.lox");
+        let stderr = String::from_utf8(rlox(&[backend, &script]).stderr).unwrap();
+        assert!(
+            stderr.starts_with(&format!("{script}:3:7: runtime error: ")),
+            "{backend}: {stderr}"
+        );
+    }
+}
+
+#[test]
+fn test_warnings_go_to_stderr() {
+    let script = fixture("warning.lox");
+    for backend in BACKENDS {
+        let output = rlox(&[backend, &script]);
+        assert_eq!(output.status.code(), Some(0), "{backend}");
+        assert_eq!(output.stdout, b"out\n", "{backend}");
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(stderr.contains("warning"), "{backend}: {stderr}");
+    }
+}
+
+#[test]
+fn test_deterministic() {
+    let script = fixture("random.lox");
+    let first = rlox(&[BACKENDS[0], "--deterministic", &script]);
+    assert_eq!(first.status.code(), Some(0));
+    for backend in BACKENDS {
+        // the same on every run, and on either backend
+        for _ in 0..2 {
+            let output = rlox(&[backend, "--deterministic", &script]);
+            assert_eq!(output.stdout, first.stdout, "{backend}");
+        }
+        assert_ne!(
+            rlox(&[backend, &script]).stdout,
+            rlox(&[backend, &script]).stdout,
+            "{backend}"
+        );
+    }
+}
+
+#[test]
+fn test_help_and_version() {
+    let output = rlox(&["--help"]);
+    assert_eq!(output.status.code(), Some(0));
+    let help = String::from_utf8(output.stdout).unwrap();
+    assert!(help.contains("--backend <treewalk|bytecode>"), "{help}");
+    assert_eq!(
+        rlox(&["-V"]).stdout,
+        concat!("rlox ", env!("CARGO_PKG_VERSION"), "\n").as_bytes()
+    );
+    assert_eq!(rlox(&["--trace"]).status.code(), Some(64));
+    assert_eq!(rlox(&["a.lox", "b.lox"]).status.code(), Some(64));
+}
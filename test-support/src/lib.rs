@@ -0,0 +1,23 @@
+//! What the crates' integration tests share
+use std::{fs, path::PathBuf};
+
+/// A fresh, empty directory named `name` and this process's id, so tests
+/// running at the same time, in this run or another, don't share one
+pub fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("{name}-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Writes `files` into `temp_dir(name)`; their names can include
+/// directories, which are made as needed
+pub fn write_files(name: &str, files: &[(&str, &str)]) -> PathBuf {
+    let dir = temp_dir(name);
+    for (file, source) in files {
+        let path = dir.join(file);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, source).unwrap();
+    }
+    dir
+}
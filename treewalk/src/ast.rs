@@ -1,11 +1,9 @@
 use std::{
-    cell::RefCell,
-    fmt::{Debug, Error},
+    fmt::{Debug, Display, Formatter},
     rc::Rc,
 };
 
 use crate::{
-    environment::Environment,
     location::SourceLocation,
     token::{Literal, TokenType},
 };
@@ -41,6 +39,19 @@ pub enum Expr {
         name: &'static str,
         value: Box<Expr>,
     },
+    /// `object.name`, at the name
+    Get {
+        location: SourceLocation,
+        object: Box<Expr>,
+        name: &'static str,
+    },
+    /// `object.name = value`, at the name
+    Set {
+        location: SourceLocation,
+        object: Box<Expr>,
+        name: &'static str,
+        value: Box<Expr>,
+    },
 }
 
 impl Expr {
@@ -52,21 +63,236 @@ impl Expr {
             Expr::Literal { location, .. } => *location,
             Expr::Variable { location, .. } => *location,
             Expr::Assignment { location, .. } => *location,
+            Expr::Get { location, .. } => *location,
+            Expr::Set { location, .. } => *location,
+        }
+    }
+
+    /// How tightly the expression binds, from assignment up to literals, so
+    /// printing knows where parentheses are needed
+    fn precedence(&self) -> u8 {
+        match self {
+            Expr::Assignment { .. } | Expr::Set { .. } => 1,
+            Expr::Binary { operator, .. } => match operator {
+                TokenType::Or => 2,
+                TokenType::And => 3,
+                TokenType::BangEq | TokenType::EqualEq => 4,
+                TokenType::Greater | TokenType::GreaterEq | TokenType::Less | TokenType::LessEq => {
+                    5
+                }
+                TokenType::Minus | TokenType::Plus => 6,
+                _ => 7,
+            },
+            Expr::Unary { .. } => 8,
+            Expr::Call { .. } | Expr::Get { .. } => 9,
+            Expr::Literal { .. } | Expr::Variable { .. } => 10,
+        }
+    }
+
+    /// Writes the expression, in parentheses if it binds looser than `min`
+    fn fmt_operand(&self, f: &mut Formatter<'_>, min: u8) -> std::fmt::Result {
+        if self.precedence() < min {
+            write!(f, "({self})")
+        } else {
+            write!(f, "{self}")
+        }
+    }
+}
+
+fn operator(ttype: &TokenType) -> &'static str {
+    match ttype {
+        TokenType::Plus => "+",
+        TokenType::Minus => "-",
+        TokenType::Star => "*",
+        TokenType::Slash => "/",
+        TokenType::Bang => "!",
+        TokenType::BangEq => "!=",
+        TokenType::EqualEq => "==",
+        TokenType::Greater => ">",
+        TokenType::GreaterEq => ">=",
+        TokenType::Less => "<",
+        TokenType::LessEq => "<=",
+        TokenType::And => "and",
+        TokenType::Or => "or",
+        _ => unreachable!("{ttype:?} isn't an operator"),
+    }
+}
+
+/// Prints valid Lox that parses back to the same tree, with only the
+/// parentheses precedence needs
+impl Display for Expr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Expr::Binary {
+                left,
+                operator: op,
+                right,
+                ..
+            } => {
+                // binary operators are left associative
+                left.fmt_operand(f, self.precedence())?;
+                write!(f, " {} ", operator(op))?;
+                right.fmt_operand(f, self.precedence() + 1)
+            }
+            Expr::Unary {
+                operator: op,
+                right,
+                ..
+            } => {
+                write!(f, "{}", operator(op))?;
+                right.fmt_operand(f, self.precedence() + 1)
+            }
+            Expr::Call {
+                callee, arguments, ..
+            } => {
+                callee.fmt_operand(f, self.precedence())?;
+                write!(f, "(")?;
+                for (i, argument) in arguments.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{argument}")?;
+                }
+                write!(f, ")")
+            }
+            Expr::Literal {
+                value: Literal::String(s),
+                ..
+            } => write!(f, "\"{s}\""),
+            Expr::Literal { value, .. } => write!(f, "{value}"),
+            Expr::Variable { name, .. } => write!(f, "{name}"),
+            Expr::Assignment { name, value, .. } => write!(f, "{name} = {value}"),
+            Expr::Get { object, name, .. } => {
+                object.fmt_operand(f, self.precedence())?;
+                write!(f, ".{name}")
+            }
+            Expr::Set {
+                object,
+                name,
+                value,
+                ..
+            } => {
+                // the object binds as tightly as it would in a get
+                object.fmt_operand(f, 9)?;
+                write!(f, ".{name} = {value}")
+            }
         }
     }
 }
 
-type NativeFun =
-    Box<dyn Fn(&Vec<&'static str>, Rc<RefCell<Environment>>) -> Result<Literal, Error>>;
+/// Compares structure, ignoring where in the source each node came from
+impl PartialEq for Expr {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (
+                Expr::Binary {
+                    left: l1,
+                    operator: o1,
+                    right: r1,
+                    ..
+                },
+                Expr::Binary {
+                    left: l2,
+                    operator: o2,
+                    right: r2,
+                    ..
+                },
+            ) => o1 == o2 && l1 == l2 && r1 == r2,
+            (
+                Expr::Unary {
+                    operator: o1,
+                    right: r1,
+                    ..
+                },
+                Expr::Unary {
+                    operator: o2,
+                    right: r2,
+                    ..
+                },
+            ) => o1 == o2 && r1 == r2,
+            (
+                Expr::Call {
+                    callee: c1,
+                    arguments: a1,
+                    ..
+                },
+                Expr::Call {
+                    callee: c2,
+                    arguments: a2,
+                    ..
+                },
+            ) => c1 == c2 && a1 == a2,
+            (Expr::Literal { value: v1, .. }, Expr::Literal { value: v2, .. }) => v1 == v2,
+            (Expr::Variable { name: n1, .. }, Expr::Variable { name: n2, .. }) => n1 == n2,
+            (
+                Expr::Assignment {
+                    name: n1,
+                    value: v1,
+                    ..
+                },
+                Expr::Assignment {
+                    name: n2,
+                    value: v2,
+                    ..
+                },
+            ) => n1 == n2 && v1 == v2,
+            (
+                Expr::Get {
+                    object: o1,
+                    name: n1,
+                    ..
+                },
+                Expr::Get {
+                    object: o2,
+                    name: n2,
+                    ..
+                },
+            ) => n1 == n2 && o1 == o2,
+            (
+                Expr::Set {
+                    object: o1,
+                    name: n1,
+                    value: v1,
+                    ..
+                },
+                Expr::Set {
+                    object: o2,
+                    name: n2,
+                    value: v2,
+                    ..
+                },
+            ) => n1 == n2 && o1 == o2 && v1 == v2,
+            _ => false,
+        }
+    }
+}
+
+type NativeFn = dyn Fn(&[Literal]) -> Result<Literal, String>;
 
-pub(crate) struct BuiltinFn {
+/// A function implemented in Rust and installed into the global scope
+pub(crate) struct Native {
     pub name: &'static str,
-    pub fun: NativeFun,
+    pub arity: usize,
+    pub fun: Box<NativeFn>,
+}
+
+impl Native {
+    pub fn new(
+        name: &'static str,
+        arity: usize,
+        fun: impl Fn(&[Literal]) -> Result<Literal, String> + 'static,
+    ) -> Self {
+        Self {
+            name,
+            arity,
+            fun: Box::new(fun),
+        }
+    }
 }
 
-impl Debug for BuiltinFn {
+impl Debug for Native {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "Builtin {}", self.name)
+        write!(f, "Native {}", self.name)
     }
 }
 
@@ -95,10 +321,18 @@ pub enum Stmt {
         params: Vec<&'static str>,
         body: Rc<Stmt>,
     },
+    /// `class Name { ... }`, whose methods are all `FunDecl`s
+    ClassDecl {
+        name: &'static str,
+        location: SourceLocation,
+        methods: Vec<Stmt>,
+    },
     Return(Expr),
-    Builtin {
-        params: Vec<&'static str>,
-        body: BuiltinFn,
+    /// `import "path";`, replaced by the imported file's statements before
+    /// the program runs
+    Import {
+        path: String,
+        location: SourceLocation,
     },
 }
 
@@ -118,14 +352,407 @@ impl Stmt {
                 }
             }
             Stmt::FunDecl { body, .. } => body.location(),
+            Stmt::ClassDecl { location, .. } => *location,
             Stmt::Return(expr) => expr.location(),
-            Stmt::Builtin { .. } => SourceLocation::new(0, 0),
+            Stmt::Import { location, .. } => *location,
         }
     }
+
+    /// Writes the statement as if it started `indent` levels in, which only
+    /// matters for the lines inside blocks
+    fn fmt_indented(&self, f: &mut Formatter<'_>, indent: usize) -> std::fmt::Result {
+        match self {
+            Stmt::Expression(expr) => write!(f, "{expr};"),
+            Stmt::Print(expr) => write!(f, "print {expr};"),
+            Stmt::VarDecl {
+                name,
+                initializer: Some(initializer),
+                ..
+            } => write!(f, "var {name} = {initializer};"),
+            Stmt::VarDecl { name, .. } => write!(f, "var {name};"),
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                write!(f, "if ({condition}) ")?;
+                then_branch.fmt_indented(f, indent)?;
+                if let Some(else_branch) = else_branch {
+                    write!(f, " else ")?;
+                    else_branch.fmt_indented(f, indent)?;
+                }
+                Ok(())
+            }
+            Stmt::While { condition, body } => {
+                write!(f, "while ({condition}) ")?;
+                body.fmt_indented(f, indent)
+            }
+            Stmt::Block(stmts) if stmts.is_empty() => write!(f, "{{}}"),
+            Stmt::Block(stmts) => {
+                writeln!(f, "{{")?;
+                for stmt in stmts {
+                    write!(f, "{:1$}", "", (indent + 1) * 4)?;
+                    stmt.fmt_indented(f, indent + 1)?;
+                    writeln!(f)?;
+                }
+                write!(f, "{:1$}}}", "", indent * 4)
+            }
+            Stmt::FunDecl { .. } => {
+                write!(f, "fun ")?;
+                self.fmt_function(f, indent)
+            }
+            Stmt::ClassDecl { name, methods, .. } if methods.is_empty() => {
+                write!(f, "class {name} {{}}")
+            }
+            Stmt::ClassDecl { name, methods, .. } => {
+                writeln!(f, "class {name} {{")?;
+                for method in methods {
+                    write!(f, "{:1$}", "", (indent + 1) * 4)?;
+                    method.fmt_function(f, indent + 1)?;
+                    writeln!(f)?;
+                }
+                write!(f, "{:1$}}}", "", indent * 4)
+            }
+            Stmt::Return(expr) => write!(f, "return {expr};"),
+            Stmt::Import { path, .. } => write!(f, "import \"{path}\";"),
+        }
+    }
+
+    /// Writes a `FunDecl` without its `fun`, the way methods are declared
+    fn fmt_function(&self, f: &mut Formatter<'_>, indent: usize) -> std::fmt::Result {
+        let Stmt::FunDecl { name, params, body } = self else {
+            unreachable!("{self:?} isn't a function");
+        };
+        write!(f, "{name}({}) ", params.join(", "))?;
+        body.fmt_indented(f, indent)
+    }
+}
+
+impl Display for Stmt {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        self.fmt_indented(f, 0)
+    }
 }
 
+/// Compares structure, ignoring where in the source each node came from
 impl PartialEq for Stmt {
-    fn eq(&self, _other: &Self) -> bool {
-        false
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Stmt::Expression(a), Stmt::Expression(b)) => a == b,
+            (Stmt::Print(a), Stmt::Print(b)) => a == b,
+            (
+                Stmt::VarDecl {
+                    name: n1,
+                    initializer: i1,
+                    ..
+                },
+                Stmt::VarDecl {
+                    name: n2,
+                    initializer: i2,
+                    ..
+                },
+            ) => n1 == n2 && i1 == i2,
+            (
+                Stmt::If {
+                    condition: c1,
+                    then_branch: t1,
+                    else_branch: e1,
+                },
+                Stmt::If {
+                    condition: c2,
+                    then_branch: t2,
+                    else_branch: e2,
+                },
+            ) => c1 == c2 && t1 == t2 && e1 == e2,
+            (
+                Stmt::While {
+                    condition: c1,
+                    body: b1,
+                },
+                Stmt::While {
+                    condition: c2,
+                    body: b2,
+                },
+            ) => c1 == c2 && b1 == b2,
+            (Stmt::Block(a), Stmt::Block(b)) => a == b,
+            (
+                Stmt::FunDecl {
+                    name: n1,
+                    params: p1,
+                    body: b1,
+                },
+                Stmt::FunDecl {
+                    name: n2,
+                    params: p2,
+                    body: b2,
+                },
+            ) => n1 == n2 && p1 == p2 && b1 == b2,
+            (
+                Stmt::ClassDecl {
+                    name: n1,
+                    methods: m1,
+                    ..
+                },
+                Stmt::ClassDecl {
+                    name: n2,
+                    methods: m2,
+                    ..
+                },
+            ) => n1 == n2 && m1 == m2,
+            (Stmt::Return(a), Stmt::Return(b)) => a == b,
+            (Stmt::Import { path: a, .. }, Stmt::Import { path: b, .. }) => a == b,
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{parser::Parser, scanner::Scanner};
+
+    /// Builds programs from a tape of choices, reading 0 once the tape runs
+    /// out. Choice 0 is always the simplest option, so a shorter tape or
+    /// smaller numbers give a smaller program, which is all shrinking needs.
+    struct Gen<'a> {
+        tape: &'a [u32],
+        pos: usize,
+        depth: usize,
+    }
+
+    const NAMES: [&str; 3] = ["a", "b", "f"];
+    const STRINGS: [&str; 3] = ["", "s", "hello world"];
+    const BINARY: [TokenType; 12] = [
+        TokenType::Plus,
+        TokenType::Minus,
+        TokenType::Star,
+        TokenType::Slash,
+        TokenType::BangEq,
+        TokenType::EqualEq,
+        TokenType::Greater,
+        TokenType::GreaterEq,
+        TokenType::Less,
+        TokenType::LessEq,
+        TokenType::And,
+        TokenType::Or,
+    ];
+    const MAX_DEPTH: usize = 4;
+
+    impl Gen<'_> {
+        fn choose(&mut self, n: usize) -> usize {
+            let choice = self.tape.get(self.pos).copied().unwrap_or(0) as usize % n;
+            self.pos += 1;
+            choice
+        }
+
+        fn name(&mut self) -> &'static str {
+            NAMES[self.choose(NAMES.len())]
+        }
+
+        fn nested<T>(&mut self, generate: impl FnOnce(&mut Self) -> T) -> T {
+            self.depth += 1;
+            let t = generate(self);
+            self.depth -= 1;
+            t
+        }
+
+        fn program(&mut self) -> Vec<Stmt> {
+            (0..=self.choose(4)).map(|_| self.stmt()).collect()
+        }
+
+        fn stmt(&mut self) -> Stmt {
+            let kinds = if self.depth < MAX_DEPTH { 9 } else { 3 };
+            match self.choose(kinds) {
+                0 => Stmt::Expression(self.expr()),
+                1 => Stmt::Print(self.expr()),
+                2 => Stmt::VarDecl {
+                    name: self.name(),
+                    location: SourceLocation::new(0, 0),
+                    initializer: (self.choose(2) == 1).then(|| self.expr()),
+                },
+                3 => self.nested(Self::block),
+                4 => self.nested(|g| {
+                    let condition = g.expr();
+                    let has_else = g.choose(2) == 1;
+                    // a bare `if` as the then branch would steal the else
+                    let then_branch = if has_else { g.block() } else { g.stmt() };
+                    Stmt::If {
+                        condition,
+                        then_branch: Box::new(then_branch),
+                        else_branch: has_else.then(|| Box::new(g.stmt())),
+                    }
+                }),
+                5 => self.nested(|g| Stmt::While {
+                    condition: g.expr(),
+                    body: Box::new(g.stmt()),
+                }),
+                6 => self.nested(Self::function),
+                7 => self.nested(|g| Stmt::ClassDecl {
+                    name: g.name(),
+                    location: SourceLocation::new(0, 0),
+                    methods: (0..g.choose(3)).map(|_| g.function()).collect(),
+                }),
+                _ => Stmt::Return(self.expr()),
+            }
+        }
+
+        fn function(&mut self) -> Stmt {
+            Stmt::FunDecl {
+                name: self.name(),
+                params: NAMES[..self.choose(NAMES.len() + 1)].to_vec(),
+                body: Rc::new(self.block()),
+            }
+        }
+
+        fn block(&mut self) -> Stmt {
+            Stmt::Block((0..self.choose(3)).map(|_| self.stmt()).collect())
+        }
+
+        fn expr(&mut self) -> Expr {
+            let location = SourceLocation::new(0, 0);
+            let kinds = if self.depth < MAX_DEPTH { 8 } else { 2 };
+            match self.choose(kinds) {
+                0 => Expr::Literal {
+                    location,
+                    value: match self.choose(5) {
+                        0 => Literal::Number(self.choose(100) as f64 / 4.0),
+                        1 => {
+                            Literal::String(STRINGS[self.choose(STRINGS.len())].to_string().into())
+                        }
+                        2 => Literal::True,
+                        3 => Literal::False,
+                        _ => Literal::Nil,
+                    },
+                },
+                1 => Expr::Variable {
+                    location,
+                    name: self.name(),
+                },
+                2 => self.nested(|g| Expr::Binary {
+                    location,
+                    left: Box::new(g.expr()),
+                    operator: BINARY[g.choose(BINARY.len())],
+                    right: Box::new(g.expr()),
+                }),
+                3 => self.nested(|g| Expr::Unary {
+                    location,
+                    operator: [TokenType::Minus, TokenType::Bang][g.choose(2)],
+                    right: Box::new(g.expr()),
+                }),
+                4 => self.nested(|g| Expr::Call {
+                    location,
+                    callee: Box::new(g.expr()),
+                    arguments: (0..g.choose(3)).map(|_| g.expr()).collect(),
+                }),
+                5 => self.nested(|g| Expr::Assignment {
+                    location,
+                    name: g.name(),
+                    value: Box::new(g.expr()),
+                }),
+                6 => self.nested(|g| Expr::Get {
+                    location,
+                    object: Box::new(g.expr()),
+                    name: g.name(),
+                }),
+                _ => self.nested(|g| Expr::Set {
+                    location,
+                    object: Box::new(g.expr()),
+                    name: g.name(),
+                    value: Box::new(g.expr()),
+                }),
+            }
+        }
+    }
+
+    fn generate(tape: &[u32]) -> Vec<Stmt> {
+        Gen {
+            tape,
+            pos: 0,
+            depth: 0,
+        }
+        .program()
+    }
+
+    fn print(program: &[Stmt]) -> String {
+        program.iter().map(|stmt| format!("{stmt}\n")).collect()
+    }
+
+    /// Whether printing the tape's program and parsing it back gives the
+    /// same program
+    fn round_trips(tape: &[u32]) -> bool {
+        let program = generate(tape);
+        let source: &'static str = print(&program).leak();
+        let Ok(tokens) = Scanner::new().scan(source) else {
+            return false;
+        };
+        Parser::new()
+            .parse(tokens)
+            .is_ok_and(|parsed| parsed == program)
+    }
+
+    /// The smallest tape found that still fails, by dropping choices and
+    /// making them smaller until nothing helps
+    fn shrink(mut tape: Vec<u32>) -> Vec<u32> {
+        loop {
+            let mut smaller = None;
+            for i in 0..tape.len() {
+                let mut without = tape.clone();
+                without.remove(i);
+                let mut reduced = tape.clone();
+                reduced[i] /= 2;
+                smaller = [without, reduced]
+                    .into_iter()
+                    .find(|candidate| *candidate != tape && !round_trips(candidate));
+                if smaller.is_some() {
+                    break;
+                }
+            }
+            match smaller {
+                Some(smaller) => tape = smaller,
+                None => return tape,
+            }
+        }
+    }
+
+    #[test]
+    fn test_print_parse_round_trip() {
+        // xorshift, so failures reproduce without a rand dependency
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        for run in 0..1000 {
+            let tape: Vec<u32> = (0..run % 64 + 1)
+                .map(|_| {
+                    state ^= state << 13;
+                    state ^= state >> 7;
+                    state ^= state << 17;
+                    (state >> 32) as u32
+                })
+                .collect();
+            if !round_trips(&tape) {
+                let tape = shrink(tape);
+                let program = generate(&tape);
+                let source = print(&program);
+                let parsed = Scanner::new()
+                    .scan(source.clone().leak())
+                    .map_err(|e| format!("{e:?}"))
+                    .and_then(|tokens| Parser::new().parse(tokens).map_err(|e| format!("{e:?}")));
+                panic!("{source}\nfrom {program:#?}\nparsed as {parsed:#?}\ntape {tape:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_print_parentheses() {
+        let source = "print (1 + 2) * -(a = 3) - (4 - 5);\n\
+                      (f)(1)(2, !true);\n\
+                      print a = b = c or d and e;\n\
+                      (a.b = c).d = (e).f.g;\n";
+        let tokens = Scanner::new().scan(source).unwrap();
+        let program = Parser::new().parse(tokens).unwrap();
+        let printed: String = program.iter().map(|stmt| format!("{stmt}\n")).collect();
+        assert_eq!(
+            printed,
+            "print (1 + 2) * -(a = 3) - (4 - 5);\nf(1)(2, !true);\nprint a = b = c or d and e;\n\
+             (a.b = c).d = e.f.g;\n"
+        );
     }
 }
@@ -0,0 +1,267 @@
+use diagnostic::{Diagnostic, Phase, Span};
+
+use crate::{interpreter, location::SourceLocation, parser, resolver, scanner};
+
+impl SourceLocation {
+    /// `Span` columns count from 1 where ours count from 0
+    fn span(self) -> Span {
+        Span::point(self.line(), self.column() + 1).in_file(self.file())
+    }
+
+    fn span_of(self, text: &str) -> Span {
+        Span::of(self.line(), self.column() + 1, text).in_file(self.file())
+    }
+}
+
+impl From<&scanner::Error> for Diagnostic {
+    fn from(e: &scanner::Error) -> Self {
+        let span = match e {
+            scanner::Error::UnexpectedCharacter { c, location } => {
+                location.span_of(c.encode_utf8(&mut [0; 4]))
+            }
+            _ => e.location().span(),
+        };
+        Diagnostic::error(Phase::Scan, e.code(), e.message(), Some(span))
+    }
+}
+
+impl From<&parser::Error> for Diagnostic {
+    fn from(e: &parser::Error) -> Self {
+        let span = match e {
+            parser::Error::UnexpectedToken { lexeme, location } => location.span_of(lexeme),
+            _ => e.location().span(),
+        };
+        Diagnostic::error(Phase::Parse, e.code(), e.message(), Some(span))
+    }
+}
+
+/// The resolver's problems don't stop a program from running, so they're
+/// warnings
+impl From<&resolver::Error> for Diagnostic {
+    fn from(e: &resolver::Error) -> Self {
+        Diagnostic::warning(
+            Phase::Resolve,
+            e.code(),
+            e.message(),
+            Some(e.location().span()),
+        )
+    }
+}
+
+impl From<&interpreter::Error> for Diagnostic {
+    fn from(e: &interpreter::Error) -> Self {
+        let span = e.location().map(SourceLocation::span);
+        Diagnostic::error(Phase::Runtime, e.code(), e.message(), span)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use diagnostic::Severity;
+
+    fn at(line: usize, column: usize) -> SourceLocation {
+        SourceLocation::new(line, column)
+    }
+
+    fn check(diagnostic: Diagnostic, phase: Phase, code: &str, message: &str, span: Option<Span>) {
+        assert_eq!(diagnostic.phase, phase);
+        assert_eq!(diagnostic.code, code);
+        assert_eq!(diagnostic.message, message);
+        assert_eq!(diagnostic.span, span);
+    }
+
+    #[test]
+    fn test_scanner_errors() {
+        use scanner::Error;
+        check(
+            (&Error::UnexpectedCharacter {
+                c: '@',
+                location: at(2, 3),
+            })
+                .into(),
+            Phase::Scan,
+            "unexpected-character",
+            "Unexpected character `@`",
+            Some(Span::of(2, 4, "@")),
+        );
+        check(
+            (&Error::UnterminatedString { location: at(1, 0) }).into(),
+            Phase::Scan,
+            "unterminated-string",
+            "Unterminated string",
+            Some(Span::point(1, 1)),
+        );
+        check(
+            (&Error::UnterminatedComment { location: at(4, 2) }).into(),
+            Phase::Scan,
+            "unterminated-comment",
+            "Unterminated block comment",
+            Some(Span::point(4, 3)),
+        );
+    }
+
+    #[test]
+    fn test_parser_errors() {
+        use parser::Error;
+        let location = at(1, 5);
+        let point = Some(Span::point(1, 6));
+        let cases = [
+            (
+                Error::UnterminatedParen { location },
+                "unterminated-paren",
+                "Expected ')' after expression",
+            ),
+            (
+                Error::ExpectedSemicolon { location },
+                "expected-semicolon",
+                "Expected ';' after expression",
+            ),
+            (
+                Error::UnterminatedBrace { location },
+                "unterminated-brace",
+                "Expected '}' after block",
+            ),
+            (
+                Error::ExpectedToken {
+                    expected: "(".to_string(),
+                    stmt_type: "if".to_string(),
+                    location,
+                },
+                "expected-token",
+                "Expected '(' after 'if'",
+            ),
+            (
+                Error::InvalidAssignmentTarget { location },
+                "invalid-assignment-target",
+                "Invalid assignment target",
+            ),
+            (
+                Error::TooManyArguments { location },
+                "too-many-arguments",
+                "Can't have more than 255 arguments",
+            ),
+            (
+                Error::TooManyParameters { location },
+                "too-many-parameters",
+                "Can't have more than 255 parameters",
+            ),
+            (
+                Error::ExpectedParameterName { location },
+                "expected-parameter-name",
+                "Expected parameter name",
+            ),
+            (
+                Error::ExpectedPropertyName { location },
+                "expected-property-name",
+                "Expected property name after '.'",
+            ),
+            (
+                Error::ExpectedImportPath { location },
+                "expected-import-path",
+                "Expected a path string after 'import'",
+            ),
+            (
+                Error::NestedImport { location },
+                "nested-import",
+                "Imports are only allowed at the top level",
+            ),
+            (
+                Error::TooMuchNesting { location },
+                "too-much-nesting",
+                "Too much nesting",
+            ),
+        ];
+        for (error, code, message) in cases {
+            check((&error).into(), Phase::Parse, code, message, point);
+        }
+        check(
+            (&Error::UnexpectedToken {
+                lexeme: "else".to_string(),
+                location,
+            })
+                .into(),
+            Phase::Parse,
+            "unexpected-token",
+            "Unexpected token 'else'.  Expected expression",
+            Some(Span::of(1, 6, "else")),
+        );
+    }
+
+    #[test]
+    fn test_resolver_errors() {
+        use resolver::Error;
+        let name = "a".to_string();
+        let location = at(3, 8);
+        let cases = [
+            (
+                Error::AccessInInitializer {
+                    name: name.clone(),
+                    location,
+                },
+                "access-in-initializer",
+                "Can't read local variable 'a' in its own initializer",
+            ),
+            (
+                Error::AccessUndefined {
+                    name: name.clone(),
+                    location,
+                },
+                "access-undefined",
+                "Access undeclared variable 'a'",
+            ),
+            (
+                Error::DuplicateVariable { name, location },
+                "duplicate-variable",
+                "Duplicate variable 'a' found in scope",
+            ),
+        ];
+        for (error, code, message) in cases {
+            let span = Some(Span::point(3, 9));
+            let diagnostic = Diagnostic::from(&error);
+            assert_eq!(diagnostic.severity, Severity::Warning);
+            check(diagnostic, Phase::Resolve, code, message, span);
+        }
+    }
+
+    #[test]
+    fn test_interpreter_errors() {
+        use interpreter::Error;
+        check(
+            (&Error::Runtime {
+                message: "Operands must be numbers".to_string(),
+                location: at(1, 2),
+            })
+                .into(),
+            Phase::Runtime,
+            "runtime",
+            "Operands must be numbers",
+            Some(Span::point(1, 3)),
+        );
+        check(
+            (&Error::Builtin {
+                message: "clock failed".to_string(),
+            })
+                .into(),
+            Phase::Runtime,
+            "native",
+            "clock failed",
+            None,
+        );
+        check(
+            (&Error::Parse { location: at(2, 0) }).into(),
+            Phase::Runtime,
+            "unparsed-expression",
+            "Parser failed to parse expression",
+            Some(Span::point(2, 1)),
+        );
+        let io = std::io::Error::new(std::io::ErrorKind::BrokenPipe, "closed");
+        check(
+            (&Error::Io(io)).into(),
+            Phase::Runtime,
+            "io",
+            "closed",
+            None,
+        );
+    }
+}
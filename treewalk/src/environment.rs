@@ -12,6 +12,15 @@ pub enum Error {
     ResolverError { name: String },
 }
 
+/// What looking up or assigning a name found
+#[derive(Debug, Clone)]
+pub enum Lookup {
+    Undefined,
+    /// declared with `var name;` and never assigned
+    Uninitialized,
+    Value(Literal),
+}
+
 #[derive(Debug)]
 pub struct Environment {
     parent: Option<Rc<RefCell<Environment>>>,
@@ -37,7 +46,19 @@ impl Environment {
         self.values.insert(name.to_owned(), value);
     }
 
-    pub fn get_at(&self, name: &str, depth: usize) -> Result<Option<Literal>, Error> {
+    /// This scope's own names and values, sorted by name, leaving out the
+    /// scopes around it
+    pub fn bindings(&self) -> Vec<(String, Option<Literal>)> {
+        let mut bindings: Vec<_> = self
+            .values
+            .iter()
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+        bindings.sort_by(|(a, _), (b, _)| a.cmp(b));
+        bindings
+    }
+
+    pub fn get_at(&self, name: &str, depth: usize) -> Result<Lookup, Error> {
         if depth > 0 {
             match &self.parent {
                 Some(parent) => parent.borrow().get_at(name, depth - 1),
@@ -46,21 +67,25 @@ impl Environment {
                 }),
             }
         } else {
-            self.get(name).ok_or(Error::ResolverError {
-                name: name.to_string(),
-            })
+            match self.get(name) {
+                Lookup::Undefined => Err(Error::ResolverError {
+                    name: name.to_string(),
+                }),
+                lookup => Ok(lookup),
+            }
         }
     }
 
-    pub fn get(&self, name: &str) -> Option<Option<Literal>> {
+    pub fn get(&self, name: &str) -> Lookup {
         match self.values.get(name) {
-            Some(value) => Some(value.clone()),
+            Some(Some(value)) => Lookup::Value(value.clone()),
+            Some(None) => Lookup::Uninitialized,
             None => match &self.parent {
                 Some(parent) => {
                     let parent = parent.borrow();
                     parent.get(name)
                 }
-                None => None,
+                None => Lookup::Undefined,
             },
         }
     }
@@ -79,25 +104,63 @@ impl Environment {
                 }),
             }
         } else {
-            self.update(name, value).ok_or(Error::ResolverError {
-                name: name.to_string(),
-            })
+            match self.update(name, value) {
+                Lookup::Value(value) => Ok(value),
+                _ => Err(Error::ResolverError {
+                    name: name.to_string(),
+                }),
+            }
         }
     }
 
-    pub fn update(&mut self, name: &str, value: Literal) -> Option<Literal> {
+    /// Assigns to the innermost `name`, giving back the value, or
+    /// `Undefined` if no scope declares it
+    pub fn update(&mut self, name: &str, value: Literal) -> Lookup {
         match self.values.get_mut(name) {
             Some(v) => {
                 *v = Some(value.clone());
-                Some(value)
+                Lookup::Value(value)
             }
             None => match &self.parent {
                 Some(parent) => {
                     let mut parent = parent.borrow_mut();
                     parent.update(name, value)
                 }
-                None => None,
+                None => Lookup::Undefined,
             },
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_lookup() {
+        let globals = Rc::new(RefCell::new(Environment::new()));
+        globals.borrow_mut().define("a", Some(Literal::Number(1.0)));
+        globals.borrow_mut().define("b", None);
+        let mut local = Environment::new_with_parent(globals.clone());
+        assert!(matches!(
+            local.get("a"),
+            Lookup::Value(Literal::Number(1.0))
+        ));
+        assert!(matches!(local.get("b"), Lookup::Uninitialized));
+        assert!(matches!(local.get("c"), Lookup::Undefined));
+        assert!(matches!(local.get_at("b", 1), Ok(Lookup::Uninitialized)));
+        assert!(local.get_at("c", 0).is_err());
+        assert!(local.get_at("a", 2).is_err());
+
+        assert!(matches!(
+            local.update("b", Literal::True),
+            Lookup::Value(Literal::True)
+        ));
+        assert!(matches!(
+            globals.borrow().get("b"),
+            Lookup::Value(Literal::True)
+        ));
+        assert!(matches!(local.update("c", Literal::Nil), Lookup::Undefined));
+        assert!(local.update_at("c", Literal::Nil, 0).is_err());
+    }
+}
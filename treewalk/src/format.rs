@@ -0,0 +1,282 @@
+//! The canonical layout `lox-fmt` gives a program, laid over its tokens so
+//! that the comments the parser never sees stay where they were
+use itertools::Itertools;
+use thiserror::Error;
+
+use crate::{
+    parser::Parser,
+    scanner::{Comment, Scanner},
+    token::{TokenItem, TokenType},
+};
+
+const INDENT: &str = "    ";
+
+#[derive(Error, Debug)]
+pub enum FormatError {
+    /// The source doesn't scan or parse, so it has no layout to fix
+    #[error(transparent)]
+    Invalid(#[from] crate::Error),
+
+    /// The formatted source is a different program, or lost a comment
+    #[error("Formatting would change the program, so it was left as it is")]
+    Changed,
+}
+
+impl FormatError {
+    /// 65 for a source that doesn't parse, and 70 for a bug in the formatter
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            FormatError::Invalid(e) => e.exit_code(),
+            FormatError::Changed => 70,
+        }
+    }
+}
+
+/// Lays `source` out canonically: a statement per line, blocks indented by
+/// four spaces, operators spaced, `} else {` kept together, and no more than
+/// one blank line in a row. Fails rather than return source that parses to
+/// a different program than `source` does.
+pub fn format(source: &str) -> Result<String, FormatError> {
+    let source: &'static str = source.to_string().leak();
+    let (tokens, comments) = Scanner::new()
+        .scan_with_comments(source)
+        .map_err(crate::Error::Scanner)?;
+    let program = Parser::new()
+        .parse(tokens.clone())
+        .map_err(crate::Error::Parser)?;
+
+    let mut printer = Printer::default();
+    let items = tokens
+        .iter()
+        .filter(|token| token.ttype != TokenType::EoF)
+        .map(Item::Token)
+        .merge_by(comments.iter().map(Item::Comment), |a, b| {
+            a.start() <= b.start()
+        });
+    for item in items {
+        match item {
+            Item::Token(token) => printer.token(token),
+            Item::Comment(comment) => printer.comment(comment),
+        }
+    }
+    let formatted = printer.finish();
+
+    let Ok((tokens, after)) = Scanner::new().scan_with_comments(formatted.clone().leak()) else {
+        return Err(FormatError::Changed);
+    };
+    let texts = |comments: &[Comment]| comments.iter().map(|c| c.text).collect::<Vec<_>>();
+    if Parser::new().parse(tokens).ok().as_ref() != Some(&program)
+        || texts(&after) != texts(&comments)
+    {
+        return Err(FormatError::Changed);
+    }
+    Ok(formatted)
+}
+
+enum Item<'a> {
+    Token(&'a TokenItem),
+    Comment(&'a Comment),
+}
+
+impl Item<'_> {
+    fn start(&self) -> (usize, usize) {
+        let location = match self {
+            Item::Token(token) => token.location,
+            Item::Comment(comment) => comment.location,
+        };
+        (location.line(), location.column())
+    }
+}
+
+/// Why the next item starts a new line
+#[derive(Debug, Default, PartialEq)]
+enum Newline {
+    #[default]
+    No,
+    /// A statement or block ended, though `} else` and `{}` stay together
+    Statement,
+    /// A comment ended its line
+    Comment,
+}
+
+#[derive(Default)]
+struct Printer {
+    out: String,
+    indent: usize,
+    parens: usize,
+    /// The last token written
+    prev: Option<TokenType>,
+    /// Whether `prev` was a `-` or `!` applied to what follows it
+    prev_unary: bool,
+    /// Whether a comment was written since `prev`
+    after_comment: bool,
+    /// The source line the last item written ended on
+    line: usize,
+    newline: Newline,
+}
+
+impl Printer {
+    fn token(&mut self, token: &TokenItem) {
+        let ttype = token.ttype;
+        let joins = matches!(
+            (self.prev, ttype),
+            (Some(TokenType::RightBrace), TokenType::Else)
+                | (Some(TokenType::LeftBrace), TokenType::RightBrace)
+        ) && self.newline == Newline::Statement
+            && !self.after_comment;
+        if ttype == TokenType::RightBrace {
+            self.indent = self.indent.saturating_sub(1);
+        }
+        if self.newline != Newline::No && !joins {
+            let continued = self.newline == Newline::Comment && self.mid_statement();
+            self.break_line(
+                token.location.line(),
+                ttype != TokenType::RightBrace,
+                continued,
+            );
+        } else if self.spaced(ttype) {
+            self.out.push(' ');
+        }
+        self.out.push_str(token.lexeme);
+
+        let unary = match ttype {
+            TokenType::Bang => true,
+            TokenType::Minus => !matches!(
+                self.prev,
+                Some(
+                    TokenType::Identifier
+                        | TokenType::Number
+                        | TokenType::String
+                        | TokenType::True
+                        | TokenType::False
+                        | TokenType::Nil
+                        | TokenType::This
+                        | TokenType::Super
+                        | TokenType::RightParen
+                )
+            ),
+            _ => false,
+        };
+        self.prev = Some(ttype);
+        self.prev_unary = unary;
+        self.after_comment = false;
+        self.line = token.location.line() + token.lexeme.matches('\n').count();
+        self.newline = Newline::No;
+        match ttype {
+            TokenType::LeftParen => self.parens += 1,
+            TokenType::RightParen => self.parens = self.parens.saturating_sub(1),
+            TokenType::Semicolon if self.parens == 0 => self.newline = Newline::Statement,
+            TokenType::LeftBrace => {
+                self.indent += 1;
+                self.newline = Newline::Statement;
+            }
+            TokenType::RightBrace => self.newline = Newline::Statement,
+            _ => {}
+        }
+    }
+
+    /// Comments on the same line as the code before them stay there; the
+    /// rest get a line of their own
+    fn comment(&mut self, comment: &Comment) {
+        let trailing = !self.out.is_empty() && comment.location.line() == self.line;
+        if trailing {
+            self.out.push(' ');
+        } else {
+            let continued = self.newline != Newline::Statement && self.mid_statement();
+            self.break_line(comment.location.line(), true, continued);
+        }
+        self.out.push_str(comment.text);
+        self.after_comment = true;
+        self.line = comment.location.line() + comment.text.matches('\n').count();
+        if !trailing || comment.text.starts_with("//") {
+            self.newline = Newline::Comment;
+        }
+    }
+
+    fn finish(mut self) -> String {
+        if !self.out.is_empty() {
+            self.out.push('\n');
+        }
+        self.out
+    }
+
+    /// Starts a new line for an item from source line `next`, keeping one
+    /// blank line before it if the source had any. `continued` lines are
+    /// the rest of a statement a comment broke, indented one level more.
+    fn break_line(&mut self, next: usize, blank_ok: bool, continued: bool) {
+        if self.out.is_empty() {
+            return;
+        }
+        self.out.push('\n');
+        let opened = self.prev == Some(TokenType::LeftBrace) && !self.after_comment;
+        if blank_ok && !continued && !opened && next > self.line + 1 {
+            self.out.push('\n');
+        }
+        self.out
+            .push_str(&INDENT.repeat(self.indent + continued as usize));
+    }
+
+    fn mid_statement(&self) -> bool {
+        self.parens > 0
+            || !matches!(
+                self.prev,
+                None | Some(TokenType::Semicolon | TokenType::LeftBrace | TokenType::RightBrace)
+            )
+    }
+
+    /// Whether a space goes between `prev` and a `next` on the same line
+    fn spaced(&self, next: TokenType) -> bool {
+        match (self.prev, next) {
+            (None, _) => false,
+            (
+                _,
+                TokenType::RightParen | TokenType::Comma | TokenType::Semicolon | TokenType::Dot,
+            ) => false,
+            (Some(TokenType::LeftParen | TokenType::Dot), _) => false,
+            (Some(TokenType::LeftBrace), TokenType::RightBrace) => false,
+            // `- -x`, which reads better than `--x`
+            (Some(TokenType::Minus), TokenType::Minus) => true,
+            // calls
+            (Some(TokenType::Identifier | TokenType::RightParen), TokenType::LeftParen) => false,
+            _ => !self.prev_unary,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_format() {
+        assert_eq!(
+            format("var   x=1+-2*(3 -4);print x ;").unwrap(),
+            "var x = 1 + -2 * (3 - 4);\nprint x;\n"
+        );
+        assert_eq!(
+            format("fun f (a,b){return a-b;}\nprint f(1,-2)-f (3,!true);").unwrap(),
+            "fun f(a, b) {\n    return a - b;\n}\nprint f(1, -2) - f(3, !true);\n"
+        );
+        assert_eq!(
+            format("if(a){}\nelse if (b) {print 1;}else{ print 2; }").unwrap(),
+            "if (a) {} else if (b) {\n    print 1;\n} else {\n    print 2;\n}\n"
+        );
+        assert_eq!(
+            format("for(var i=0;i<3;i=i+1)print i;for(;;){}").unwrap(),
+            "for (var i = 0; i < 3; i = i + 1) print i;\nfor (;;) {}\n"
+        );
+        assert_eq!(format("").unwrap(), "");
+    }
+
+    #[test]
+    fn test_format_errors() {
+        assert!(matches!(
+            format("print 1"),
+            Err(FormatError::Invalid(crate::Error::Parser(_)))
+        ));
+        assert!(matches!(
+            format("print @;"),
+            Err(FormatError::Invalid(crate::Error::Scanner(_)))
+        ));
+    }
+}
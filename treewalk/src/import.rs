@@ -0,0 +1,106 @@
+use std::{
+    cell::RefCell,
+    collections::HashSet,
+    path::{Path, PathBuf},
+};
+
+use cli::{Timings, timed};
+
+use crate::{Error, SourceMap, ast::Stmt, parser::Parser, scanner::Scanner};
+
+/// Loads a program's files, splicing each imported file's statements in
+/// place of its first `import` so the whole program resolves and runs as one
+pub(crate) struct Loader<'a> {
+    /// every source scanned, which locations' `file` indexes
+    sources: &'a RefCell<SourceMap>,
+    /// where scanning and parsing times go, if they're timed
+    timings: Option<&'a RefCell<Timings>>,
+    /// canonical paths of the files being loaded, outermost first
+    loading: Vec<PathBuf>,
+    /// canonical paths already spliced in, which later imports skip
+    loaded: HashSet<PathBuf>,
+}
+
+impl<'a> Loader<'a> {
+    pub(crate) fn new(
+        sources: &'a RefCell<SourceMap>,
+        timings: Option<&'a RefCell<Timings>>,
+    ) -> Self {
+        Self {
+            sources,
+            timings,
+            loading: Vec::new(),
+            loaded: HashSet::new(),
+        }
+    }
+
+    /// Loads source that isn't from a file, like a REPL line, under a made-up
+    /// `name`; its imports are relative to `dir`
+    pub(crate) fn load_source(
+        &mut self,
+        source: &str,
+        name: &str,
+        dir: &Path,
+    ) -> Result<Vec<Stmt>, Error> {
+        let stmts = self.parse(source, name)?;
+        self.expand(stmts, dir)
+    }
+
+    /// Loads a file and everything it imports, or nothing if it's already loaded
+    pub(crate) fn load_file(&mut self, path: &Path) -> Result<Vec<Stmt>, Error> {
+        let canonical = path.canonicalize()?;
+        if self.loaded.contains(&canonical) {
+            return Ok(Vec::new());
+        }
+        if let Some(start) = self.loading.iter().position(|p| *p == canonical) {
+            let mut chain = self.loading[start..].to_vec();
+            chain.push(canonical);
+            return Err(Error::ImportCycle(chain));
+        }
+        let source = std::fs::read_to_string(&canonical)?;
+        self.loading.push(canonical);
+        // named and resolved relative to the path as given, which reads better
+        // in errors than the canonical one
+        let dir = path.parent().unwrap_or(Path::new(""));
+        let stmts = self
+            .parse(&source, &path.display().to_string())
+            .and_then(|stmts| self.expand(stmts, dir));
+        if let Some(canonical) = self.loading.pop() {
+            self.loaded.insert(canonical);
+        }
+        stmts
+    }
+
+    fn parse(&mut self, source: &str, name: &str) -> Result<Vec<Stmt>, Error> {
+        let file = self.sources.borrow_mut().add(name, source);
+        // because lexemes are stored as &static str to reduce allocations, leak the contents
+        let source: &'static str = source.to_string().leak();
+        let tokens = timed(self.timings, "scan", || {
+            Scanner::for_file(file).scan(source)
+        })
+        .map_err(Error::Scanner)?;
+        timed(self.timings, "parse", || Parser::new().parse(tokens)).map_err(Error::Parser)
+    }
+
+    fn expand(&mut self, stmts: Vec<Stmt>, dir: &Path) -> Result<Vec<Stmt>, Error> {
+        let mut program = Vec::with_capacity(stmts.len());
+        for stmt in stmts {
+            let Stmt::Import { path, .. } = stmt else {
+                program.push(stmt);
+                continue;
+            };
+            let path = dir.join(path);
+            match self.load_file(&path) {
+                Ok(stmts) => program.extend(stmts),
+                Err(e @ Error::ImportCycle(_)) => return Err(e),
+                Err(e) => {
+                    return Err(Error::Import {
+                        path,
+                        error: Box::new(e),
+                    });
+                }
+            }
+        }
+        Ok(program)
+    }
+}
@@ -1,21 +1,23 @@
 use std::{
-    cell::RefCell,
+    cell::{Cell, RefCell},
     cmp::Ordering,
     collections::HashMap,
+    io::{BufRead, Write},
     rc::Rc,
-    time::{SystemTime, UNIX_EPOCH},
 };
 
 use crate::{
-    ast::{BuiltinFn, Expr, Stmt},
-    environment::Environment,
+    ast::{Expr, Native, Stmt},
+    environment::{Environment, Lookup},
     location::SourceLocation,
-    token::{Literal, TokenType},
+    token::{Class, Instance, Literal, TokenType},
 };
 
+use random::{Random, random_seed};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
+#[non_exhaustive]
 pub enum Error {
     #[error("Runtime Error: {message} at {location}")]
     Runtime {
@@ -28,6 +30,55 @@ pub enum Error {
 
     #[error("Parser failed to parse expression at {location}")]
     Parse { location: SourceLocation },
+
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+impl Error {
+    pub fn message(&self) -> String {
+        match self {
+            Error::Runtime { message, .. } | Error::Builtin { message } => message.clone(),
+            Error::Parse { .. } => "Parser failed to parse expression".to_string(),
+            Error::Io(e) => e.to_string(),
+        }
+    }
+
+    /// Stable name for the kind of error, see `Diagnostic::code`
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::Runtime { .. } => "runtime",
+            Error::Builtin { .. } => "native",
+            Error::Parse { .. } => "unparsed-expression",
+            Error::Io(_) => "io",
+        }
+    }
+
+    pub fn location(&self) -> Option<SourceLocation> {
+        match self {
+            Error::Runtime { location, .. } | Error::Parse { location } => Some(*location),
+            Error::Builtin { .. } | Error::Io(_) => None,
+        }
+    }
+}
+
+// by hand because `io::Error` isn't PartialEq; those compare by kind
+impl PartialEq for Error {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (
+                Self::Runtime { message, location },
+                Self::Runtime {
+                    message: other_message,
+                    location: other_location,
+                },
+            ) => message == other_message && location == other_location,
+            (Self::Builtin { message }, Self::Builtin { message: other }) => message == other,
+            (Self::Parse { location }, Self::Parse { location: other }) => location == other,
+            (Self::Io(e), Self::Io(other)) => e.kind() == other.kind(),
+            _ => false,
+        }
+    }
 }
 
 #[derive(Clone, Copy)]
@@ -36,12 +87,47 @@ enum FunctionType {
     None,
 }
 
+/// deeper recursion is a runtime error; calls grow the native stack as they
+/// go, so this bounds memory rather than the thread's stack size
+const MAX_CALL_DEPTH: usize = 4096;
+
+/// A call starts a new native stack segment when less than this is left,
+/// enough for a body nested as deeply as the parser allows in a debug build
+const STACK_RED_ZONE: usize = 1024 * 1024;
+const STACK_SEGMENT: usize = 8 * 1024 * 1024;
+
+/// What a running program carries through every statement and expression
+struct Context {
+    function_stack: Vec<FunctionType>,
+    /// loop iterations and calls left before the program is stopped, if
+    /// it has a budget
+    steps_left: Option<u64>,
+}
+
+impl Context {
+    /// Counts a loop iteration or call against the budget
+    fn step(&mut self, location: SourceLocation) -> Result<(), Error> {
+        match &mut self.steps_left {
+            Some(0) => Err(Error::Runtime {
+                message: "Execution budget exceeded".to_string(),
+                location,
+            }),
+            Some(left) => {
+                *left -= 1;
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+}
+
 trait EvaluateExpr {
     fn evaluate(
         &self,
         environment: Rc<RefCell<Environment>>,
         locals: &HashMap<SourceLocation, usize>,
-        function_stack: &mut Vec<FunctionType>,
+        context: &mut Context,
+        out: &mut dyn Write,
     ) -> Result<Literal, Error>;
 }
 
@@ -50,7 +136,8 @@ impl EvaluateExpr for Expr {
         &self,
         environment: Rc<RefCell<Environment>>,
         locals: &HashMap<SourceLocation, usize>,
-        function_stack: &mut Vec<FunctionType>,
+        context: &mut Context,
+        out: &mut dyn Write,
     ) -> Result<Literal, Error> {
         match self {
             Expr::Binary {
@@ -59,8 +146,18 @@ impl EvaluateExpr for Expr {
                 operator,
                 right,
             } => {
-                let left = left.evaluate(environment.clone(), locals, function_stack)?;
-                let right = right.evaluate(environment, locals, function_stack)?;
+                let left = left.evaluate(environment.clone(), locals, context, out)?;
+                // the right operand of a logical operator only runs if the
+                // left doesn't decide the result
+                match operator {
+                    TokenType::Or if left.is_truthy() => return Ok(left),
+                    TokenType::And if !left.is_truthy() => return Ok(left),
+                    TokenType::Or | TokenType::And => {
+                        return right.evaluate(environment, locals, context, out);
+                    }
+                    _ => {}
+                }
+                let right = right.evaluate(environment, locals, context, out)?;
                 let res = match operator {
                     TokenType::EqualEq => Literal::from(left == right),
                     TokenType::BangEq => Literal::from(left != right),
@@ -103,9 +200,13 @@ impl EvaluateExpr for Expr {
                         }
                         (Literal::String(a), b) => Literal::String(format!("{}{}", a, b).into()),
                         (a, Literal::String(b)) => Literal::String(format!("{}{}", a, b).into()),
-                        _ => {
+                        (a, b) => {
                             return Err(Error::Runtime {
-                                message: "Cannot add values.  Operands must be both numbers or both strings".to_string(),
+                                message: format!(
+                                    "Operands to '+' must be two numbers or involve a string; got {} and {}",
+                                    a.type_name(),
+                                    b.type_name()
+                                ),
                                 location: *location,
                             });
                         }
@@ -148,18 +249,6 @@ impl EvaluateExpr for Expr {
                             });
                         }
                     },
-                    TokenType::Or => {
-                        if left.is_truthy() {
-                            return Ok(left);
-                        }
-                        return Ok(right);
-                    }
-                    TokenType::And => {
-                        if !left.is_truthy() {
-                            return Ok(left);
-                        }
-                        return Ok(right);
-                    }
                     _ => {
                         return Err(Error::Parse {
                             location: *location,
@@ -173,7 +262,7 @@ impl EvaluateExpr for Expr {
                 operator,
                 right,
             } => {
-                let right = right.evaluate(environment, locals, function_stack)?;
+                let right = right.evaluate(environment, locals, context, out)?;
                 let res = match operator {
                     TokenType::Minus => match right {
                         Literal::Number(n) => Literal::Number(-n),
@@ -196,7 +285,7 @@ impl EvaluateExpr for Expr {
             Expr::Literal { value, .. } => Ok(value.clone()),
             Expr::Variable { location, name } => {
                 let depth = locals.get(location);
-                let val = match depth {
+                let lookup = match depth {
                     Some(d) => {
                         environment
                             .borrow()
@@ -206,22 +295,26 @@ impl EvaluateExpr for Expr {
                                 location: *location,
                             })?
                     }
-                    None => environment.borrow().get(name).ok_or(Error::Runtime {
+                    None => environment.borrow().get(name),
+                };
+                match lookup {
+                    Lookup::Value(value) => Ok(value),
+                    Lookup::Uninitialized => Err(Error::Runtime {
+                        message: format!("Uninitialized variable `{}` used", name),
+                        location: *location,
+                    }),
+                    Lookup::Undefined => Err(Error::Runtime {
                         message: format!("Undefined variable `{}`", name),
                         location: *location,
-                    })?,
-                };
-                val.ok_or(Error::Runtime {
-                    message: format!("Uninitialized variable `{}` used", name),
-                    location: *location,
-                })
+                    }),
+                }
             }
             Expr::Assignment {
                 location,
                 name,
                 value,
             } => {
-                let value = value.evaluate(environment.clone(), locals, function_stack)?;
+                let value = value.evaluate(environment.clone(), locals, context, out)?;
                 let depth = locals.get(location);
                 match depth {
                     Some(d) => environment
@@ -231,13 +324,13 @@ impl EvaluateExpr for Expr {
                             message: e.to_string(),
                             location: *location,
                         }),
-                    None => environment
-                        .borrow_mut()
-                        .update(name, value)
-                        .ok_or(Error::Runtime {
+                    None => match environment.borrow_mut().update(name, value) {
+                        Lookup::Value(value) => Ok(value),
+                        _ => Err(Error::Runtime {
                             message: format!("Undefined variable `{}`", name),
                             location: *location,
                         }),
+                    },
                 }
             }
             Expr::Call {
@@ -245,23 +338,24 @@ impl EvaluateExpr for Expr {
                 callee,
                 arguments,
             } => {
-                let callee = callee.evaluate(environment.clone(), locals, function_stack)?;
-                let Literal::Function {
-                    params,
-                    body,
-                    closure,
-                } = callee
-                else {
-                    return Err(Error::Runtime {
-                        message: "Can only call functions and classes.".to_string(),
-                        location: *location,
-                    });
+                context.step(*location)?;
+                let callee = callee.evaluate(environment.clone(), locals, context, out)?;
+                let arity = match &callee {
+                    Literal::Function { params, .. } => params.len(),
+                    Literal::Native(native) => native.arity,
+                    Literal::Class(_) => 0,
+                    _ => {
+                        return Err(Error::Runtime {
+                            message: "Can only call functions and classes.".to_string(),
+                            location: *location,
+                        });
+                    }
                 };
-                if arguments.len() != params.len() {
+                if arguments.len() != arity {
                     return Err(Error::Runtime {
                         message: format!(
-                            "Expected {} arguments bug got {}",
-                            params.len(),
+                            "Expected {} arguments but got {}",
+                            arity,
                             arguments.len()
                         ),
                         location: *location,
@@ -269,22 +363,88 @@ impl EvaluateExpr for Expr {
                 }
                 let arguments: Result<Vec<Literal>, Error> = arguments
                     .iter()
-                    .map(|e| e.evaluate(environment.clone(), locals, function_stack))
+                    .map(|e| e.evaluate(environment.clone(), locals, context, out))
                     .collect();
                 let Ok(arguments) = arguments else {
                     return Err(arguments.unwrap_err());
                 };
+                let (params, body, closure) = match callee {
+                    Literal::Function {
+                        params,
+                        body,
+                        closure,
+                    } => (params, body, closure),
+                    Literal::Native(native) => {
+                        return (native.fun)(&arguments).map_err(|e| Error::Builtin {
+                            message: format!(
+                                "Something went wrong inside builtin function {}: {}",
+                                native.name, e
+                            ),
+                        });
+                    }
+                    Literal::Class(class) => {
+                        return Ok(Literal::Instance(Rc::new(RefCell::new(Instance::new(
+                            class,
+                        )))));
+                    }
+                    _ => unreachable!("callee checked above"),
+                };
+                if context.function_stack.len() > MAX_CALL_DEPTH {
+                    return Err(Error::Runtime {
+                        message: "Stack overflow".to_string(),
+                        location: *location,
+                    });
+                }
                 let new_env = Rc::new(RefCell::new(Environment::new_with_parent(closure)));
                 params.into_iter().zip(arguments).for_each(|(p, l)| {
                     new_env.borrow_mut().define(p, Some(l));
                 });
-                function_stack.push(FunctionType::Function);
-                let res = body
-                    .execute(new_env.clone(), locals, function_stack)
-                    .map(|(v, _)| v.unwrap_or(Literal::Nil))?;
-                function_stack.pop();
+                context.function_stack.push(FunctionType::Function);
+                let res = stacker::maybe_grow(STACK_RED_ZONE, STACK_SEGMENT, || {
+                    body.execute(new_env.clone(), locals, context, out)
+                })
+                .map(|(v, _)| v.unwrap_or(Literal::Nil))?;
+                context.function_stack.pop();
                 Ok(res)
             }
+            Expr::Get {
+                location,
+                object,
+                name,
+            } => match object.evaluate(environment, locals, context, out)? {
+                Literal::Instance(instance) => {
+                    instance.borrow().get(name).ok_or_else(|| Error::Runtime {
+                        message: format!("Undefined property `{name}`"),
+                        location: *location,
+                    })
+                }
+                other => Err(Error::Runtime {
+                    message: format!("Only instances have properties, not {}", other.type_name()),
+                    location: *location,
+                }),
+            },
+            Expr::Set {
+                location,
+                object,
+                name,
+                value,
+            } => {
+                let instance = match object.evaluate(environment.clone(), locals, context, out)? {
+                    Literal::Instance(instance) => instance,
+                    other => {
+                        return Err(Error::Runtime {
+                            message: format!(
+                                "Only instances have fields, not {}",
+                                other.type_name()
+                            ),
+                            location: *location,
+                        });
+                    }
+                };
+                let value = value.evaluate(environment, locals, context, out)?;
+                instance.borrow_mut().fields.insert(name, value.clone());
+                Ok(value)
+            }
         }
     }
 }
@@ -295,7 +455,8 @@ trait ExecuteStmt {
         &self,
         environment: Rc<RefCell<Environment>>,
         locals: &HashMap<SourceLocation, usize>,
-        function_stack: &mut Vec<FunctionType>,
+        context: &mut Context,
+        out: &mut dyn Write,
     ) -> Result<(Option<Literal>, bool), Error>;
 }
 
@@ -304,25 +465,24 @@ impl ExecuteStmt for Stmt {
         &self,
         environment: Rc<RefCell<Environment>>,
         locals: &HashMap<SourceLocation, usize>,
-        function_stack: &mut Vec<FunctionType>,
+        context: &mut Context,
+        out: &mut dyn Write,
     ) -> Result<(Option<Literal>, bool), Error> {
         match self {
             Stmt::Expression(expr) => {
-                let value = expr.evaluate(environment, locals, function_stack)?;
+                let value = expr.evaluate(environment, locals, context, out)?;
                 Ok((Some(value), false))
             }
             Stmt::Print(expr) => {
-                let value = expr.evaluate(environment, locals, function_stack)?;
-                println!("{}", value);
+                let value = expr.evaluate(environment, locals, context, out)?;
+                writeln!(out, "{}", value)?;
                 Ok((None, false))
             }
             Stmt::VarDecl {
                 name, initializer, ..
             } => {
                 let value = match initializer {
-                    Some(expr) => {
-                        Some(expr.evaluate(environment.clone(), locals, function_stack)?)
-                    }
+                    Some(expr) => Some(expr.evaluate(environment.clone(), locals, context, out)?),
                     None => None,
                 };
                 environment.borrow_mut().define(name, value);
@@ -334,22 +494,23 @@ impl ExecuteStmt for Stmt {
                 else_branch,
             } => {
                 if condition
-                    .evaluate(environment.clone(), locals, function_stack)?
+                    .evaluate(environment.clone(), locals, context, out)?
                     .is_truthy()
                 {
-                    then_branch.execute(environment.clone(), locals, function_stack)
+                    then_branch.execute(environment.clone(), locals, context, out)
                 } else if let Some(else_branch) = else_branch {
-                    else_branch.execute(environment.clone(), locals, function_stack)
+                    else_branch.execute(environment.clone(), locals, context, out)
                 } else {
                     Ok((None, false))
                 }
             }
             Stmt::While { condition, body } => {
                 while condition
-                    .evaluate(environment.clone(), locals, function_stack)?
+                    .evaluate(environment.clone(), locals, context, out)?
                     .is_truthy()
                 {
-                    let res = body.execute(environment.clone(), locals, function_stack)?;
+                    context.step(condition.location())?;
+                    let res = body.execute(environment.clone(), locals, context, out)?;
                     if res.1 {
                         // is return
                         return Ok(res);
@@ -363,7 +524,7 @@ impl ExecuteStmt for Stmt {
                     environment.clone(),
                 )));
                 for inner in vec {
-                    res = inner.execute(new_env.clone(), locals, function_stack)?;
+                    res = inner.execute(new_env.clone(), locals, context, out)?;
                     if res.1 {
                         // is return
                         break;
@@ -383,24 +544,40 @@ impl ExecuteStmt for Stmt {
                 );
                 Ok((None, false))
             }
+            Stmt::ClassDecl { name, methods, .. } => {
+                let methods = methods
+                    .iter()
+                    .filter_map(|method| match method {
+                        Stmt::FunDecl { name, params, body } => Some((
+                            *name,
+                            Literal::Function {
+                                params: params.to_vec(),
+                                body: body.clone(),
+                                closure: environment.clone(),
+                            },
+                        )),
+                        _ => None,
+                    })
+                    .collect();
+                environment
+                    .borrow_mut()
+                    .define(name, Some(Literal::Class(Rc::new(Class { name, methods }))));
+                Ok((None, false))
+            }
             Stmt::Return(val) => {
-                let last = function_stack.len() - 1;
-                if matches!(function_stack[last], FunctionType::None) {
+                if matches!(context.function_stack.last(), Some(FunctionType::None)) {
                     return Err(Error::Runtime {
                         message: "Can't return from outside a function".to_string(),
                         location: val.location(),
                     });
                 }
-                val.evaluate(environment, locals, function_stack)
+                val.evaluate(environment, locals, context, out)
                     .map(|l| (Some(l), true))
             }
-            Stmt::Builtin { params, body } => {
-                let res = (*body.fun)(params, environment.clone());
-                res.map_err(|e| Error::Builtin {
-                    message: format!("Something went wrong inside builtin function {}", e),
-                })
-                .map(|val| (Some(val), true))
-            }
+            Stmt::Import { path, location } => Err(Error::Runtime {
+                message: format!("Import of \"{path}\" wasn't loaded before running"),
+                location: *location,
+            }),
         }
     }
 }
@@ -408,59 +585,430 @@ impl ExecuteStmt for Stmt {
 pub struct Interpreter {
     environment: Rc<RefCell<Environment>>,
     locals: HashMap<SourceLocation, usize>,
+    /// loop iterations and calls each `interpret` may run
+    budget: Option<u64>,
 }
 
 impl Interpreter {
     pub fn new() -> Self {
-        Self {
-            environment: Rc::new(RefCell::new(Environment::new())),
-            locals: HashMap::new(),
-        }
+        Self::new_with_locals(HashMap::new())
     }
 
     pub fn new_with_locals(locals: HashMap<SourceLocation, usize>) -> Self {
+        Self::with_natives(locals, default_natives())
+    }
+
+    /// An interpreter whose globals start out as just `natives`
+    pub(crate) fn with_natives(
+        locals: HashMap<SourceLocation, usize>,
+        natives: Vec<Native>,
+    ) -> Self {
+        let mut globals = Environment::new();
+        for native in natives {
+            globals.define(native.name, Some(Literal::Native(Rc::new(native))));
+        }
         Self {
-            environment: Rc::new(RefCell::new(Environment::new())),
+            environment: Rc::new(RefCell::new(globals)),
             locals,
+            budget: None,
         }
     }
 
-    pub fn interpret(&self, stmts: Vec<Stmt>) -> Result<Option<Literal>, Error> {
+    /// Stops each `interpret` with an error once it has run `steps` loop
+    /// iterations and calls, so a program can't run forever
+    pub(crate) fn with_budget(mut self, steps: u64) -> Self {
+        self.budget = Some(steps);
+        self
+    }
+
+    /// Adds the resolved depths of another program's variables, for running
+    /// it after the ones before
+    pub(crate) fn add_locals(&mut self, locals: HashMap<SourceLocation, usize>) {
+        self.locals.extend(locals);
+    }
+
+    /// The globals and their values, sorted by name; `None` for a global
+    /// declared without a value
+    pub(crate) fn globals(&self) -> Vec<(String, Option<Literal>)> {
+        self.environment.borrow().bindings()
+    }
+
+    /// Defines or replaces a global, as a top-level `var` would
+    pub(crate) fn define_global(&self, name: &str, value: Literal) {
+        self.environment.borrow_mut().define(name, Some(value));
+    }
+
+    /// Runs `stmts`, writing `print` output to `out`
+    pub fn interpret(
+        &self,
+        stmts: Vec<Stmt>,
+        out: &mut dyn Write,
+    ) -> Result<Option<Literal>, Error> {
         let mut res = None;
-        self.builtin_clock();
+        let mut context = Context {
+            function_stack: vec![FunctionType::None],
+            steps_left: self.budget,
+        };
         for stmt in stmts {
             res = stmt
-                .execute(
-                    self.environment.clone(),
-                    &self.locals,
-                    &mut vec![FunctionType::None],
-                )?
+                .execute(self.environment.clone(), &self.locals, &mut context, out)?
                 .0;
         }
         Ok(res)
     }
+}
 
-    fn builtin_clock(&self) {
-        let clock = Literal::Function {
-            params: Vec::new(),
-            body: Stmt::Builtin {
-                params: Vec::new(),
-                body: BuiltinFn {
-                    name: "clock",
-                    fun: Box::new(move |_, _| {
-                        Ok(Literal::Number(
-                            SystemTime::now()
-                                .duration_since(UNIX_EPOCH)
-                                .unwrap()
-                                .as_millis_f64()
-                                / 1000.0,
-                        ))
-                    }),
-                },
+/// Natives an interpreter has unless its `Lox` turns them off
+pub(crate) fn default_natives() -> Vec<Native> {
+    let mut natives = vec![clock(), random(random_seed())];
+    natives.extend(script_args(Rc::default()));
+    natives
+}
+
+/// `clock()`, seconds since the Unix epoch
+pub(crate) fn clock() -> Native {
+    Native::new("clock", 0, |_| now())
+}
+
+/// `clock()` for deterministic runs: a millisecond more on each call,
+/// starting from 0.001
+pub(crate) fn counting_clock() -> Native {
+    let calls = Cell::new(0u64);
+    Native::new("clock", 0, move |_| {
+        calls.set(calls.get() + 1);
+        Ok(Literal::Number(calls.get() as f64 / 1000.0))
+    })
+}
+
+/// `random()`, a number from 0 up to but not including 1
+pub(crate) fn random(seed: u64) -> Native {
+    let random = RefCell::new(Random::new(seed));
+    Native::new("random", 0, move |_| {
+        Ok(Literal::Number(random.borrow_mut().next_f64()))
+    })
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn now() -> Result<Literal, String> {
+    use std::time::{SystemTime, UNIX_EPOCH};
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| Literal::Number(d.as_millis_f64() / 1000.0))
+        .map_err(|e| e.to_string())
+}
+
+// `SystemTime::now` panics on wasm32-unknown-unknown
+#[cfg(target_arch = "wasm32")]
+fn now() -> Result<Literal, String> {
+    Err("there's no system clock on this platform".to_string())
+}
+
+/// `readLine()`, the next line of `input` without its line ending, or nil
+/// once it's exhausted
+pub(crate) fn read_line(input: Rc<RefCell<Box<dyn BufRead>>>) -> Native {
+    Native::new("readLine", 0, move |_| {
+        let mut line = String::new();
+        match input.borrow_mut().read_line(&mut line) {
+            Ok(0) => Ok(Literal::Nil),
+            Ok(_) => {
+                let len = line.trim_end_matches(['\n', '\r']).len();
+                line.truncate(len);
+                Ok(Literal::String(Rc::new(line)))
             }
-            .into(),
-            closure: self.environment.clone(),
+            Err(e) => Err(e.to_string()),
+        }
+    })
+}
+
+/// `getenv(name)`, the value `fixed` gives it, or else the environment
+/// variable's value if it's in `allowed`, or nil. Deterministic runs don't
+/// read the environment at all, so only `fixed` values are seen.
+pub(crate) fn getenv(
+    allowed: Vec<String>,
+    fixed: Vec<(String, String)>,
+    deterministic: bool,
+) -> Native {
+    Native::new("getenv", 1, move |arguments| match &arguments[0] {
+        Literal::String(name) => {
+            let value = match fixed.iter().find(|(fixed, _)| fixed == name.as_str()) {
+                Some((_, value)) => Some(value.clone()),
+                None if deterministic || !allowed.contains(name) => None,
+                None => std::env::var(name.as_str()).ok(),
+            };
+            Ok(value.map_or(Literal::Nil, |value| Literal::String(Rc::new(value))))
+        }
+        other => Err(format!(
+            "Variable name must be a string, not {}",
+            other.type_name()
+        )),
+    })
+}
+
+/// `argc()` and `argAt(i)`, the number of arguments the script was given and
+/// the argument at index `i`. Lox has no lists to return them all in.
+pub(crate) fn script_args(args: Rc<Vec<String>>) -> [Native; 2] {
+    let count = args.len();
+    let arg_at = Native::new("argAt", 1, move |arguments| match &arguments[0] {
+        Literal::Number(i) if i.fract() == 0.0 && *i >= 0.0 && (*i as usize) < args.len() => {
+            Ok(Literal::String(Rc::new(args[*i as usize].clone())))
+        }
+        Literal::Number(i) => Err(format!(
+            "Argument index {i} out of range for {} arguments",
+            args.len()
+        )),
+        other => Err(format!(
+            "Argument index must be a number, not {}",
+            other.type_name()
+        )),
+    });
+    [
+        Native::new("argc", 0, move |_| Ok(Literal::Number(count as f64))),
+        arg_at,
+    ]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{parser::Parser, resolver::Resolver, scanner::Scanner};
+    use random::DETERMINISTIC_SEED;
+
+    fn run(source: &'static str) -> Result<Option<Literal>, Error> {
+        let tokens = Scanner::new().scan(source).unwrap();
+        let ast = Parser::new().parse(tokens).unwrap();
+        let locals = Resolver::new().resolve_all(&ast).0;
+        Interpreter::new_with_locals(locals).interpret(ast, &mut std::io::sink())
+    }
+
+    fn number(source: &'static str) -> f64 {
+        match run(source).unwrap() {
+            Some(Literal::Number(n)) => n,
+            other => panic!("expected a number, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_variables() {
+        assert_eq!(number("var x = 5; x;"), 5.0);
+        assert_eq!(number("var x = 5; x = x + 1; x;"), 6.0);
+        assert_eq!(number("var x = 1; var x = 2; x;"), 2.0);
+        assert_eq!(number("var a; var b; a = b = 3; a + b;"), 6.0);
+        // declared without a value, so reading it is an error until assigned
+        assert_eq!(
+            run("var x; x;"),
+            Err(Error::Runtime {
+                message: "Uninitialized variable `x` used".to_string(),
+                location: SourceLocation::new(1, 7),
+            })
+        );
+        assert_eq!(number("var x; x = 4; x;"), 4.0);
+    }
+
+    #[test]
+    fn test_scoped_variables() {
+        assert_eq!(number("var x = 1; { var x = 2; x = 3; } x;"), 1.0);
+        assert_eq!(number("var x = 1; { x = 2; } x;"), 2.0);
+        assert_eq!(
+            number("var x = 1; fun f() { var y = x; x = y + 1; } f(); f(); x;"),
+            3.0
+        );
+    }
+
+    #[test]
+    fn test_logical_operators() {
+        assert!(matches!(run("nil or \"x\";"), Ok(Some(Literal::String(s))) if *s == "x"));
+        assert!(matches!(run("nil and 1;"), Ok(Some(Literal::Nil))));
+        assert_eq!(number("1 or 2;"), 1.0);
+        assert_eq!(number("1 and 2;"), 2.0);
+        assert_eq!(number("nil and 1 or 2;"), 2.0);
+        assert!(matches!(run("1 == 2 or 3 == 3;"), Ok(Some(Literal::True))));
+        // anywhere an expression goes
+        assert_eq!(number("var x = nil or 2; x;"), 2.0);
+        assert_eq!(number("(false or 3) + 1;"), 4.0);
+        assert!(run("print nil or \"x\";").is_ok());
+        assert_eq!(number("var y; var x = y = 2; x + y;"), 4.0);
+        assert_eq!(number("var a; (a = 5) + a;"), 10.0);
+    }
+
+    #[test]
+    fn test_logical_short_circuit() {
+        let calls = |expr: &str| {
+            let source = format!(
+                "var calls = 0; fun f(v) {{ calls = calls + 1; return v; }} {expr}; calls;"
+            );
+            number(source.leak())
+        };
+        assert_eq!(calls("true or f(true)"), 0.0);
+        assert_eq!(calls("false or f(true)"), 1.0);
+        assert_eq!(calls("false and f(true)"), 0.0);
+        assert_eq!(calls("true and f(true)"), 1.0);
+        assert_eq!(calls("f(false) or f(nil) or f(1) or f(2)"), 3.0);
+    }
+
+    #[test]
+    fn test_for_loops() {
+        assert_eq!(
+            number("var sum = 0; for (var i = 1; i <= 4; i = i + 1) sum = sum + i; sum;"),
+            10.0
+        );
+        assert_eq!(number("var i; for (i = 0; i < 5;) i = i + 2; i;"), 6.0);
+        // the loop variable is scoped to the loop
+        assert_eq!(
+            number("var i = 9; for (var i = 0; i < 2; i = i + 1) {} i;"),
+            9.0
+        );
+    }
+
+    #[test]
+    fn test_clock() {
+        let elapsed = number(
+            "var start = clock();
+            var i = 0;
+            while (i < 1000) i = i + 1;
+            clock() - start;",
+        );
+        assert!(elapsed >= 0.0);
+        assert!(number("clock();") > 0.0);
+        assert!(matches!(run("clock(1);"), Err(Error::Runtime { .. })));
+        assert!(matches!(
+            run("var clock = 1; clock();"),
+            Err(Error::Runtime { .. })
+        ));
+    }
+
+    #[test]
+    fn test_random() {
+        let numbers = |native: &Native| -> Vec<f64> {
+            (0..100)
+                .map(|_| match (native.fun)(&[]) {
+                    Ok(Literal::Number(n)) => n,
+                    other => panic!("expected a number, got {other:?}"),
+                })
+                .collect()
+        };
+        let first = numbers(&random(DETERMINISTIC_SEED));
+        assert!(first.iter().all(|n| (0.0..1.0).contains(n)));
+        assert_eq!(first, numbers(&random(DETERMINISTIC_SEED)));
+        assert_ne!(first, numbers(&random(DETERMINISTIC_SEED + 1)));
+        assert!(matches!(run("random(1);"), Err(Error::Runtime { .. })));
+    }
+
+    #[test]
+    fn test_undefined_variable() {
+        let message = |source| match run(source) {
+            Err(Error::Runtime { message, .. }) => message,
+            other => panic!("expected a runtime error, got {other:?}"),
+        };
+        assert_eq!(message("print y;"), "Undefined variable `y`");
+        assert_eq!(message("y = 1;"), "Undefined variable `y`");
+        assert_eq!(
+            message("var x; print x;"),
+            "Uninitialized variable `x` used"
+        );
+        assert_eq!(
+            message("{ var x; print x; }"),
+            "Uninitialized variable `x` used"
+        );
+        assert_eq!(number("var x; { x = 2; } x;"), 2.0);
+    }
+
+    #[test]
+    fn test_stack_overflow() {
+        let error = run("fun f(n) { return f(n + 1); } f(0);").unwrap_err();
+        assert_eq!(error.message(), "Stack overflow");
+        // deep recursion is fine, even on a test thread's small stack
+        assert_eq!(
+            number("fun f(n) { if (n == 0) return 0; return f(n - 1) + 1; } f(1000);"),
+            1000.0
+        );
+        assert_eq!(
+            number("fun f(n) { if (n == 0) return 0; return f(n - 1) + 1; } f(4000);"),
+            4000.0
+        );
+    }
+
+    #[test]
+    fn test_budget() {
+        let run_with_budget = |source: &'static str, steps| {
+            let tokens = Scanner::new().scan(source).unwrap();
+            let ast = Parser::new().parse(tokens).unwrap();
+            let locals = Resolver::new().resolve_all(&ast).0;
+            Interpreter::new_with_locals(locals)
+                .with_budget(steps)
+                .interpret(ast, &mut std::io::sink())
+        };
+        let error = run_with_budget("while (true) {}", 1000).unwrap_err();
+        assert_eq!(error.message(), "Execution budget exceeded");
+        let error = run_with_budget("fun f() { f(); } f();", 10).unwrap_err();
+        assert_eq!(error.message(), "Execution budget exceeded");
+        // loop iterations and calls count, and nothing else does
+        let program = "var i = 0; while (i < 3) i = i + 1; clock(); i;";
+        assert!(run_with_budget(program, 4).is_ok());
+        assert!(run_with_budget(program, 3).is_err());
+    }
+
+    #[test]
+    fn test_classes() {
+        assert_eq!(number("class Point {} var p = Point(); p.x = 3; p.x;"), 3.0);
+        // each instance has its own fields
+        assert_eq!(
+            number("class A {} var a = A(); var b = A(); a.n = 1; b.n = 2; a.n + b.n;"),
+            3.0
+        );
+        assert_eq!(
+            number("class A { twice(n) { return n * 2; } } A().twice(4);"),
+            8.0
+        );
+        // a field hides the method with the same name
+        assert_eq!(
+            number("class A { f() { return 1; } } var a = A(); a.f = 2; a.f;"),
+            2.0
+        );
+        assert_eq!(
+            number(
+                "fun make() { var n = 5; class C { get() { return n; } } return C; } make()().get();"
+            ),
+            5.0
+        );
+        assert!(matches!(
+            run("class A {} A() == A();"),
+            Ok(Some(Literal::False))
+        ));
+        assert!(matches!(
+            run("class A {} var a = A(); a == a;"),
+            Ok(Some(Literal::True))
+        ));
+
+        let mut out = Vec::new();
+        let tokens = Scanner::new()
+            .scan("class A {} var a = A(); print A; print a;")
+            .unwrap();
+        let ast = Parser::new().parse(tokens).unwrap();
+        let locals = Resolver::new().resolve_all(&ast).0;
+        Interpreter::new_with_locals(locals)
+            .interpret(ast, &mut out)
+            .unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "A\nA instance\n");
+    }
+
+    #[test]
+    fn test_property_errors() {
+        let message = |source| match run(source) {
+            Err(Error::Runtime { message, .. }) => message,
+            other => panic!("expected a runtime error, got {other:?}"),
         };
-        self.environment.borrow_mut().define("clock", Some(clock));
+        assert_eq!(message("class A {} A().x;"), "Undefined property `x`");
+        assert_eq!(
+            message("var a = 1; a.x;"),
+            "Only instances have properties, not number"
+        );
+        assert_eq!(
+            message("class A {} A.x = 1;"),
+            "Only instances have fields, not class"
+        );
+        assert_eq!(
+            message("class A {} A(1);"),
+            "Expected 0 arguments but got 1"
+        );
     }
 }
@@ -1,22 +1,47 @@
 #![allow(dead_code)]
 #![feature(duration_millis_float)]
-use resolver::Resolver;
+use itertools::Itertools;
+use resolver::{Resolver, globals_defined};
+use std::cell::{Ref, RefCell};
+use std::collections::HashMap;
 use std::fmt::Debug;
-use std::io::Write;
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
 use thiserror::Error;
 
+use ::cli::{Timings, timed};
+use ast::{Native, Stmt};
+use import::Loader;
 use interpreter::Interpreter;
 use parser::Parser;
+#[cfg(feature = "repl")]
+use repl::ReplOutcome;
 use scanner::Scanner;
+use token::Literal;
 
 mod ast;
+mod diagnostic;
 mod environment;
+mod format;
+mod import;
 mod interpreter;
 mod location;
 mod parser;
+mod persist;
 mod resolver;
 mod scanner;
 mod token;
+mod watch;
+
+pub use ::diagnostic::{Diagnostic, Phase, Severity, SourceFile, SourceMap, Span};
+pub use format::{FormatError, format};
+pub use interpreter::Error as RuntimeError;
+pub use location::SourceLocation;
+pub use parser::Error as ParseError;
+pub use resolver::Error as ResolveError;
+pub use scanner::Error as ScanError;
+pub use watch::{Poll, Watcher};
 
 #[derive(Error)]
 pub enum Error {
@@ -31,6 +56,14 @@ pub enum Error {
 
     #[error(transparent)]
     Io(#[from] std::io::Error),
+
+    /// Loading an imported file failed
+    #[error("In {}: {error}", .path.display())]
+    Import { path: PathBuf, error: Box<Error> },
+
+    /// Files that import each other, starting and ending with the same file
+    #[error("Import cycle: {}", .0.iter().map(|path| path.display()).join(" -> "))]
+    ImportCycle(Vec<PathBuf>),
 }
 
 impl Debug for Error {
@@ -39,59 +72,728 @@ impl Debug for Error {
     }
 }
 
-pub struct Lox {}
+impl Error {
+    /// The `sysexits.h` code for a binary to exit with: 65 for problems found
+    /// before the program runs, 70 for runtime errors, and 74 for failing to
+    /// read or write
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Scanner(_) | Error::Parser(_) | Error::Import { .. } | Error::ImportCycle(_) => {
+                65
+            }
+            Error::Runtime(RuntimeError::Io(_)) | Error::Io(_) => 74,
+            Error::Runtime(_) => 70,
+        }
+    }
+
+    pub fn scan_errors(&self) -> Option<&[ScanError]> {
+        match self {
+            Error::Scanner(errors) => Some(errors),
+            _ => None,
+        }
+    }
+
+    pub fn parse_errors(&self) -> Option<&[ParseError]> {
+        match self {
+            Error::Parser(errors) => Some(errors),
+            _ => None,
+        }
+    }
+
+    pub fn runtime_error(&self) -> Option<&RuntimeError> {
+        match self {
+            Error::Runtime(error) => Some(error),
+            _ => None,
+        }
+    }
+
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        match self {
+            Error::Scanner(errors) => errors.iter().map(Diagnostic::from).collect(),
+            Error::Parser(errors) => errors.iter().map(Diagnostic::from).collect(),
+            Error::Runtime(error) => vec![error.into()],
+            Error::Io(error) => vec![Diagnostic::error(
+                Phase::Runtime,
+                "io",
+                error.to_string(),
+                None,
+            )],
+            // problems inside the file name it with their span already
+            Error::Import { path, error } => error
+                .diagnostics()
+                .into_iter()
+                .map(|d| match d.span {
+                    Some(_) => d,
+                    None => Diagnostic {
+                        message: format!("In {}: {}", path.display(), d.message),
+                        ..d
+                    },
+                })
+                .collect(),
+            Error::ImportCycle(_) => vec![Diagnostic::error(
+                Phase::Resolve,
+                "import-cycle",
+                self.to_string(),
+                None,
+            )],
+        }
+    }
+
+    /// Each of `diagnostics` as `file:line:column: ...`, one per line
+    pub fn render(&self, sources: &SourceMap) -> String {
+        self.diagnostics()
+            .iter()
+            .map(|d| d.render(sources))
+            .join("\n")
+    }
+}
+
+/// The phases `LoxOptions::timings` times, in the order they run
+const PHASES: [&str; 4] = ["scan", "parse", "resolve", "execute"];
+
+pub struct Lox {
+    /// where `interpret` writes, instead of stdout
+    output: Option<RefCell<Box<dyn Write>>>,
+    /// what `readLine()` reads; without it there's no `readLine`
+    input: Option<Rc<RefCell<Box<dyn BufRead>>>>,
+    /// whether `clock()` is defined
+    clock: bool,
+    /// what `argc()` and `argAt(i)` see
+    args: Rc<Vec<String>>,
+    /// environment variables `getenv` can read; without any, or any
+    /// `env_values`, there's no `getenv`
+    env: Vec<String>,
+    /// what `getenv` returns for these names, whatever the environment says
+    env_values: Vec<(String, String)>,
+    /// `clock()` counts calls and `random()` always starts from the same seed
+    deterministic: bool,
+    /// loop iterations and calls a program may run
+    budget: Option<u64>,
+    /// every file this `Lox` has loaded, for rendering errors
+    sources: RefCell<SourceMap>,
+    /// how long each phase has taken, when they're being timed
+    timings: Option<RefCell<Timings>>,
+}
+
+impl Default for Lox {
+    fn default() -> Self {
+        Self {
+            output: None,
+            input: None,
+            clock: true,
+            args: Rc::default(),
+            env: Vec::new(),
+            env_values: Vec::new(),
+            deterministic: false,
+            budget: None,
+            sources: RefCell::default(),
+            timings: None,
+        }
+    }
+}
+
+/// Configures a `Lox`, e.g. `Lox::builder().output(sink).build()`
+#[derive(Default)]
+pub struct LoxOptions(Lox);
+
+impl LoxOptions {
+    pub fn output(mut self, out: Box<dyn Write>) -> Self {
+        self.0.output = Some(RefCell::new(out));
+        self
+    }
+
+    /// Defines `readLine()`, reading from `input`
+    pub fn input(mut self, input: Box<dyn BufRead>) -> Self {
+        self.0.input = Some(Rc::new(RefCell::new(input)));
+        self
+    }
+
+    /// Whether to define `clock()`, on by default. It always fails on
+    /// wasm32, which has no system clock.
+    pub fn clock(mut self, enabled: bool) -> Self {
+        self.0.clock = enabled;
+        self
+    }
+
+    /// The script's command-line arguments, for `argc()` and `argAt(i)`;
+    /// none by default
+    pub fn args(mut self, args: Vec<String>) -> Self {
+        self.0.args = Rc::new(args);
+        self
+    }
+
+    /// Defines `getenv(name)`, which reads only the environment variables
+    /// in `names` and returns nil for any other. It's not defined by
+    /// default, or when `names` is empty.
+    pub fn allow_env(mut self, names: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.0.env = names.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Fixes what `getenv(name)` returns, defining `getenv` if it isn't
+    pub fn env_value(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.0.env_values.push((name.into(), value.into()));
+        self
+    }
+
+    /// Makes a program print the same thing every run: `clock()` goes up a
+    /// millisecond per call, `random()` starts from a fixed seed, and
+    /// `getenv` only sees `env_value`s. The script's `args` are already
+    /// fixed by whoever gives them.
+    pub fn deterministic(mut self, deterministic: bool) -> Self {
+        self.0.deterministic = deterministic;
+        self
+    }
+
+    /// Stops a program with a runtime error once it has run `steps` loop
+    /// iterations and calls, so untrusted code can't run forever
+    pub fn max_steps(mut self, steps: u64) -> Self {
+        self.0.budget = Some(steps);
+        self
+    }
+
+    /// Times scanning, parsing, resolving and running each program, for
+    /// `Lox::timings`
+    pub fn timings(mut self, enabled: bool) -> Self {
+        self.0.timings = enabled.then(|| RefCell::new(Timings::new(&PHASES)));
+        self
+    }
+
+    pub fn build(self) -> Lox {
+        self.0
+    }
+}
 
 impl Lox {
+    pub fn builder() -> LoxOptions {
+        LoxOptions::default()
+    }
+
+    /// How long each phase has taken so far, if they're timed
+    pub fn timings(&self) -> Option<Timings> {
+        self.timings
+            .as_ref()
+            .map(|timings| timings.borrow().clone())
+    }
+
     pub fn run(file: String) -> Result<(), Error> {
-        let file = file.leak();
-        let tokens = Scanner::new().scan(file).map_err(Error::Scanner)?;
-        let ast = Parser::new().parse(tokens).map_err(Error::Parser)?;
-        let locals = Resolver::new().resolve(&ast);
-        let interpreter = Interpreter::new_with_locals(locals);
-        let res = interpreter.interpret(ast).map_err(Error::Runtime)?;
-        if let Some(res) = res {
-            println!("{}", res);
+        Lox::default().run_source(&file, &mut std::io::stdout())
+    }
+
+    /// Whether it was built with `LoxOptions::deterministic`
+    pub fn is_deterministic(&self) -> bool {
+        self.deterministic
+    }
+
+    /// The files loaded so far, which the spans of this `Lox`'s errors index
+    pub fn sources(&self) -> Ref<'_, SourceMap> {
+        self.sources.borrow()
+    }
+
+    /// Runs a whole program with this `Lox`'s options
+    pub fn interpret(&self, source: &str) -> Result<(), Error> {
+        let ast = self.load_source(source)?;
+        self.run_to_output(ast)
+    }
+
+    /// Runs a script with this `Lox`'s options; its imports are relative to
+    /// the directory of the file that imports them
+    pub fn run_file(&self, path: &Path) -> Result<(), Error> {
+        self.session().run_file(path)
+    }
+
+    /// Starts a session, for running several files against the same globals
+    pub fn session(&self) -> LoxSession<'_> {
+        let natives = self.natives();
+        let names = natives.iter().map(|native| native.name).collect();
+        let mut interpreter = Interpreter::with_natives(HashMap::new(), natives);
+        if let Some(steps) = self.budget {
+            interpreter = interpreter.with_budget(steps);
+        }
+        LoxSession {
+            lox: self,
+            loader: Loader::new(&self.sources, self.timings.as_ref()),
+            interpreter,
+            natives: names,
+            defined: Vec::new(),
+        }
+    }
+
+    /// Starts checking files against the same globals, like `session`
+    /// without ever running them
+    pub fn check_session(&self) -> CheckSession<'_> {
+        CheckSession {
+            lox: self,
+            loader: Loader::new(&self.sources, self.timings.as_ref()),
+            natives: self.natives().iter().map(|native| native.name).collect(),
+            defined: Vec::new(),
+        }
+    }
+
+    /// Finds every problem in a script and what it imports that can be found
+    /// without running them, including not being able to read them
+    pub fn check_file(&self, path: &Path) -> Vec<Diagnostic> {
+        self.check_session().check_file(path)
+    }
+
+    /// Runs a whole program with this `Lox`'s options, returning what it
+    /// printed, resolver errors included, instead of writing it anywhere
+    pub fn run_to_string(&self, source: &str) -> Result<String, Error> {
+        let mut out = Vec::new();
+        self.run_source(source, &mut out)?;
+        Ok(String::from_utf8_lossy(&out).into_owned())
+    }
+
+    fn run_to_output(&self, ast: Vec<Stmt>) -> Result<(), Error> {
+        self.session().run_to_output(ast)
+    }
+
+    /// Like `run_file`, but writes to `out` and hands resolver errors to `report`
+    pub fn run_file_reporting(
+        &self,
+        path: &Path,
+        out: &mut impl Write,
+        report: &mut dyn FnMut(Diagnostic),
+    ) -> Result<(), Error> {
+        self.session().run_file_reporting(path, out, report)
+    }
+
+    /// Runs a script read from stdin, named `<stdin>`; its imports are
+    /// relative to the working directory
+    pub fn run_stdin(&self) -> Result<(), Error> {
+        self.session().run_stdin()
+    }
+
+    /// Like `run_stdin`, but writes to `out` and hands resolver errors to `report`
+    pub fn run_stdin_reporting(
+        &self,
+        out: &mut impl Write,
+        report: &mut dyn FnMut(Diagnostic),
+    ) -> Result<(), Error> {
+        self.session().run_stdin_reporting(out, report)
+    }
+
+    /// Runs a whole program with this `Lox`'s options, writing its output and
+    /// any resolver errors to `out`. Imports are relative to the working
+    /// directory, and the spans of errors index `sources`.
+    pub fn run_source(&self, source: &str, out: &mut impl Write) -> Result<(), Error> {
+        let ast = self.load_source(source)?;
+        self.run_ast(ast, out, None)
+    }
+
+    /// Like `interpret`, but writes to `out` and hands resolver errors to
+    /// `report`
+    pub fn interpret_reporting(
+        &self,
+        source: &str,
+        out: &mut impl Write,
+        report: &mut dyn FnMut(Diagnostic),
+    ) -> Result<(), Error> {
+        let ast = self.load_source(source)?;
+        self.run_ast(ast, out, Some(report))
+    }
+
+    /// Source that isn't from a file is named `<script>`, with imports
+    /// relative to the working directory
+    fn load_source(&self, source: &str) -> Result<Vec<Stmt>, Error> {
+        Loader::new(&self.sources, self.timings.as_ref()).load_source(
+            source,
+            "<script>",
+            Path::new(""),
+        )
+    }
+
+    fn run_ast(
+        &self,
+        ast: Vec<Stmt>,
+        out: &mut dyn Write,
+        report: Option<&mut dyn FnMut(Diagnostic)>,
+    ) -> Result<(), Error> {
+        self.session().run_ast(ast, out, report)
+    }
+
+    fn natives(&self) -> Vec<Native> {
+        let mut natives = Vec::new();
+        if self.clock && self.deterministic {
+            natives.push(interpreter::counting_clock());
+        } else if self.clock {
+            natives.push(interpreter::clock());
         }
+        let seed = if self.deterministic {
+            random::DETERMINISTIC_SEED
+        } else {
+            random::random_seed()
+        };
+        natives.push(interpreter::random(seed));
+        if let Some(input) = &self.input {
+            natives.push(interpreter::read_line(input.clone()));
+        }
+        if !self.env.is_empty() || !self.env_values.is_empty() {
+            natives.push(interpreter::getenv(
+                self.env.clone(),
+                self.env_values.clone(),
+                self.deterministic,
+            ));
+        }
+        natives.extend(interpreter::script_args(self.args.clone()));
+        natives
+    }
+
+    /// Finds every problem in a program that can be found without running it
+    pub fn check(source: &str) -> Vec<Diagnostic> {
+        let source: &'static str = source.to_string().leak();
+        let (tokens, scan_errors) = Scanner::new().scan_all(source);
+        let mut diagnostics: Vec<Diagnostic> = scan_errors.iter().map(Diagnostic::from).collect();
+        match Parser::new().parse(tokens) {
+            Ok(ast) => {
+                let (_, errors) = Resolver::new().resolve_all(&ast);
+                diagnostics.extend(errors.iter().map(Diagnostic::from));
+            }
+            Err(errors) => diagnostics.extend(errors.iter().map(Diagnostic::from)),
+        }
+        diagnostics
+    }
+
+    /// `check`, as one JSON object per line, naming the source `<script>`
+    pub fn check_json(source: &str) -> String {
+        let mut sources = SourceMap::default();
+        sources.add("<script>", source);
+        Self::check(source)
+            .iter()
+            .map(|d| d.to_json(&sources) + "\n")
+            .collect()
+    }
+
+    /// Each entry is leaked so that names and function bodies defined in it
+    /// can live in the interpreter's globals for the rest of the session.
+    /// A file imported by one entry isn't loaded again by a later one.
+    /// Errors name the entry they're in as `<repl:N>`. Entries run in one
+    /// `LoxSession`, like files given together.
+    #[cfg(feature = "repl")]
+    pub fn run_prompt(&self) -> Result<(), Error> {
+        let mut session = self.session();
+        let mut number = 1;
+        repl::run(|entry| {
+            if let Some(command) = repl::Command::parse(entry) {
+                run_command(&mut session, command);
+                return ReplOutcome::Done;
+            }
+            let name = format!("<repl:{number}>");
+            let ast = match session.loader.load_source(entry, &name, Path::new("")) {
+                Ok(ast) => ast,
+                Err(e) if is_unfinished(&e, entry) => return ReplOutcome::Incomplete,
+                Err(e) => {
+                    eprintln!("{}", e.render(&self.sources()));
+                    number += 1;
+                    return ReplOutcome::Done;
+                }
+            };
+            number += 1;
+            let report = &mut |d: Diagnostic| eprintln!("{}", d.render(&self.sources()));
+            if let Err(e) = session.run_ast(ast, &mut std::io::stdout(), Some(report)) {
+                eprintln!("{}", e.render(&self.sources()));
+            }
+            ReplOutcome::Done
+        })?;
         Ok(())
     }
+}
+
+/// Files run one after another in the same interpreter, so each can use the
+/// globals and functions the ones before it defined, like a prelude and then
+/// a script. A file an earlier one ran or imported isn't run again.
+pub struct LoxSession<'a> {
+    lox: &'a Lox,
+    loader: Loader<'a>,
+    interpreter: Interpreter,
+    /// names of the natives the interpreter was given
+    natives: Vec<&'static str>,
+    /// globals defined by the files run so far
+    defined: Vec<&'static str>,
+}
+
+impl LoxSession<'_> {
+    /// Runs a script with the session's `Lox`'s options; its imports are
+    /// relative to the directory of the file that imports them
+    pub fn run_file(&mut self, path: &Path) -> Result<(), Error> {
+        let ast = self.loader.load_file(path)?;
+        self.run_to_output(ast)
+    }
+
+    /// Like `run_file`, but writes to `out` and hands resolver errors to `report`
+    pub fn run_file_reporting(
+        &mut self,
+        path: &Path,
+        out: &mut impl Write,
+        report: &mut dyn FnMut(Diagnostic),
+    ) -> Result<(), Error> {
+        let ast = self.loader.load_file(path)?;
+        self.run_ast(ast, out, Some(report))
+    }
 
-    pub fn run_prompt() -> Result<(), Error> {
-        let interpreter = Interpreter::new();
-        loop {
-            print!(">");
-            std::io::stdout().flush()?;
-            let mut line = String::new();
-            if std::io::stdin().read_line(&mut line)? > 0 {
-                // because lexemes are stored as &static str to reduce allocations, leak the contents
-                let line: &'static str = line.leak();
-                let tokens = match Scanner::new().scan(line).map_err(Error::Scanner) {
-                    Ok(tokens) => tokens,
-                    Err(e) => {
-                        eprintln!("{}", e);
-                        continue;
-                    }
-                };
-                let ast = match Parser::new().parse(tokens).map_err(Error::Parser) {
-                    Ok(ast) => ast,
-                    Err(e) => {
-                        eprintln!("{}", e);
-                        continue;
-                    }
-                };
-                let res = match interpreter.interpret(ast) {
-                    Ok(res) => res,
-                    Err(e) => {
-                        eprintln!("{}", e);
-                        continue;
-                    }
-                };
-                if let Some(res) = res {
-                    println!("{}", res);
+    /// Runs a script read from stdin, named `<stdin>`; its imports are
+    /// relative to the working directory
+    pub fn run_stdin(&mut self) -> Result<(), Error> {
+        let ast = self.load_stdin()?;
+        self.run_to_output(ast)
+    }
+
+    /// Like `run_stdin`, but writes to `out` and hands resolver errors to `report`
+    pub fn run_stdin_reporting(
+        &mut self,
+        out: &mut impl Write,
+        report: &mut dyn FnMut(Diagnostic),
+    ) -> Result<(), Error> {
+        let ast = self.load_stdin()?;
+        self.run_ast(ast, out, Some(report))
+    }
+
+    fn load_stdin(&mut self) -> Result<Vec<Stmt>, Error> {
+        let source = std::io::read_to_string(std::io::stdin())?;
+        self.loader.load_source(&source, "<stdin>", Path::new(""))
+    }
+
+    fn run_to_output(&mut self, ast: Vec<Stmt>) -> Result<(), Error> {
+        match &self.lox.output {
+            Some(out) => self.run_ast(ast, &mut *out.borrow_mut(), None),
+            None => self.run_ast(ast, &mut std::io::stdout(), None),
+        }
+    }
+
+    /// Without `report`, resolver errors are written to `out`
+    fn run_ast(
+        &mut self,
+        ast: Vec<Stmt>,
+        out: &mut dyn Write,
+        report: Option<&mut dyn FnMut(Diagnostic)>,
+    ) -> Result<(), Error> {
+        let timings = self.lox.timings.as_ref();
+        let (locals, errors) = timed(timings, "resolve", || {
+            Resolver::with_globals(self.natives.clone())
+                .with_defined(self.defined.clone())
+                .resolve_all(&ast)
+        });
+        match report {
+            Some(report) => errors.iter().for_each(|e| report(e.into())),
+            None => {
+                let sources = self.lox.sources();
+                for e in errors {
+                    writeln!(out, "{}", Diagnostic::from(&e).render(&sources))?;
                 }
-            } else {
-                break;
             }
         }
+        self.interpreter.add_locals(locals);
+        self.defined.extend(globals_defined(&ast));
+        let res = timed(timings, "execute", || self.interpreter.interpret(ast, out))
+            .map_err(Error::Runtime)?;
+        if let Some(res) = res {
+            writeln!(out, "{}", res)?;
+        }
         Ok(())
     }
 }
+
+/// What `LoxSession::save` wrote
+#[derive(Debug, Default, PartialEq)]
+pub struct Saved {
+    /// globals written, sorted by name
+    pub saved: Vec<String>,
+    /// globals left out because their values, like functions, can't be
+    /// written
+    pub skipped: Vec<String>,
+}
+
+/// What `LoxSession::load` did
+#[derive(Debug, Default, PartialEq)]
+pub struct Loaded {
+    pub loaded: Vec<String>,
+    /// globals in the file the session already had
+    pub conflicts: Vec<String>,
+}
+
+impl LoxSession<'_> {
+    /// Writes the globals whose values are plain data, numbers, strings,
+    /// booleans and nil, to `path`
+    pub fn save(&self, path: &Path) -> Result<Saved, Error> {
+        let mut saved = Saved::default();
+        let mut globals = Vec::new();
+        for (name, value) in self.interpreter.globals() {
+            // declared without a value reads as nil
+            let value = value.unwrap_or(Literal::Nil);
+            if persist::is_plain(&value) {
+                saved.saved.push(name.clone());
+                globals.push((name, value));
+            } else if !(matches!(value, Literal::Native(_))
+                && self.natives.contains(&name.as_str()))
+            {
+                // natives are in every session, so they aren't missed
+                saved.skipped.push(name);
+            }
+        }
+        std::fs::write(path, persist::write(&globals))?;
+        Ok(saved)
+    }
+
+    /// Defines the globals `save` wrote to `path`. If the session already
+    /// has any of them, nothing is loaded unless `force`, which replaces
+    /// them.
+    pub fn load(&mut self, path: &Path, force: bool) -> Result<Loaded, Error> {
+        let file = std::fs::read_to_string(path)?;
+        let globals = persist::read(&file).map_err(|e| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("{}: {e}", path.display()),
+            )
+        })?;
+        let existing = self.interpreter.globals();
+        let conflicts: Vec<_> = globals
+            .iter()
+            .map(|(name, _)| name.clone())
+            .filter(|name| existing.iter().any(|(defined, _)| defined == name))
+            .collect();
+        if !conflicts.is_empty() && !force {
+            return Ok(Loaded {
+                loaded: Vec::new(),
+                conflicts,
+            });
+        }
+        let mut loaded = Vec::new();
+        for (name, value) in globals {
+            self.interpreter.define_global(&name, value);
+            if !self.defined.contains(&name.as_str()) {
+                self.defined.push(name.clone().leak());
+            }
+            loaded.push(name);
+        }
+        Ok(Loaded { loaded, conflicts })
+    }
+}
+
+/// Files checked one after another, each against the globals the ones
+/// before it defined, as a `LoxSession` would run them
+pub struct CheckSession<'a> {
+    lox: &'a Lox,
+    loader: Loader<'a>,
+    /// names of the natives a session would define
+    natives: Vec<&'static str>,
+    /// globals defined by the files checked so far
+    defined: Vec<&'static str>,
+}
+
+impl CheckSession<'_> {
+    /// Scans, parses and resolves a script and its imports
+    pub fn check_file(&mut self, path: &Path) -> Vec<Diagnostic> {
+        let ast = self.loader.load_file(path);
+        self.check(ast)
+    }
+
+    /// Like `check_file`, for a script read from stdin, named `<stdin>`
+    pub fn check_stdin(&mut self) -> Vec<Diagnostic> {
+        let ast = std::io::read_to_string(std::io::stdin())
+            .map_err(Error::Io)
+            .and_then(|source| self.loader.load_source(&source, "<stdin>", Path::new("")));
+        self.check(ast)
+    }
+
+    fn check(&mut self, ast: Result<Vec<Stmt>, Error>) -> Vec<Diagnostic> {
+        let ast = match ast {
+            Ok(ast) => ast,
+            Err(e) => return self.load_diagnostics(&e),
+        };
+        let (_, errors) = timed(self.lox.timings.as_ref(), "resolve", || {
+            Resolver::with_globals(self.natives.clone())
+                .with_defined(self.defined.clone())
+                .resolve_all(&ast)
+        });
+        self.defined.extend(globals_defined(&ast));
+        errors.iter().map(Diagnostic::from).collect()
+    }
+
+    /// The loader stops at a file's scanner errors; like `Lox::check`, carry
+    /// on past them to find its parser errors too. That file is the last one
+    /// loaded.
+    fn load_diagnostics(&self, e: &Error) -> Vec<Diagnostic> {
+        let mut innermost = e;
+        while let Error::Import { error, .. } = innermost {
+            innermost = error;
+        }
+        let sources = self.lox.sources();
+        let (Error::Scanner(_), Some(file)) = (innermost, sources.len().checked_sub(1)) else {
+            return e.diagnostics();
+        };
+        let Some(script) = sources.get(file) else {
+            return e.diagnostics();
+        };
+        let source: &'static str = script.text.clone().leak();
+        let (tokens, scan_errors) = Scanner::for_file(file).scan_all(source);
+        let mut diagnostics: Vec<Diagnostic> = scan_errors.iter().map(Diagnostic::from).collect();
+        if let Err(errors) = Parser::new().parse(tokens) {
+            diagnostics.extend(errors.iter().map(Diagnostic::from));
+        }
+        diagnostics
+    }
+}
+
+/// `:save <file>`, `:load <file>`, and `:load! <file>` to replace globals
+/// the session already has
+#[cfg(feature = "repl")]
+fn run_command(session: &mut LoxSession, command: repl::Command) {
+    let file = command.arg;
+    match command.name {
+        "save" | "load" | "load!" if file.is_empty() => {
+            eprintln!("`:{}` needs a file", command.name)
+        }
+        "save" => match session.save(Path::new(file)) {
+            Ok(saved) => {
+                println!("Saved {} to {file}", count(saved.saved.len(), "global"));
+                if !saved.skipped.is_empty() {
+                    println!(
+                        "Skipped {}: only numbers, strings, booleans and nil can be saved",
+                        saved.skipped.join(", ")
+                    );
+                }
+            }
+            Err(e) => eprintln!("{e}"),
+        },
+        "load" | "load!" => match session.load(Path::new(file), command.name == "load!") {
+            Ok(loaded) if loaded.loaded.is_empty() && !loaded.conflicts.is_empty() => eprintln!(
+                "Already defined: {}; `:load! {file}` replaces them",
+                loaded.conflicts.join(", ")
+            ),
+            Ok(loaded) => println!(
+                "Loaded {} from {file}",
+                count(loaded.loaded.len(), "global")
+            ),
+            Err(e) => eprintln!("{e}"),
+        },
+        name => eprintln!(
+            "Unknown command `:{name}`; the commands are `:save <file>` and `:load <file>`"
+        ),
+    }
+}
+
+fn count(n: usize, noun: &str) -> String {
+    if n == 1 {
+        format!("{n} {noun}")
+    } else {
+        format!("{n} {noun}s")
+    }
+}
+
+/// Whether a REPL entry failed only because it stopped too soon, inside a
+/// string, comment or block or partway through a statement, so another line
+/// might finish it
+fn is_unfinished(error: &Error, entry: &str) -> bool {
+    match error {
+        Error::Scanner(errors) => matches!(
+            errors.first(),
+            Some(ScanError::UnterminatedString { .. } | ScanError::UnterminatedComment { .. })
+        ),
+        // entries end in a newline, so only the end of input is on the line
+        // after their last
+        Error::Parser(errors) => errors
+            .first()
+            .is_some_and(|e| e.location().line() == entry.lines().count() + 1),
+        _ => false,
+    }
+}
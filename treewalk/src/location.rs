@@ -2,13 +2,32 @@ use std::fmt::Display;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct SourceLocation {
+    /// which file of a multi-file program, numbered as they're loaded
+    file: usize,
     line: usize,
     pos: usize,
 }
 
 impl SourceLocation {
     pub fn new(line: usize, pos: usize) -> Self {
-        Self { line, pos }
+        Self { file: 0, line, pos }
+    }
+
+    pub fn in_file(self, file: usize) -> Self {
+        Self { file, ..self }
+    }
+
+    pub fn file(&self) -> usize {
+        self.file
+    }
+
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    /// Counted from 0
+    pub fn column(&self) -> usize {
+        self.pos
     }
 
     pub fn advance_by(&mut self, count: usize) {
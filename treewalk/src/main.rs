@@ -1,19 +1,261 @@
-use std::fs::read_to_string;
+use std::{
+    io::{IsTerminal, Write, stdout},
+    path::PathBuf,
+    time::Duration,
+};
 
-use treewalk::Lox;
+use cli::{Cli, CliArgs, Flag, Script};
+use treewalk::{Diagnostic, Lox, Severity, SourceMap, Watcher};
 
-fn main() -> Result<(), treewalk::Error> {
-    let args: Vec<String> = std::env::args().collect();
+const CLI: Cli = Cli {
+    name: env!("CARGO_PKG_NAME"),
+    version: env!("CARGO_PKG_VERSION"),
+    about: "Runs a Lox script with the tree-walking interpreter",
+    flags: &[
+        Flag::option(
+            "--error-format",
+            "<human|json>",
+            "Print errors as text, or as a line of JSON each",
+        ),
+        Flag::switch(
+            "--deterministic",
+            "Make `clock()` count calls and `random()` the same each run",
+        ),
+        Flag::switch(
+            "--check",
+            "Report the problems found without running the scripts, and how many",
+        ),
+        Flag::switch(
+            "--timings",
+            "Print how long scanning, parsing, resolving and running took",
+        ),
+        Flag::switch(
+            "--watch",
+            "Run the scripts again whenever they or their imports are saved",
+        ),
+        Flag::option(
+            "--watch-interval",
+            "<ms>",
+            "How often `--watch` checks for saves, 500 by default",
+        ),
+    ],
+    script_args: true,
+    many_scripts: true,
+    without_script: "starts a REPL",
+};
 
-    #[allow(clippy::comparison_chain)]
-    if args.len() > 2 {
-        println!("Usage: {} [script]", args[0]);
-        std::process::exit(64);
-    } else if args.len() == 2 {
-        let contents = read_to_string(&args[1]).map_err(treewalk::Error::Io)?;
-        // because lexemes are stored as &static str to reduce allocations, leak the contents
-        Lox::run(contents)
+fn main() {
+    let args = CLI.args();
+    let json = match args.value("--error-format") {
+        None | Some("human") => false,
+        Some("json") => true,
+        Some(format) => CLI.usage_error(format!("unknown error format `{format}`")),
+    };
+    let interval = match args.parse("--watch-interval") {
+        Ok(ms) => Duration::from_millis(ms.unwrap_or(500)),
+        Err(e) => CLI.usage_error(e),
+    };
+    let lox = Lox::builder()
+        .deterministic(args.flag("--deterministic"))
+        .timings(args.flag("--timings"))
+        .args(args.script_args.clone())
+        .build();
+    if args.scripts.is_empty() {
+        if json {
+            CLI.usage_error("`--error-format` needs a script");
+        }
+        if args.flag("--watch") {
+            CLI.usage_error("`--watch` needs a script");
+        }
+        if args.flag("--check") {
+            CLI.usage_error("`--check` needs a script");
+        }
+        if args.flag("--timings") {
+            CLI.usage_error("`--timings` needs a script");
+        }
+        if let Err(e) = lox.run_prompt() {
+            eprintln!("{e}");
+            std::process::exit(e.exit_code());
+        }
+        return;
+    }
+
+    if args.flag("--check") {
+        let code = check(&lox, &args.scripts, json);
+        print_timings(&lox);
+        std::process::exit(code);
+    }
+    if args.flag("--watch") {
+        let Some(paths) = args
+            .scripts
+            .iter()
+            .map(|script| match script {
+                Script::Path(path) => Some(path.clone()),
+                Script::Stdin => None,
+            })
+            .collect::<Option<Vec<_>>>()
+        else {
+            CLI.usage_error("`--watch` can't watch stdin");
+        };
+        watch(&paths, &args, json, interval);
+    }
+    let result = run(&lox, &args.scripts, json);
+    print_timings(&lox);
+    if let Err(e) = result {
+        std::process::exit(e.exit_code());
+    }
+}
+
+/// The `--timings` table, if there is one, after whatever the run printed
+fn print_timings(lox: &Lox) {
+    if let Some(timings) = lox.timings() {
+        eprintln!("{timings}");
+    }
+}
+
+/// Runs `scripts` in one session, so each sees what the ones before it
+/// defined, reporting the error that stops them
+fn run(lox: &Lox, scripts: &[Script], json: bool) -> Result<(), treewalk::Error> {
+    let mut session = lox.session();
+    for script in scripts {
+        // warnings go to stderr, so they never mix with what the script prints
+        let out = &mut stdout();
+        let report = &mut |d: Diagnostic| {
+            if json {
+                eprintln!("{}", d.to_json(&lox.sources()));
+            } else {
+                eprintln!("{}", d.render(&lox.sources()));
+            }
+        };
+        let result = match script {
+            Script::Path(path) => session.run_file_reporting(path, out, report),
+            Script::Stdin => session.run_stdin_reporting(out, report),
+        };
+        if let Err(e) = result {
+            if json {
+                report_json(lox, &e);
+            } else {
+                eprintln!("{}", render(lox, script, &e.diagnostics()));
+            }
+            return Err(e);
+        }
+    }
+    Ok(())
+}
+
+/// Checks `scripts` in one session without running them, reporting every
+/// problem and then how many there were, and returns the exit code
+fn check(lox: &Lox, scripts: &[Script], json: bool) -> i32 {
+    let mut session = lox.check_session();
+    let (mut errors, mut warnings) = (0, 0);
+    for script in scripts {
+        let diagnostics = match script {
+            Script::Path(path) => session.check_file(path),
+            Script::Stdin => session.check_stdin(),
+        };
+        for d in &diagnostics {
+            match d.severity {
+                Severity::Error => errors += 1,
+                Severity::Warning => warnings += 1,
+            }
+        }
+        if json {
+            for d in &diagnostics {
+                eprintln!("{}", d.to_json(&lox.sources()));
+            }
+        } else if !diagnostics.is_empty() {
+            eprintln!("{}", render(lox, script, &diagnostics));
+        }
+    }
+    // every line of JSON output is a diagnostic
+    if !json {
+        eprintln!(
+            "{} checked, {}, {}",
+            count(scripts.len(), "file"),
+            count(errors, "error"),
+            count(warnings, "warning")
+        );
+    }
+    if errors > 0 { 65 } else { 0 }
+}
+
+fn count(n: usize, noun: &str) -> String {
+    if n == 1 {
+        format!("{n} {noun}")
+    } else {
+        format!("{n} {noun}s")
+    }
+}
+
+/// Runs `paths` with a fresh interpreter each time they or the files they
+/// import change, until Ctrl-C
+fn watch(paths: &[PathBuf], args: &CliArgs, json: bool, interval: Duration) -> ! {
+    if let Err(e) = ctrlc::set_handler(|| std::process::exit(0)) {
+        eprintln!("can't handle Ctrl-C: {e}");
+        std::process::exit(71);
+    }
+    let scripts: Vec<_> = paths.iter().cloned().map(Script::Path).collect();
+    let mut watcher = Watcher::default();
+    loop {
+        if stdout().is_terminal() {
+            print!("\x1b[2J\x1b[H");
+            let _ = stdout().flush();
+        }
+        let lox = Lox::builder()
+            .deterministic(args.flag("--deterministic"))
+            .timings(args.flag("--timings"))
+            .args(args.script_args.clone())
+            .build();
+        // errors are reported, and fixing them is the next change
+        let _ = run(&lox, &scripts, json);
+        print_timings(&lox);
+        let imports: Vec<_> = (0..lox.sources().len())
+            .map(|file| PathBuf::from(lox.sources().name(file)))
+            .collect();
+        watcher.watch(paths.iter().cloned().chain(imports));
+        eprintln!("[watching for changes, Ctrl-C to stop]");
+        watcher.wait(interval);
+    }
+}
+
+/// Problems without a location, like a script that can't be read, are named
+/// for the script they stopped
+fn render(lox: &Lox, script: &Script, diagnostics: &[Diagnostic]) -> String {
+    let sources = lox.sources();
+    let name = match script {
+        Script::Path(path) => path.display().to_string(),
+        Script::Stdin => "<stdin>".to_string(),
+    };
+    diagnostics
+        .iter()
+        .map(|d| match d.span {
+            Some(_) => d.render(&sources),
+            None => format!("{name}: {}", d.render(&sources)),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Writes every diagnostic to stderr as a line of JSON
+fn report_json(lox: &Lox, e: &treewalk::Error) {
+    let sources = lox.sources();
+    // `check` carries on past scanner errors to find parser errors too; a
+    // script that doesn't parse is the last file loaded, and its spans are
+    // into it alone
+    if let treewalk::Error::Scanner(_) | treewalk::Error::Parser(_) = e
+        && let Some(script) = sources
+            .len()
+            .checked_sub(1)
+            .and_then(|last| sources.get(last))
+    {
+        let mut alone = SourceMap::default();
+        alone.add(&script.name, &script.text);
+        for d in Lox::check(&script.text) {
+            eprintln!("{}", d.to_json(&alone));
+        }
     } else {
-        Lox::run_prompt()
+        for d in e.diagnostics() {
+            eprintln!("{}", d.to_json(&sources));
+        }
     }
 }
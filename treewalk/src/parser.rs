@@ -1,4 +1,4 @@
-use std::rc::Rc;
+use std::{cell::Cell, fmt::Display, rc::Rc};
 
 use crate::{
     ast::{Expr, Stmt},
@@ -7,41 +7,120 @@ use crate::{
 };
 use thiserror::Error;
 
-#[derive(Error, Debug)]
+#[derive(Error, Debug, PartialEq)]
+#[non_exhaustive]
 pub enum Error {
-    #[error("Expected ')' after expression at {location}")]
-    UnterminatedParen { location: SourceLocation },
-
-    #[error("Expected ';' after expression at {location}")]
-    ExpectedSemicolon { location: SourceLocation },
-
-    #[error("Expected '}}' after block at {location}")]
-    UnterminatedBrace { location: SourceLocation },
-
-    #[error("Expected '{expected}' at after '{stmt_type}' {location}")]
+    UnterminatedParen {
+        location: SourceLocation,
+    },
+    ExpectedSemicolon {
+        location: SourceLocation,
+    },
+    UnterminatedBrace {
+        location: SourceLocation,
+    },
     ExpectedToken {
         expected: String,
         stmt_type: String,
         location: SourceLocation,
     },
-
-    #[error("Invalid assignment target at {location}")]
-    InvalidAssignmentTarget { location: SourceLocation },
-
-    #[error("Unexpected token '{lexeme}'.  Expected expression at {location}")]
+    InvalidAssignmentTarget {
+        location: SourceLocation,
+    },
     UnexpectedToken {
         lexeme: String,
         location: SourceLocation,
     },
+    TooManyArguments {
+        location: SourceLocation,
+    },
+    TooManyParameters {
+        location: SourceLocation,
+    },
+    ExpectedParameterName {
+        location: SourceLocation,
+    },
+    ExpectedPropertyName {
+        location: SourceLocation,
+    },
+    ExpectedImportPath {
+        location: SourceLocation,
+    },
+    NestedImport {
+        location: SourceLocation,
+    },
+    TooMuchNesting {
+        location: SourceLocation,
+    },
+}
 
-    #[error("Can't have more than 255 arguments at {location}")]
-    TooManyArguments { location: SourceLocation },
+impl Error {
+    pub fn message(&self) -> String {
+        match self {
+            Error::UnterminatedParen { .. } => "Expected ')' after expression".to_string(),
+            Error::ExpectedSemicolon { .. } => "Expected ';' after expression".to_string(),
+            Error::UnterminatedBrace { .. } => "Expected '}' after block".to_string(),
+            Error::ExpectedToken {
+                expected,
+                stmt_type,
+                ..
+            } => format!("Expected '{expected}' after '{stmt_type}'"),
+            Error::InvalidAssignmentTarget { .. } => "Invalid assignment target".to_string(),
+            Error::UnexpectedToken { lexeme, .. } => {
+                format!("Unexpected token '{lexeme}'.  Expected expression")
+            }
+            Error::TooManyArguments { .. } => "Can't have more than 255 arguments".to_string(),
+            Error::TooManyParameters { .. } => "Can't have more than 255 parameters".to_string(),
+            Error::ExpectedParameterName { .. } => "Expected parameter name".to_string(),
+            Error::ExpectedPropertyName { .. } => "Expected property name after '.'".to_string(),
+            Error::ExpectedImportPath { .. } => "Expected a path string after 'import'".to_string(),
+            Error::NestedImport { .. } => "Imports are only allowed at the top level".to_string(),
+            Error::TooMuchNesting { .. } => "Too much nesting".to_string(),
+        }
+    }
 
-    #[error("Can't have more than 255 parameters at {location}")]
-    TooManyParameters { location: SourceLocation },
+    /// Stable name for the kind of error, see `Diagnostic::code`
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::UnterminatedParen { .. } => "unterminated-paren",
+            Error::ExpectedSemicolon { .. } => "expected-semicolon",
+            Error::UnterminatedBrace { .. } => "unterminated-brace",
+            Error::ExpectedToken { .. } => "expected-token",
+            Error::InvalidAssignmentTarget { .. } => "invalid-assignment-target",
+            Error::UnexpectedToken { .. } => "unexpected-token",
+            Error::TooManyArguments { .. } => "too-many-arguments",
+            Error::TooManyParameters { .. } => "too-many-parameters",
+            Error::ExpectedParameterName { .. } => "expected-parameter-name",
+            Error::ExpectedPropertyName { .. } => "expected-property-name",
+            Error::ExpectedImportPath { .. } => "expected-import-path",
+            Error::NestedImport { .. } => "nested-import",
+            Error::TooMuchNesting { .. } => "too-much-nesting",
+        }
+    }
 
-    #[error("Expected parameter name at {location}")]
-    ExpectedParameterName { location: SourceLocation },
+    pub fn location(&self) -> SourceLocation {
+        match self {
+            Error::UnterminatedParen { location }
+            | Error::ExpectedSemicolon { location }
+            | Error::UnterminatedBrace { location }
+            | Error::ExpectedToken { location, .. }
+            | Error::InvalidAssignmentTarget { location }
+            | Error::UnexpectedToken { location, .. }
+            | Error::TooManyArguments { location }
+            | Error::TooManyParameters { location }
+            | Error::ExpectedParameterName { location }
+            | Error::ExpectedPropertyName { location }
+            | Error::ExpectedImportPath { location }
+            | Error::NestedImport { location }
+            | Error::TooMuchNesting { location } => *location,
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} at {}", self.message(), self.location())
+    }
 }
 
 macro_rules! binary_expr {
@@ -58,7 +137,7 @@ macro_rules! binary_expr {
             let right = if let Ok(right) = try_right {
                 right
             } else {
-                return (try_right, new_cursor);
+                return (try_right, next_cursor);
             };
             new_cursor = next_cursor;
             left = Expr::Binary {
@@ -72,38 +151,75 @@ macro_rules! binary_expr {
     }};
 }
 
-// For chapter 6, we will only parse equality expressions.
-pub struct Parser {}
+/// deeper nesting is a parse error rather than a native stack overflow, in
+/// the parser or in the passes that walk what it returns
+const MAX_DEPTH: usize = 128;
+
+pub struct Parser {
+    /// statements, assignments and unary operators being parsed inside one
+    /// another
+    depth: Cell<usize>,
+}
 
 impl Parser {
     pub fn new() -> Self {
-        Self {}
+        Self {
+            depth: Cell::new(0),
+        }
     }
 
+    /// Runs `parse` one level deeper, or fails if that's too deep
+    fn nested<T>(
+        &self,
+        tokens: &[TokenItem],
+        cursor: usize,
+        parse: impl FnOnce() -> (Result<T, Error>, usize),
+    ) -> (Result<T, Error>, usize) {
+        if self.depth.get() == MAX_DEPTH {
+            let location = tokens[cursor].location;
+            return (Err(Error::TooMuchNesting { location }), cursor);
+        }
+        self.depth.set(self.depth.get() + 1);
+        let res = parse();
+        self.depth.set(self.depth.get() - 1);
+        res
+    }
+
+    /// An error in a statement with a `TokenType::Error` token in it is
+    /// left out, since it's most likely from the scanner's error, which is
+    /// reported already. Parsing still fails then, even with no errors.
     pub fn parse(self, source: Vec<TokenItem>) -> Result<Vec<Stmt>, Vec<Error>> {
         let mut statements = Vec::new();
         let mut errors = Vec::new();
+        let mut failed = false;
         let mut cursor = 0;
         while cursor < source.len() && !matches!(source[cursor].ttype, TokenType::EoF) {
-            let (stmt, next_cursor) = self.statement(&source, cursor);
+            let start = cursor;
+            let (stmt, next_cursor) = match source[cursor].ttype {
+                TokenType::Import => self.import_stmt(&source, cursor + 1),
+                _ => self.statement(&source, cursor),
+            };
             cursor = next_cursor;
             match stmt {
                 Ok(stmt) => statements.push(stmt),
                 Err(err) => {
-                    errors.push(err);
+                    failed = true;
                     cursor = self.synchronize(&source, cursor + 1);
+                    let end = cursor.min(source.len());
+                    if !source[start..end]
+                        .iter()
+                        .any(|token| token.ttype == TokenType::Error)
+                    {
+                        errors.push(err);
+                    }
                 }
             }
         }
-        if errors.is_empty() {
-            Ok(statements)
-        } else {
-            Err(errors)
-        }
+        if failed { Err(errors) } else { Ok(statements) }
     }
 
     fn statement(&self, tokens: &[TokenItem], cursor: usize) -> (Result<Stmt, Error>, usize) {
-        match tokens[cursor].ttype {
+        self.nested(tokens, cursor, || match tokens[cursor].ttype {
             TokenType::Print => self.print_stmt(tokens, cursor + 1),
             TokenType::Var => self.var_decl(tokens, cursor + 1),
             TokenType::LeftBrace => self.block(tokens, cursor + 1),
@@ -111,15 +227,53 @@ impl Parser {
             TokenType::While => self.while_stmt(tokens, cursor + 1),
             TokenType::For => self.for_stmt(tokens, cursor + 1),
             TokenType::Fun => self.fun_stmt(tokens, cursor + 1),
+            TokenType::Class => self.class_stmt(tokens, cursor + 1),
             TokenType::Return => self.return_stmt(tokens, cursor + 1),
+            TokenType::Import => (
+                Err(Error::NestedImport {
+                    location: tokens[cursor].location,
+                }),
+                cursor,
+            ),
             _ => self.expr_stmt(tokens, cursor),
+        })
+    }
+
+    fn import_stmt(&self, tokens: &[TokenItem], cursor: usize) -> (Result<Stmt, Error>, usize) {
+        let Some(Literal::String(path)) = &tokens[cursor].literal else {
+            return (
+                Err(Error::ExpectedImportPath {
+                    location: tokens[cursor].location,
+                }),
+                cursor,
+            );
+        };
+        if tokens[cursor + 1].ttype == TokenType::Semicolon {
+            (
+                Ok(Stmt::Import {
+                    path: path.to_string(),
+                    location: tokens[cursor].location,
+                }),
+                cursor + 2,
+            )
+        } else {
+            (
+                Err(Error::ExpectedSemicolon {
+                    location: tokens[cursor + 1].location,
+                }),
+                cursor + 1,
+            )
         }
     }
 
     fn expr_stmt(&self, tokens: &[TokenItem], cursor: usize) -> (Result<Stmt, Error>, usize) {
         let (expr, cursor) = self.expression(tokens, cursor);
+        let expr = match expr {
+            Ok(expr) => expr,
+            Err(e) => return (Err(e), cursor),
+        };
         if tokens[cursor].ttype == TokenType::Semicolon {
-            (expr.map(Stmt::Expression), cursor + 1)
+            (Ok(Stmt::Expression(expr)), cursor + 1)
         } else {
             (
                 Err(Error::ExpectedSemicolon {
@@ -141,8 +295,12 @@ impl Parser {
             );
         }
         let (expr, cursor) = self.expression(tokens, cursor);
+        let expr = match expr {
+            Ok(expr) => expr,
+            Err(e) => return (Err(e), cursor),
+        };
         if tokens[cursor].ttype == TokenType::Semicolon {
-            (expr.map(Stmt::Return), cursor + 1)
+            (Ok(Stmt::Return(expr)), cursor + 1)
         } else {
             (
                 Err(Error::ExpectedSemicolon {
@@ -154,9 +312,13 @@ impl Parser {
     }
 
     fn print_stmt(&self, tokens: &[TokenItem], cursor: usize) -> (Result<Stmt, Error>, usize) {
-        let (expr, cursor) = self.equality(tokens, cursor);
+        let (expr, cursor) = self.expression(tokens, cursor);
+        let expr = match expr {
+            Ok(expr) => expr,
+            Err(e) => return (Err(e), cursor),
+        };
         if tokens[cursor].ttype == TokenType::Semicolon {
-            (expr.map(Stmt::Print), cursor + 1)
+            (Ok(Stmt::Print(expr)), cursor + 1)
         } else {
             (
                 Err(Error::ExpectedSemicolon {
@@ -189,10 +351,14 @@ impl Parser {
                 cursor + 1,
             ),
             TokenType::Equal => {
-                let (expr, cursor) = self.equality(tokens, cursor + 1);
+                let (expr, cursor) = self.expression(tokens, cursor + 1);
+                let expr = match expr {
+                    Ok(expr) => expr,
+                    Err(e) => return (Err(e), cursor),
+                };
                 if tokens[cursor].ttype == TokenType::Semicolon {
                     (
-                        expr.map(|expr| Stmt::VarDecl {
+                        Ok(Stmt::VarDecl {
                             name,
                             location: tokens[cursor].location,
                             initializer: Some(expr),
@@ -230,8 +396,9 @@ impl Parser {
             );
         }
         let (condition, cursor) = self.expression(tokens, cursor + 1);
-        let Ok(condition) = condition else {
-            return (condition.map(Stmt::Expression), cursor);
+        let condition = match condition {
+            Ok(condition) => condition,
+            Err(e) => return (Err(e), cursor),
         };
         if !matches!(tokens[cursor].ttype, TokenType::RightParen) {
             return (
@@ -247,14 +414,14 @@ impl Parser {
         let Ok(then_branch) = then_branch else {
             return (then_branch, cursor);
         };
-        let else_branch = if matches!(tokens[cursor].ttype, TokenType::Else) {
+        let (else_branch, cursor) = if matches!(tokens[cursor].ttype, TokenType::Else) {
             let (else_branch, cursor) = self.statement(tokens, cursor + 1);
             let Ok(else_branch) = else_branch else {
                 return (else_branch, cursor);
             };
-            Some(else_branch)
+            (Some(else_branch), cursor)
         } else {
-            None
+            (None, cursor)
         };
         (
             Ok(Stmt::If {
@@ -278,8 +445,9 @@ impl Parser {
             );
         }
         let (condition, cursor) = self.expression(tokens, cursor + 1);
-        let Ok(condition) = condition else {
-            return (condition.map(Stmt::Expression), cursor);
+        let condition = match condition {
+            Ok(condition) => condition,
+            Err(e) => return (Err(e), cursor),
         };
         if !matches!(tokens[cursor].ttype, TokenType::RightParen) {
             return (
@@ -291,16 +459,6 @@ impl Parser {
                 cursor,
             );
         }
-        if !matches!(tokens[cursor].ttype, TokenType::RightParen) {
-            return (
-                Err(Error::ExpectedToken {
-                    expected: ")".to_string(),
-                    stmt_type: "while".to_string(),
-                    location: tokens[cursor].location,
-                }),
-                cursor,
-            );
-        }
         let (block, cursor) = self.statement(tokens, cursor + 1);
         let Ok(block) = block else {
             return (block, cursor);
@@ -336,7 +494,7 @@ impl Parser {
                 (Some(var_decl), cursor)
             }
             _ => {
-                let (expr_stmt, cursor) = self.expr_stmt(tokens, cursor + 1);
+                let (expr_stmt, cursor) = self.expr_stmt(tokens, cursor);
                 let Ok(expr_stmt) = expr_stmt else {
                     return (expr_stmt, cursor);
                 };
@@ -347,8 +505,9 @@ impl Parser {
             TokenType::Semicolon => (None, cursor + 1),
             _ => {
                 let (condition, cursor) = self.expression(tokens, cursor);
-                let Ok(condition) = condition else {
-                    return (condition.map(Stmt::Expression), cursor);
+                let condition = match condition {
+                    Ok(condition) => condition,
+                    Err(e) => return (Err(e), cursor),
                 };
                 if !matches!(tokens[cursor].ttype, TokenType::Semicolon) {
                     return (
@@ -390,15 +549,15 @@ impl Parser {
         let Ok(body) = body else {
             return (body, cursor);
         };
-        let body = if increment.is_some() {
-            Stmt::Block(vec![body, increment.unwrap()])
+        let body = if let Some(increment) = increment {
+            Stmt::Block(vec![body, increment])
         } else {
             body
         };
 
-        let for_loop = if initializer.is_some() {
+        let for_loop = if let Some(initializer) = initializer {
             Stmt::Block(vec![
-                initializer.unwrap(),
+                initializer,
                 Stmt::While {
                     condition,
                     body: Box::new(body),
@@ -508,6 +667,58 @@ impl Parser {
         )
     }
 
+    fn class_stmt(&self, tokens: &[TokenItem], cursor: usize) -> (Result<Stmt, Error>, usize) {
+        // classDecl      → "class" IDENTIFIER "{" function* "}" ;
+        if !matches!(tokens[cursor].ttype, TokenType::Identifier) {
+            return (
+                Err(Error::UnexpectedToken {
+                    lexeme: tokens[cursor].lexeme.to_string(),
+                    location: tokens[cursor].location,
+                }),
+                cursor,
+            );
+        }
+        let name = tokens[cursor].lexeme;
+        let location = tokens[cursor].location;
+        let mut cursor = cursor + 1;
+        if !matches!(tokens[cursor].ttype, TokenType::LeftBrace) {
+            return (
+                Err(Error::ExpectedToken {
+                    expected: "{".to_string(),
+                    stmt_type: "class".to_string(),
+                    location: tokens[cursor].location,
+                }),
+                cursor,
+            );
+        }
+        cursor += 1;
+        let mut methods = Vec::new();
+        while !matches!(tokens[cursor].ttype, TokenType::RightBrace | TokenType::EoF) {
+            let (method, next_cursor) = self.fun_stmt(tokens, cursor);
+            cursor = next_cursor;
+            let Ok(method) = method else {
+                return (method, cursor);
+            };
+            methods.push(method);
+        }
+        if !matches!(tokens[cursor].ttype, TokenType::RightBrace) {
+            return (
+                Err(Error::UnterminatedBrace {
+                    location: tokens[cursor].location,
+                }),
+                cursor,
+            );
+        }
+        (
+            Ok(Stmt::ClassDecl {
+                name,
+                location,
+                methods,
+            }),
+            cursor + 1,
+        )
+    }
+
     fn block(&self, tokens: &[TokenItem], cursor: usize) -> (Result<Stmt, Error>, usize) {
         let mut stmts = Vec::new();
 
@@ -540,8 +751,16 @@ impl Parser {
     }
 
     fn assignment(&self, tokens: &[TokenItem], cursor: usize) -> (Result<Expr, Error>, usize) {
-        // assignment     → IDENTIFIER "=" assignment | equality ;
-        let (expr, cursor) = self.equality(tokens, cursor);
+        self.nested(tokens, cursor, || self.assignment_inner(tokens, cursor))
+    }
+
+    fn assignment_inner(
+        &self,
+        tokens: &[TokenItem],
+        cursor: usize,
+    ) -> (Result<Expr, Error>, usize) {
+        // assignment     → ( call "." )? IDENTIFIER "=" assignment | logic_or ;
+        let (expr, cursor) = self.logic_or(tokens, cursor);
         let Ok(expr) = expr else {
             return (expr, cursor);
         };
@@ -549,12 +768,15 @@ impl Parser {
             return (Ok(expr), cursor);
         }
         let assignment_location = tokens[cursor].location;
+        // groupings don't get their own node, so `(a)` parses to the variable
+        // itself; only a bare name or property can be assigned to
+        let parenthesized = matches!(tokens[cursor - 1].ttype, TokenType::RightParen);
         let (value, cursor) = self.expression(tokens, cursor + 1);
         let Ok(value) = value else {
             return (value, cursor);
         };
         match expr {
-            Expr::Variable { name, location } => (
+            Expr::Variable { name, location } if !parenthesized => (
                 Ok(Expr::Assignment {
                     location,
                     name,
@@ -562,6 +784,19 @@ impl Parser {
                 }),
                 cursor,
             ),
+            Expr::Get {
+                location,
+                object,
+                name,
+            } if !parenthesized => (
+                Ok(Expr::Set {
+                    location,
+                    object,
+                    name,
+                    value: Box::new(value),
+                }),
+                cursor,
+            ),
             _ => (
                 Err(Error::InvalidAssignmentTarget {
                     location: assignment_location,
@@ -571,6 +806,16 @@ impl Parser {
         }
     }
 
+    fn logic_or(&self, tokens: &[TokenItem], cursor: usize) -> (Result<Expr, Error>, usize) {
+        // logic_or       → logic_and ( "or" logic_and )* ;
+        binary_expr!(self, tokens, cursor, logic_and, TokenType::Or)
+    }
+
+    fn logic_and(&self, tokens: &[TokenItem], cursor: usize) -> (Result<Expr, Error>, usize) {
+        // logic_and      → equality ( "and" equality )* ;
+        binary_expr!(self, tokens, cursor, equality, TokenType::And)
+    }
+
     fn equality(&self, tokens: &[TokenItem], cursor: usize) -> (Result<Expr, Error>, usize) {
         // equality       → comparison ( ( "!=" | "==" ) comparison )* ;
         binary_expr!(
@@ -619,7 +864,8 @@ impl Parser {
         // unary          → ( "!" | "-" ) unary | primary ;
         if matches!(tokens[cursor].ttype, TokenType::Bang | TokenType::Minus) {
             let operator = tokens[cursor].ttype;
-            let (try_right, next_cursor) = self.unary(tokens, cursor + 1);
+            let (try_right, next_cursor) =
+                self.nested(tokens, cursor, || self.unary(tokens, cursor + 1));
             let Ok(right) = try_right else {
                 return (try_right, next_cursor);
             };
@@ -642,7 +888,28 @@ impl Parser {
             return (try_callee, next_cursor);
         };
         let mut cursor = next_cursor;
-        while cursor < tokens.len() - 1 && matches!(tokens[cursor].ttype, TokenType::LeftParen) {
+        // call           → primary ( "(" arguments? ")" | "." IDENTIFIER )* ;
+        while cursor < tokens.len() - 1
+            && matches!(tokens[cursor].ttype, TokenType::LeftParen | TokenType::Dot)
+        {
+            if matches!(tokens[cursor].ttype, TokenType::Dot) {
+                cursor += 1;
+                if !matches!(tokens[cursor].ttype, TokenType::Identifier) {
+                    return (
+                        Err(Error::ExpectedPropertyName {
+                            location: tokens[cursor].location,
+                        }),
+                        cursor,
+                    );
+                }
+                callee = Expr::Get {
+                    location: tokens[cursor].location,
+                    object: callee.into(),
+                    name: tokens[cursor].lexeme,
+                };
+                cursor += 1;
+                continue;
+            }
             let mut arguments = Vec::new();
             cursor += 1;
             if !matches!(tokens[cursor].ttype, TokenType::RightParen) {
@@ -714,7 +981,7 @@ impl Parser {
                 (Ok(Expr::Variable { location, name }), cursor + 1)
             }
             TokenType::LeftParen => {
-                let (try_expression, next_cursor) = self.equality(tokens, cursor + 1);
+                let (try_expression, next_cursor) = self.expression(tokens, cursor + 1);
                 let expression = if let Ok(expression) = try_expression {
                     expression
                 } else {
@@ -753,10 +1020,344 @@ impl Parser {
                 | TokenType::If
                 | TokenType::While
                 | TokenType::Print
-                | TokenType::Return => return cursor,
+                | TokenType::Return
+                | TokenType::Import => return cursor,
                 _ => cursor += 1,
             }
         }
         cursor
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{ast::Expr, scanner::Scanner, token::Literal};
+
+    fn parse(source: &'static str) -> Vec<Stmt> {
+        let tokens = Scanner::new().scan(source).unwrap();
+        Parser::new().parse(tokens).unwrap()
+    }
+
+    #[test]
+    fn test_variables() {
+        let stmts = parse("var x = 5; var y; x = y; print x;");
+        assert!(matches!(
+            &stmts[0],
+            Stmt::VarDecl {
+                name: "x",
+                initializer: Some(Expr::Literal {
+                    value: Literal::Number(5.0),
+                    ..
+                }),
+                ..
+            }
+        ));
+        assert!(matches!(
+            &stmts[1],
+            Stmt::VarDecl {
+                name: "y",
+                initializer: None,
+                ..
+            }
+        ));
+        let Stmt::Expression(Expr::Assignment { name, value, .. }) = &stmts[2] else {
+            panic!("expected an assignment, got {:?}", stmts[2]);
+        };
+        assert_eq!(*name, "x");
+        assert!(matches!(**value, Expr::Variable { name: "y", .. }));
+        assert!(matches!(
+            &stmts[3],
+            Stmt::Print(Expr::Variable { name: "x", .. })
+        ));
+    }
+
+    #[test]
+    fn test_logical_precedence() {
+        let stmts = parse("a = b or c and d == e;");
+        let Stmt::Expression(Expr::Assignment { value, .. }) = &stmts[0] else {
+            panic!("expected an assignment, got {:?}", stmts[0]);
+        };
+        let Expr::Binary {
+            operator: TokenType::Or,
+            right,
+            ..
+        } = &**value
+        else {
+            panic!("expected 'or' at the top, got {value:?}");
+        };
+        let Expr::Binary {
+            operator: TokenType::And,
+            right,
+            ..
+        } = &**right
+        else {
+            panic!("expected 'and' under 'or', got {right:?}");
+        };
+        assert!(matches!(
+            **right,
+            Expr::Binary {
+                operator: TokenType::EqualEq,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_for_desugaring() {
+        // { var i = 0; while (i < 3) { { print i; } i = i + 1; } }
+        let stmts = parse("for (var i = 0; i < 3; i = i + 1) { print i; }");
+        let [Stmt::Block(outer)] = &stmts[..] else {
+            panic!("expected a block, got {stmts:?}");
+        };
+        let [
+            Stmt::VarDecl { name: "i", .. },
+            Stmt::While { condition, body },
+        ] = &outer[..]
+        else {
+            panic!("expected an initializer and a loop, got {outer:?}");
+        };
+        assert!(matches!(
+            condition,
+            Expr::Binary {
+                operator: TokenType::Less,
+                ..
+            }
+        ));
+        let Stmt::Block(body) = &**body else {
+            panic!("expected the body and increment, got {body:?}");
+        };
+        assert!(matches!(
+            &body[..],
+            [
+                Stmt::Block(_),
+                Stmt::Expression(Expr::Assignment { name: "i", .. })
+            ]
+        ));
+
+        // an expression initializer is kept as a statement
+        let stmts = parse("var i; for (i = 0; i < 3;) print i;");
+        let Stmt::Block(outer) = &stmts[1] else {
+            panic!("expected a block, got {:?}", stmts[1]);
+        };
+        assert!(matches!(
+            &outer[..],
+            [
+                Stmt::Expression(Expr::Assignment { name: "i", .. }),
+                Stmt::While { body, .. }
+            ] if matches!(**body, Stmt::Print(_))
+        ));
+    }
+
+    #[test]
+    fn test_for_without_clauses() {
+        let stmts = parse("for (;;) print 1;");
+        let [Stmt::While { condition, body }] = &stmts[..] else {
+            panic!("expected a bare loop, got {stmts:?}");
+        };
+        assert!(matches!(
+            condition,
+            Expr::Literal {
+                value: Literal::True,
+                ..
+            }
+        ));
+        assert!(matches!(**body, Stmt::Print(_)));
+    }
+
+    #[test]
+    fn test_statements_take_full_expressions() {
+        let stmts = parse("print a = 1; var x = y = 2; (a = b or c);");
+        assert!(matches!(
+            &stmts[0],
+            Stmt::Print(Expr::Assignment { name: "a", .. })
+        ));
+        assert!(matches!(
+            &stmts[1],
+            Stmt::VarDecl {
+                name: "x",
+                initializer: Some(Expr::Assignment { name: "y", .. }),
+                ..
+            }
+        ));
+        let Stmt::Expression(Expr::Assignment {
+            name: "a", value, ..
+        }) = &stmts[2]
+        else {
+            panic!("expected an assignment, got {:?}", stmts[2]);
+        };
+        assert!(matches!(
+            **value,
+            Expr::Binary {
+                operator: TokenType::Or,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_chained_assignment() {
+        let stmts = parse("a = b = (c);");
+        let Stmt::Expression(Expr::Assignment {
+            name: "a", value, ..
+        }) = &stmts[0]
+        else {
+            panic!("expected an assignment, got {:?}", stmts[0]);
+        };
+        let Expr::Assignment {
+            name: "b", value, ..
+        } = &**value
+        else {
+            panic!("assignment should be right-associative, got {value:?}");
+        };
+        assert!(matches!(**value, Expr::Variable { name: "c", .. }));
+    }
+
+    #[test]
+    fn test_invalid_assignment_target() {
+        let target_error = |source: &'static str| {
+            let tokens = Scanner::new().scan(source).unwrap();
+            match Parser::new().parse(tokens).unwrap_err().as_slice() {
+                [Error::InvalidAssignmentTarget { location }] => *location,
+                errors => panic!("expected one invalid target, got {errors:?}"),
+            }
+        };
+        // reported at the '='
+        assert_eq!(target_error("1 = 2;"), SourceLocation::new(1, 2));
+        assert_eq!(target_error("(a) = 3;"), SourceLocation::new(1, 4));
+        assert_eq!(target_error("(a.b) = 3;"), SourceLocation::new(1, 6));
+        assert_eq!(target_error("a.b() = 3;"), SourceLocation::new(1, 6));
+        assert_eq!(target_error("a + b = 3;"), SourceLocation::new(1, 6));
+        assert_eq!(
+            target_error("var x;\nx = 1 = x;"),
+            SourceLocation::new(2, 6)
+        );
+    }
+
+    #[test]
+    fn test_scanner_error_tokens() {
+        let errors = |source: &'static str| {
+            let (tokens, _) = Scanner::new().scan_all(source);
+            Parser::new().parse(tokens).unwrap_err()
+        };
+        // what's left of a statement the scanner failed in isn't reported
+        assert_eq!(errors("var a = @;"), []);
+        assert_eq!(errors("print \"open;"), []);
+        assert_eq!(
+            errors("var a = #;\nprint 1 +;"),
+            [Error::UnexpectedToken {
+                lexeme: ";".to_string(),
+                location: SourceLocation::new(2, 9),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_too_much_nesting() {
+        let errors = |source: String| {
+            let tokens = Scanner::new().scan(source.leak()).unwrap();
+            Parser::new().parse(tokens)
+        };
+        let nested = |depth: usize| format!("print {}1{};", "(".repeat(depth), ")".repeat(depth));
+        assert!(errors(nested(MAX_DEPTH - 2)).is_ok());
+        assert!(matches!(
+            errors(nested(10_000)).unwrap_err().as_slice(),
+            [Error::TooMuchNesting { .. }, ..]
+        ));
+        for repeated in ["-", "{", "if (true) ", "a = "] {
+            let source = format!("{}1;", repeated.repeat(10_000));
+            assert!(
+                matches!(
+                    errors(source).unwrap_err().as_slice(),
+                    [Error::TooMuchNesting { .. }, ..]
+                ),
+                "{repeated}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_import() {
+        let stmts = parse("import \"lib/helpers.lox\"; print 1;");
+        assert!(matches!(
+            &stmts[..],
+            [Stmt::Import { path, .. }, Stmt::Print(_)] if path == "lib/helpers.lox"
+        ));
+        let errors = |source: &'static str| {
+            let tokens = Scanner::new().scan(source).unwrap();
+            Parser::new().parse(tokens).unwrap_err()
+        };
+        assert_eq!(
+            errors("import helpers;"),
+            [Error::ExpectedImportPath {
+                location: SourceLocation::new(1, 7)
+            }]
+        );
+        assert_eq!(
+            errors("if (true) import \"a.lox\";"),
+            [Error::NestedImport {
+                location: SourceLocation::new(1, 10)
+            }]
+        );
+    }
+
+    #[test]
+    fn test_class() {
+        let stmts = parse("class Point { area() { return 0; } scale(by) {} }");
+        let [Stmt::ClassDecl { name, methods, .. }] = &stmts[..] else {
+            panic!("expected a class, got {stmts:?}");
+        };
+        assert_eq!(*name, "Point");
+        assert!(matches!(
+            &methods[..],
+            [
+                Stmt::FunDecl { name: "area", .. },
+                Stmt::FunDecl { name: "scale", params, .. },
+            ] if params == &["by"]
+        ));
+
+        let errors = |source: &'static str| {
+            let tokens = Scanner::new().scan(source).unwrap();
+            Parser::new().parse(tokens).unwrap_err()
+        };
+        assert!(matches!(
+            errors("class { }").as_slice(),
+            [Error::UnexpectedToken { .. }]
+        ));
+        assert!(matches!(
+            errors("class A { fun f() {} }").as_slice(),
+            [Error::UnexpectedToken { .. }]
+        ));
+        assert!(matches!(
+            errors("class A { f() {}").as_slice(),
+            [Error::UnterminatedBrace { .. }]
+        ));
+    }
+
+    #[test]
+    fn test_properties() {
+        let stmts = parse("a.b(1).c = d.e;");
+        let Stmt::Expression(Expr::Set {
+            object,
+            name: "c",
+            value,
+            ..
+        }) = &stmts[0]
+        else {
+            panic!("expected a set, got {:?}", stmts[0]);
+        };
+        let Expr::Call { callee, .. } = &**object else {
+            panic!("expected a call, got {object:?}");
+        };
+        assert!(matches!(**callee, Expr::Get { name: "b", .. }));
+        assert!(matches!(**value, Expr::Get { name: "e", .. }));
+
+        let tokens = Scanner::new().scan("print a.;").unwrap();
+        assert_eq!(
+            Parser::new().parse(tokens).unwrap_err(),
+            [Error::ExpectedPropertyName {
+                location: SourceLocation::new(1, 8)
+            }]
+        );
+    }
+}
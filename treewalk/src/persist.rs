@@ -0,0 +1,150 @@
+//! The file a REPL's `:save` writes globals to and `:load` reads them back
+//! from: a version line, then a `name type value` line for each global
+use std::rc::Rc;
+
+use crate::token::Literal;
+
+const HEADER: &str = "lox-session";
+const VERSION: u32 = 1;
+
+/// Whether `value` can be written to a session file, which functions,
+/// classes and instances can't
+pub(crate) fn is_plain(value: &Literal) -> bool {
+    matches!(
+        value,
+        Literal::String(_) | Literal::Number(_) | Literal::True | Literal::False | Literal::Nil
+    )
+}
+
+/// `globals` as a session file; they must all be plain
+pub(crate) fn write(globals: &[(String, Literal)]) -> String {
+    let mut file = format!("{HEADER} {VERSION}\n");
+    for (name, value) in globals {
+        let line = match value {
+            Literal::String(s) => format!("{name} string {}", escape(s)),
+            // `{:?}` keeps every digit, so the value reads back the same
+            Literal::Number(n) => format!("{name} number {n:?}"),
+            Literal::True => format!("{name} bool true"),
+            Literal::False => format!("{name} bool false"),
+            Literal::Nil => format!("{name} nil"),
+            Literal::Function { .. }
+            | Literal::Native(_)
+            | Literal::Class(_)
+            | Literal::Instance(_) => continue,
+        };
+        file.push_str(&line);
+        file.push('\n');
+    }
+    file
+}
+
+/// The globals in a session file, or what's wrong with it
+pub(crate) fn read(file: &str) -> Result<Vec<(String, Literal)>, String> {
+    let mut lines = file.lines();
+    let version = lines
+        .next()
+        .and_then(|header| header.strip_prefix(HEADER))
+        .map(str::trim)
+        .ok_or("not a session file")?;
+    if version != VERSION.to_string() {
+        return Err(format!("session file version {version} isn't supported"));
+    }
+    let mut globals = Vec::new();
+    for (i, line) in lines.enumerate().filter(|(_, line)| !line.is_empty()) {
+        let invalid = || format!("line {}: invalid global `{line}`", i + 2);
+        let mut parts = line.splitn(3, ' ');
+        let (Some(name), Some(kind)) = (parts.next(), parts.next()) else {
+            return Err(invalid());
+        };
+        if !is_identifier(name) {
+            return Err(invalid());
+        }
+        let value = match (kind, parts.next()) {
+            ("string", Some(s)) => Literal::String(Rc::new(unescape(s).ok_or_else(invalid)?)),
+            ("number", Some(n)) => Literal::Number(n.parse().map_err(|_| invalid())?),
+            ("bool", Some("true")) => Literal::True,
+            ("bool", Some("false")) => Literal::False,
+            ("nil", None) => Literal::Nil,
+            _ => return Err(invalid()),
+        };
+        globals.push((name.to_string(), value));
+    }
+    Ok(globals)
+}
+
+fn is_identifier(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Keeps each global on one line
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+}
+
+fn unescape(s: &str) -> Option<String> {
+    let mut unescaped = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next()? {
+            '\\' => unescaped.push('\\'),
+            'n' => unescaped.push('\n'),
+            'r' => unescaped.push('\r'),
+            _ => return None,
+        }
+    }
+    Some(unescaped)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let globals = vec![
+            ("a".to_string(), Literal::Number(0.1 + 0.2)),
+            ("b".to_string(), Literal::Number(-0.0)),
+            (
+                "c".to_string(),
+                Literal::String(Rc::new("two\nlines \\n and\r spaces ".to_string())),
+            ),
+            ("d".to_string(), Literal::True),
+            ("e".to_string(), Literal::False),
+            ("f".to_string(), Literal::Nil),
+        ];
+        let file = write(&globals);
+        assert_eq!(file.lines().count(), 7);
+        let read = read(&file).unwrap();
+        assert_eq!(read, globals);
+        // equal as numbers isn't enough for -0
+        assert!(matches!(read[1].1, Literal::Number(n) if n.is_sign_negative()));
+    }
+
+    #[test]
+    fn test_read_errors() {
+        assert_eq!(read(""), Err("not a session file".to_string()));
+        assert_eq!(
+            read("lox-session 2\n"),
+            Err("session file version 2 isn't supported".to_string())
+        );
+        assert_eq!(
+            read("lox-session 1\na number 1\nb number one\n"),
+            Err("line 3: invalid global `b number one`".to_string())
+        );
+        assert_eq!(
+            read("lox-session 1\n1a nil\n"),
+            Err("line 2: invalid global `1a nil`".to_string())
+        );
+        assert_eq!(
+            read("lox-session 1\na string bad\\escape\n"),
+            Err("line 2: invalid global `a string bad\\escape`".to_string())
+        );
+    }
+}
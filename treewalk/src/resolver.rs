@@ -1,33 +1,67 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, fmt::Display};
 
 use crate::{
     ast::{Expr, Stmt},
+    interpreter::default_natives,
     location::SourceLocation,
 };
 
 use thiserror::Error;
 
-#[derive(Error, Debug)]
+#[derive(Error, Debug, PartialEq)]
+#[non_exhaustive]
 pub enum Error {
-    #[error("Can't read local variable '{name}' in its own initializer at {location}")]
     AccessInInitializer {
         name: String,
         location: SourceLocation,
     },
-
-    #[error("Access undeclared variable '{name}' at {location}")]
     AccessUndefined {
         name: String,
         location: SourceLocation,
     },
-
-    #[error("Duplicate variable '{name}' found in scope at {location}")]
     DuplicateVariable {
         name: String,
         location: SourceLocation,
     },
 }
 
+impl Error {
+    pub fn message(&self) -> String {
+        match self {
+            Error::AccessInInitializer { name, .. } => {
+                format!("Can't read local variable '{name}' in its own initializer")
+            }
+            Error::AccessUndefined { name, .. } => format!("Access undeclared variable '{name}'"),
+            Error::DuplicateVariable { name, .. } => {
+                format!("Duplicate variable '{name}' found in scope")
+            }
+        }
+    }
+
+    /// Stable name for the kind of error, see `Diagnostic::code`
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::AccessInInitializer { .. } => "access-in-initializer",
+            Error::AccessUndefined { .. } => "access-undefined",
+            Error::DuplicateVariable { .. } => "duplicate-variable",
+        }
+    }
+
+    pub fn location(&self) -> SourceLocation {
+        match self {
+            Error::AccessInInitializer { location, .. }
+            | Error::AccessUndefined { location, .. }
+            | Error::DuplicateVariable { location, .. } => *location,
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} at {}", self.message(), self.location())
+    }
+}
+
 trait ResolveExpr {
     fn resolve(
         &self,
@@ -58,24 +92,7 @@ impl ResolveExpr for Expr {
                         name: name.to_string(),
                         location: *location,
                     }),
-                    _ => {
-                        let depth = scopes.iter().rev().enumerate().find_map(|(depth, scope)| {
-                            if scope.contains_key(name) {
-                                Some(depth)
-                            } else {
-                                None
-                            }
-                        });
-                        if let Some(depth) = depth {
-                            locals.insert(*location, depth);
-                            Ok(())
-                        } else {
-                            Err(Error::AccessUndefined {
-                                name: name.to_string(),
-                                location: *location,
-                            })
-                        }
-                    }
+                    _ => resolve_local(name, *location, scopes, locals),
                 }
             }
             Expr::Assignment {
@@ -84,22 +101,7 @@ impl ResolveExpr for Expr {
                 value,
             } => {
                 value.resolve(scopes, locals)?;
-                let depth = scopes.iter().rev().enumerate().find_map(|(depth, scope)| {
-                    if scope.contains_key(name) {
-                        Some(depth)
-                    } else {
-                        None
-                    }
-                });
-                if let Some(depth) = depth {
-                    locals.insert(*location, depth);
-                    Ok(())
-                } else {
-                    Err(Error::AccessUndefined {
-                        name: name.to_string(),
-                        location: *location,
-                    })
-                }
+                resolve_local(name, *location, scopes, locals)
             }
             Expr::Call {
                 callee, arguments, ..
@@ -110,6 +112,12 @@ impl ResolveExpr for Expr {
                 }
                 Ok(())
             }
+            // properties are looked up at runtime, so only the object resolves
+            Expr::Get { object, .. } => object.resolve(scopes, locals),
+            Expr::Set { object, value, .. } => {
+                object.resolve(scopes, locals)?;
+                value.resolve(scopes, locals)
+            }
         }
     }
 }
@@ -147,11 +155,12 @@ impl ResolveStmt for Stmt {
                     });
                 }
                 scopes[last].insert(name, false);
-                if let Some(initializer) = initializer {
-                    initializer.resolve(scopes, locals)?;
-                }
+                let res = match initializer {
+                    Some(initializer) => initializer.resolve(scopes, locals),
+                    None => Ok(()),
+                };
                 scopes[last].insert(name, true);
-                Ok(())
+                res
             }
             Stmt::If {
                 condition,
@@ -171,56 +180,143 @@ impl ResolveStmt for Stmt {
             }
             Stmt::Block(vec) => {
                 scopes.push(HashMap::new());
-                for stmt in vec {
-                    stmt.resolve(scopes, locals)?;
-                }
+                // the scope is popped even after an error, so what follows
+                // the block still resolves at the right depth
+                let res = vec.iter().try_for_each(|stmt| stmt.resolve(scopes, locals));
                 scopes.pop();
-                Ok(())
+                res
             }
             Stmt::FunDecl { name, params, body } => {
                 assert!(!scopes.is_empty());
-                let mut last = scopes.len() - 1;
-                {
-                    scopes[last].insert(name, true);
-                }
-                // closure
-                scopes.push(HashMap::new());
-                last += 1;
-                for param in params {
-                    scopes[last].insert(param, true);
+                let last = scopes.len() - 1;
+                scopes[last].insert(name, true);
+                resolve_function(params, body, scopes, locals)
+            }
+            Stmt::ClassDecl { name, methods, .. } => {
+                assert!(!scopes.is_empty());
+                let last = scopes.len() - 1;
+                scopes[last].insert(name, true);
+                // methods are only reached through instances, so their names
+                // aren't declared anywhere
+                for method in methods {
+                    if let Stmt::FunDecl { params, body, .. } = method {
+                        resolve_function(params, body, scopes, locals)?;
+                    }
                 }
-                body.resolve(scopes, locals)?;
-                scopes.pop();
                 Ok(())
             }
             Stmt::Return(val) => val.resolve(scopes, locals),
-            Stmt::Builtin { .. } => Ok(()),
+            Stmt::Import { .. } => Ok(()),
         }
     }
 }
 
-pub struct Resolver {}
+/// Resolves a function's body in a new scope with its `params`, the scope
+/// its closure will have when it's called
+fn resolve_function(
+    params: &[&'static str],
+    body: &Stmt,
+    scopes: &mut Vec<HashMap<&'static str, bool>>,
+    locals: &mut HashMap<SourceLocation, usize>,
+) -> Result<(), Error> {
+    scopes.push(params.iter().map(|&param| (param, true)).collect());
+    let res = body.resolve(scopes, locals);
+    scopes.pop();
+    res
+}
+
+/// Records how many scopes out `name` is. A global the program declares
+/// later, like a function called by one declared before it, is looked up by
+/// name when it runs instead.
+fn resolve_local(
+    name: &'static str,
+    location: SourceLocation,
+    scopes: &[HashMap<&'static str, bool>],
+    locals: &mut HashMap<SourceLocation, usize>,
+) -> Result<(), Error> {
+    let depth = scopes
+        .iter()
+        .rev()
+        .position(|scope| scope.contains_key(name));
+    match depth {
+        Some(depth) if depth == scopes.len() - 1 => Ok(()),
+        Some(depth) => {
+            locals.insert(location, depth);
+            Ok(())
+        }
+        None => Err(Error::AccessUndefined {
+            name: name.to_string(),
+            location,
+        }),
+    }
+}
+
+pub struct Resolver {
+    /// names already defined when the program starts, like natives
+    globals: Vec<&'static str>,
+    /// globals earlier files in the same session defined, which can be
+    /// defined again at the top level
+    defined: Vec<&'static str>,
+}
 
 impl Resolver {
     pub fn new() -> Self {
-        Self {}
+        Self::with_globals(default_natives().iter().map(|n| n.name).collect())
+    }
+
+    pub fn with_globals(globals: Vec<&'static str>) -> Self {
+        Self {
+            globals,
+            defined: Vec::new(),
+        }
+    }
+
+    /// Also knows about globals defined by files run before this one
+    pub fn with_defined(mut self, defined: Vec<&'static str>) -> Self {
+        self.defined = defined;
+        self
     }
 
-    pub fn resolve(&self, stmts: &Vec<Stmt>) -> HashMap<SourceLocation, usize> {
+    /// Resolves every statement, handing back the errors found along the way
+    pub fn resolve_all(&self, stmts: &Vec<Stmt>) -> (HashMap<SourceLocation, usize>, Vec<Error>) {
         let mut res = HashMap::new();
-        let mut scopes = vec![HashMap::new()];
-        self.builtin_clock(&mut scopes);
+        let mut errors = Vec::new();
+        // under the globals, every global the program declares, for
+        // `resolve_local` to find when they're used before they're declared
+        let declared = globals_defined(stmts).map(|name| (name, true)).collect();
+        let mut scopes = vec![declared, HashMap::new()];
+        scopes[1].extend(self.globals.iter().map(|&name| (name, true)));
+        scopes[1].extend(self.defined.iter().map(|&name| (name, true)));
         for stmt in stmts {
-            let res = stmt.resolve(&mut scopes, &mut res);
-            if let Err(e) = res {
-                println!("Resolver Error: {e}");
+            if let Err(e) = stmt.resolve(&mut scopes, &mut res) {
+                errors.push(e);
             }
         }
-        res
+        // like assigning it, when an earlier file defined the name
+        errors.retain(|e| match e {
+            Error::DuplicateVariable { name, location } => {
+                !(self.defined.contains(&name.as_str()) && is_global_decl(stmts, *location))
+            }
+            _ => true,
+        });
+        (res, errors)
     }
+}
 
-    fn builtin_clock(&self, scopes: &mut [HashMap<&'static str, bool>]) {
-        let last = scopes.len() - 1;
-        scopes[last].insert("clock", true);
-    }
+/// Whether a top-level `var` is declared at `location`
+fn is_global_decl(stmts: &[Stmt], location: SourceLocation) -> bool {
+    stmts
+        .iter()
+        .any(|stmt| matches!(stmt, Stmt::VarDecl { location: l, .. } if *l == location))
+}
+
+/// The globals a program defines, which `Resolver::with_defined` gives to
+/// files run after it
+pub(crate) fn globals_defined(stmts: &[Stmt]) -> impl Iterator<Item = &'static str> + '_ {
+    stmts.iter().filter_map(|stmt| match stmt {
+        Stmt::VarDecl { name, .. } | Stmt::FunDecl { name, .. } | Stmt::ClassDecl { name, .. } => {
+            Some(*name)
+        }
+        _ => None,
+    })
 }
@@ -1,22 +1,51 @@
-use std::str::CharIndices;
+use std::{fmt::Display, str::CharIndices};
 
 use crate::{location::SourceLocation, token::*};
 
 use itertools::{Itertools, MultiPeek};
 use thiserror::Error;
 
-#[derive(Error, Debug)]
+#[derive(Error, Debug, PartialEq)]
+#[non_exhaustive]
 pub enum Error {
-    #[error("Unexpected character `{c}` at {location}")]
     UnexpectedCharacter { c: char, location: SourceLocation },
-
-    #[error("Unterminated string starting at {location}")]
     UnterminatedString { location: SourceLocation },
-
-    #[error("Unterminated /* block comment */ starting at {location}")]
     UnterminatedComment { location: SourceLocation },
 }
 
+impl Error {
+    pub fn message(&self) -> String {
+        match self {
+            Error::UnexpectedCharacter { c, .. } => format!("Unexpected character `{c}`"),
+            Error::UnterminatedString { .. } => "Unterminated string".to_string(),
+            Error::UnterminatedComment { .. } => "Unterminated block comment".to_string(),
+        }
+    }
+
+    /// Stable name for the kind of error, see `Diagnostic::code`
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::UnexpectedCharacter { .. } => "unexpected-character",
+            Error::UnterminatedString { .. } => "unterminated-string",
+            Error::UnterminatedComment { .. } => "unterminated-comment",
+        }
+    }
+
+    pub fn location(&self) -> SourceLocation {
+        match self {
+            Error::UnexpectedCharacter { location, .. }
+            | Error::UnterminatedString { location }
+            | Error::UnterminatedComment { location } => *location,
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} at {}", self.message(), self.location())
+    }
+}
+
 trait Offset {
     fn offset(&mut self, max: usize) -> usize;
 }
@@ -27,17 +56,68 @@ impl Offset for MultiPeek<CharIndices<'_>> {
     }
 }
 
-pub struct Scanner {}
+/// A `//` or `/* */` comment, kept only by `Scanner::scan_with_comments`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comment {
+    /// The whole comment, including the slashes
+    pub text: &'static str,
+    pub location: SourceLocation,
+}
+
+pub struct Scanner {
+    file: usize,
+}
 
 impl Scanner {
     pub fn new() -> Self {
-        Self {}
+        Self { file: 0 }
+    }
+
+    /// Stamps every location with `file`, so that programs loaded from
+    /// several files don't share them
+    pub fn for_file(file: usize) -> Self {
+        Self { file }
     }
 
     pub fn scan(self, input: &'static str) -> Result<Vec<TokenItem>, Vec<Error>> {
+        let (tokens, errors) = self.scan_all(input);
+        if errors.is_empty() {
+            Ok(tokens)
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Like `scan`, but keeps the tokens around the errors so they can still
+    /// be parsed for more diagnostics. What couldn't be scanned is left as
+    /// `TokenType::Error` tokens, so the parser knows not to report it again.
+    pub fn scan_all(self, input: &'static str) -> (Vec<TokenItem>, Vec<Error>) {
+        self.scan_trivia(input, &mut Vec::new())
+    }
+
+    /// Like `scan`, but also returns the comments, in order, which the
+    /// tokens leave out
+    pub fn scan_with_comments(
+        self,
+        input: &'static str,
+    ) -> Result<(Vec<TokenItem>, Vec<Comment>), Vec<Error>> {
+        let mut comments = Vec::new();
+        let (tokens, errors) = self.scan_trivia(input, &mut comments);
+        if errors.is_empty() {
+            Ok((tokens, comments))
+        } else {
+            Err(errors)
+        }
+    }
+
+    fn scan_trivia(
+        self,
+        input: &'static str,
+        comments: &mut Vec<Comment>,
+    ) -> (Vec<TokenItem>, Vec<Error>) {
         let mut tokens = Vec::new();
         let mut errors = Vec::new();
-        let mut location = SourceLocation::new(1, 0);
+        let mut location = SourceLocation::new(1, 0).in_file(self.file);
         let mut chars = input.char_indices().multipeek();
         let max = input.len();
         let basic_token =
@@ -183,12 +263,24 @@ impl Scanner {
                             chars.next();
                             increment += 1;
                         }
+                        chars.reset_peek();
+                        comments.push(Comment {
+                            text: input[ci.0..chars.offset(max)].trim_end(),
+                            location,
+                        });
                     } else if matches!(c2, Some((_, '*'))) {
+                        // the opening `*` can't also start the closing `*/`
+                        chars.next();
                         if let Some(move_by) = Self::parse_multiline_comment(&mut chars) {
+                            comments.push(Comment {
+                                text: &input[ci.0..chars.offset(max)],
+                                location,
+                            });
                             location.merge(move_by);
                             increment = 0;
                         } else {
                             errors.push(Error::UnterminatedComment { location });
+                            tokens.push(basic_token(TokenType::Error, &input[ci.0..], location));
                         }
                     } else {
                         tokens.push(basic_token(
@@ -210,6 +302,7 @@ impl Scanner {
                         increment = 0;
                     } else {
                         errors.push(Error::UnterminatedString { location });
+                        tokens.push(basic_token(TokenType::Error, &input[ci.0..], location));
                     }
                 }
                 c if c.is_ascii_digit() => {
@@ -249,7 +342,11 @@ impl Scanner {
                 ' ' | '\r' | '\t' => {
                     // ignore whitespace
                 }
-                other => errors.push(Error::UnexpectedCharacter { c: other, location }),
+                other => {
+                    errors.push(Error::UnexpectedCharacter { c: other, location });
+                    let lexeme = &input[ci.0..ci.0 + other.len_utf8()];
+                    tokens.push(basic_token(TokenType::Error, lexeme, location));
+                }
             }
             location.advance_by(increment);
         }
@@ -259,11 +356,7 @@ impl Scanner {
             literal: None,
             location,
         });
-        if errors.is_empty() {
-            Ok(tokens)
-        } else {
-            Err(errors)
-        }
+        (tokens, errors)
     }
 
     fn parse_number(max: usize, chars: &mut MultiPeek<CharIndices<'_>>) -> (usize, usize) {
@@ -343,7 +436,7 @@ impl Scanner {
 
     fn parse_multiline_comment(chars: &mut MultiPeek<CharIndices<'_>>) -> Option<SourceLocation> {
         let mut move_by = SourceLocation::new(0, 0);
-        let mut increment = 1;
+        let mut increment = 2;
         // dept of comment nesting
         let mut comment_level = 1;
         while let Some(c2) = chars.next() {
@@ -547,6 +640,67 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_scanner_keeps_comments() {
+        let (tokens, comments) = Scanner::new()
+            .scan_with_comments("x; // one\n/* two\n */ y")
+            .unwrap();
+        assert_eq!(tokens.len(), 4);
+        assert_eq!(
+            comments,
+            vec![
+                Comment {
+                    text: "// one",
+                    location: SourceLocation::new(1, 3)
+                },
+                Comment {
+                    text: "/* two\n */",
+                    location: SourceLocation::new(2, 0)
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_scanner_comment_errors() {
+        // `/*/` opens a comment without closing it
+        let tokens = Scanner::new().scan("/*/ still a comment */ x").unwrap();
+        assert_eq!(tokens[0].lexeme, "x");
+        assert_eq!(tokens[0].location, SourceLocation::new(1, 23));
+
+        assert_eq!(
+            Scanner::new().scan("var a;\n  /* /* nested */\nprint a;"),
+            Err(vec![Error::UnterminatedComment {
+                location: SourceLocation::new(2, 2)
+            }])
+        );
+    }
+
+    #[test]
+    fn test_scanner_error_recovery() {
+        // tokens after a bad character keep their real positions
+        assert_eq!(
+            Scanner::new().scan("@ x\n  # \"open\nstring"),
+            Err(vec![
+                Error::UnexpectedCharacter {
+                    c: '@',
+                    location: SourceLocation::new(1, 0)
+                },
+                Error::UnexpectedCharacter {
+                    c: '#',
+                    location: SourceLocation::new(2, 2)
+                },
+                Error::UnterminatedString {
+                    location: SourceLocation::new(2, 4)
+                },
+            ])
+        );
+        let tokens = Scanner::new().scan("/* a\nb */ \"c\nd\" e").unwrap();
+        assert_eq!(tokens[0].location, SourceLocation::new(2, 5));
+        assert_eq!(tokens[1].lexeme, "e");
+        assert_eq!(tokens[1].location, SourceLocation::new(3, 3));
+    }
+
     #[test]
     fn test_scanner_string() {
         let tokens = Scanner::new().scan("var x = \"hello world\";").unwrap();
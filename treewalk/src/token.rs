@@ -1,6 +1,15 @@
-use std::{cell::RefCell, fmt::Display, rc::Rc};
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fmt::{Debug, Display},
+    rc::Rc,
+};
 
-use crate::{ast::Stmt, environment::Environment, location::SourceLocation};
+use crate::{
+    ast::{Native, Stmt},
+    environment::Environment,
+    location::SourceLocation,
+};
 
 #[derive(Debug, Clone)]
 pub enum Literal {
@@ -9,6 +18,9 @@ pub enum Literal {
         body: Rc<Stmt>,
         closure: Rc<RefCell<Environment>>,
     },
+    Native(Rc<Native>),
+    Class(Rc<Class>),
+    Instance(Rc<RefCell<Instance>>),
     String(Rc<String>),
     Number(f64),
     True,
@@ -20,6 +32,18 @@ impl Literal {
     pub(crate) fn is_truthy(&self) -> bool {
         !matches!(self, Literal::Nil | Literal::False)
     }
+
+    pub(crate) fn type_name(&self) -> &'static str {
+        match self {
+            Literal::Function { .. } | Literal::Native(_) => "function",
+            Literal::Class(_) => "class",
+            Literal::Instance(_) => "instance",
+            Literal::String(_) => "string",
+            Literal::Number(_) => "number",
+            Literal::True | Literal::False => "bool",
+            Literal::Nil => "nil",
+        }
+    }
 }
 
 impl PartialOrd for Literal {
@@ -41,14 +65,13 @@ impl Display for Literal {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Literal::Function { .. } => write!(f, "function"),
+            Literal::Native(native) => write!(f, "<native fn {}>", native.name),
+            Literal::Class(class) => write!(f, "{}", class.name),
+            Literal::Instance(instance) => write!(f, "{} instance", instance.borrow().class.name),
             Literal::String(s) => write!(f, "{}", s),
-            Literal::Number(n) => {
-                if n.fract() == 0.0 {
-                    write!(f, "{}", *n as i64)
-                } else {
-                    write!(f, "{}", n)
-                }
-            }
+            // integral values print without a fraction, keeping the sign of -0
+            // and the digits of values too big for an integer type
+            Literal::Number(n) => write!(f, "{}", n),
             Literal::True => write!(f, "true"),
             Literal::False => write!(f, "false"),
             Literal::Nil => write!(f, "nil"),
@@ -61,6 +84,9 @@ impl PartialEq for Literal {
         match (self, other) {
             (Self::Function { .. }, _) => false,
             (_, Self::Function { .. }) => false,
+            (Literal::Native(a), Literal::Native(b)) => Rc::ptr_eq(a, b),
+            (Literal::Class(a), Literal::Class(b)) => Rc::ptr_eq(a, b),
+            (Literal::Instance(a), Literal::Instance(b)) => Rc::ptr_eq(a, b),
             (Literal::String(a), Literal::String(b)) => a == b,
             (Literal::Number(a), Literal::Number(b)) => a == b,
             (Literal::True, Literal::True) => true,
@@ -71,6 +97,43 @@ impl PartialEq for Literal {
     }
 }
 
+/// What `class` declares; calling it makes an instance
+#[derive(Debug)]
+pub(crate) struct Class {
+    pub name: &'static str,
+    /// each a `Literal::Function`
+    pub methods: HashMap<&'static str, Literal>,
+}
+
+pub(crate) struct Instance {
+    pub class: Rc<Class>,
+    pub fields: HashMap<&'static str, Literal>,
+}
+
+impl Instance {
+    pub fn new(class: Rc<Class>) -> Self {
+        Self {
+            class,
+            fields: HashMap::new(),
+        }
+    }
+
+    /// The field called `name`, or else the method
+    pub fn get(&self, name: &str) -> Option<Literal> {
+        self.fields
+            .get(name)
+            .or_else(|| self.class.methods.get(name))
+            .cloned()
+    }
+}
+
+// by hand because a field can hold the instance itself
+impl Debug for Instance {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Instance {}", self.class.name)
+    }
+}
+
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub enum TokenType {
     // Basic
@@ -101,6 +164,7 @@ pub enum TokenType {
     Fun,
     For,
     If,
+    Import,
     Nil,
     Or,
     Print,
@@ -115,6 +179,8 @@ pub enum TokenType {
     Number,
     // Other
     Identifier,
+    /// input the scanner reported an error for, from `Scanner::scan_all`
+    Error,
     EoF,
 }
 
@@ -128,6 +194,7 @@ impl TokenType {
             "fun" => Some(TokenType::Fun),
             "for" => Some(TokenType::For),
             "if" => Some(TokenType::If),
+            "import" => Some(TokenType::Import),
             "nil" => Some(TokenType::Nil),
             "or" => Some(TokenType::Or),
             "print" => Some(TokenType::Print),
@@ -0,0 +1,120 @@
+//! Noticing when a script or a file it imports is saved, for `--watch`
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+/// What changed since the files were last run
+#[derive(Debug, PartialEq)]
+pub enum Poll {
+    Unchanged,
+    /// Every file is there and at least one was modified, so it's time to
+    /// run again
+    Changed,
+    /// A file is gone, like while an editor replaces it on save, so running
+    /// now would only fail
+    Missing(PathBuf),
+}
+
+/// The files a run loaded and their modification times as of that run
+#[derive(Debug, Default)]
+pub struct Watcher {
+    files: Vec<(PathBuf, Option<SystemTime>)>,
+}
+
+impl Watcher {
+    /// Watches `paths` from now on, instead of the files before. Call it
+    /// right after each run, so saves made while it ran count as changes
+    /// only if they came after it read them.
+    pub fn watch(&mut self, paths: impl IntoIterator<Item = PathBuf>) {
+        self.files.clear();
+        for path in paths {
+            if !self.files.iter().any(|(watched, _)| *watched == path) {
+                let modified = modified(&path);
+                self.files.push((path, modified));
+            }
+        }
+    }
+
+    pub fn poll(&self) -> Poll {
+        let mut changed = false;
+        for (path, before) in &self.files {
+            match modified(path) {
+                None => return Poll::Missing(path.clone()),
+                now => changed |= now != *before,
+            }
+        }
+        if changed {
+            Poll::Changed
+        } else {
+            Poll::Unchanged
+        }
+    }
+
+    /// Polls every `interval` until a file changes
+    pub fn wait(&self, interval: Duration) {
+        while self.poll() != Poll::Changed {
+            std::thread::sleep(interval);
+        }
+    }
+}
+
+/// `None` when the file can't be read, most likely because it's not there
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs::{self, File};
+
+    use super::*;
+
+    /// Sets `path`'s modification time `secs` seconds after the epoch, so
+    /// tests don't depend on how finely the file system records it
+    fn touch(path: &Path, secs: u64) {
+        let file = File::options().write(true).open(path).unwrap();
+        file.set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(secs))
+            .unwrap();
+    }
+
+    #[test]
+    fn test_poll() {
+        let dir = std::env::temp_dir().join(format!("treewalk-watch-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let script = dir.join("game.lox");
+        let import = dir.join("lib.lox");
+        for path in [&script, &import] {
+            fs::write(path, "print 1;").unwrap();
+            touch(path, 1000);
+        }
+
+        let mut watcher = Watcher::default();
+        watcher.watch([script.clone(), import.clone(), script.clone()]);
+        assert_eq!(watcher.poll(), Poll::Unchanged);
+
+        // a save of an import counts too
+        touch(&import, 1001);
+        assert_eq!(watcher.poll(), Poll::Changed);
+        // and keeps counting until the next run
+        assert_eq!(watcher.poll(), Poll::Changed);
+        watcher.watch([script.clone(), import.clone()]);
+        assert_eq!(watcher.poll(), Poll::Unchanged);
+
+        // partway through a save that removes the file and writes a new one
+        fs::remove_file(&script).unwrap();
+        assert_eq!(watcher.poll(), Poll::Missing(script.clone()));
+        fs::write(&script, "print 2;").unwrap();
+        touch(&script, 1002);
+        assert_eq!(watcher.poll(), Poll::Changed);
+
+        // a file missing when it ran is a change once it's there
+        watcher.watch([script.clone(), dir.join("later.lox")]);
+        assert_eq!(watcher.poll(), Poll::Missing(dir.join("later.lox")));
+        fs::write(dir.join("later.lox"), "").unwrap();
+        assert_eq!(watcher.poll(), Poll::Changed);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
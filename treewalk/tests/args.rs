@@ -0,0 +1,101 @@
+use std::{
+    io::Write,
+    process::{Command, Output, Stdio},
+};
+
+/// The fixtures `rlox`'s tests run both backends against
+fn fixture(name: &str) -> String {
+    format!(
+        "{}/../rlox/tests/fixtures/{name}",
+        env!("CARGO_MANIFEST_DIR")
+    )
+}
+
+fn run(args: &[&str], stdin: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_treewalk"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(stdin.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+fn exit_code(args: &[&str]) -> Option<i32> {
+    let output = run(args, "");
+    assert!(output.status.success() || !output.stderr.is_empty());
+    output.status.code()
+}
+
+#[test]
+fn test_exit_codes() {
+    for format in [&[][..], &["--error-format=json"]] {
+        let run = |name: &str| exit_code(&[format, &[fixture(name).as_str()]].concat());
+        assert_eq!(run("shared.lox"), Some(0), "{format:?}");
+        assert_eq!(run("syntax_error.lox"), Some(65), "{format:?}");
+        assert_eq!(run("runtime_error.lox"), Some(70), "{format:?}");
+        assert_eq!(run("missing.lox"), Some(74), "{format:?}");
+    }
+    assert_eq!(exit_code(&["-", "-"]), Some(64));
+    assert_eq!(exit_code(&["--error-format=xml", "a.lox"]), Some(64));
+    assert_eq!(exit_code(&["--", "arg"]), Some(64));
+    assert_eq!(exit_code(&["--watch"]), Some(64));
+    assert_eq!(exit_code(&["--watch", "-"]), Some(64));
+    assert_eq!(exit_code(&["--watch-interval=soon", "a.lox"]), Some(64));
+    assert_eq!(exit_code(&["--check"]), Some(64));
+    assert_eq!(exit_code(&["--timings"]), Some(64));
+}
+
+#[test]
+fn test_help_and_version() {
+    let output = run(&["--help"], "");
+    assert_eq!(output.status.code(), Some(0));
+    let help = String::from_utf8(output.stdout).unwrap();
+    assert!(help.contains("Usage: "), "{help}");
+    assert!(help.contains("--error-format <human|json>"), "{help}");
+
+    let output = run(&["--version"], "");
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        concat!("treewalk ", env!("CARGO_PKG_VERSION"), "\n")
+    );
+
+    let output = run(&["--bogus", "a.lox"], "");
+    assert_eq!(output.status.code(), Some(64));
+    assert!(output.stdout.is_empty());
+    assert!(
+        String::from_utf8(output.stderr)
+            .unwrap()
+            .starts_with("unknown flag `--bogus`\nUsage: ")
+    );
+}
+
+#[test]
+fn test_script_from_stdin() {
+    let output = run(&["-"], "print 1 + 2;");
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(output.stdout, b"3\n");
+}
+
+#[test]
+fn test_warnings_go_to_stderr() {
+    let script = fixture("warning.lox");
+    for format in [&[][..], &["--error-format=json"]] {
+        for args in [vec![script.as_str()], vec!["-"]] {
+            let stdin = std::fs::read_to_string(&script).unwrap();
+            let output = run(&[format, &args].concat(), &stdin);
+            assert_eq!(output.status.code(), Some(0), "{format:?} {args:?}");
+            assert_eq!(output.stdout, b"out\n", "{format:?} {args:?}");
+            let stderr = String::from_utf8(output.stderr).unwrap();
+            assert!(stderr.contains("nope"), "{format:?} {args:?}: {stderr}");
+        }
+    }
+}
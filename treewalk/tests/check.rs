@@ -0,0 +1,103 @@
+use std::{
+    io::Write,
+    path::Path,
+    process::{Command, Output, Stdio},
+};
+
+use treewalk::{Lox, Phase};
+
+/// Runs `treewalk --check` in `tests/check` with `stdin`
+fn check(args: &[&str], stdin: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_treewalk"))
+        .current_dir(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/check"))
+        .arg("--check")
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(stdin.as_bytes())
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    // the fixtures print when run, so any output means one ran
+    assert!(output.stdout.is_empty(), "{args:?} ran");
+    output
+}
+
+fn stderr(output: Output) -> String {
+    String::from_utf8(output.stderr).unwrap()
+}
+
+#[test]
+fn test_check_good() {
+    let output = check(&["good.lox"], "");
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stderr(output), "1 file checked, 0 errors, 0 warnings\n");
+
+    // later files see the globals earlier ones define
+    let output = check(&["prelude.lox", "uses_prelude.lox", "-"], "print greeting;");
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stderr(output), "3 files checked, 0 errors, 0 warnings\n");
+}
+
+#[test]
+fn test_check_warnings() {
+    // the resolver's problems don't stop a program running, so they don't
+    // fail a check either
+    let output = check(&["resolve.lox"], "");
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(
+        stderr(output),
+        "resolve.lox:4:12: resolve warning: Duplicate variable 'a' found in scope\n\
+         resolve.lox:7:11: resolve warning: Can't read local variable 'b' in its own initializer\n\
+         1 file checked, 0 errors, 2 warnings\n"
+    );
+
+    let output = check(&["uses_prelude.lox"], "");
+    assert_eq!(output.status.code(), Some(0));
+    assert!(stderr(output).contains("resolve warning: Access undeclared variable 'greeting'"));
+}
+
+#[test]
+fn test_check_bad() {
+    // carries on past scanner errors, without the parse errors they cause,
+    // and past files with errors
+    let output = check(&["syntax.lox", "missing.lox", "good.lox"], "");
+    assert_eq!(output.status.code(), Some(65));
+    let report = stderr(output);
+    let lines: Vec<_> = report.lines().collect();
+    assert_eq!(
+        lines[..2],
+        [
+            "syntax.lox:2:9: scan error: Unexpected character `@`",
+            "syntax.lox:3:7: parse error: Expected ')' after expression",
+        ]
+    );
+    assert!(lines[2].starts_with("missing.lox: runtime error: "));
+    assert_eq!(lines[3..], ["3 files checked, 3 errors, 0 warnings"]);
+
+    let output = check(&["-"], "var s = \"open;\n");
+    assert_eq!(
+        stderr(output),
+        "<stdin>:1:9: scan error: Unterminated string\n\
+         1 file checked, 1 error, 0 warnings\n"
+    );
+}
+
+#[test]
+fn test_check_file() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/check");
+    let lox = Lox::default();
+    assert_eq!(lox.check_file(&dir.join("good.lox")), []);
+    let phases: Vec<_> = lox
+        .check_file(&dir.join("syntax.lox"))
+        .iter()
+        .map(|d| d.phase)
+        .collect();
+    assert_eq!(phases, [Phase::Scan, Phase::Parse]);
+}
@@ -0,0 +1,91 @@
+use std::process::Command;
+
+use serde_json::{Value, json};
+use treewalk::Lox;
+
+fn parse_lines(output: &str) -> Vec<Value> {
+    output
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect()
+}
+
+const SOURCE: &str = "var a = 1;\nprint a @;\nprint (1;\n";
+
+#[test]
+fn test_check_json() {
+    assert_eq!(
+        parse_lines(&Lox::check_json(SOURCE)),
+        [
+            json!({
+                "severity": "error",
+                "phase": "scan",
+                "code": "unexpected-character",
+                "message": "Unexpected character `@`",
+                "file": "<script>",
+                "line": 2,
+                "column": 9,
+                "end_line": 2,
+                "end_column": 10,
+            }),
+            json!({
+                "severity": "error",
+                "phase": "parse",
+                "code": "unterminated-paren",
+                "message": "Expected ')' after expression",
+                "file": "<script>",
+                "line": 3,
+                "column": 7,
+                "end_line": 3,
+                "end_column": 7,
+            }),
+        ]
+    );
+    assert_eq!(Lox::check_json("print 1;"), "");
+    // resolver errors don't stop a program, but are still reported
+    assert_eq!(
+        parse_lines(&Lox::check_json("{ var a = 1; var a = 2; }"))[0]["phase"],
+        "resolve"
+    );
+}
+
+#[test]
+fn test_error_format_flag() {
+    let dir = std::env::temp_dir().join(format!("treewalk-diagnostics-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let run = |name: &str, source: &str| {
+        let path = dir.join(name);
+        std::fs::write(&path, source).unwrap();
+        Command::new(env!("CARGO_BIN_EXE_treewalk"))
+            .arg("--error-format=json")
+            .arg(&path)
+            .output()
+            .unwrap()
+    };
+
+    let output = run("static.lox", SOURCE);
+    assert_eq!(output.status.code(), Some(65));
+    let diagnostics = parse_lines(&String::from_utf8(output.stderr).unwrap());
+    assert_eq!(diagnostics.len(), 2);
+    assert_eq!(diagnostics[0]["phase"], "scan");
+    assert_eq!(diagnostics[1]["phase"], "parse");
+
+    let output = run("runtime.lox", "print 1;\nprint -\"a\";\n");
+    assert_eq!(output.status.code(), Some(70));
+    assert_eq!(output.stdout, b"1\n");
+    assert_eq!(
+        parse_lines(&String::from_utf8(output.stderr).unwrap()),
+        [json!({
+            "severity": "error",
+            "phase": "runtime",
+            "code": "runtime",
+            "message": "Cannot negate a non-number",
+            "file": dir.join("runtime.lox").display().to_string(),
+            "line": 2,
+            "column": 7,
+            "end_line": 2,
+            "end_column": 7,
+        })]
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,124 @@
+use std::path::Path;
+
+use test_support::write_files;
+use treewalk::{Error, Lox};
+
+fn run(path: &Path) -> Result<String, Error> {
+    let mut out = Vec::new();
+    Lox::default().run_file_reporting(path, &mut out, &mut |d| panic!("unexpected {d}"))?;
+    Ok(String::from_utf8(out).unwrap())
+}
+
+/// The error running `path`, rendered with the names of the files it loaded
+fn rendered_error(path: &Path) -> String {
+    let lox = Lox::default();
+    match lox.run_file(path) {
+        Ok(()) => panic!("expected {} to fail", path.display()),
+        Err(e) => e.render(&lox.sources()),
+    }
+}
+
+#[test]
+fn test_shared_function() {
+    let dir = write_files(
+        "treewalk-import-shared",
+        &[
+            ("main.lox", "import \"lib/math.lox\";\nprint square(3);\n"),
+            (
+                "lib/math.lox",
+                "fun square(n) {\n  var result = n * n;\n  return result;\n}\n",
+            ),
+        ],
+    );
+    assert_eq!(run(&dir.join("main.lox")).unwrap(), "9\n");
+}
+
+#[test]
+fn test_repeated_import() {
+    let dir = write_files(
+        "treewalk-import-repeated",
+        &[
+            (
+                "main.lox",
+                "import \"counter.lox\";\nimport \"other.lox\";\nimport \"./counter.lox\";\nprint count;\n",
+            ),
+            ("counter.lox", "var count = 0;\ncount = count + 1;\n"),
+            (
+                "other.lox",
+                "import \"counter.lox\";\ncount = count + 10;\n",
+            ),
+        ],
+    );
+    assert_eq!(run(&dir.join("main.lox")).unwrap(), "11\n");
+}
+
+#[test]
+fn test_import_cycle() {
+    let dir = write_files(
+        "treewalk-import-cycle",
+        &[
+            ("a.lox", "import \"b.lox\";\n"),
+            ("b.lox", "import \"c.lox\";\n"),
+            ("c.lox", "import \"a.lox\";\n"),
+        ],
+    );
+    let Err(Error::ImportCycle(chain)) = run(&dir.join("a.lox")) else {
+        panic!("expected an import cycle");
+    };
+    let names: Vec<_> = chain
+        .iter()
+        .map(|path| path.file_name().unwrap().to_str().unwrap())
+        .collect();
+    assert_eq!(names, ["a.lox", "b.lox", "c.lox", "a.lox"]);
+    let error = run(&dir.join("a.lox")).unwrap_err().to_string();
+    assert!(error.starts_with("Import cycle: "), "{error}");
+}
+
+#[test]
+fn test_errors_name_the_imported_file() {
+    let dir = write_files(
+        "treewalk-import-errors",
+        &[
+            ("main.lox", "import \"broken.lox\";\n"),
+            ("broken.lox", "print (1;\n"),
+            ("missing.lox", "import \"nowhere.lox\";\n"),
+        ],
+    );
+    let error = run(&dir.join("main.lox")).unwrap_err();
+    let Error::Import { path, error } = &error else {
+        panic!("expected an import error, got {error}");
+    };
+    assert!(path.ends_with("broken.lox"));
+    assert!(matches!(**error, Error::Parser(_)));
+    assert_eq!(
+        rendered_error(&dir.join("main.lox")),
+        format!(
+            "{}:1:7: parse error: Expected ')' after expression",
+            dir.join("broken.lox").display()
+        )
+    );
+
+    let error = rendered_error(&dir.join("missing.lox"));
+    assert!(error.contains("nowhere.lox"), "{error}");
+}
+
+#[test]
+fn test_runtime_errors_name_the_imported_file() {
+    let dir = write_files(
+        "treewalk-import-runtime",
+        &[
+            (
+                "main.lox",
+                "import \"lib/negate.lox\";\nprint negate(1);\nnegate(\"a\");\n",
+            ),
+            ("lib/negate.lox", "fun negate(n) {\n  return -n;\n}\n"),
+        ],
+    );
+    assert_eq!(
+        rendered_error(&dir.join("main.lox")),
+        format!(
+            "{}:2:10: runtime error: Cannot negate a non-number",
+            dir.join("lib/negate.lox").display()
+        )
+    );
+}
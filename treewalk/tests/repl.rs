@@ -0,0 +1,126 @@
+use std::{
+    io::Write,
+    process::{Command, Stdio},
+};
+
+fn repl(input: &str) -> (String, String) {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_treewalk"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    (
+        String::from_utf8(output.stdout).unwrap(),
+        String::from_utf8(output.stderr).unwrap(),
+    )
+}
+
+#[test]
+fn test_repl_keeps_state_across_lines() {
+    let (stdout, stderr) = repl(
+        "var a = 1;
+fun add(x) { return x + a; }
+print b;
+a = a + 1;
+print add(3);",
+    );
+    assert_eq!(stdout, ">>>>2\n>5\n>");
+    // each line is its own chunk, named for where it was entered, and is
+    // resolved against what earlier lines defined like a script would be
+    assert_eq!(
+        stderr,
+        "<repl:3>:1:7: resolve warning: Access undeclared variable 'b'\n\
+         <repl:3>:1:7: runtime error: Undefined variable `b`\n"
+    );
+}
+
+#[test]
+fn test_repl_recovers_from_static_errors() {
+    let (stdout, stderr) = repl("var a = @;\nvar a = ;\nprint 1;\n");
+    assert_eq!(stdout, ">>>1\n>");
+    assert_eq!(
+        stderr,
+        "<repl:1>:1:9: scan error: Unexpected character `@`\n\
+         <repl:2>:1:9: parse error: Unexpected token ';'.  Expected expression\n"
+    );
+}
+
+#[test]
+fn test_repl_continues_unfinished_entries() {
+    let (stdout, stderr) = repl(
+        "fun add(a,
+  b) {
+  return a + b;
+}
+print \"one
+two\";
+print add(1,
+
+2);
+print (1;
+print 3;",
+    );
+    // the entry is only run once it's finished, and counts as one
+    assert_eq!(stdout, ">......>..one\ntwo\n>....3\n>>3\n>");
+    assert_eq!(
+        stderr,
+        "<repl:4>:1:7: parse error: Expected ')' after expression\n"
+    );
+}
+
+#[test]
+fn test_repl_save_and_load() {
+    let path = std::env::temp_dir().join(format!("treewalk-repl-{}.session", std::process::id()));
+    let path = path.display();
+    let (stdout, stderr) = repl(&format!(
+        "var a = 0.1 + 0.2;
+var s = \"two
+lines\";
+var t = true;
+var n;
+fun f() {{}}
+:save {path}"
+    ));
+    assert_eq!(
+        stdout,
+        format!(
+            ">>..>>>>Saved 4 globals to {path}\n\
+             Skipped f: only numbers, strings, booleans and nil can be saved\n>"
+        )
+    );
+    assert_eq!(stderr, "");
+
+    // names the session already has are only replaced when forced
+    let (stdout, stderr) = repl(&format!(
+        "var a = 1;
+:load {path}
+print a;
+:load! {path}
+print a == 0.1 + 0.2;
+print s;
+print t and n == nil;
+print f;"
+    ));
+    assert_eq!(
+        stdout,
+        format!(">>>1\n>Loaded 4 globals from {path}\n>true\n>two\nlines\n>true\n>>")
+    );
+    assert_eq!(
+        stderr,
+        format!(
+            "Already defined: a; `:load! {path}` replaces them\n\
+             <repl:6>:1:7: resolve warning: Access undeclared variable 'f'\n\
+             <repl:6>:1:7: runtime error: Undefined variable `f`\n"
+        )
+    );
+    std::fs::remove_file(path.to_string()).unwrap();
+}
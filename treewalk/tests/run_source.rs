@@ -0,0 +1,414 @@
+use std::{cell::RefCell, io::Write, rc::Rc};
+
+use treewalk::{Error, Lox, ParseError, RuntimeError, ScanError, SourceLocation};
+
+fn run(source: &str) -> Result<String, Error> {
+    let mut out = Vec::new();
+    Lox::default().run_source(source, &mut out)?;
+    Ok(String::from_utf8(out).unwrap())
+}
+
+fn error(source: &str) -> Error {
+    match run(source) {
+        Ok(out) => panic!("expected an error, got output {out:?}"),
+        Err(e) => e,
+    }
+}
+
+fn runtime_error(source: &str) -> RuntimeError {
+    match error(source) {
+        Error::Runtime(e) => e,
+        other => panic!("expected a runtime error, got {other}"),
+    }
+}
+
+#[test]
+fn test_variables() {
+    assert_eq!(
+        run("var a = 1; var b = a + 2; print b; a = b = 5; print a;").unwrap(),
+        "3\n5\n"
+    );
+    assert_eq!(run("var s = \"lox\"; print s + \"!\";").unwrap(), "lox!\n");
+}
+
+#[test]
+fn test_blocks_and_shadowing() {
+    let source = "var a = \"global\";
+        {
+            var a = \"outer\";
+            {
+                var a = \"inner\";
+                print a;
+            }
+            print a;
+        }
+        print a;";
+    assert_eq!(run(source).unwrap(), "inner\nouter\nglobal\n");
+}
+
+#[test]
+fn test_if_else() {
+    assert_eq!(
+        run("if (1 < 2) print \"yes\"; else print \"no\";").unwrap(),
+        "yes\n"
+    );
+    assert_eq!(
+        run("if (nil) print \"yes\"; else print \"no\";").unwrap(),
+        "no\n"
+    );
+    assert_eq!(run("if (false) print 1;").unwrap(), "");
+    assert_eq!(
+        run("if (true) print 1; else print 2; print 3;").unwrap(),
+        "1\n3\n"
+    );
+}
+
+#[test]
+fn test_while() {
+    assert_eq!(
+        run("var i = 0; while (i < 3) { print i; i = i + 1; }").unwrap(),
+        "0\n1\n2\n"
+    );
+}
+
+#[test]
+fn test_for() {
+    assert_eq!(
+        run("for (var i = 0; i < 3; i = i + 1) print i;").unwrap(),
+        "0\n1\n2\n"
+    );
+    assert_eq!(
+        run("var i; for (i = 5; i < 7; i = i + 1) print i; print i;").unwrap(),
+        "5\n6\n7\n"
+    );
+}
+
+#[test]
+fn test_trailing_expression() {
+    assert_eq!(run("print 1; 1 + 2;").unwrap(), "1\n3\n");
+}
+
+#[test]
+fn test_undefined_variable() {
+    assert_eq!(
+        runtime_error("print 1; print y;"),
+        RuntimeError::Runtime {
+            message: "Undefined variable `y`".to_string(),
+            location: SourceLocation::new(1, 15),
+        }
+    );
+}
+
+#[test]
+fn test_type_errors() {
+    assert_eq!(
+        runtime_error("-\"a\";"),
+        RuntimeError::Runtime {
+            message: "Cannot negate a non-number".to_string(),
+            location: SourceLocation::new(1, 0),
+        }
+    );
+    assert_eq!(
+        runtime_error("1 + nil;"),
+        RuntimeError::Runtime {
+            message: "Operands to '+' must be two numbers or involve a string; got number and nil"
+                .to_string(),
+            location: SourceLocation::new(1, 7),
+        }
+    );
+    assert_eq!(
+        runtime_error("1 < \"a\";"),
+        RuntimeError::Runtime {
+            message: "Cannot compare values. Operands must both be numbers".to_string(),
+            location: SourceLocation::new(1, 7),
+        }
+    );
+}
+
+#[test]
+fn test_static_errors() {
+    let e = error("var s = \"open;");
+    assert_eq!(
+        e.scan_errors(),
+        Some(
+            &[ScanError::UnterminatedString {
+                location: SourceLocation::new(1, 8)
+            }][..]
+        )
+    );
+    assert_eq!(e.parse_errors(), None);
+
+    let e = error("print 1");
+    assert_eq!(
+        e.parse_errors(),
+        Some(
+            &[ParseError::ExpectedSemicolon {
+                location: SourceLocation::new(1, 7)
+            }][..]
+        )
+    );
+    assert_eq!(e.runtime_error(), None);
+}
+
+#[test]
+fn test_reports_independent_syntax_errors() {
+    let source = "var a = ;
+if (a print a;
+while (a + ) print a;
+print \"ok\";";
+    assert_eq!(
+        error(source).parse_errors(),
+        Some(
+            &[
+                ParseError::UnexpectedToken {
+                    lexeme: ";".to_string(),
+                    location: SourceLocation::new(1, 8),
+                },
+                ParseError::ExpectedToken {
+                    expected: ")".to_string(),
+                    stmt_type: "if".to_string(),
+                    location: SourceLocation::new(2, 6),
+                },
+                ParseError::UnexpectedToken {
+                    lexeme: ")".to_string(),
+                    location: SourceLocation::new(3, 11),
+                },
+            ][..]
+        )
+    );
+    // an operand error is reported where it happens, not as a missing ';'
+    assert_eq!(
+        error("print 1 +;").parse_errors(),
+        Some(
+            &[ParseError::UnexpectedToken {
+                lexeme: ";".to_string(),
+                location: SourceLocation::new(1, 9),
+            }][..]
+        )
+    );
+}
+
+#[test]
+fn test_builder_output() {
+    #[derive(Clone, Default)]
+    struct SharedBuf(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let out = SharedBuf::default();
+    let lox = Lox::builder().output(Box::new(out.clone())).build();
+    lox.interpret("print 1;").unwrap();
+    lox.interpret("print \"two\";").unwrap();
+    assert_eq!(*out.0.borrow(), b"1\ntwo\n");
+}
+
+#[test]
+fn test_run_source_writes_everything_to_out() {
+    let lox = Lox::default();
+    let mut out = Vec::new();
+    lox.run_source("{ var a = 1; var a = 2; print a; }", &mut out)
+        .unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "<script>:1:23: resolve warning: Duplicate variable 'a' found in scope\n2\n"
+    );
+    // errors point into this `Lox`'s sources, so they can be rendered
+    let e = lox
+        .run_source("print 1;\nprint -nil;", &mut Vec::new())
+        .unwrap_err();
+    assert_eq!(
+        e.render(&lox.sources()),
+        "<script>:2:7: runtime error: Cannot negate a non-number"
+    );
+}
+
+#[test]
+fn test_forward_references() {
+    let source = "fun a() { return b(); }\nfun b() { return 1; }\nprint a();";
+    assert_eq!(Lox::default().run_to_string(source).unwrap(), "1\n");
+    assert_eq!(Lox::check(source), []);
+
+    // an error inside a function doesn't throw off what comes after it
+    let out = Lox::default()
+        .run_to_string("fun f() { print nope; }\nvar z = 3;\nfun g() { return z; }\nprint g();")
+        .unwrap();
+    assert!(
+        out.starts_with("<script>:1:17: resolve warning: Access undeclared variable 'nope'"),
+        "{out:?}"
+    );
+    assert!(out.ends_with("\n3\n"), "{out:?}");
+}
+
+#[test]
+fn test_run_to_string() {
+    let lox = Lox::default();
+    assert_eq!(
+        lox.run_to_string("print 1; print \"two\"; 3;").unwrap(),
+        "1\ntwo\n3\n"
+    );
+    // resolver errors don't stop the program, and are captured with its output
+    let out = lox
+        .run_to_string("{ var a = 1; var a = 2; print a; }")
+        .unwrap();
+    assert!(
+        out.starts_with("<script>:1:23: resolve warning: Duplicate variable 'a'"),
+        "{out:?}"
+    );
+    assert!(out.ends_with("\n2\n"), "{out:?}");
+    assert!(matches!(
+        lox.run_to_string("print -nil;"),
+        Err(Error::Runtime(_))
+    ));
+}
+
+#[test]
+fn test_input_and_clock_capabilities() {
+    let input = std::io::Cursor::new("first line\r\nsecond\n");
+    let lox = Lox::builder().input(Box::new(input)).build();
+    assert_eq!(
+        lox.run_to_string("print readLine(); print readLine(); print readLine();")
+            .unwrap(),
+        "first line\nsecond\nnil\n"
+    );
+    assert!(Lox::default().run_to_string("print clock() > 0;").unwrap() == "true\n");
+
+    // natives that weren't asked for aren't defined
+    let undefined = |lox: &Lox, source: &str| match lox.run_to_string(source) {
+        Err(Error::Runtime(e)) => e.message(),
+        other => panic!("expected a runtime error, got {other:?}"),
+    };
+    assert_eq!(
+        undefined(&Lox::default(), "readLine();"),
+        "Undefined variable `readLine`"
+    );
+    assert_eq!(
+        undefined(&Lox::builder().clock(false).build(), "clock();"),
+        "Undefined variable `clock`"
+    );
+}
+
+#[test]
+fn test_script_args() {
+    let echo = "for (var i = 0; i < argc(); i = i + 1) print argAt(i);";
+    let args = vec!["foo".to_string(), "two words".to_string(), "42".to_string()];
+    let lox = Lox::builder().args(args).build();
+    assert_eq!(lox.run_to_string(echo).unwrap(), "foo\ntwo words\n42\n");
+    // arguments are always strings
+    assert_eq!(
+        lox.run_to_string("print argAt(2) + \"!\";").unwrap(),
+        "42!\n"
+    );
+    assert_eq!(
+        Lox::default().run_to_string("print argc();").unwrap(),
+        "0\n"
+    );
+
+    let message = |source: &str| match lox.run_to_string(source) {
+        Err(Error::Runtime(e)) => e
+            .message()
+            .trim_start_matches("Something went wrong inside builtin function argAt: ")
+            .to_string(),
+        other => panic!("expected a runtime error, got {other:?}"),
+    };
+    assert_eq!(
+        message("argAt(3);"),
+        "Argument index 3 out of range for 3 arguments"
+    );
+    assert_eq!(
+        message("argAt(0.5);"),
+        "Argument index 0.5 out of range for 3 arguments"
+    );
+    assert_eq!(
+        message("argAt(\"0\");"),
+        "Argument index must be a number, not string"
+    );
+}
+
+#[test]
+fn test_getenv() {
+    // cargo sets both for the tests it runs, so nothing has to change the
+    // environment while other tests read it
+    let package = std::env::var("CARGO_PKG_NAME").unwrap();
+    assert!(std::env::var_os("CARGO_MANIFEST_DIR").is_some());
+    let lox = Lox::builder()
+        .allow_env(["CARGO_PKG_NAME", "RLOX_TEST_UNSET"])
+        .build();
+    assert_eq!(
+        lox.run_to_string(
+            "print getenv(\"CARGO_PKG_NAME\");
+             print getenv(\"RLOX_TEST_UNSET\");
+             print getenv(\"CARGO_MANIFEST_DIR\");"
+        )
+        .unwrap(),
+        format!("{package}\nnil\nnil\n")
+    );
+    assert!(matches!(
+        lox.run_to_string("getenv(1);"),
+        Err(Error::Runtime(_))
+    ));
+
+    // without an allow-list, or with an empty one, there's no getenv
+    for lox in [
+        Lox::default(),
+        Lox::builder().allow_env(Vec::<String>::new()).build(),
+    ] {
+        match lox.run_to_string("getenv(\"CARGO_PKG_NAME\");") {
+            Err(Error::Runtime(e)) => assert_eq!(e.message(), "Undefined variable `getenv`"),
+            other => panic!("expected a runtime error, got {other:?}"),
+        }
+    }
+}
+
+#[test]
+fn test_deterministic() {
+    // set by cargo, but hidden from deterministic runs
+    assert!(std::env::var_os("CARGO_PKG_NAME").is_some());
+    let program = "var start = clock();
+        for (var i = 0; i < 3; i = i + 1) print random();
+        print clock() - start;
+        print getenv(\"CARGO_PKG_NAME\");
+        print getenv(\"RLOX_TEST_FIXED\");";
+    let run = || {
+        let lox = Lox::builder()
+            .deterministic(true)
+            .allow_env(["CARGO_PKG_NAME"])
+            .env_value("RLOX_TEST_FIXED", "fixed")
+            .build();
+        assert!(lox.is_deterministic());
+        lox.run_to_string(program).unwrap()
+    };
+    let output = run();
+    assert_eq!(output, run());
+    let lines: Vec<_> = output.lines().collect();
+    assert_eq!(lines[3..], ["0.001", "nil", "fixed"]);
+
+    // otherwise `random()` differs between runs
+    let lox = Lox::default();
+    assert!(!lox.is_deterministic());
+    let random = "for (var i = 0; i < 3; i = i + 1) print random();";
+    assert_ne!(
+        lox.run_to_string(random).unwrap(),
+        Lox::default().run_to_string(random).unwrap()
+    );
+}
+
+#[test]
+fn test_max_steps() {
+    let lox = Lox::builder().max_steps(1000).build();
+    match lox.run_to_string("print 1; while (true) {}") {
+        Err(Error::Runtime(e)) => assert_eq!(e.message(), "Execution budget exceeded"),
+        other => panic!("expected a runtime error, got {other:?}"),
+    }
+    // each run gets the whole budget
+    let count = "var i = 0; while (i < 600) i = i + 1; print i;";
+    assert_eq!(lox.run_to_string(count).unwrap(), "600\n");
+    assert_eq!(lox.run_to_string(count).unwrap(), "600\n");
+}
@@ -0,0 +1,90 @@
+use std::process::Command;
+
+use test_support::write_files;
+use treewalk::Lox;
+
+const PRELUDE: &str =
+    "var greeting = \"hi\";\nfun greet(name) {\n  return greeting + \" \" + name;\n}\n";
+
+#[test]
+fn test_later_files_see_earlier_globals() {
+    let dir = write_files(
+        "treewalk-session-globals",
+        &[
+            ("prelude.lox", PRELUDE),
+            (
+                "main.lox",
+                "print greet(\"lox\");\nvar greeting = \"hello\";\nprint greet(\"again\");\n",
+            ),
+        ],
+    );
+    let lox = Lox::default();
+    let mut session = lox.session();
+    let mut out = Vec::new();
+    // defining a global an earlier file defined replaces it, as in one file
+    let report = &mut |d| panic!("unexpected {d}");
+    for name in ["prelude.lox", "main.lox"] {
+        session
+            .run_file_reporting(&dir.join(name), &mut out, report)
+            .unwrap();
+    }
+    assert_eq!(String::from_utf8(out).unwrap(), "hi lox\nhello again\n");
+}
+
+#[test]
+fn test_binary_runs_files_in_order() {
+    let dir = write_files(
+        "treewalk-session-binary",
+        &[
+            ("prelude.lox", PRELUDE),
+            ("main.lox", "print greet(\"lox\");\n"),
+            ("fails.lox", "print 1;\nprint -greet(\"x\");\n"),
+            ("never.lox", "print \"unreachable\";\n"),
+        ],
+    );
+    let treewalk = |names: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_treewalk"))
+            .current_dir(&dir)
+            .args(names)
+            .output()
+            .unwrap()
+    };
+
+    let output = treewalk(&["prelude.lox", "main.lox"]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "hi lox\n");
+
+    // the error is reported in the file it happened in, and stops the rest
+    let output = treewalk(&["prelude.lox", "fails.lox", "never.lox"]);
+    assert_eq!(output.status.code(), Some(70));
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "1\n");
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "fails.lox:2:7: runtime error: Cannot negate a non-number\n"
+    );
+
+    // without the prelude, `greet` isn't defined
+    let output = treewalk(&["main.lox"]);
+    assert_eq!(output.status.code(), Some(70));
+    assert!(
+        String::from_utf8(output.stderr)
+            .unwrap()
+            .contains("main.lox:1:7: runtime error: Undefined variable `greet`"),
+    );
+}
+
+#[test]
+fn test_unreadable_file_is_named() {
+    let dir = write_files("treewalk-session-unreadable", &[("prelude.lox", PRELUDE)]);
+    let output = Command::new(env!("CARGO_BIN_EXE_treewalk"))
+        .current_dir(&dir)
+        .args(["prelude.lox", "missing.lox"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(74));
+    assert!(
+        String::from_utf8(output.stderr)
+            .unwrap()
+            .starts_with("missing.lox: runtime error: "),
+    );
+}
@@ -0,0 +1,25 @@
+use std::process::Command;
+
+#[test]
+fn test_timings() {
+    let output = Command::new(env!("CARGO_BIN_EXE_treewalk"))
+        .current_dir(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/check"))
+        .args(["--timings", "good.lox"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "0\n");
+
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    let mut lines = stderr.lines();
+    assert_eq!(lines.next(), Some("phase            ms"));
+    let rows: Vec<(&str, f64)> = lines
+        .map(|line| {
+            let (phase, ms) = line.split_once(' ').unwrap();
+            (phase, ms.trim().parse().unwrap())
+        })
+        .collect();
+    let phases: Vec<_> = rows.iter().map(|(phase, _)| *phase).collect();
+    assert_eq!(phases, ["scan", "parse", "resolve", "execute", "total"]);
+    assert!(rows.iter().all(|(_, ms)| *ms >= 0.0), "{stderr}");
+}